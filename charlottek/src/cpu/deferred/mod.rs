@@ -0,0 +1,17 @@
+//! # Deferred Work
+//!
+//! Interrupt handlers should acknowledge their device and return as quickly as possible. Work that
+//! does not have to happen with interrupts masked is deferred with one of two mechanisms:
+//!
+//! - [Tasklets](tasklet) run on the LP that scheduled them as soon as the outermost interrupt
+//!   handler on that LP returns, with interrupts unmasked. Like interrupt handlers they must not
+//!   block.
+//! - [Work queues](work_queue) run their items in a worker context that may block. A kernel thread
+//!   on each LP serves the [system work queue](work_queue::SYSTEM_WORK_QUEUE) and other queues can
+//!   be given worker threads of their own with [`WorkQueue::spawn_worker`].
+
+pub mod tasklet;
+pub mod work_queue;
+
+pub use tasklet::{run_tasklets, schedule_tasklet};
+pub use work_queue::{DelayedWork, WorkQueue, queue_delayed_work, queue_work};
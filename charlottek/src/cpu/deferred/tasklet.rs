@@ -0,0 +1,60 @@
+//! # Tasklets
+//!
+//! Every LP has a queue of tasklets which is drained when the LP leaves interrupt context. Tasklets
+//! queued while the queue is being drained, including by the interrupt handlers that can now
+//! interrupt the draining, are run in the same pass. To keep an interrupt storm from starving
+//! threads the queue is only drained a limited number of times per pass; whatever is left over
+//! runs on the next interrupt exit or once the LP goes idle.
+
+use alloc::boxed::Box;
+use alloc::collections::vec_deque::VecDeque;
+use core::arch::asm;
+
+use crate::cpu::sync::IrqSpinlock;
+use crate::cpu::sync::context::SoftirqContextGuard;
+use crate::isa::lp::ops::{interrupts_enabled, mask_interrupts, unmask_interrupts};
+use crate::{lock_class, percpu};
+
+const MAX_DRAIN_ROUNDS: usize = 8;
+
+pub type Tasklet = Box<dyn FnOnce() + Send>;
+
+percpu! {
+    static TASKLETS: IrqSpinlock<VecDeque<Tasklet>> =
+        IrqSpinlock::with_class(lock_class!("TASKLETS"), VecDeque::new());
+}
+
+/// Runs `tasklet` on the current LP once it leaves interrupt context
+pub fn schedule_tasklet(tasklet: impl FnOnce() + Send + 'static) {
+    TASKLETS.get().lock().push_back(Box::new(tasklet));
+}
+
+/// Returns true if tasklets are waiting to run on the current LP
+pub fn has_pending_tasklets() -> bool {
+    !TASKLETS.get().lock().is_empty()
+}
+
+/// Runs the tasklets queued on the current LP unless it is already running them further up the
+/// stack. The scheduler does not preempt the LP until this returns.
+pub fn run_tasklets() {
+    let Some(_softirq) = SoftirqContextGuard::try_enter() else {
+        return;
+    };
+    let interrupts_were_enabled = interrupts_enabled();
+    for _ in 0..MAX_DRAIN_ROUNDS {
+        let tasklets = core::mem::take(&mut *TASKLETS.get().lock());
+        if tasklets.is_empty() {
+            break;
+        }
+        unmask_interrupts!();
+        for tasklet in tasklets {
+            tasklet();
+        }
+        mask_interrupts!();
+    }
+    // Records logged by the interrupt handlers and tasklets were left for this point.
+    crate::log::flush();
+    if interrupts_were_enabled {
+        unmask_interrupts!();
+    }
+}
@@ -0,0 +1,171 @@
+//! # Work Queues
+//!
+//! A work queue is a FIFO of work items that are run in a context that may block. Items can be
+//! queued from any context including interrupt handlers. Every queued item runs exactly once, on
+//! whichever worker dequeues it first, so items on a queue served by several workers may run
+//! concurrently.
+//!
+//! Every LP that runs a scheduler gets a kernel thread pinned to it that serves the system work
+//! queue, which sleeps while the queue is empty and may block in the items it runs. LPs without a
+//! scheduler, e.g. during initialization, serve the system work queue from their idle loop instead,
+//! where blocking falls back to spinning. An LP only returns to its idle loop after an interrupt,
+//! so work queued while no worker thread exists waits until an idle LP next takes one.
+
+use alloc::boxed::Box;
+use alloc::collections::vec_deque::VecDeque;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use crate::cpu::scheduler::GLOBAL_SCHEDULER;
+use crate::cpu::sync::context::debug_assert_can_block;
+use crate::cpu::sync::{Deadline, IrqSpinlock, Semaphore};
+use crate::cpu::threads::{AffinityError, Thread};
+use crate::cpu::timers::{self, TimerHandle};
+use crate::isa::lp::LpId;
+use crate::isa::lp::ops::get_lp_id;
+use crate::{lock_class, logln, percpu, warn};
+
+pub type Work = Box<dyn FnOnce() + Send>;
+
+/// The work queue served by a worker thread on every LP with a scheduler and by the idle loop of
+/// the others
+pub static SYSTEM_WORK_QUEUE: WorkQueue = WorkQueue::new("system");
+
+percpu! {
+    /// Whether a worker thread serves the system work queue on each LP
+    static HAS_SYSTEM_WORKER: AtomicBool = AtomicBool::new(false);
+}
+
+pub struct WorkQueue {
+    name: &'static str,
+    // Items are queued from interrupt handlers.
+    items: IrqSpinlock<VecDeque<Work>>,
+    /// Counts the queued items that no worker has claimed yet
+    pending: Semaphore,
+}
+
+impl WorkQueue {
+    pub const fn new(name: &'static str) -> Self {
+        WorkQueue {
+            name,
+            items: IrqSpinlock::with_class(lock_class!("WorkQueue.items"), VecDeque::new()),
+            pending: Semaphore::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Queues `work` to be run by one of the queue's workers
+    pub fn queue(&self, work: impl FnOnce() + Send + 'static) {
+        self.items.lock().push_back(Box::new(work));
+        self.pending.release();
+    }
+
+    /// Queues `work` once `delay` has elapsed. The delay is measured on the current LP but the
+    /// work may run on any LP serving the queue.
+    pub fn queue_delayed(
+        &'static self,
+        delay: Duration,
+        work: impl FnOnce() + Send + 'static,
+    ) -> DelayedWork {
+        let timer = timers::add_timer(Deadline::after(delay), move || self.queue(work));
+        DelayedWork {
+            timer,
+        }
+    }
+
+    /// Returns true if items are queued that no worker has claimed yet
+    pub fn has_pending(&self) -> bool {
+        self.pending.available() != 0
+    }
+
+    /// Runs the items queued so far without waiting for more. Returns the number of items run.
+    pub fn run_pending(&self) -> usize {
+        let mut n_run = 0;
+        while self.pending.try_acquire() {
+            self.run_claimed();
+            n_run += 1;
+        }
+        n_run
+    }
+
+    /// Spawns a kernel thread pinned to `lp_id` that serves the queue. Returns an error if the LP
+    /// is offline or has no scheduler.
+    pub fn spawn_worker(&'static self, lp_id: LpId) -> Result<(), AffinityError> {
+        let queue = self as *const Self as usize;
+        GLOBAL_SCHEDULER.add_thread(Thread::new_kernel_pinned(worker_main, queue, lp_id))?;
+        Ok(())
+    }
+
+    /// Serves the queue forever. Meant to be the body of a dedicated worker thread.
+    pub fn run_worker(&self) -> ! {
+        loop {
+            debug_assert_can_block();
+            self.pending.acquire();
+            self.run_claimed();
+        }
+    }
+
+    /// Runs an item after a unit of `pending` has been taken for it
+    fn run_claimed(&self) {
+        // Items are pushed before the unit for them is released so one must be available.
+        let work = self.items.lock().pop_front();
+        if let Some(work) = work {
+            work();
+        }
+    }
+}
+
+extern "C" fn worker_main(queue: usize) -> ! {
+    // The queue is a `&'static WorkQueue` passed through the thread's argument register.
+    let queue = unsafe { &*(queue as *const WorkQueue) };
+    queue.run_worker()
+}
+
+/// Spawns the system work queue's worker thread for the current LP. Called once the LP's scheduler
+/// has been started.
+pub fn start_system_worker() {
+    let lp_id = get_lp_id!();
+    match SYSTEM_WORK_QUEUE.spawn_worker(lp_id) {
+        Ok(()) => {
+            HAS_SYSTEM_WORKER.get().store(true, Ordering::Release);
+            logln!("LP{}: Started the system work queue worker.", lp_id);
+        }
+        Err(error) => warn!(
+            "LP{}: Failed to start the system work queue worker: {:?}. The idle loop serves the \
+             queue instead.",
+            lp_id, error
+        ),
+    }
+}
+
+/// Returns true if the current LP's idle loop has to serve the system work queue because no worker
+/// thread does
+pub fn idle_serves_system_queue() -> bool {
+    !HAS_SYSTEM_WORKER.get().load(Ordering::Acquire)
+}
+
+/// A work item that is queued once its delay elapses
+#[derive(Debug)]
+pub struct DelayedWork {
+    timer: TimerHandle,
+}
+
+impl DelayedWork {
+    /// Prevents the work from being queued. Returns false if it already has been.
+    pub fn cancel(&self) -> bool {
+        timers::cancel_timer(&self.timer)
+    }
+}
+
+/// Queues `work` on the system work queue
+pub fn queue_work(work: impl FnOnce() + Send + 'static) {
+    SYSTEM_WORK_QUEUE.queue(work);
+}
+
+/// Queues `work` on the system work queue once `delay` has elapsed
+pub fn queue_delayed_work(delay: Duration, work: impl FnOnce() + Send + 'static) -> DelayedWork {
+    SYSTEM_WORK_QUEUE.queue_delayed(delay, work)
+}
@@ -0,0 +1,47 @@
+//! # Interrupt Wakers
+//!
+//! An [`AtomicWaker`] holds the waker of the task waiting on a device so that the device's
+//! interrupt handler can wake it. The task registers its waker each time it is polled, before it
+//! checks the device state, so an interrupt arriving in between is never missed.
+
+use core::task::Waker;
+
+use crate::cpu::sync::IrqSpinlock;
+use crate::lock_class;
+
+pub struct AtomicWaker {
+    // Woken from interrupt handlers.
+    waker: IrqSpinlock<Option<Waker>>,
+}
+
+impl AtomicWaker {
+    pub const fn new() -> Self {
+        AtomicWaker {
+            waker: IrqSpinlock::with_class(lock_class!("AtomicWaker.waker"), None),
+        }
+    }
+
+    /// Registers `waker` to be woken by the next call to [`wake`](Self::wake), replacing any
+    /// previously registered waker
+    pub fn register(&self, waker: &Waker) {
+        let mut registered = self.waker.lock();
+        match registered.as_ref() {
+            Some(current) if current.will_wake(waker) => {}
+            _ => *registered = Some(waker.clone()),
+        }
+    }
+
+    /// Wakes the registered waker, if any. Safe to call from interrupt handlers.
+    pub fn wake(&self) {
+        let waker = self.waker.lock().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for AtomicWaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,237 @@
+//! # Async Executor
+//!
+//! A cooperative executor for kernel futures, letting drivers express their device logic as
+//! `async fn`s. Every LP has a queue of tasks that are ready to be polled. A task stays on the LP
+//! it was spawned on and is queued there again whenever it is woken, whether by another task, by a
+//! [`Completion`](crate::event::Completion), by a [timer](timer) or by an interrupt handler through
+//! an [`AtomicWaker`].
+//!
+//! Each LP's idle loop polls the tasks queued on it. Kernel threads pinned to an LP may serve its
+//! queue as well with [`run_worker`]. Tasks must not block since they hold up every other task on
+//! their LP while they are being polled.
+
+pub mod atomic_waker;
+pub mod task;
+pub mod timer;
+
+use alloc::boxed::Box;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::arch::asm;
+use core::future::Future;
+use core::pin::{Pin, pin};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+
+pub use atomic_waker::AtomicWaker;
+use task::Task;
+pub use timer::{sleep, sleep_until, timeout};
+
+use crate::cpu::multiprocessor::get_lp_count;
+use crate::cpu::scheduler::GLOBAL_SCHEDULER;
+use crate::cpu::sync::context::debug_assert_can_block;
+use crate::cpu::sync::{IrqSpinlock, Spinlock, WaitQueue};
+use crate::event::Completion;
+use crate::isa::lp::LpId;
+use crate::isa::lp::ops::{
+    get_lp_id,
+    interrupts_enabled,
+    mask_interrupts,
+    unmask_interrupts,
+    wait_for_interrupt,
+};
+use crate::{lock_class, percpu};
+
+percpu! {
+    // Tasks are woken from interrupt handlers.
+    static RUN_QUEUE: IrqSpinlock<VecDeque<Arc<Task>>> =
+        IrqSpinlock::with_class(lock_class!("EXECUTOR_RUN_QUEUE"), VecDeque::new());
+    /// The worker threads waiting for tasks to be queued on the LP
+    static WORKERS: WaitQueue = WaitQueue::new();
+}
+
+fn enqueue(task: Arc<Task>) {
+    let lp_id = task.lp_id();
+    let (Some(run_queue), Some(workers)) = (RUN_QUEUE.get_for(lp_id), WORKERS.get_for(lp_id))
+    else {
+        panic!("A task was queued on LP{} which has not been initialized", lp_id);
+    };
+    run_queue.lock().push_back(task);
+    workers.wake_one();
+}
+
+/// Spawns `future` as a task on the current LP
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_on(get_lp_id!(), future)
+}
+
+/// Spawns `future` as a task on the given LP
+pub fn spawn_on<F>(lp_id: LpId, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    assert!(lp_id < get_lp_count(), "Cannot spawn a task on nonexistent LP{}", lp_id);
+    let state = Arc::new(JoinState {
+        completion: Completion::new(),
+        output: Spinlock::with_class(lock_class!("JoinState.output"), None),
+    });
+    let task_state = state.clone();
+    let task = Task::new(
+        lp_id,
+        Box::pin(async move {
+            let output = future.await;
+            *task_state.output.lock() = Some(output);
+            task_state.completion.complete();
+        }),
+    );
+    task.schedule();
+    JoinHandle {
+        state,
+    }
+}
+
+/// Returns true if tasks are waiting to be polled on the current LP
+pub fn has_pending() -> bool {
+    !RUN_QUEUE.get().lock().is_empty()
+}
+
+/// Polls the tasks that are queued on the current LP. Tasks woken while doing so are left for the
+/// next call. Returns the number of tasks polled.
+pub fn run_pending() -> usize {
+    let n_queued = RUN_QUEUE.get().lock().len();
+    for _ in 0..n_queued {
+        let task = RUN_QUEUE.get().lock().pop_front();
+        match task {
+            Some(task) => {
+                task.poll();
+            }
+            None => return n_queued,
+        }
+    }
+    n_queued
+}
+
+/// Serves the current LP's task queue forever. Meant to be the body of a kernel thread that is
+/// pinned to the LP.
+pub fn run_worker() -> ! {
+    loop {
+        debug_assert_can_block();
+        run_pending();
+        WORKERS.get().wait_if(|| RUN_QUEUE.get().lock().is_empty(), None);
+    }
+}
+
+/// Wakes the thread blocked in [`block_on`]
+struct ThreadWaker {
+    woken: AtomicBool,
+    waiters: WaitQueue,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        self.waiters.wake_all();
+    }
+}
+
+/// Runs `future` to completion on the current thread, blocking it whenever the future is pending.
+/// Outside of a scheduled thread the LP serves its task queue and waits for interrupts instead.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    debug_assert_can_block();
+    let mut future = pin!(future);
+    let thread_waker = Arc::new(ThreadWaker {
+        woken: AtomicBool::new(false),
+        waiters: WaitQueue::new(),
+    });
+    let waker = Waker::from(thread_waker.clone());
+    let mut cx = Context::from_waker(&waker);
+    let is_thread = GLOBAL_SCHEDULER.current_thread_id().is_some();
+    let interrupts_were_enabled = interrupts_enabled();
+    loop {
+        thread_waker.woken.store(false, Ordering::Release);
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        if is_thread {
+            thread_waker.waiters.wait_if(|| !thread_waker.woken.load(Ordering::Acquire), None);
+            continue;
+        }
+        // The future may be waiting on a task queued on this very LP.
+        while !thread_waker.woken.load(Ordering::Acquire) {
+            if run_pending() != 0 {
+                continue;
+            }
+            // An interrupt handler that wakes the future after the check below ends the wait at
+            // once, since interrupts are only unmasked together with halting.
+            mask_interrupts!();
+            if !thread_waker.woken.load(Ordering::Acquire) && !has_pending() {
+                wait_for_interrupt();
+            }
+            if interrupts_were_enabled {
+                unmask_interrupts!();
+            }
+        }
+    }
+}
+
+struct JoinState<T> {
+    completion: Completion,
+    output: Spinlock<Option<T>>,
+}
+
+/// Waits for a spawned task to finish and retrieves its output, either by blocking in
+/// [`join`](Self::join) or by being awaited
+pub struct JoinHandle<T> {
+    state: Arc<JoinState<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn is_finished(&self) -> bool {
+        self.state.completion.is_complete()
+    }
+
+    /// Blocks the current thread until the task has finished and returns its output
+    pub fn join(self) -> T {
+        self.state.completion.wait();
+        self.take_output()
+    }
+
+    fn take_output(&self) -> T {
+        self.state.output.lock().take().expect("A task's output was taken twice")
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        if self.state.completion.register_waker(cx.waker()) {
+            Poll::Ready(self.take_output())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Returns a future that yields to the other tasks on the LP once before resolving
+pub fn yield_now() -> impl Future<Output = ()> {
+    let mut has_yielded = false;
+    core::future::poll_fn(move |cx| {
+        if has_yielded {
+            return Poll::Ready(());
+        }
+        has_yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+}
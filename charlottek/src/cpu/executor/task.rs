@@ -0,0 +1,78 @@
+//! # Tasks
+//!
+//! A task owns a spawned future and is queued on the executor queue of its home LP whenever its
+//! waker is woken. Waking a task that is already queued has no effect, so a task is polled once no
+//! matter how many times it was woken in the meantime.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use super::enqueue;
+use crate::cpu::sync::Spinlock;
+use crate::isa::lp::LpId;
+use crate::lock_class;
+
+pub type BoxedFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+pub struct Task {
+    /// The LP whose executor queue the task is placed on when woken
+    lp_id: LpId,
+    // Taken once the future has completed so that its resources are released right away.
+    future: Spinlock<Option<BoxedFuture>>,
+    is_queued: AtomicBool,
+}
+
+impl Task {
+    pub fn new(lp_id: LpId, future: BoxedFuture) -> Arc<Self> {
+        Arc::new(Task {
+            lp_id,
+            future: Spinlock::with_class(lock_class!("Task.future"), Some(future)),
+            is_queued: AtomicBool::new(false),
+        })
+    }
+
+    pub fn lp_id(&self) -> LpId {
+        self.lp_id
+    }
+
+    /// Queues the task on its home LP unless it is already queued
+    pub fn schedule(self: &Arc<Self>) {
+        if !self.is_queued.swap(true, Ordering::AcqRel) {
+            enqueue(self.clone());
+        }
+    }
+
+    /// Polls the task's future once. Returns true if it has completed.
+    pub fn poll(self: &Arc<Self>) -> bool {
+        // Cleared first so that a wakeup during the poll queues the task again.
+        self.is_queued.store(false, Ordering::Release);
+        let mut future = self.future.lock();
+        let Some(inner) = future.as_mut() else {
+            return true;
+        };
+        let waker = Waker::from(self.clone());
+        let mut cx = Context::from_waker(&waker);
+        match inner.as_mut().poll(&mut cx) {
+            Poll::Ready(()) => {
+                *future = None;
+                true
+            }
+            Poll::Pending => false,
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.schedule();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.schedule();
+    }
+}
@@ -0,0 +1,97 @@
+//! # Timer Futures
+//!
+//! Futures that resolve once a deadline expires. The kernel timer backing a future is added on the
+//! LP that first polls it and is cancelled if the future is dropped before it fires.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use super::AtomicWaker;
+use crate::cpu::sync::Deadline;
+use crate::cpu::timers::{self, TimerHandle};
+
+/// Resolves once its deadline has expired
+pub struct Sleep {
+    deadline: Deadline,
+    waker: Arc<AtomicWaker>,
+    timer: Option<TimerHandle>,
+}
+
+/// Returns a future that resolves once `duration` has elapsed
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Deadline::after(duration))
+}
+
+/// Returns a future that resolves once `deadline` has expired
+pub fn sleep_until(deadline: Deadline) -> Sleep {
+    Sleep {
+        deadline,
+        waker: Arc::new(AtomicWaker::new()),
+        timer: None,
+    }
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Deadline {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        if this.deadline.has_expired() {
+            return Poll::Ready(());
+        }
+        this.waker.register(cx.waker());
+        if this.timer.is_none() {
+            let waker = this.waker.clone();
+            this.timer = Some(timers::add_timer(this.deadline, move || waker.wake()));
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(timer) = &self.timer {
+            timers::cancel_timer(timer);
+        }
+    }
+}
+
+/// The error returned when a future does not resolve within its timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Resolves to the output of the wrapped future or to [`Elapsed`] if its deadline expires first
+pub struct Timeout<F: Future> {
+    future: Pin<Box<F>>,
+    sleep:  Sleep,
+}
+
+/// Returns a future that resolves to the output of `future` unless `duration` elapses first
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future: Box::pin(future),
+        sleep:  sleep(duration),
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Poll::Ready(output) = this.future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut this.sleep).poll(cx).map(|()| Err(Elapsed))
+    }
+}
@@ -1,6 +1,10 @@
 use crate::memory::pmem::PAddr;
 
+pub mod deferred;
+pub mod executor;
 pub mod multiprocessor;
+pub mod percpu;
 pub mod scheduler;
 pub mod sync;
 pub mod threads;
+pub mod timers;
@@ -0,0 +1,155 @@
+//! # LP Masks
+//!
+//! Sets of LPs represented as bitmaps with one bit per possible LP. [`LpMask`] is a plain value
+//! while [`AtomicLpMask`] can be updated concurrently from several LPs.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::MAX_LP_COUNT;
+use crate::isa::lp::LpId;
+
+const WORDS: usize = MAX_LP_COUNT.div_ceil(64);
+
+fn word_and_bit(lp_id: LpId) -> (usize, u64) {
+    let lp_id = lp_id as usize;
+    assert!(lp_id < MAX_LP_COUNT, "LP{} is beyond the maximum number of LPs", lp_id);
+    (lp_id / 64, 1 << (lp_id % 64))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LpMask {
+    words: [u64; WORDS],
+}
+
+impl LpMask {
+    pub const fn empty() -> Self {
+        LpMask {
+            words: [0; WORDS],
+        }
+    }
+
+    /// Returns a mask containing every possible LP
+    pub const fn all() -> Self {
+        LpMask {
+            words: [u64::MAX; WORDS],
+        }
+    }
+
+    /// Returns a mask containing only the given LP
+    pub fn single(lp_id: LpId) -> Self {
+        let mut mask = Self::empty();
+        mask.insert(lp_id);
+        mask
+    }
+
+    pub fn contains(&self, lp_id: LpId) -> bool {
+        let (word, bit) = word_and_bit(lp_id);
+        self.words[word] & bit != 0
+    }
+
+    pub fn insert(&mut self, lp_id: LpId) {
+        let (word, bit) = word_and_bit(lp_id);
+        self.words[word] |= bit;
+    }
+
+    pub fn remove(&mut self, lp_id: LpId) {
+        let (word, bit) = word_and_bit(lp_id);
+        self.words[word] &= !bit;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|word| *word == 0)
+    }
+
+    pub fn count(&self) -> u32 {
+        self.words.iter().map(|word| word.count_ones()).sum()
+    }
+
+    pub fn intersection(&self, other: &LpMask) -> LpMask {
+        let mut mask = *self;
+        for (word, other) in mask.words.iter_mut().zip(other.words.iter()) {
+            *word &= other;
+        }
+        mask
+    }
+
+    pub fn union(&self, other: &LpMask) -> LpMask {
+        let mut mask = *self;
+        for (word, other) in mask.words.iter_mut().zip(other.words.iter()) {
+            *word |= other;
+        }
+        mask
+    }
+
+    /// Iterates over the LPs in the mask in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = LpId> + '_ {
+        self.words.iter().enumerate().flat_map(|(index, word)| {
+            let mut remaining = *word;
+            core::iter::from_fn(move || {
+                if remaining == 0 {
+                    return None;
+                }
+                let bit = remaining.trailing_zeros();
+                remaining &= remaining - 1;
+                Some((index * 64) as LpId + bit)
+            })
+        })
+    }
+}
+
+impl Default for LpMask {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl fmt::Debug for LpMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+pub struct AtomicLpMask {
+    words: [AtomicU64; WORDS],
+}
+
+impl AtomicLpMask {
+    pub const fn empty() -> Self {
+        AtomicLpMask {
+            words: [const { AtomicU64::new(0) }; WORDS],
+        }
+    }
+
+    /// Adds an LP to the mask. Returns false if it was already present.
+    pub fn insert(&self, lp_id: LpId) -> bool {
+        let (word, bit) = word_and_bit(lp_id);
+        self.words[word].fetch_or(bit, Ordering::AcqRel) & bit == 0
+    }
+
+    /// Removes an LP from the mask. Returns false if it was not present.
+    pub fn remove(&self, lp_id: LpId) -> bool {
+        let (word, bit) = word_and_bit(lp_id);
+        self.words[word].fetch_and(!bit, Ordering::AcqRel) & bit != 0
+    }
+
+    pub fn contains(&self, lp_id: LpId) -> bool {
+        let (word, bit) = word_and_bit(lp_id);
+        self.words[word].load(Ordering::Acquire) & bit != 0
+    }
+
+    /// Returns a snapshot of the mask. Each word is read atomically but the mask as a whole is not.
+    pub fn load(&self) -> LpMask {
+        let mut mask = LpMask::empty();
+        for (word, atomic) in mask.words.iter_mut().zip(self.words.iter()) {
+            *word = atomic.load(Ordering::Acquire);
+        }
+        mask
+    }
+}
+
+impl Default for AtomicLpMask {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
@@ -1,9 +1,18 @@
 //! # Multi-Processor Management
+
+pub mod lp_mask;
+
+use lp_mask::{AtomicLpMask, LpMask};
 use spin::{Lazy, RwLock};
 
+use crate::cpu::scheduler::GLOBAL_SCHEDULER;
 use crate::environment::boot_protocol::limine::MP;
 use crate::{ap_main, logln};
 
+/// The maximum number of logical processors the kernel will manage. This bounds statically sized
+/// per-LP tables that must be usable before the kernel allocator is available.
+pub const MAX_LP_COUNT: usize = 256;
+
 static LP_COUNT: RwLock<Lazy<u32>> = RwLock::new(Lazy::new(|| {
     if let Some(mp_res) = MP.get_response() {
         mp_res.cpus().len() as u32
@@ -16,6 +25,31 @@ pub fn get_lp_count() -> u32 {
     **LP_COUNT.read()
 }
 
+/// The LPs that have completed their initialization and have not been taken offline
+static ONLINE_LPS: AtomicLpMask = AtomicLpMask::empty();
+
+pub fn online_lps() -> LpMask {
+    ONLINE_LPS.load()
+}
+
+pub fn is_lp_online(lp_id: LpId) -> bool {
+    ONLINE_LPS.contains(lp_id)
+}
+
+/// Marks the current LP as available to run threads and returns the threads parked while it was
+/// offline to it
+pub fn set_local_lp_online() {
+    ONLINE_LPS.insert(get_lp_id!());
+    GLOBAL_SCHEDULER.online_local_lp();
+}
+
+/// Marks the current LP as no longer available to run threads and moves its threads to the LPs
+/// that remain online. Must be called outside of any of the LP's threads.
+pub fn set_local_lp_offline() {
+    ONLINE_LPS.remove(get_lp_id!());
+    GLOBAL_SCHEDULER.offline_local_lp();
+}
+
 #[derive(Debug)]
 pub enum MpError {
     SecondaryLpStartupFailed,
@@ -45,6 +79,7 @@ pub fn start_secondary_lps() -> Result<(), MpError> {
 
 use core::sync::atomic::{AtomicU32, Ordering};
 
+use crate::isa::lp::LpId;
 use crate::isa::lp::ops::*;
 
 pub static ID_COUNTER: AtomicU32 = AtomicU32::new(0);
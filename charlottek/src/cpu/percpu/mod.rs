@@ -0,0 +1,163 @@
+//! # Per-LP Data
+//!
+//! Every LP owns a local data area whose address is kept in an ISA specific base register, GS on
+//! x86_64 and TPIDR_EL1 on aarch64. Variables declared with [`percpu!`](crate::percpu) are placed
+//! in the `.percpu` linker section, which serves as the template each LP's area is copied from, and
+//! are accessed at the same offset within the current LP's area. Their initial value is therefore
+//! duplicated bitwise for every LP.
+//!
+//! The area starts with an [`LpLocalHeader`] holding the state that assembly routines need. The
+//! offsets of its fields are exported as `PERCPU_OFFSET_*` symbols for use in assembly.
+//!
+//! The BSP's area is reserved statically since it is set up before the kernel allocator is
+//! available while the areas of the APs are allocated on the heap. Per-LP variables must not be
+//! accessed before the current LP's area has been set up.
+
+use alloc::alloc::{Layout, alloc_zeroed};
+use core::mem::offset_of;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::cpu::multiprocessor::MAX_LP_COUNT;
+use crate::isa::lp::LpId;
+use crate::isa::lp::ops::{get_lp_id, lp_local_base, set_lp_local_base};
+use crate::isa::lp::thread_context::ThreadContext;
+
+const AREA_ALIGN: usize = 64;
+const BSP_AREA_SIZE: usize = 16 * 1024;
+
+unsafe extern "C" {
+    static __percpu_start: u8;
+    static __percpu_end: u8;
+}
+
+#[repr(C, align(64))]
+struct BspArea([u8; BSP_AREA_SIZE]);
+
+static mut BSP_AREA: BspArea = BspArea([0; BSP_AREA_SIZE]);
+static AREAS: [AtomicPtr<u8>; MAX_LP_COUNT] = [const { AtomicPtr::new(null_mut()) }; MAX_LP_COUNT];
+
+/// The fixed portion at the start of every LP's local data area
+#[repr(C)]
+pub struct LpLocalHeader {
+    /// The address of the area itself which lets the current LP find it with a single load
+    /// relative to its base register
+    base: usize,
+    /// The thread context the next context switch saves the current register state into or null
+    /// if there is nothing to save
+    pub save_context: AtomicPtr<ThreadContext>,
+    /// The thread context the next context switch loads
+    pub load_context: AtomicPtr<ThreadContext>,
+}
+
+// Kept even while unreferenced since the area is laid out around the header.
+#[used]
+#[unsafe(link_section = ".percpu.header")]
+pub static LP_LOCAL_HEADER: PerLp<LpLocalHeader> = PerLp::new(LpLocalHeader {
+    base: 0,
+    save_context: AtomicPtr::new(null_mut()),
+    load_context: AtomicPtr::new(null_mut()),
+});
+
+#[unsafe(no_mangle)]
+pub static PERCPU_OFFSET_SAVE_CONTEXT: usize = offset_of!(LpLocalHeader, save_context);
+#[unsafe(no_mangle)]
+pub static PERCPU_OFFSET_LOAD_CONTEXT: usize = offset_of!(LpLocalHeader, load_context);
+
+/// Declares one or more per-LP variables
+///
+/// ```ignore
+/// percpu! {
+///     pub static IPI_QUEUE: IrqSpinlock<VecDeque<Ipi>> = IrqSpinlock::new(VecDeque::new());
+/// }
+/// ```
+#[macro_export]
+macro_rules! percpu {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)*) => {
+        $(
+            $(#[$attr])*
+            #[unsafe(link_section = ".percpu")]
+            $vis static $name: $crate::cpu::percpu::PerLp<$ty> =
+                $crate::cpu::percpu::PerLp::new($init);
+        )*
+    };
+}
+pub use percpu;
+
+/// A variable of which every LP has its own instance. The value stored in the static itself is
+/// only the template the instances are copied from and is never accessed.
+#[repr(transparent)]
+pub struct PerLp<T> {
+    template: T,
+}
+
+// Each instance is only reachable from other LPs through `get_for` which requires `T: Sync`.
+unsafe impl<T> Sync for PerLp<T> {}
+
+impl<T> PerLp<T> {
+    #[doc(hidden)]
+    pub const fn new(template: T) -> Self {
+        PerLp {
+            template,
+        }
+    }
+
+    fn offset(&self) -> usize {
+        &raw const self.template as usize - template_start()
+    }
+
+    /// Returns the current LP's instance
+    #[inline(always)]
+    pub fn get(&self) -> &T {
+        unsafe { &*((lp_local_base() + self.offset()) as *const T) }
+    }
+}
+
+impl<T: Sync> PerLp<T> {
+    /// Returns the instance belonging to the given LP or `None` if its area has not been set up
+    pub fn get_for(&self, lp_id: LpId) -> Option<&T> {
+        let area = AREAS.get(lp_id as usize)?.load(Ordering::Acquire);
+        if area.is_null() {
+            None
+        } else {
+            Some(unsafe { &*(area.add(self.offset()) as *const T) })
+        }
+    }
+}
+
+fn template_start() -> usize {
+    &raw const __percpu_start as usize
+}
+
+fn template_size() -> usize {
+    &raw const __percpu_end as usize - template_start()
+}
+
+/// Sets up the BSP's local data area
+pub fn init_bsp_area() {
+    assert!(
+        template_size() <= BSP_AREA_SIZE,
+        "The per-LP data template is larger than the BSP's local data area"
+    );
+    unsafe { install_area((&raw mut BSP_AREA).cast()) }
+}
+
+/// Sets up the current AP's local data area. The kernel allocator must be available.
+pub fn init_ap_area() {
+    let layout = Layout::from_size_align(template_size(), AREA_ALIGN).unwrap();
+    let area = unsafe { alloc_zeroed(layout) };
+    assert!(!area.is_null(), "Failed to allocate a local data area for LP{}", (get_lp_id!()));
+    unsafe { install_area(area) }
+}
+
+/// # Safety
+/// `area` must be valid for writes of the template's size, aligned to `AREA_ALIGN` and never freed.
+unsafe fn install_area(area: *mut u8) {
+    let lp_id = get_lp_id!();
+    unsafe {
+        core::ptr::copy_nonoverlapping(&raw const __percpu_start, area, template_size());
+        (*area.cast::<LpLocalHeader>()).base = area as usize;
+        set_lp_local_base(area as usize);
+    }
+    AREAS[lp_id as usize].store(area, Ordering::Release);
+}
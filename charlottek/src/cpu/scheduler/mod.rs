@@ -1,45 +1,413 @@
+pub mod round_robin;
+
 use alloc::boxed::Box;
-use alloc::collections::BTreeSet;
+use alloc::collections::BTreeMap;
 use alloc::collections::vec_deque::VecDeque;
 use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
-use spin::Mutex;
-use spin::rwlock::RwLock;
+use spin::Once;
 
-use crate::cpu::threads::{Thread, ThreadId};
-use crate::isa::lp::ops::get_lp_id;
+use crate::cpu::multiprocessor::lp_mask::LpMask;
+use crate::cpu::multiprocessor::{get_lp_count, is_lp_online, online_lps};
+use crate::cpu::sync::context::{in_interrupt_context, is_preemptible};
+use crate::cpu::sync::{Deadline, IrqSpinlock, rcu};
+use crate::cpu::threads::{AffinityError, Thread, ThreadId};
+use crate::cpu::{deferred, timers};
+use crate::isa::interrupts::context_switch::request_context_switch;
+use crate::isa::interrupts::ipis::{Ipi, send_ipi};
+use crate::isa::lp::LpId;
+use crate::isa::lp::ops::{get_lp_id, interrupts_enabled, mask_interrupts, unmask_interrupts};
 use crate::memory::AddressSpaceId;
+use crate::{lock_class, logln, percpu};
 
 pub static GLOBAL_SCHEDULER: GlobalScheduler = GlobalScheduler::new();
 
+percpu! {
+    static LP_SCHEDULER: Once<IrqSpinlock<Box<dyn LpScheduler>>> = Once::new();
+    /// Threads to be moved to another LP once they have been switched out, with their destination
+    static PENDING_MIGRATIONS: IrqSpinlock<Vec<(ThreadId, LpId)>> =
+        IrqSpinlock::with_class(lock_class!("PENDING_MIGRATIONS"), Vec::new());
+}
+
+/// Where the search for an LP to place a thread on starts, so that threads are spread out
+static NEXT_PLACEMENT: AtomicU32 = AtomicU32::new(0);
+
 pub struct GlobalScheduler {
-    blocked_threads: RwLock<BTreeSet<ThreadId>>,
-    ready_unassigned: Mutex<VecDeque<ThreadId>>,
-    lp_schedulers: Vec<Mutex<Box<dyn LpScheduler>>>,
+    /// Threads that are waiting on a synchronization primitive or event, mapped to the LP whose
+    /// scheduler they belong to
+    blocked_threads: IrqSpinlock<BTreeMap<ThreadId, LpId>>,
+    /// Threads woken while the LP they belong to was offline, which are handed back to it once it
+    /// comes back online
+    ready_unassigned: IrqSpinlock<VecDeque<ThreadId>>,
+    /// Pinned threads whose LP has gone offline, waiting for it to come back online
+    parked_threads: IrqSpinlock<Vec<(LpId, ThreadId, Thread)>>,
 }
 
 impl GlobalScheduler {
     pub const fn new() -> Self {
         Self {
-            blocked_threads: RwLock::new(BTreeSet::new()),
-            ready_unassigned: Mutex::new(VecDeque::new()),
-            lp_schedulers: Vec::new(),
+            blocked_threads: IrqSpinlock::with_class(
+                lock_class!("GLOBAL_SCHEDULER.blocked_threads"),
+                BTreeMap::new(),
+            ),
+            ready_unassigned: IrqSpinlock::with_class(
+                lock_class!("GLOBAL_SCHEDULER.ready_unassigned"),
+                VecDeque::new(),
+            ),
+            parked_threads: IrqSpinlock::with_class(
+                lock_class!("GLOBAL_SCHEDULER.parked_threads"),
+                Vec::new(),
+            ),
+        }
+    }
+
+    /// Starts scheduling threads on the current LP with the given LP scheduler and spawns the LP's
+    /// system work queue worker
+    pub fn start_local_lp_scheduler(&self, lp_scheduler: Box<dyn LpScheduler>) {
+        if LP_SCHEDULER.get().is_completed() {
+            return;
+        }
+        LP_SCHEDULER.get().call_once(|| IrqSpinlock::new(lp_scheduler));
+        deferred::work_queue::start_system_worker();
+    }
+
+    pub fn get_local_lp_scheduler(&self) -> &IrqSpinlock<Box<dyn LpScheduler>> {
+        LP_SCHEDULER.get().get().expect("No LP scheduler has been started on the current LP")
+    }
+
+    /// Returns the scheduler of the given LP if one has been started on it
+    pub fn try_get_lp_scheduler(&self, lp_id: LpId) -> Option<&IrqSpinlock<Box<dyn LpScheduler>>> {
+        LP_SCHEDULER.get_for(lp_id)?.get()
+    }
+
+    /// Returns the ID of the thread running on the current LP or `None` if the current LP is not
+    /// executing in the context of a scheduled thread e.g. during system initialization
+    pub fn current_thread_id(&self) -> Option<ThreadId> {
+        self.try_get_lp_scheduler(get_lp_id!())?.lock().current_thread()
+    }
+
+    /// Blocks the current thread until [`wake_thread`](Self::wake_thread) is called on it or
+    /// `deadline` expires. The thread is not blocked if `woken` is already set or the deadline has
+    /// already expired once it has been recorded as blocked, which closes the window between a
+    /// waiter deciding to block and a waker observing it as blocked.
+    pub fn block_current_thread(&self, woken: &AtomicBool, deadline: Option<Deadline>) {
+        let lp_id = get_lp_id!();
+        let Some(lp_scheduler) = self.try_get_lp_scheduler(lp_id) else {
+            return;
+        };
+        let Some(tid) = lp_scheduler.lock().current_thread() else {
+            return;
+        };
+        // The timer fires on this LP and so cannot run while the blocked threads are locked below.
+        let timer = deadline
+            .map(|deadline| timers::add_timer(deadline, move || GLOBAL_SCHEDULER.wake_thread(tid)));
+        let blocked = {
+            let mut blocked = self.blocked_threads.lock();
+            if woken.load(Ordering::Acquire) || deadline.is_some_and(|d| d.has_expired()) {
+                false
+            } else {
+                blocked.insert(tid, lp_id);
+                lp_scheduler.lock().block_thread(tid);
+                true
+            }
+        };
+        if blocked {
+            self.yield_current();
+        }
+        if let Some(timer) = timer {
+            timers::cancel_timer(&timer);
+        }
+    }
+
+    /// Makes a blocked thread runnable again on the LP it was blocked on. Waking a thread that is
+    /// not blocked has no effect.
+    pub fn wake_thread(&self, tid: ThreadId) {
+        let lp_id = self.blocked_threads.lock().remove(&tid);
+        if let Some(lp_id) = lp_id {
+            match self.try_get_lp_scheduler(lp_id).filter(|_| is_lp_online(lp_id)) {
+                Some(lp_scheduler) => {
+                    lp_scheduler.lock().wake_thread(tid);
+                    self.reschedule(lp_id);
+                }
+                None => self.ready_unassigned.lock().push_back(tid),
+            }
+        }
+    }
+
+    /// Lets an LP that threads have been made runnable on know about them. An LP whose run queue
+    /// was empty has stopped its tick and may be waiting for an interrupt in its idle loop.
+    fn reschedule(&self, lp_id: LpId) {
+        if lp_id == get_lp_id!() {
+            timers::restart_tick();
+        } else {
+            send_ipi(LpMask::single(lp_id), Ipi::Reschedule);
+        }
+    }
+
+    /// Preempts the current thread on a scheduler tick unless it is in an RCU read-side critical
+    /// section, holds a spinlock or the tick interrupted deferred work, none of which may be
+    /// interrupted by a context switch
+    pub fn tick(&self) {
+        if !rcu::in_rcu_read_section() && !in_interrupt_context() && is_preemptible() {
+            self.yield_current();
+        }
+    }
+
+    /// Gives up the remainder of the current thread's time slice
+    pub fn yield_current(&self) {
+        if let Some(lp_scheduler) = self.try_get_lp_scheduler(get_lp_id!()) {
+            rcu::note_quiescent_state();
+            // Nothing else may run on this LP between selecting the next thread and switching to
+            // it, or the selection could be undone before the switch takes place.
+            let interrupts_were_enabled = interrupts_enabled();
+            mask_interrupts!();
+            lp_scheduler.lock().advance();
+            request_context_switch();
+            if interrupts_were_enabled {
+                unmask_interrupts!();
+            }
+            // Execution resumes here on whichever LP the thread is scheduled on next, once the
+            // threads switched out in the meantime can be moved.
+            self.run_pending_migrations();
+        }
+    }
+
+    /// Selects an online LP with a running scheduler out of `affinity`
+    fn select_lp(&self, affinity: LpMask) -> Option<LpId> {
+        let candidates = affinity.intersection(&online_lps());
+        let start = NEXT_PLACEMENT.fetch_add(1, Ordering::Relaxed) % get_lp_count();
+        candidates
+            .iter()
+            .filter(|&lp_id| lp_id >= start)
+            .chain(candidates.iter().filter(|&lp_id| lp_id < start))
+            .find(|&lp_id| self.try_get_lp_scheduler(lp_id).is_some())
+    }
+
+    /// Hands a new thread to the scheduler of an LP it has affinity for. Returns the LP chosen.
+    pub fn add_thread(&self, thread: Thread) -> Result<LpId, AffinityError> {
+        let lp_id = self.select_lp(thread.affinity()).ok_or(AffinityError::NoOnlineLp)?;
+        self.get_lp_scheduler(lp_id).lock().add_thread(thread);
+        self.reschedule(lp_id);
+        Ok(lp_id)
+    }
+
+    fn get_lp_scheduler(&self, lp_id: LpId) -> &IrqSpinlock<Box<dyn LpScheduler>> {
+        self.try_get_lp_scheduler(lp_id)
+            .unwrap_or_else(|| panic!("No LP scheduler has been started on LP{}", lp_id))
+    }
+
+    /// Restricts a thread to the LPs in `affinity`. A thread on an LP outside of the new affinity
+    /// is migrated to one inside it; if it is running at the time it is moved as soon as it has
+    /// been switched out.
+    pub fn set_affinity(&self, tid: ThreadId, affinity: LpMask) -> Result<(), AffinityError> {
+        if affinity.intersection(&online_lps()).is_empty() {
+            return Err(AffinityError::NoOnlineLp);
+        }
+        let mut host = None;
+        for lp_id in online_lps().iter() {
+            let Some(lp_scheduler) = self.try_get_lp_scheduler(lp_id) else {
+                continue;
+            };
+            if let Some(thread) = lp_scheduler.lock().thread_mut(tid) {
+                thread.set_affinity(affinity)?;
+                host = Some(lp_id);
+                break;
+            }
+        }
+        let host = host.ok_or(AffinityError::NoSuchThread)?;
+        if affinity.contains(host) {
+            return Ok(());
+        }
+        let destination = self.select_lp(affinity).ok_or(AffinityError::NoOnlineLp)?;
+        if host == get_lp_id!() {
+            self.migrate_local_thread(tid, destination);
+        } else {
+            send_ipi(LpMask::single(host), Ipi::MigrateThread(tid, destination));
+        }
+        Ok(())
+    }
+
+    /// Moves a thread of the current LP's scheduler to `destination`. A running thread is taken
+    /// out of the set of threads eligible to run and moved once it has been switched out.
+    pub fn migrate_local_thread(&self, tid: ThreadId, destination: LpId) {
+        let lp_scheduler = self.get_local_lp_scheduler();
+        let thread = {
+            let mut lp_scheduler = lp_scheduler.lock();
+            if lp_scheduler.current_thread() == Some(tid) {
+                lp_scheduler.block_thread(tid);
+                None
+            } else {
+                lp_scheduler.take_thread(tid)
+            }
+        };
+        match thread {
+            Some(thread) => self.move_thread(tid, thread, destination),
+            None => {
+                PENDING_MIGRATIONS.get().lock().push((tid, destination));
+                // A thread moving itself leaves right away. Otherwise it is switched out by the
+                // next tick.
+                if self.current_thread_id() == Some(tid) && !in_interrupt_context() {
+                    self.yield_current();
+                }
+            }
+        }
+    }
+
+    /// Moves the threads waiting to be migrated off the current LP that are no longer running
+    pub fn run_pending_migrations(&self) {
+        let Some(lp_scheduler) = self.try_get_lp_scheduler(get_lp_id!()) else {
+            return;
+        };
+        let mut ready = Vec::new();
+        PENDING_MIGRATIONS.get().lock().retain(|&(tid, destination)| {
+            match lp_scheduler.lock().take_thread(tid) {
+                Some(thread) => {
+                    ready.push((tid, thread, destination));
+                    false
+                }
+                None => true,
+            }
+        });
+        for (tid, thread, destination) in ready {
+            self.move_thread(tid, thread, destination);
+        }
+    }
+
+    /// Adds a thread taken from another LP's scheduler to the scheduler of `destination`,
+    /// preserving whether it is blocked
+    fn move_thread(&self, tid: ThreadId, thread: Thread, destination: LpId) {
+        {
+            let mut blocked = self.blocked_threads.lock();
+            let mut lp_scheduler = self.get_lp_scheduler(destination).lock();
+            lp_scheduler.add_thread(thread);
+            if let Some(lp_id) = blocked.get_mut(&tid) {
+                *lp_id = destination;
+                lp_scheduler.block_thread(tid);
+            } else {
+                // The thread may have been woken while its LP was going offline.
+                self.ready_unassigned.lock().retain(|&ready| ready != tid);
+            }
         }
+        self.reschedule(destination);
     }
 
-    pub fn get_local_lp_scheduler(&self) -> &Mutex<Box<dyn LpScheduler>> {
-        &self.lp_schedulers[get_lp_id!() as usize]
+    /// Moves every thread off the current LP once it has been marked offline. Must be called on
+    /// the LP itself outside of any of its threads. Pinned threads are parked until the LP comes
+    /// back online and threads that have no other LP to run on lose their affinity.
+    pub fn offline_local_lp(&self) {
+        let lp_id = get_lp_id!();
+        debug_assert!(!is_lp_online(lp_id), "LP{} was drained while still online", lp_id);
+        let Some(lp_scheduler) = self.try_get_lp_scheduler(lp_id) else {
+            return;
+        };
+        debug_assert!(
+            lp_scheduler.lock().current_thread().is_none(),
+            "LP{} was drained from one of its own threads",
+            lp_id
+        );
+        PENDING_MIGRATIONS.get().lock().clear();
+        let threads = lp_scheduler.lock().take_threads();
+        for (tid, mut thread) in threads {
+            if thread.is_pinned() {
+                self.parked_threads.lock().push((lp_id, tid, thread));
+                continue;
+            }
+            let destination = match self.select_lp(thread.affinity()) {
+                Some(destination) => destination,
+                None => {
+                    logln!(
+                        "LP{}: Thread {} has no other LP to run on and loses its affinity.",
+                        lp_id,
+                        tid
+                    );
+                    let _ = thread.set_affinity(LpMask::all());
+                    self.select_lp(LpMask::all()).expect("No LP is left online to run threads on")
+                }
+            };
+            self.move_thread(tid, thread, destination);
+        }
+    }
+
+    /// Returns the threads parked while the current LP was offline to its scheduler. Those that
+    /// were woken in the meantime are handed out as runnable. Must be called once the LP has been
+    /// marked online.
+    pub fn online_local_lp(&self) {
+        let lp_id = get_lp_id!();
+        let Some(lp_scheduler) = self.try_get_lp_scheduler(lp_id) else {
+            return;
+        };
+        let parked: Vec<_> = self
+            .parked_threads
+            .lock()
+            .extract_if(.., |(parked_lp, _, _)| *parked_lp == lp_id)
+            .collect();
+        for (_, tid, thread) in parked {
+            let blocked = self.blocked_threads.lock();
+            let mut lp_scheduler = lp_scheduler.lock();
+            lp_scheduler.add_thread(thread);
+            if blocked.contains_key(&tid) {
+                lp_scheduler.block_thread(tid);
+            } else {
+                self.ready_unassigned.lock().retain(|&ready| ready != tid);
+            }
+        }
+        self.reschedule(lp_id);
     }
 }
 
 unsafe impl Sync for GlobalScheduler {}
 
-pub trait LpScheduler {
-    extern "C" fn advance(&self);
+/// Why the global scheduler keeps track of a thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackedThread {
+    /// Blocked on the given LP
+    Blocked(LpId),
+    /// Woken while its LP was offline and not assigned to an LP yet
+    ReadyUnassigned,
+    /// Pinned to the given LP, which is offline
+    Parked(LpId),
+}
+
+impl GlobalScheduler {
+    /// Lists the threads the global scheduler keeps track of, which excludes runnable threads that
+    /// are assigned to an LP. Returns `None` instead of waiting if any of its locks is held, so
+    /// that debugging tools can call it while the holder is stopped.
+    pub fn try_list_tracked_threads(&self) -> Option<Vec<(ThreadId, TrackedThread)>> {
+        let blocked = self.blocked_threads.try_lock()?;
+        let ready_unassigned = self.ready_unassigned.try_lock()?;
+        let parked = self.parked_threads.try_lock()?;
+        let mut threads: Vec<_> = blocked
+            .iter()
+            .map(|(&tid, &lp_id)| (tid, TrackedThread::Blocked(lp_id)))
+            .chain(ready_unassigned.iter().map(|&tid| (tid, TrackedThread::ReadyUnassigned)))
+            .chain(parked.iter().map(|&(lp_id, tid, _)| (tid, TrackedThread::Parked(lp_id))))
+            .collect();
+        threads.sort_unstable_by_key(|&(tid, _)| tid);
+        Some(threads)
+    }
+}
+
+pub trait LpScheduler: Send {
+    /// Selects the next thread to run on this LP. The switch itself takes place once the context
+    /// switch interrupt is taken.
+    fn advance(&mut self);
     fn add_thread(&mut self, thread: Thread);
     fn terminate_threads(&mut self, thread_ids: Vec<ThreadId>);
     fn abort_threads(&mut self, thread_ids: Vec<ThreadId>);
     fn abort_as_threads(&mut self, asid: AddressSpaceId);
     fn is_idle(&self) -> bool;
     fn asid_to_pcid(&self, asid: AddressSpaceId) -> Option<u16>;
+    fn current_thread(&self) -> Option<ThreadId>;
+    /// Removes a thread from the set of threads eligible to run on this LP
+    fn block_thread(&mut self, tid: ThreadId);
+    /// Returns a previously blocked thread to the set of threads eligible to run on this LP
+    fn wake_thread(&mut self, tid: ThreadId);
+    fn thread_mut(&mut self, tid: ThreadId) -> Option<&mut Thread>;
+    /// Removes a thread from this LP so that it can be moved to another one. Returns `None` if the
+    /// thread is not on this LP or is still running, i.e. has not been switched out yet.
+    fn take_thread(&mut self, tid: ThreadId) -> Option<Thread>;
+    /// Removes every thread that is not running from this LP
+    fn take_threads(&mut self) -> Vec<(ThreadId, Thread)>;
 }
@@ -0,0 +1,170 @@
+//! # Round Robin LP Scheduler
+//!
+//! Runs the runnable threads of an LP in turn, one time slice each. The context the LP was
+//! executing in when the scheduler was started, i.e. its initialization and idle loop, takes part
+//! in the rotation like a thread that never blocks, so an LP always has something to run.
+
+use alloc::boxed::Box;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use core::ptr::null_mut;
+use core::sync::atomic::Ordering;
+
+use super::LpScheduler;
+use crate::cpu::percpu::LP_LOCAL_HEADER;
+use crate::cpu::threads::{Thread, ThreadId};
+use crate::isa::lp::thread_context::ThreadContext;
+use crate::memory::AddressSpaceId;
+
+/// Stands in for the context the LP was executing in when the scheduler was started. No thread is
+/// ever given this ID.
+const LP_CONTEXT: ThreadId = 0;
+
+pub struct RoundRobin {
+    /// Boxed so that the contexts handed to the context switch do not move
+    threads: BTreeMap<ThreadId, Box<Thread>>,
+    run_queue: VecDeque<ThreadId>,
+    blocked: BTreeSet<ThreadId>,
+    current: ThreadId,
+    lp_context: ThreadContext,
+    /// Threads that ended while running, which are freed once the LP has switched off their stack.
+    /// Only their stacks have to stay in place, so they need not be boxed.
+    exited: Vec<Thread>,
+}
+
+impl RoundRobin {
+    pub fn new() -> Self {
+        RoundRobin {
+            threads: BTreeMap::new(),
+            run_queue: VecDeque::new(),
+            blocked: BTreeSet::new(),
+            current: LP_CONTEXT,
+            lp_context: ThreadContext::default(),
+            exited: Vec::new(),
+        }
+    }
+
+    fn context_of(&mut self, tid: ThreadId) -> *mut ThreadContext {
+        if tid == LP_CONTEXT {
+            &mut self.lp_context
+        } else {
+            match self.threads.get_mut(&tid) {
+                Some(thread) => thread.context_mut(),
+                None => null_mut(),
+            }
+        }
+    }
+
+    fn remove_threads(&mut self, thread_ids: Vec<ThreadId>) {
+        for tid in thread_ids {
+            let Some(thread) = self.threads.remove(&tid) else {
+                continue;
+            };
+            self.run_queue.retain(|&queued| queued != tid);
+            self.blocked.remove(&tid);
+            // The thread keeps running until the next context switch, which does not save it.
+            if tid == self.current {
+                self.exited.push(*thread);
+            }
+        }
+    }
+}
+
+impl Default for RoundRobin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LpScheduler for RoundRobin {
+    fn advance(&mut self) {
+        let previous = self.current;
+        self.exited.retain(|thread| thread.id() == previous);
+        let is_runnable = previous == LP_CONTEXT
+            || (self.threads.contains_key(&previous) && !self.blocked.contains(&previous));
+        if is_runnable {
+            self.run_queue.push_back(previous);
+        }
+        // The LP's own context never leaves the run queue for long, so there is always a next one.
+        let next = self.run_queue.pop_front().unwrap_or(LP_CONTEXT);
+        self.current = next;
+        let save_context = self.context_of(previous);
+        let load_context = self.context_of(next);
+        let header = LP_LOCAL_HEADER.get();
+        header.save_context.store(save_context, Ordering::Relaxed);
+        header.load_context.store(load_context, Ordering::Relaxed);
+    }
+
+    fn add_thread(&mut self, thread: Thread) {
+        let tid = thread.id();
+        self.threads.insert(tid, Box::new(thread));
+        self.run_queue.push_back(tid);
+    }
+
+    fn take_thread(&mut self, tid: ThreadId) -> Option<Thread> {
+        if tid == self.current {
+            return None;
+        }
+        let thread = self.threads.remove(&tid)?;
+        self.run_queue.retain(|&queued| queued != tid);
+        self.blocked.remove(&tid);
+        Some(*thread)
+    }
+
+    fn take_threads(&mut self) -> Vec<(ThreadId, Thread)> {
+        let current = self.current;
+        let threads: Vec<_> = self
+            .threads
+            .extract_if(.., |&tid, _| tid != current)
+            .map(|(tid, thread)| (tid, *thread))
+            .collect();
+        self.run_queue.retain(|&queued| queued == LP_CONTEXT || queued == current);
+        self.blocked.retain(|&tid| tid == current);
+        threads
+    }
+
+    fn terminate_threads(&mut self, thread_ids: Vec<ThreadId>) {
+        self.remove_threads(thread_ids);
+    }
+
+    fn abort_threads(&mut self, thread_ids: Vec<ThreadId>) {
+        self.remove_threads(thread_ids);
+    }
+
+    fn abort_as_threads(&mut self, _asid: AddressSpaceId) {
+        // Only kernel threads are scheduled, none of which has an address space of its own.
+    }
+
+    fn is_idle(&self) -> bool {
+        let current_is_runnable = self.current != LP_CONTEXT
+            && self.threads.contains_key(&self.current)
+            && !self.blocked.contains(&self.current);
+        !current_is_runnable && self.run_queue.iter().all(|&tid| tid == LP_CONTEXT)
+    }
+
+    fn asid_to_pcid(&self, _asid: AddressSpaceId) -> Option<u16> {
+        None
+    }
+
+    fn current_thread(&self) -> Option<ThreadId> {
+        (self.current != LP_CONTEXT).then_some(self.current)
+    }
+
+    fn block_thread(&mut self, tid: ThreadId) {
+        if self.threads.contains_key(&tid) && self.blocked.insert(tid) {
+            self.run_queue.retain(|&queued| queued != tid);
+        }
+    }
+
+    fn wake_thread(&mut self, tid: ThreadId) {
+        // The current thread is requeued by the next call to advance.
+        if self.blocked.remove(&tid) && tid != self.current {
+            self.run_queue.push_back(tid);
+        }
+    }
+
+    fn thread_mut(&mut self, tid: ThreadId) -> Option<&mut Thread> {
+        self.threads.get_mut(&tid).map(|thread| &mut **thread)
+    }
+}
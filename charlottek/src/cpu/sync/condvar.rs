@@ -0,0 +1,101 @@
+//! # Condition Variables
+
+use core::time::Duration;
+
+use super::context::debug_assert_can_block;
+use super::{Deadline, MutexGuard, WaitQueue, WaitTimeoutResult};
+
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Atomically releases the mutex guarded by `guard` and blocks until notified, then reacquires
+    /// the mutex before returning
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        debug_assert_can_block();
+        self.wait_until(guard, None).0
+    }
+
+    /// Like [`wait`](Self::wait) but gives up waiting for a notification after `timeout`
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        debug_assert_can_block();
+        self.wait_until(guard, Some(Deadline::after(timeout)))
+    }
+
+    /// Blocks for as long as `condition` returns true
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Like [`wait_while`](Self::wait_while) but gives up after `timeout` has elapsed in total
+    pub fn wait_timeout_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        timeout: Duration,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        debug_assert_can_block();
+        let deadline = Deadline::after(timeout);
+        while condition(&mut *guard) {
+            let (new_guard, result) = self.wait_until(guard, Some(deadline));
+            guard = new_guard;
+            if result.timed_out() {
+                let still_waiting = condition(&mut *guard);
+                return (guard, WaitTimeoutResult::new(still_waiting));
+            }
+        }
+        (guard, WaitTimeoutResult::new(false))
+    }
+
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+    }
+
+    fn wait_until<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        deadline: Option<Deadline>,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        let mutex = guard.mutex;
+        core::mem::forget(guard);
+        // The mutex is released while the wait queue is locked so a notification sent after the
+        // caller's condition changed cannot slip in before this thread is enqueued.
+        let result = self.waiters.wait_if(
+            || {
+                unsafe { mutex.force_unlock() };
+                true
+            },
+            deadline,
+        );
+        let guard = mutex.lock_until(None).expect("Untimed mutex acquisition timed out");
+        (guard, result)
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Condvar::new()
+    }
+}
@@ -0,0 +1,104 @@
+//! # Execution Context Tracking
+//!
+//! Interrupt handlers record that they are running by holding an [`InterruptContextGuard`] so that
+//! code which must not run in interrupt context, such as blocking synchronization primitives, can
+//! detect when it is being misused. Deferred work queued with
+//! [`schedule_tasklet`](crate::cpu::deferred::schedule_tasklet) runs once the outermost interrupt
+//! handler on an LP drops its guard and counts as interrupt context as well.
+//!
+//! Spinlocks also disable preemption on their LP while they are held, since a thread switched out
+//! while holding one would leave every other context on the LP spinning on it.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::cpu::multiprocessor::MAX_LP_COUNT;
+use crate::isa::lp::ops::get_lp_id;
+
+static INTERRUPT_DEPTH: [AtomicU32; MAX_LP_COUNT] = [const { AtomicU32::new(0) }; MAX_LP_COUNT];
+static IN_SOFTIRQ: [AtomicBool; MAX_LP_COUNT] = [const { AtomicBool::new(false) }; MAX_LP_COUNT];
+static PREEMPTION_DISABLED: [AtomicU32; MAX_LP_COUNT] = [const { AtomicU32::new(0) }; MAX_LP_COUNT];
+
+/// Marks the current LP as executing an interrupt handler for as long as it is held
+pub struct InterruptContextGuard {
+    lp_id: usize,
+}
+
+impl InterruptContextGuard {
+    pub fn enter() -> Self {
+        let lp_id = get_lp_id!() as usize;
+        INTERRUPT_DEPTH[lp_id].fetch_add(1, Ordering::Relaxed);
+        InterruptContextGuard {
+            lp_id,
+        }
+    }
+}
+
+impl Drop for InterruptContextGuard {
+    fn drop(&mut self) {
+        if INTERRUPT_DEPTH[self.lp_id].fetch_sub(1, Ordering::Relaxed) == 1 {
+            crate::cpu::deferred::run_tasklets();
+        }
+    }
+}
+
+/// Marks the current LP as running deferred work for as long as it is held. At most one guard
+/// exists per LP at a time.
+pub struct SoftirqContextGuard {
+    lp_id: usize,
+}
+
+impl SoftirqContextGuard {
+    /// Returns `None` if the current LP is already running deferred work
+    pub fn try_enter() -> Option<Self> {
+        let lp_id = get_lp_id!() as usize;
+        (!IN_SOFTIRQ[lp_id].swap(true, Ordering::Relaxed)).then_some(SoftirqContextGuard {
+            lp_id,
+        })
+    }
+}
+
+impl Drop for SoftirqContextGuard {
+    fn drop(&mut self) {
+        IN_SOFTIRQ[self.lp_id].store(false, Ordering::Relaxed);
+    }
+}
+
+/// Returns true if the current LP is executing an interrupt handler or deferred work
+pub fn in_interrupt_context() -> bool {
+    let lp_id = get_lp_id!() as usize;
+    INTERRUPT_DEPTH[lp_id].load(Ordering::Relaxed) != 0 || IN_SOFTIRQ[lp_id].load(Ordering::Relaxed)
+}
+
+/// Keeps the scheduler tick from switching threads on the current LP until the matching call to
+/// [`enable_preemption`]. Calls nest.
+pub fn disable_preemption() {
+    PREEMPTION_DISABLED[get_lp_id!() as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn enable_preemption() {
+    PREEMPTION_DISABLED[get_lp_id!() as usize].fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Returns true if the current context may be switched out by the scheduler tick
+pub fn is_preemptible() -> bool {
+    PREEMPTION_DISABLED[get_lp_id!() as usize].load(Ordering::Relaxed) == 0
+}
+
+/// Asserts in debug builds that the current LP is allowed to block
+#[track_caller]
+#[inline(always)]
+pub fn debug_assert_can_block() {
+    if cfg!(debug_assertions) {
+        assert!(
+            !in_interrupt_context(),
+            "A blocking synchronization primitive was used in interrupt context on LP{}",
+            (get_lp_id!())
+        );
+        assert!(
+            !super::rcu::in_rcu_read_section(),
+            "A blocking synchronization primitive was used inside an RCU read-side critical \
+             section on LP{}",
+            (get_lp_id!())
+        );
+    }
+}
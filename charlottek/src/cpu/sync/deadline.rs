@@ -0,0 +1,52 @@
+//! # Timeout Deadlines
+//!
+//! Deadlines are points in [monotonic time](crate::time::monotonic_now), so a deadline computed on
+//! one LP expires at the same instant on every other LP.
+
+use core::time::Duration;
+
+use crate::time::monotonic_now;
+
+/// A point in time after which a timed wait gives up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline {
+    nanos: u64,
+}
+
+impl Deadline {
+    /// Creates a deadline that has just expired
+    pub fn now() -> Self {
+        Deadline {
+            nanos: monotonic_now(),
+        }
+    }
+
+    /// Creates a deadline that expires once monotonic time reaches `nanos`
+    pub const fn from_monotonic(nanos: u64) -> Self {
+        Deadline {
+            nanos,
+        }
+    }
+
+    /// Creates a deadline that expires once `timeout` has elapsed from now
+    pub fn after(timeout: Duration) -> Self {
+        let timeout = u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX);
+        Deadline {
+            nanos: monotonic_now().saturating_add(timeout),
+        }
+    }
+
+    pub fn has_expired(&self) -> bool {
+        monotonic_now() >= self.nanos
+    }
+
+    /// The monotonic time at which the deadline expires in nanoseconds
+    pub fn monotonic_nanos(&self) -> u64 {
+        self.nanos
+    }
+
+    /// Returns the time left until the deadline expires, or zero if it already has
+    pub fn remaining(&self) -> Duration {
+        Duration::from_nanos(self.nanos.saturating_sub(monotonic_now()))
+    }
+}
@@ -0,0 +1,124 @@
+//! # Interrupt Safe Spinlocks
+
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+use super::lockdep::LockClass;
+use super::spinlock::RawSpinlock;
+use crate::isa::lp::ops::{interrupts_enabled, mask_interrupts, unmask_interrupts};
+
+/// A spinlock that masks interrupts on the current LP for as long as it is held
+///
+/// The interrupt state at the time of acquisition is saved in the guard and restored once the lock
+/// has been released, so nested acquisitions do not unmask interrupts prematurely.
+pub struct IrqSpinlock<T: ?Sized> {
+    raw:  RawSpinlock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for IrqSpinlock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for IrqSpinlock<T> {}
+
+impl<T> IrqSpinlock<T> {
+    pub const fn new(value: T) -> Self {
+        IrqSpinlock {
+            raw:  RawSpinlock::new(None),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Creates a spinlock whose acquisitions are validated as part of the given lock class
+    pub const fn with_class(class: &'static LockClass, value: T) -> Self {
+        IrqSpinlock {
+            raw:  RawSpinlock::new(Some(class)),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> IrqSpinlock<T> {
+    #[track_caller]
+    pub fn lock(&self) -> IrqSpinlockGuard<'_, T> {
+        let interrupts_were_enabled = interrupts_enabled();
+        mask_interrupts!();
+        self.raw.lock(true);
+        IrqSpinlockGuard {
+            lock: self,
+            interrupts_were_enabled,
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<IrqSpinlockGuard<'_, T>> {
+        let interrupts_were_enabled = interrupts_enabled();
+        mask_interrupts!();
+        if self.raw.try_lock(true) {
+            Some(IrqSpinlockGuard {
+                lock: self,
+                interrupts_were_enabled,
+            })
+        } else {
+            if interrupts_were_enabled {
+                unmask_interrupts!();
+            }
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.raw.is_locked()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Releases the lock without a guard and leaves interrupts masked
+    ///
+    /// # Safety
+    /// The caller must own the lock and must not use the guard it was acquired with afterwards.
+    /// This is intended for paths that never return to the holder, e.g. a panic on another LP.
+    pub unsafe fn force_unlock(&self) {
+        self.raw.force_unlock();
+    }
+}
+
+pub struct IrqSpinlockGuard<'a, T: ?Sized> {
+    lock: &'a IrqSpinlock<T>,
+    interrupts_were_enabled: bool,
+}
+
+impl<T: ?Sized> Deref for IrqSpinlockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSpinlockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for IrqSpinlockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.raw.unlock();
+        if self.interrupts_were_enabled {
+            unmask_interrupts!();
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for IrqSpinlockGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
@@ -0,0 +1,150 @@
+//! # Lock Validation
+//!
+//! Every lock may be assigned a [`LockClass`] shared by all locks created at the same site,
+//! usually with the [`lock_class!`](crate::lock_class) macro. Locks without a class are not
+//! validated.
+//!
+//! In debug builds each acquisition records the contexts the class has been used in. A class that
+//! is acquired both from an interrupt handler and from thread context with interrupts enabled can
+//! deadlock its LP if the interrupt arrives while the lock is held, so unless the class is
+//! interrupt safe this is reported the first time it is observed.
+//!
+//! With the `lockdep` feature enabled the order in which spinlock classes are acquired is tracked
+//! as well. See [`order`] for details. Sleeping locks are only checked for the contexts they are
+//! used in.
+
+#[cfg(feature = "lockdep")]
+pub mod order;
+
+use core::panic::Location;
+#[cfg(feature = "lockdep")]
+use core::sync::atomic::AtomicU16;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use super::context::in_interrupt_context;
+use crate::isa::lp::ops::interrupts_enabled;
+use crate::logln;
+
+const USED_IN_INTERRUPT: u8 = 1 << 0;
+const USED_WITH_INTERRUPTS_ENABLED: u8 = 1 << 1;
+
+pub struct LockClass {
+    name: &'static str,
+    usage: AtomicU8,
+    reported: AtomicBool,
+    /// Index of the class in the lock order graph, zero until the class is first validated
+    #[cfg(feature = "lockdep")]
+    id: AtomicU16,
+}
+
+impl LockClass {
+    pub const fn new(name: &'static str) -> Self {
+        LockClass {
+            name,
+            usage: AtomicU8::new(0),
+            reported: AtomicBool::new(false),
+            #[cfg(feature = "lockdep")]
+            id: AtomicU16::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// Declares a lock class for the locks created at the invocation site and evaluates to a
+/// `&'static LockClass` referring to it
+#[macro_export]
+macro_rules! lock_class {
+    ($name:expr) => {{
+        static CLASS: $crate::cpu::sync::lockdep::LockClass =
+            $crate::cpu::sync::lockdep::LockClass::new($name);
+        &CLASS
+    }};
+}
+pub use lock_class;
+
+/// Must be called before waiting to acquire a lock of the given class so that an acquisition
+/// which could deadlock is reported before the LP hangs
+#[inline(always)]
+pub fn will_acquire(class: Option<&'static LockClass>, location: &'static Location<'static>) {
+    #[cfg(feature = "lockdep")]
+    if let Some(class) = class {
+        order::validate(class, location);
+    }
+    #[cfg(not(feature = "lockdep"))]
+    let _ = (class, location);
+}
+
+/// Records an acquisition of a lock of the given class. `irq_safe` indicates whether the lock
+/// masks interrupts while it is held.
+#[inline(always)]
+pub fn note_acquire(
+    class: Option<&'static LockClass>,
+    location: &'static Location<'static>,
+    irq_safe: bool,
+) {
+    let Some(class) = class else {
+        return;
+    };
+    if cfg!(debug_assertions) {
+        check_usage(class, irq_safe);
+    }
+    #[cfg(feature = "lockdep")]
+    order::push_held(class, location);
+    #[cfg(not(feature = "lockdep"))]
+    let _ = location;
+}
+
+/// Records an acquisition of a sleeping lock of the given class. Sleeping locks may be held across
+/// context switches and migrations, which the per-LP lock order tracking cannot follow.
+#[inline(always)]
+pub fn note_sleeping_acquire(class: Option<&'static LockClass>) {
+    if let Some(class) = class
+        && cfg!(debug_assertions)
+    {
+        check_usage(class, false);
+    }
+}
+
+/// Records the release of a lock of the given class
+#[inline(always)]
+pub fn note_release(class: Option<&'static LockClass>) {
+    #[cfg(feature = "lockdep")]
+    if let Some(class) = class {
+        order::pop_held(class);
+    }
+    #[cfg(not(feature = "lockdep"))]
+    let _ = class;
+}
+
+/// Stops validating the lock order for good. Called once the kernel has crashed and is about to
+/// release locks held by the LPs it stopped, which would otherwise be reported.
+pub fn disable() {
+    #[cfg(feature = "lockdep")]
+    order::disable_quietly();
+}
+
+fn check_usage(class: &LockClass, irq_safe: bool) {
+    let usage = if in_interrupt_context() {
+        USED_IN_INTERRUPT
+    } else if interrupts_enabled() {
+        USED_WITH_INTERRUPTS_ENABLED
+    } else {
+        0
+    };
+    let prior_usage = class.usage.fetch_or(usage, Ordering::Relaxed);
+    let all_usage = prior_usage | usage;
+    if !irq_safe
+        && all_usage & USED_IN_INTERRUPT != 0
+        && all_usage & USED_WITH_INTERRUPTS_ENABLED != 0
+        && !class.reported.swap(true, Ordering::Relaxed)
+    {
+        logln!(
+            "lockdep: lock class {} is acquired in interrupt context and in thread context with \
+             interrupts enabled but does not mask interrupts while held",
+            (class.name)
+        );
+    }
+}
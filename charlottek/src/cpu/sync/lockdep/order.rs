@@ -0,0 +1,492 @@
+//! # Lock Order Validation
+//!
+//! Each LP keeps a stack of the classed spinlocks it currently holds. Sleeping locks are left out,
+//! since a thread may block or migrate to another LP while holding one. Before a lock is waited on,
+//! an edge from every held class to the class being acquired is added to a global lock order graph.
+//! If the graph already contains a path in the opposite direction the two orders can deadlock
+//! against each other. In that case the locks held by the current LP are logged along with the
+//! locks that were held when each dependency on the opposite path was first observed, after which
+//! validation is turned off. The reported dependency is not added, so the graph stays acyclic.
+//!
+//! All state lives in fixed size tables so that validating the kernel allocator's lock never
+//! requires an allocation.
+
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::panic::Location;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU16, AtomicUsize, Ordering};
+
+use super::LockClass;
+use crate::cpu::multiprocessor::MAX_LP_COUNT;
+use crate::isa::lp::LpId;
+use crate::isa::lp::ops::{get_lp_id, interrupts_enabled, mask_interrupts, unmask_interrupts};
+use crate::logln;
+
+const MAX_LOCK_CLASSES: usize = 256;
+const MAX_HELD_LOCKS: usize = 16;
+const MAX_DEPENDENCIES: usize = 512;
+/// The number of dependencies on the opposite path that are logged when a deadlock is reported
+const MAX_REPORTED_DEPENDENCIES: usize = 4;
+const GRAPH_WORDS: usize = MAX_LOCK_CLASSES / u64::BITS as usize;
+const NO_CLASS: u16 = u16::MAX;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+/// The number of potential deadlocks that have been reported
+static REPORTS: AtomicUsize = AtomicUsize::new(0);
+// Class ID 0 marks a class that has not been registered yet.
+static NEXT_CLASS_ID: AtomicU16 = AtomicU16::new(1);
+static CLASSES: [AtomicPtr<LockClass>; MAX_LOCK_CLASSES] =
+    [const { AtomicPtr::new(null_mut()) }; MAX_LOCK_CLASSES];
+static GRAPH_LOCKED: AtomicBool = AtomicBool::new(false);
+static GRAPH: GraphCell = GraphCell(UnsafeCell::new(Graph::new()));
+static LP_STATES: [LpStateCell; MAX_LP_COUNT] =
+    [const { LpStateCell(UnsafeCell::new(LpState::new())) }; MAX_LP_COUNT];
+
+#[derive(Clone, Copy)]
+struct HeldLock {
+    class: u16,
+    location: Option<&'static Location<'static>>,
+}
+
+impl HeldLock {
+    const EMPTY: HeldLock = HeldLock {
+        class: NO_CLASS,
+        location: None,
+    };
+}
+
+#[derive(Clone, Copy)]
+struct HeldLocks {
+    depth: usize,
+    locks: [HeldLock; MAX_HELD_LOCKS],
+}
+
+impl HeldLocks {
+    const fn new() -> Self {
+        HeldLocks {
+            depth: 0,
+            locks: [HeldLock::EMPTY; MAX_HELD_LOCKS],
+        }
+    }
+
+    fn iter(&self) -> core::slice::Iter<'_, HeldLock> {
+        self.locks[..self.depth].iter()
+    }
+
+    fn push(&mut self, lock: HeldLock) -> bool {
+        if self.depth == MAX_HELD_LOCKS {
+            return false;
+        }
+        self.locks[self.depth] = lock;
+        self.depth += 1;
+        true
+    }
+
+    /// Removes the most recently acquired lock of the given class. Locks are not required to be
+    /// released in the reverse order of their acquisition.
+    fn remove(&mut self, class: u16) {
+        if let Some(index) = self.iter().rposition(|lock| lock.class == class) {
+            self.locks.copy_within(index + 1..self.depth, index);
+            self.depth -= 1;
+        }
+    }
+
+    fn log(&self) {
+        for lock in self.iter() {
+            match lock.location {
+                Some(location) => {
+                    logln!("lockdep:   {} acquired at {}", (class_name(lock.class)), location)
+                }
+                None => logln!("lockdep:   {}", (class_name(lock.class))),
+            }
+        }
+    }
+}
+
+struct LpState {
+    held: HeldLocks,
+    /// Set while lockdep is running on the LP so that the locks it takes to log a report are not
+    /// themselves validated
+    busy: bool,
+}
+
+impl LpState {
+    const fn new() -> Self {
+        LpState {
+            held: HeldLocks::new(),
+            busy: false,
+        }
+    }
+}
+
+// Each LP only ever accesses its own state and does so with interrupts masked.
+struct LpStateCell(UnsafeCell<LpState>);
+
+unsafe impl Sync for LpStateCell {}
+
+/// The first observation of a class being acquired while another was held
+#[derive(Clone, Copy)]
+struct Dependency {
+    from: u16,
+    to: u16,
+    lp_id: LpId,
+    /// The locks held at the time including the acquisition of `to`
+    held: HeldLocks,
+}
+
+impl Dependency {
+    const EMPTY: Dependency = Dependency {
+        from: NO_CLASS,
+        to: NO_CLASS,
+        lp_id: 0,
+        held: HeldLocks::new(),
+    };
+}
+
+struct Graph {
+    /// Bit `b` of `edges[a]` is set if class `b` has been acquired while holding class `a`
+    edges: [[u64; GRAPH_WORDS]; MAX_LOCK_CLASSES],
+    dependencies: [Dependency; MAX_DEPENDENCIES],
+    dependency_count: usize,
+}
+
+impl Graph {
+    const fn new() -> Self {
+        Graph {
+            edges: [[0; GRAPH_WORDS]; MAX_LOCK_CLASSES],
+            dependencies: [Dependency::EMPTY; MAX_DEPENDENCIES],
+            dependency_count: 0,
+        }
+    }
+
+    fn has_edge(&self, from: u16, to: u16) -> bool {
+        self.edges[from as usize][to as usize / 64] & (1 << (to % 64)) != 0
+    }
+
+    fn add_edge(&mut self, from: u16, to: u16, lp_id: LpId, held: &HeldLocks, acquiring: HeldLock) {
+        self.edges[from as usize][to as usize / 64] |= 1 << (to % 64);
+        // Dependencies beyond the capacity of the table are still validated but cannot be
+        // described in a report.
+        if self.dependency_count < MAX_DEPENDENCIES {
+            let mut held = *held;
+            held.push(acquiring);
+            self.dependencies[self.dependency_count] = Dependency {
+                from,
+                to,
+                lp_id,
+                held,
+            };
+            self.dependency_count += 1;
+        }
+    }
+
+    fn dependency(&self, from: u16, to: u16) -> Option<&Dependency> {
+        self.dependencies[..self.dependency_count]
+            .iter()
+            .find(|dependency| dependency.from == from && dependency.to == to)
+    }
+
+    /// Searches the graph breadth first for a path between two classes and returns the
+    /// predecessor of each class visited along the way if one is found
+    fn find_path(&self, from: u16, to: u16) -> Option<[u16; MAX_LOCK_CLASSES]> {
+        let mut predecessors = [NO_CLASS; MAX_LOCK_CLASSES];
+        let mut queue = [0u16; MAX_LOCK_CLASSES];
+        let (mut head, mut tail) = (0, 1);
+        queue[0] = from;
+        predecessors[from as usize] = from;
+        while head < tail {
+            let class = queue[head];
+            head += 1;
+            for (word_index, &word) in self.edges[class as usize].iter().enumerate() {
+                let mut word = word;
+                while word != 0 {
+                    let next = (word_index * 64 + word.trailing_zeros() as usize) as u16;
+                    word &= word - 1;
+                    if predecessors[next as usize] != NO_CLASS {
+                        continue;
+                    }
+                    predecessors[next as usize] = class;
+                    if next == to {
+                        return Some(predecessors);
+                    }
+                    queue[tail] = next;
+                    tail += 1;
+                }
+            }
+        }
+        None
+    }
+}
+
+struct GraphCell(UnsafeCell<Graph>);
+
+unsafe impl Sync for GraphCell {}
+
+struct GraphGuard;
+
+impl GraphGuard {
+    fn lock() -> Self {
+        while GRAPH_LOCKED
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        GraphGuard
+    }
+
+    fn graph(&mut self) -> &mut Graph {
+        unsafe { &mut *GRAPH.0.get() }
+    }
+}
+
+impl Drop for GraphGuard {
+    fn drop(&mut self) {
+        GRAPH_LOCKED.store(false, Ordering::Release);
+    }
+}
+
+/// Describes a potential deadlock. The dependencies are copied out of the graph so that the report
+/// can be logged without holding the graph lock.
+// Boxing the dependencies is not an option since lockdep must not allocate.
+#[allow(clippy::large_enum_variant)]
+enum Report {
+    /// A lock was acquired while a lock of the same class was already held
+    Recursion,
+    /// A lock was acquired while holding `held`, but `held` has previously been acquired after it
+    Inversion {
+        held: u16,
+        path_length: usize,
+        dependencies: [Option<Dependency>; MAX_REPORTED_DEPENDENCIES],
+    },
+}
+
+impl Report {
+    fn inversion(
+        graph: &Graph,
+        predecessors: &[u16; MAX_LOCK_CLASSES],
+        from: u16,
+        held: u16,
+    ) -> Self {
+        let mut dependencies = [None; MAX_REPORTED_DEPENDENCIES];
+        let mut path_length = 0;
+        let mut class = held;
+        while class != from {
+            let predecessor = predecessors[class as usize];
+            if path_length < MAX_REPORTED_DEPENDENCIES {
+                dependencies[path_length] = graph.dependency(predecessor, class).copied();
+            }
+            path_length += 1;
+            class = predecessor;
+        }
+        Report::Inversion {
+            held,
+            path_length,
+            dependencies,
+        }
+    }
+
+    fn log(&self, lp_id: LpId, held: &HeldLocks, acquiring: HeldLock) {
+        logln!("lockdep: possible deadlock detected on LP{}", lp_id);
+        logln!(
+            "lockdep: acquiring {} at {} while holding:",
+            (class_name(acquiring.class)),
+            (acquiring.location.unwrap())
+        );
+        held.log();
+        match self {
+            Report::Recursion => {
+                logln!(
+                    "lockdep: a lock of class {} is already held",
+                    (class_name(acquiring.class))
+                );
+            }
+            Report::Inversion {
+                held,
+                path_length,
+                dependencies,
+            } => {
+                logln!(
+                    "lockdep: {} has previously been acquired after {}:",
+                    (class_name(*held)),
+                    (class_name(acquiring.class))
+                );
+                // The dependencies were collected walking back from the held class.
+                for dependency in
+                    dependencies[..(*path_length).min(MAX_REPORTED_DEPENDENCIES)].iter().rev()
+                {
+                    match dependency {
+                        Some(dependency) => {
+                            logln!(
+                                "lockdep: {} was acquired on LP{} while holding:",
+                                (class_name(dependency.to)),
+                                (dependency.lp_id)
+                            );
+                            dependency.held.log();
+                        }
+                        None => logln!("lockdep: (dependency was not recorded)"),
+                    }
+                }
+                if *path_length > MAX_REPORTED_DEPENDENCIES {
+                    logln!(
+                        "lockdep: ... and {} more dependencies",
+                        (path_length - MAX_REPORTED_DEPENDENCIES)
+                    );
+                }
+            }
+        }
+        logln!("lockdep: lock order validation is now disabled");
+    }
+}
+
+/// Runs `f` on the current LP's lockdep state with interrupts masked. Returns `None` without
+/// running `f` if lockdep is already running on the current LP.
+fn with_lp_state<R>(f: impl FnOnce(LpId, &mut LpState) -> R) -> Option<R> {
+    let interrupts_were_enabled = interrupts_enabled();
+    mask_interrupts!();
+    let lp_id = get_lp_id!();
+    let state = unsafe { &mut *LP_STATES[lp_id as usize].0.get() };
+    let result = if state.busy {
+        None
+    } else {
+        state.busy = true;
+        let result = f(lp_id, state);
+        state.busy = false;
+        Some(result)
+    };
+    if interrupts_were_enabled {
+        unmask_interrupts!();
+    }
+    result
+}
+
+fn disable(reason: &str) {
+    if ENABLED.swap(false, Ordering::Relaxed) {
+        with_lp_state(|_, _| {
+            logln!("lockdep: {}, lock order validation is now disabled", reason);
+        });
+    }
+}
+
+/// Disables validation without logging a reason
+pub fn disable_quietly() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// Whether lock orders are still being validated
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Turns validation back on after a potential deadlock has been reported. The dependency that was
+/// reported is never added to the graph, which therefore remains acyclic.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Returns the number of potential deadlocks that have been reported
+pub fn report_count() -> usize {
+    REPORTS.load(Ordering::Relaxed)
+}
+
+fn class_id(class: &'static LockClass) -> Option<u16> {
+    let id = class.id.load(Ordering::Acquire);
+    if id != 0 {
+        return Some(id);
+    }
+    let new_id = NEXT_CLASS_ID.fetch_add(1, Ordering::Relaxed);
+    if new_id as usize >= MAX_LOCK_CLASSES {
+        disable("too many lock classes");
+        return None;
+    }
+    match class.id.compare_exchange(0, new_id, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => {
+            CLASSES[new_id as usize]
+                .store(class as *const LockClass as *mut LockClass, Ordering::Release);
+            Some(new_id)
+        }
+        // Another LP registered the class first so the new ID goes unused.
+        Err(id) => Some(id),
+    }
+}
+
+fn class_name(id: u16) -> &'static str {
+    match CLASSES.get(id as usize).map(|class| class.load(Ordering::Acquire)) {
+        Some(class) if !class.is_null() => unsafe { (*class).name },
+        _ => "<unknown>",
+    }
+}
+
+/// Adds the dependencies created by acquiring a lock of the given class to the lock order graph
+/// and reports any potential deadlock they introduce
+pub fn validate(class: &'static LockClass, location: &'static Location<'static>) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(id) = class_id(class) else {
+        return;
+    };
+    let acquiring = HeldLock {
+        class: id,
+        location: Some(location),
+    };
+    with_lp_state(|lp_id, state| {
+        if state.held.depth == 0 {
+            return;
+        }
+        let report = {
+            let mut guard = GraphGuard::lock();
+            let graph = guard.graph();
+            let mut report = None;
+            for held in state.held.iter() {
+                if held.class == id {
+                    report = Some(Report::Recursion);
+                    break;
+                }
+                if graph.has_edge(held.class, id) {
+                    continue;
+                }
+                if let Some(predecessors) = graph.find_path(id, held.class) {
+                    report = Some(Report::inversion(graph, &predecessors, id, held.class));
+                    break;
+                }
+                graph.add_edge(held.class, id, lp_id, &state.held, acquiring);
+            }
+            // Only the first potential deadlock is reported.
+            report.filter(|_| ENABLED.swap(false, Ordering::Relaxed))
+        };
+        if let Some(report) = report {
+            REPORTS.fetch_add(1, Ordering::Relaxed);
+            report.log(lp_id, &state.held, acquiring);
+        }
+    });
+}
+
+/// Records that the current LP holds a lock of the given class
+pub fn push_held(class: &'static LockClass, location: &'static Location<'static>) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(id) = class_id(class) else {
+        return;
+    };
+    let pushed = with_lp_state(|_, state| {
+        state.held.push(HeldLock {
+            class: id,
+            location: Some(location),
+        })
+    });
+    if pushed == Some(false) {
+        disable("too many locks are held at once");
+    }
+}
+
+/// Records that the current LP has released a lock of the given class. Releases are recorded even
+/// while validation is disabled so that the held locks are accurate if it is enabled again.
+pub fn pop_held(class: &'static LockClass) {
+    let id = class.id.load(Ordering::Acquire);
+    if id != 0 {
+        with_lp_state(|_, state| state.held.remove(id));
+    }
+}
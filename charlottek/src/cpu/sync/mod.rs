@@ -1 +1,43 @@
+//! # Synchronization Primitives
+//!
+//! This module provides scheduler aware synchronization primitives. Unlike the busy waiting locks
+//! from the `spin` crate, these park the calling thread in the global scheduler's set of blocked
+//! threads when they cannot make progress and have it woken by the LP scheduler it belongs to once
+//! they can. When the current LP is not running a scheduled thread, e.g. during system
+//! initialization, waiting falls back to spinning.
+//!
+//! All blocking operations have variants that accept a timeout. Blocking is never permitted in
+//! interrupt context and debug builds assert that it does not happen. Releasing and notifying
+//! operations may be used from any context.
+//!
+//! For short critical sections that must not block there are also the busy waiting [`Spinlock`]
+//! and [`IrqSpinlock`], the latter masking interrupts on the current LP while it is held. Any
+//! state shared with interrupt handlers must be protected by an `IrqSpinlock`. Spinlocks may be
+//! given a [`LockClass`](lockdep::LockClass), in which case debug builds report classes that are
+//! used both in interrupt context and with interrupts enabled without masking them.
+//! Building with the `lockdep` feature additionally validates the order in which classed locks,
+//! including classed [`Mutex`]es, are acquired.
+//!
+//! Read-mostly data can be protected with [`rcu`] instead of a lock so that readers never wait.
 
+pub mod condvar;
+pub mod context;
+pub mod deadline;
+pub mod irq_spinlock;
+pub mod lockdep;
+pub mod mutex;
+pub mod rcu;
+pub mod rwlock;
+pub mod semaphore;
+pub mod spinlock;
+pub mod wait_queue;
+
+pub use condvar::Condvar;
+pub use deadline::Deadline;
+pub use irq_spinlock::{IrqSpinlock, IrqSpinlockGuard};
+pub use mutex::{Mutex, MutexGuard};
+pub use rcu::{RcuCell, RcuReadGuard, call_rcu, rcu_read_lock, synchronize_rcu};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+pub use spinlock::{Spinlock, SpinlockGuard};
+pub use wait_queue::{WaitQueue, WaitTimeoutResult};
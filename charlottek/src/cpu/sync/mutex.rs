@@ -0,0 +1,131 @@
+//! # Blocking Mutex
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use super::context::debug_assert_can_block;
+use super::lockdep::{self, LockClass};
+use super::{Deadline, WaitQueue};
+
+pub struct Mutex<T: ?Sized> {
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    class: Option<&'static LockClass>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Mutex {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            class: None,
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Creates a mutex whose acquisitions are validated as part of the given lock class
+    pub const fn with_class(class: &'static LockClass, value: T) -> Self {
+        Mutex {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            class: Some(class),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Acquires the mutex, blocking the current thread until it is available
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        debug_assert_can_block();
+        self.lock_until(None).expect("Untimed mutex acquisition timed out")
+    }
+
+    /// Acquires the mutex, blocking the current thread for at most `timeout`
+    pub fn lock_timeout(&self, timeout: Duration) -> Option<MutexGuard<'_, T>> {
+        debug_assert_can_block();
+        self.lock_until(Some(Deadline::after(timeout)))
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+            lockdep::note_sleeping_acquire(self.class);
+            Some(MutexGuard {
+                mutex: self,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub(super) fn lock_until(&self, deadline: Option<Deadline>) -> Option<MutexGuard<'_, T>> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return Some(guard);
+            }
+            let result = self.waiters.wait_if(|| self.locked.load(Ordering::Relaxed), deadline);
+            if result.timed_out() {
+                return self.try_lock();
+            }
+        }
+    }
+
+    /// Releases the mutex without a guard
+    ///
+    /// # Safety
+    /// The caller must own the lock and must not use the guard it was acquired with afterwards.
+    pub(super) unsafe fn force_unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+        self.waiters.wake_one();
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    pub(super) mutex: &'a Mutex<T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { self.mutex.force_unlock() }
+    }
+}
@@ -0,0 +1,231 @@
+//! # Read-Copy-Update (RCU)
+//!
+//! RCU allows data that is read far more often than it is written to be read without taking any
+//! locks. Readers enter a read-side critical section with [`rcu_read_lock`] and may dereference
+//! RCU protected pointers such as an [`RcuCell`] for as long as the returned guard lives. Writers
+//! publish a new version of the data and defer freeing the old one until every reader that could
+//! still observe it has finished, either by waiting in [`synchronize_rcu`] or by queueing a
+//! callback with [`call_rcu`].
+//!
+//! Read-side critical sections must not block or yield and are not preempted by the scheduler tick.
+//! A context switch is therefore a quiescent state in which the LP holds no references to RCU
+//! protected data, and the scheduler reports one whenever the current thread yields. An LP that is
+//! observed outside of any read-side critical section has passed through a quiescent state as well,
+//! which is how idle LPs and LPs without a scheduler end grace periods. A grace period has elapsed
+//! once every LP has passed through a quiescent state since it began.
+//!
+//! Entering and leaving a read-side critical section only updates the nesting depth of the current
+//! LP and constrains the compiler. The depth counts the critical sections of interrupt handlers
+//! too, so readers in ISRs, NMIs and on LPs that are still initializing are waited for like any
+//! other. The scheduler tick also consults it before preempting.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicPtr, AtomicU32, AtomicU64, Ordering, compiler_fence, fence};
+
+use super::{IrqSpinlock, Spinlock};
+use crate::cpu::multiprocessor::{MAX_LP_COUNT, get_lp_count};
+use crate::cpu::scheduler::GLOBAL_SCHEDULER;
+use crate::isa::lp::ops::get_lp_id;
+use crate::lock_class;
+
+type RcuCallback = Box<dyn FnOnce() + Send>;
+
+static QUIESCENT_STATES: [AtomicU64; MAX_LP_COUNT] = [const { AtomicU64::new(0) }; MAX_LP_COUNT];
+static READ_DEPTH: [AtomicU32; MAX_LP_COUNT] = [const { AtomicU32::new(0) }; MAX_LP_COUNT];
+static CALLBACKS: IrqSpinlock<CallbackQueue> =
+    IrqSpinlock::with_class(lock_class!("RCU_CALLBACKS"), CallbackQueue::new());
+
+/// A snapshot of the quiescent state counters of all LPs taken at the start of a grace period
+struct GracePeriod {
+    quiescent_states: [u64; MAX_LP_COUNT],
+}
+
+impl GracePeriod {
+    const fn new() -> Self {
+        GracePeriod {
+            quiescent_states: [0; MAX_LP_COUNT],
+        }
+    }
+
+    fn start(&mut self) {
+        for (snapshot, counter) in self.quiescent_states.iter_mut().zip(QUIESCENT_STATES.iter()) {
+            *snapshot = counter.load(Ordering::Acquire);
+        }
+    }
+
+    /// Returns true if every LP has reported a quiescent state or been observed outside of any
+    /// read-side critical section since the grace period started
+    fn has_elapsed(&self) -> bool {
+        // Pairs with the increment in `rcu_read_lock`: an LP seen at depth zero here either left
+        // its critical section or will see every pointer published before the grace period began.
+        fence(Ordering::SeqCst);
+        (0..get_lp_count()).all(|lp_id| {
+            QUIESCENT_STATES[lp_id as usize].load(Ordering::Acquire)
+                != self.quiescent_states[lp_id as usize]
+                || READ_DEPTH[lp_id as usize].load(Ordering::Relaxed) == 0
+        })
+    }
+}
+
+struct CallbackQueue {
+    /// Callbacks that are waiting for a grace period to start
+    next: Vec<RcuCallback>,
+    /// Callbacks that are waiting for the current grace period to elapse
+    current: Vec<RcuCallback>,
+    grace_period: GracePeriod,
+}
+
+impl CallbackQueue {
+    const fn new() -> Self {
+        CallbackQueue {
+            next: Vec::new(),
+            current: Vec::new(),
+            grace_period: GracePeriod::new(),
+        }
+    }
+}
+
+/// Marks a read-side critical section on the current LP for as long as it is held
+pub struct RcuReadGuard {
+    // Read-side critical sections are tracked per LP.
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Enters a read-side critical section
+#[inline(always)]
+pub fn rcu_read_lock() -> RcuReadGuard {
+    READ_DEPTH[get_lp_id!() as usize].fetch_add(1, Ordering::SeqCst);
+    compiler_fence(Ordering::SeqCst);
+    RcuReadGuard {
+        _not_send: PhantomData,
+    }
+}
+
+impl Drop for RcuReadGuard {
+    #[inline(always)]
+    fn drop(&mut self) {
+        compiler_fence(Ordering::SeqCst);
+        READ_DEPTH[get_lp_id!() as usize].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns true if the current LP is inside a read-side critical section
+pub fn in_rcu_read_section() -> bool {
+    READ_DEPTH[get_lp_id!() as usize].load(Ordering::Relaxed) != 0
+}
+
+/// Reports that the current LP holds no references to RCU protected data and runs any callbacks
+/// whose grace period has elapsed
+pub fn note_quiescent_state() {
+    debug_assert!(
+        !in_rcu_read_section(),
+        "A quiescent state was reported inside an RCU read-side critical section"
+    );
+    QUIESCENT_STATES[get_lp_id!() as usize].fetch_add(1, Ordering::Release);
+    process_callbacks();
+}
+
+/// Waits until every read-side critical section that was in progress when it was called has ended
+pub fn synchronize_rcu() {
+    super::context::debug_assert_can_block();
+    note_quiescent_state();
+    let mut grace_period = GracePeriod::new();
+    grace_period.start();
+    while !grace_period.has_elapsed() {
+        if GLOBAL_SCHEDULER.current_thread_id().is_some() {
+            GLOBAL_SCHEDULER.yield_current();
+        } else {
+            core::hint::spin_loop();
+        }
+    }
+    process_callbacks();
+}
+
+/// Runs `callback` once a grace period has elapsed. Callbacks run on whichever LP notices the end
+/// of the grace period and must not block.
+pub fn call_rcu(callback: impl FnOnce() + Send + 'static) {
+    CALLBACKS.lock().next.push(Box::new(callback));
+}
+
+/// Runs the callbacks whose grace period has elapsed and starts a grace period for the callbacks
+/// queued since. It may be called from any context that can take an interrupt-masking spinlock.
+pub fn process_callbacks() {
+    let ready = {
+        let mut queue = CALLBACKS.lock();
+        let ready = if !queue.current.is_empty() {
+            if !queue.grace_period.has_elapsed() {
+                return;
+            }
+            core::mem::take(&mut queue.current)
+        } else {
+            Vec::new()
+        };
+        if !queue.next.is_empty() {
+            queue.current = core::mem::take(&mut queue.next);
+            queue.grace_period.start();
+        }
+        ready
+    };
+    for callback in ready {
+        callback();
+    }
+}
+
+/// An RCU protected value. Readers get a reference to the current version without any locking
+/// while writers replace it wholesale, freeing the previous version after a grace period.
+pub struct RcuCell<T> {
+    value:  AtomicPtr<T>,
+    writer: Spinlock<()>,
+}
+
+unsafe impl<T: Send> Send for RcuCell<T> {}
+unsafe impl<T: Send + Sync> Sync for RcuCell<T> {}
+
+impl<T> RcuCell<T> {
+    pub fn new(value: T) -> Self {
+        RcuCell {
+            value:  AtomicPtr::new(Box::into_raw(Box::new(value))),
+            writer: Spinlock::new(()),
+        }
+    }
+
+    /// Returns the current version of the value. It remains valid for as long as the read-side
+    /// critical section it was obtained in.
+    pub fn read<'a>(&'a self, _guard: &'a RcuReadGuard) -> &'a T {
+        unsafe { &*self.value.load(Ordering::Acquire) }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.value.load(Ordering::Relaxed) }
+    }
+}
+
+impl<T: Send + 'static> RcuCell<T> {
+    /// Publishes a copy of the current version modified by `update`. Updates are serialized with
+    /// respect to each other.
+    pub fn update(&self, update: impl FnOnce(&T) -> T) {
+        let _writer = self.writer.lock();
+        let current = unsafe { &*self.value.load(Ordering::Relaxed) };
+        self.publish(update(current));
+    }
+
+    /// Publishes a new version of the value
+    pub fn replace(&self, value: T) {
+        let _writer = self.writer.lock();
+        self.publish(value);
+    }
+
+    fn publish(&self, value: T) {
+        let new = Box::into_raw(Box::new(value));
+        let old = unsafe { Box::from_raw(self.value.swap(new, Ordering::AcqRel)) };
+        call_rcu(move || drop(old));
+    }
+}
+
+impl<T> Drop for RcuCell<T> {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(*self.value.get_mut()) });
+    }
+}
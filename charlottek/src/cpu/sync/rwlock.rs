@@ -0,0 +1,162 @@
+//! # Blocking Reader-Writer Lock
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use super::context::debug_assert_can_block;
+use super::{Deadline, WaitQueue};
+
+const WRITER: usize = 1 << (usize::BITS - 1);
+
+/// A reader-writer lock whose state is the number of active readers with the top bit set while a
+/// writer holds it
+pub struct RwLock<T: ?Sized> {
+    state: AtomicUsize,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        RwLock {
+            state: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        debug_assert_can_block();
+        self.read_until(None).expect("Untimed read lock acquisition timed out")
+    }
+
+    pub fn read_timeout(&self, timeout: Duration) -> Option<RwLockReadGuard<'_, T>> {
+        debug_assert_can_block();
+        self.read_until(Some(Deadline::after(timeout)))
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.state
+            .try_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                if state & WRITER == 0 {
+                    Some(state + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()
+            .map(|_| RwLockReadGuard {
+                lock: self,
+            })
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        debug_assert_can_block();
+        self.write_until(None).expect("Untimed write lock acquisition timed out")
+    }
+
+    pub fn write_timeout(&self, timeout: Duration) -> Option<RwLockWriteGuard<'_, T>> {
+        debug_assert_can_block();
+        self.write_until(Some(Deadline::after(timeout)))
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.state.compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed).ok().map(
+            |_| RwLockWriteGuard {
+                lock: self,
+            },
+        )
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn read_until(&self, deadline: Option<Deadline>) -> Option<RwLockReadGuard<'_, T>> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return Some(guard);
+            }
+            let result =
+                self.waiters.wait_if(|| self.state.load(Ordering::Relaxed) & WRITER != 0, deadline);
+            if result.timed_out() {
+                return self.try_read();
+            }
+        }
+    }
+
+    fn write_until(&self, deadline: Option<Deadline>) -> Option<RwLockWriteGuard<'_, T>> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return Some(guard);
+            }
+            let result = self.waiters.wait_if(|| self.state.load(Ordering::Relaxed) != 0, deadline);
+            if result.timed_out() {
+                return self.try_write();
+            }
+        }
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Only a waiting writer can be blocked on a lock held by readers.
+        if self.lock.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.lock.waiters.wake_one();
+        }
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::Release);
+        self.lock.waiters.wake_all();
+    }
+}
@@ -0,0 +1,62 @@
+//! # Counting Semaphore
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use super::context::debug_assert_can_block;
+use super::{Deadline, WaitQueue};
+
+pub struct Semaphore {
+    count: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(initial_count: usize) -> Self {
+        Semaphore {
+            count: AtomicUsize::new(initial_count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Takes one unit from the semaphore, blocking the current thread until one is available
+    pub fn acquire(&self) {
+        debug_assert_can_block();
+        self.acquire_until(None);
+    }
+
+    /// Takes one unit from the semaphore, blocking for at most `timeout`. Returns false if the
+    /// timeout elapsed without a unit becoming available.
+    pub fn acquire_timeout(&self, timeout: Duration) -> bool {
+        debug_assert_can_block();
+        self.acquire_until(Some(Deadline::after(timeout)))
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .try_update(Ordering::Acquire, Ordering::Relaxed, |count| count.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Returns one unit to the semaphore, waking a waiting thread if there is one
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    pub fn available(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn acquire_until(&self, deadline: Option<Deadline>) -> bool {
+        loop {
+            if self.try_acquire() {
+                return true;
+            }
+            let result = self.waiters.wait_if(|| self.count.load(Ordering::Relaxed) == 0, deadline);
+            if result.timed_out() {
+                return self.try_acquire();
+            }
+        }
+    }
+}
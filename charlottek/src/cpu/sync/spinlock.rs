@@ -0,0 +1,160 @@
+//! # Spinlocks
+//!
+//! Busy waiting locks for short critical sections that must not block. A [`Spinlock`] leaves the
+//! interrupt state of the LP untouched and so must not be shared with interrupt handlers. Locks
+//! that are taken in interrupt context should be an [`IrqSpinlock`](super::IrqSpinlock) instead.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::context::{disable_preemption, enable_preemption};
+use super::lockdep::{self, LockClass};
+
+/// The lock word shared by all spinlock flavors
+pub(super) struct RawSpinlock {
+    locked: AtomicBool,
+    class:  Option<&'static LockClass>,
+}
+
+impl RawSpinlock {
+    pub(super) const fn new(class: Option<&'static LockClass>) -> Self {
+        RawSpinlock {
+            locked: AtomicBool::new(false),
+            class,
+        }
+    }
+
+    #[track_caller]
+    pub(super) fn lock(&self, irq_safe: bool) {
+        disable_preemption();
+        lockdep::will_acquire(self.class, Location::caller());
+        while !self.try_acquire() {
+            while self.locked.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+        lockdep::note_acquire(self.class, Location::caller(), irq_safe);
+    }
+
+    #[track_caller]
+    pub(super) fn try_lock(&self, irq_safe: bool) -> bool {
+        disable_preemption();
+        let acquired = self.try_acquire();
+        if acquired {
+            lockdep::note_acquire(self.class, Location::caller(), irq_safe);
+        } else {
+            enable_preemption();
+        }
+        acquired
+    }
+
+    pub(super) fn unlock(&self) {
+        self.force_unlock();
+        enable_preemption();
+    }
+
+    /// Releases the lock on behalf of a holder that may be running on another LP
+    pub(super) fn force_unlock(&self) {
+        lockdep::note_release(self.class);
+        self.locked.store(false, Ordering::Release);
+    }
+
+    pub(super) fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    fn try_acquire(&self) -> bool {
+        self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+}
+
+pub struct Spinlock<T: ?Sized> {
+    raw:  RawSpinlock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Spinlock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Spinlock<T> {}
+
+impl<T> Spinlock<T> {
+    pub const fn new(value: T) -> Self {
+        Spinlock {
+            raw:  RawSpinlock::new(None),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Creates a spinlock whose acquisitions are validated as part of the given lock class
+    pub const fn with_class(class: &'static LockClass, value: T) -> Self {
+        Spinlock {
+            raw:  RawSpinlock::new(Some(class)),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Spinlock<T> {
+    #[track_caller]
+    pub fn lock(&self) -> SpinlockGuard<'_, T> {
+        self.raw.lock(false);
+        SpinlockGuard {
+            lock: self,
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<SpinlockGuard<'_, T>> {
+        if self.raw.try_lock(false) {
+            Some(SpinlockGuard {
+                lock: self,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.raw.is_locked()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+pub struct SpinlockGuard<'a, T: ?Sized> {
+    lock: &'a Spinlock<T>,
+}
+
+impl<T: ?Sized> Deref for SpinlockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinlockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinlockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.raw.unlock();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SpinlockGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
@@ -0,0 +1,150 @@
+//! # Wait Queues
+//!
+//! A wait queue is a FIFO of threads waiting for some condition to become true. It is the building
+//! block for all of the other blocking primitives in this module.
+
+use alloc::collections::vec_deque::VecDeque;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use super::context::debug_assert_can_block;
+use super::{Deadline, IrqSpinlock};
+use crate::cpu::scheduler::GLOBAL_SCHEDULER;
+use crate::cpu::threads::ThreadId;
+
+/// Indicates whether a timed wait returned because its timeout elapsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub(super) const fn new(timed_out: bool) -> Self {
+        WaitTimeoutResult(timed_out)
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+struct Waiter {
+    thread: Option<ThreadId>,
+    // Points to a flag on the waiting thread's stack. The waiter does not return while it is still
+    // in the queue so the flag outlives the entry.
+    woken:  *const AtomicBool,
+}
+
+unsafe impl Send for Waiter {}
+
+pub struct WaitQueue {
+    // Wakers may run in interrupt context.
+    waiters: IrqSpinlock<VecDeque<Waiter>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: IrqSpinlock::new(VecDeque::new()),
+        }
+    }
+
+    /// Blocks the current thread until it is woken
+    pub fn wait(&self) {
+        self.wait_if(|| true, None);
+    }
+
+    /// Blocks the current thread until it is woken or `timeout` elapses
+    pub fn wait_timeout(&self, timeout: Duration) -> WaitTimeoutResult {
+        self.wait_if(|| true, Some(Deadline::after(timeout)))
+    }
+
+    /// Blocks the current thread if `should_block` returns true. `should_block` is evaluated with
+    /// the queue locked so a wakeup issued after the condition it checks has changed cannot be
+    /// missed.
+    pub fn wait_if(
+        &self,
+        should_block: impl FnOnce() -> bool,
+        deadline: Option<Deadline>,
+    ) -> WaitTimeoutResult {
+        debug_assert_can_block();
+        let woken = AtomicBool::new(false);
+        let thread = GLOBAL_SCHEDULER.current_thread_id();
+        {
+            let mut waiters = self.waiters.lock();
+            if !should_block() {
+                return WaitTimeoutResult(false);
+            }
+            waiters.push_back(Waiter {
+                thread,
+                woken: &woken,
+            });
+        }
+        loop {
+            if woken.load(Ordering::Acquire) {
+                return WaitTimeoutResult(false);
+            }
+            if let Some(deadline) = deadline
+                && deadline.has_expired()
+            {
+                let mut waiters = self.waiters.lock();
+                // Waiters are only flagged while the queue is locked so if the entry is gone the
+                // flag has already been set.
+                return match waiters.iter().position(|w| core::ptr::eq(w.woken, &woken)) {
+                    Some(index) => {
+                        waiters.remove(index);
+                        WaitTimeoutResult(true)
+                    }
+                    None => WaitTimeoutResult(false),
+                };
+            }
+            match thread {
+                Some(_) => GLOBAL_SCHEDULER.block_current_thread(&woken, deadline),
+                None => core::hint::spin_loop(),
+            }
+        }
+    }
+
+    /// Wakes the longest waiting thread. Returns false if there were no waiters.
+    pub fn wake_one(&self) -> bool {
+        let thread = {
+            let mut waiters = self.waiters.lock();
+            match waiters.pop_front() {
+                Some(waiter) => {
+                    let thread = waiter.thread;
+                    unsafe { (*waiter.woken).store(true, Ordering::Release) };
+                    thread
+                }
+                None => return false,
+            }
+        };
+        if let Some(tid) = thread {
+            GLOBAL_SCHEDULER.wake_thread(tid);
+        }
+        true
+    }
+
+    /// Wakes all waiting threads and returns the number of threads woken. Threads that begin
+    /// waiting while this runs are not woken.
+    pub fn wake_all(&self) -> usize {
+        let woken_waiters = {
+            let mut waiters = self.waiters.lock();
+            for waiter in waiters.iter() {
+                unsafe { (*waiter.woken).store(true, Ordering::Release) };
+            }
+            core::mem::take(&mut *waiters)
+        };
+        for tid in woken_waiters.iter().filter_map(|waiter| waiter.thread) {
+            GLOBAL_SCHEDULER.wake_thread(tid);
+        }
+        woken_waiters.len()
+    }
+
+    pub fn has_waiters(&self) -> bool {
+        !self.waiters.lock().is_empty()
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        WaitQueue::new()
+    }
+}
@@ -1,9 +1,13 @@
 use alloc::boxed::Box;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use hashbrown::HashMap;
 use spin::{Lazy, Mutex, RwLock, RwLockReadGuard};
 
+use crate::cpu::multiprocessor::lp_mask::LpMask;
+use crate::isa::lp::LpId;
 use crate::isa::lp::thread_context::ThreadContext;
 use crate::klib::collections::id_table::IdTable;
 
@@ -13,7 +17,88 @@ type ThreadTable = IdTable<ThreadId, Thread>;
 
 pub type ThreadId = usize;
 
+/// The ID given to the next thread created. IDs start at 1 so that LP schedulers can use 0 for the
+/// context an LP started executing in.
+static NEXT_THREAD_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// The size of the stack of a kernel thread
+pub const KERNEL_STACK_SIZE: usize = 64 * 1024;
+
 pub struct Thread {
+    id: ThreadId,
     state: ThreadContext,
     stack_buffer: Box<[u8]>,
+    /// The LPs the thread may run on
+    affinity: LpMask,
+    /// Pinned threads serve a particular LP and their affinity cannot be changed
+    is_pinned: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AffinityError {
+    /// The mask does not contain any LP that is online
+    NoOnlineLp,
+    /// The thread is pinned to an LP
+    Pinned,
+    /// No LP scheduler holds a thread with the given ID
+    NoSuchThread,
+}
+
+impl Thread {
+    /// Creates a thread that may run on any LP
+    pub fn new(state: ThreadContext, stack_buffer: Box<[u8]>) -> Self {
+        Thread {
+            id: NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed),
+            state,
+            stack_buffer,
+            affinity: LpMask::all(),
+            is_pinned: false,
+        }
+    }
+
+    /// Creates a thread that only ever runs on the given LP, such as a per-LP worker
+    pub fn new_pinned(state: ThreadContext, stack_buffer: Box<[u8]>, lp_id: LpId) -> Self {
+        Thread {
+            affinity: LpMask::single(lp_id),
+            is_pinned: true,
+            ..Self::new(state, stack_buffer)
+        }
+    }
+
+    /// Creates a kernel thread that calls `entry` with `argument` on a stack of its own and only
+    /// ever runs on the given LP
+    pub fn new_kernel_pinned(
+        entry: extern "C" fn(usize) -> !,
+        argument: usize,
+        lp_id: LpId,
+    ) -> Self {
+        let mut stack_buffer = vec![0; KERNEL_STACK_SIZE].into_boxed_slice();
+        let state = ThreadContext::new_kernel(entry, argument, &mut stack_buffer);
+        Self::new_pinned(state, stack_buffer, lp_id)
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// The context the thread is resumed with, which is saved into when it is switched out
+    pub fn context_mut(&mut self) -> &mut ThreadContext {
+        &mut self.state
+    }
+
+    pub fn affinity(&self) -> LpMask {
+        self.affinity
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.is_pinned
+    }
+
+    pub fn set_affinity(&mut self, affinity: LpMask) -> Result<(), AffinityError> {
+        if self.is_pinned {
+            return Err(AffinityError::Pinned);
+        }
+        self.affinity = affinity;
+        Ok(())
+    }
 }
@@ -0,0 +1,247 @@
+//! # Kernel Timers
+//!
+//! Every LP keeps the timers added on it in a [`TimerWheel`](wheel::TimerWheel) and expires them
+//! from its local timer interrupt. Timer callbacks run in interrupt context on the LP the timer was
+//! added on and must not block.
+//!
+//! While the LP's scheduler has threads to run, the local timer fires every [`TICK_PERIOD`] and
+//! each interrupt is a scheduler tick that preempts the current thread. Once the run queue is empty
+//! the tick is stopped and the local timer is instead armed for the earliest pending deadline, or
+//! left disarmed if there is none, so that an idle LP is only woken when there is work to do.
+
+pub mod wheel;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+use wheel::{TimerEntry, TimerWheel};
+
+use crate::cpu::scheduler::GLOBAL_SCHEDULER;
+use crate::cpu::sync::context::{InterruptContextGuard, debug_assert_can_block};
+use crate::cpu::sync::{Deadline, IrqSpinlock};
+use crate::cpu::{deferred, executor};
+use crate::debug::watchdog;
+use crate::isa::interface::timers::{TimerIfce, TimerMode};
+use crate::isa::lp::LpId;
+use crate::isa::lp::ops::{get_lp_id, mask_interrupts, unmask_interrupts, wait_for_interrupt};
+use crate::isa::timers::LpTimer;
+use crate::{lock_class, logln, percpu, time};
+
+/// The interval between scheduler ticks while an LP has threads to run
+pub const TICK_PERIOD: Duration = Duration::from_millis(4);
+
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(0);
+
+percpu! {
+    static LOCAL_TIMERS: IrqSpinlock<LocalTimers> =
+        IrqSpinlock::with_class(lock_class!("LOCAL_TIMERS"), LocalTimers::new());
+}
+
+struct LocalTimers {
+    wheel: TimerWheel,
+    /// Set once the local timer has been set up
+    is_ready: bool,
+    /// Set while the local timer is generating the periodic scheduler tick
+    is_ticking: bool,
+    /// The deadline the local timer is armed for while the tick is stopped
+    armed_deadline: Option<Deadline>,
+}
+
+impl LocalTimers {
+    const fn new() -> Self {
+        LocalTimers {
+            wheel: TimerWheel::new(),
+            is_ready: false,
+            is_ticking: false,
+            armed_deadline: None,
+        }
+    }
+
+    /// Programs the local timer for the current state of the LP. Must be called on the LP the
+    /// timers belong to.
+    fn reprogram(&mut self, has_runnable_threads: bool) {
+        if !self.is_ready {
+            return;
+        }
+        if has_runnable_threads {
+            if !self.is_ticking {
+                LpTimer::arm_periodic(TICK_PERIOD);
+                self.is_ticking = true;
+                self.armed_deadline = None;
+            }
+            return;
+        }
+        let next_deadline = self.wheel.next_deadline();
+        if self.is_ticking || next_deadline != self.armed_deadline {
+            match next_deadline {
+                Some(deadline) if LpTimer::is_mode_supported(TimerMode::TimestampDeadline) => {
+                    LpTimer::arm_timestamp_deadline(time::local_timestamp_at(
+                        deadline.monotonic_nanos(),
+                    ))
+                }
+                Some(deadline) => LpTimer::arm_one_shot(deadline.remaining()),
+                None => LpTimer::disarm(),
+            }
+            self.is_ticking = false;
+            self.armed_deadline = next_deadline;
+        }
+    }
+}
+
+/// Identifies a timer added with [`add_timer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerHandle {
+    lp_id: LpId,
+    id: u64,
+    deadline: Deadline,
+}
+
+fn has_runnable_threads() -> bool {
+    GLOBAL_SCHEDULER
+        .try_get_lp_scheduler(get_lp_id!())
+        .is_some_and(|lp_scheduler| !lp_scheduler.lock().is_idle())
+}
+
+/// Calibrates the LP local timer. Must be called on the BSP before [`init_local`] is called on
+/// any LP.
+pub fn calibrate() {
+    if let Err(e) = LpTimer::calibrate() {
+        // Without a working timer there is no preemption and no way to wait for a deadline.
+        panic!("Failed to calibrate the LP local timer: {:?}", e);
+    }
+}
+
+/// Sets up the current LP's local timer and arms it for any timers added before
+pub fn init_local() {
+    let lp_id = get_lp_id!();
+    if let Err(e) = LpTimer::init_local() {
+        panic!("LP{}: Failed to set up the local timer: {:?}", lp_id, e);
+    }
+    let has_runnable_threads = has_runnable_threads();
+    let mut timers = LOCAL_TIMERS.get().lock();
+    timers.is_ready = true;
+    timers.reprogram(has_runnable_threads);
+    logln!("LP{}: Local timer ready.", lp_id);
+}
+
+/// Runs `callback` on the current LP in interrupt context once `deadline` has expired
+pub fn add_timer(deadline: Deadline, callback: impl FnOnce() + Send + 'static) -> TimerHandle {
+    let handle = TimerHandle {
+        lp_id: get_lp_id!(),
+        id: NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed),
+        deadline,
+    };
+    let entry = TimerEntry {
+        id: handle.id,
+        deadline,
+        callback: Box::new(callback),
+    };
+    let has_runnable_threads = has_runnable_threads();
+    let mut timers = LOCAL_TIMERS.get().lock();
+    timers.wheel.insert(entry);
+    timers.reprogram(has_runnable_threads);
+    handle
+}
+
+/// Cancels a timer. Returns false if it has already fired or been cancelled.
+pub fn cancel_timer(handle: &TimerHandle) -> bool {
+    let Some(local_timers) = LOCAL_TIMERS.get_for(handle.lp_id) else {
+        return false;
+    };
+    // The local timer may be left armed for the cancelled deadline, in which case it is
+    // reprogrammed once it fires.
+    let entry = local_timers.lock().wheel.remove(handle.id, handle.deadline);
+    entry.is_some()
+}
+
+/// Blocks the current thread until `duration` has elapsed
+pub fn sleep(duration: Duration) {
+    sleep_until(Deadline::after(duration));
+}
+
+/// Blocks the current thread until `deadline` has expired. Outside of a scheduled thread, e.g.
+/// during system initialization, the current LP spins until the deadline instead.
+pub fn sleep_until(deadline: Deadline) {
+    debug_assert_can_block();
+    if GLOBAL_SCHEDULER.current_thread_id().is_none() {
+        while !deadline.has_expired() {
+            core::hint::spin_loop();
+        }
+        return;
+    }
+    let never_woken = AtomicBool::new(false);
+    while !deadline.has_expired() {
+        GLOBAL_SCHEDULER.block_current_thread(&never_woken, Some(deadline));
+    }
+}
+
+/// Restarts the scheduler tick on the current LP if threads have become runnable on it while it
+/// was stopped
+pub fn restart_tick() {
+    let has_runnable_threads = has_runnable_threads();
+    LOCAL_TIMERS.get().lock().reprogram(has_runnable_threads);
+}
+
+/// Handles the current LP's local timer interrupt. The interrupt must have been acknowledged.
+pub fn handle_timer_interrupt(context: InterruptContextGuard) {
+    watchdog::touch();
+    let mut expired = Vec::new();
+    {
+        let mut timers = LOCAL_TIMERS.get().lock();
+        // Whatever the timer was armed for has fired.
+        timers.armed_deadline = None;
+        timers.wheel.expire(Deadline::now(), &mut expired);
+    }
+    for entry in expired {
+        (entry.callback)();
+    }
+    // Expired timers may have made threads runnable.
+    let has_runnable_threads = has_runnable_threads();
+    let is_ticking = {
+        let mut timers = LOCAL_TIMERS.get().lock();
+        timers.reprogram(has_runnable_threads);
+        timers.is_ticking
+    };
+    // The interrupted thread is not resumed until it is scheduled again, so it must not be counted
+    // as being in interrupt context in the meantime.
+    drop(context);
+    if is_ticking {
+        GLOBAL_SCHEDULER.tick();
+    }
+}
+
+/// Waits for interrupts on the current LP forever, serving the LP's async tasks and, unless a
+/// worker thread does, the system work queue in between. Runnable threads are switched to instead
+/// of waiting. The scheduler tick is stopped while the run queue is empty.
+pub fn idle() -> ! {
+    loop {
+        GLOBAL_SCHEDULER.run_pending_migrations();
+        deferred::run_tasklets();
+        unmask_interrupts!();
+        let serves_work_queue = deferred::work_queue::idle_serves_system_queue();
+        if serves_work_queue {
+            deferred::work_queue::SYSTEM_WORK_QUEUE.run_pending();
+        }
+        executor::run_pending();
+        crate::log::flush();
+        mask_interrupts!();
+        // Anything queued by an interrupt from here on wakes the LP from its wait below.
+        if deferred::tasklet::has_pending_tasklets()
+            || (serves_work_queue && deferred::work_queue::SYSTEM_WORK_QUEUE.has_pending())
+            || executor::has_pending()
+        {
+            continue;
+        }
+        if has_runnable_threads() {
+            GLOBAL_SCHEDULER.yield_current();
+            continue;
+        }
+        restart_tick();
+        watchdog::touch_idle();
+        wait_for_interrupt();
+        watchdog::touch();
+    }
+}
@@ -0,0 +1,115 @@
+//! # Timer Wheel
+//!
+//! A hashed timer wheel with one slot per scheduler tick. Timers are placed in the slot of the tick
+//! their deadline falls into, modulo the number of slots, so adding and cancelling a timer only
+//! touches a single slot and expiring timers only visits the slots of the ticks that have passed.
+//! Timers whose deadline lies more than one revolution in the future share their slot with nearer
+//! ones and are skipped until their deadline is reached.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use super::TICK_PERIOD;
+use crate::cpu::sync::Deadline;
+
+const WHEEL_SLOTS: usize = 256;
+
+pub type TimerCallback = Box<dyn FnOnce() + Send>;
+
+pub struct TimerEntry {
+    pub id: u64,
+    pub deadline: Deadline,
+    pub callback: TimerCallback,
+}
+
+pub struct TimerWheel {
+    // Allocated when the first timer is added so that the wheel can be constructed statically.
+    slots: Vec<Vec<TimerEntry>>,
+    /// The most recent tick up to which timers have been expired. Its slot may still hold timers
+    /// that expire later during the same tick.
+    current_tick: u64,
+    len: usize,
+}
+
+fn tick_of(deadline: Deadline) -> u64 {
+    deadline.monotonic_nanos() / TICK_PERIOD.as_nanos() as u64
+}
+
+impl TimerWheel {
+    pub const fn new() -> Self {
+        TimerWheel {
+            slots: Vec::new(),
+            current_tick: 0,
+            len: 0,
+        }
+    }
+
+    fn slot_of(&self, deadline: Deadline) -> usize {
+        // Timers that are already due go into the slot that is visited next.
+        (tick_of(deadline).max(self.current_tick) % WHEEL_SLOTS as u64) as usize
+    }
+
+    pub fn insert(&mut self, entry: TimerEntry) {
+        if self.slots.is_empty() {
+            self.slots.resize_with(WHEEL_SLOTS, Vec::new);
+        }
+        let slot = self.slot_of(entry.deadline);
+        self.slots[slot].push(entry);
+        self.len += 1;
+    }
+
+    /// Removes the timer with the given ID and deadline. Returns `None` if it is not in the wheel.
+    pub fn remove(&mut self, id: u64, deadline: Deadline) -> Option<TimerEntry> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let slot = self.slot_of(deadline);
+        // The timer may have been placed before the wheel advanced past its tick.
+        let slot = if self.slots[slot].iter().any(|entry| entry.id == id) {
+            slot
+        } else {
+            self.slots.iter().position(|entries| entries.iter().any(|entry| entry.id == id))?
+        };
+        let index = self.slots[slot].iter().position(|entry| entry.id == id)?;
+        self.len -= 1;
+        Some(self.slots[slot].swap_remove(index))
+    }
+
+    /// Removes all timers whose deadline has been reached by `now` and appends them to `expired`
+    pub fn expire(&mut self, now: Deadline, expired: &mut Vec<TimerEntry>) {
+        let now_tick = tick_of(now);
+        if self.len == 0 {
+            self.current_tick = self.current_tick.max(now_tick);
+            return;
+        }
+        let n_ticks = (now_tick.saturating_sub(self.current_tick) + 1).min(WHEEL_SLOTS as u64);
+        for tick in now_tick + 1 - n_ticks..=now_tick {
+            let slot = &mut self.slots[(tick % WHEEL_SLOTS as u64) as usize];
+            let mut index = 0;
+            while index < slot.len() {
+                if slot[index].deadline <= now {
+                    expired.push(slot.swap_remove(index));
+                    self.len -= 1;
+                } else {
+                    index += 1;
+                }
+            }
+        }
+        self.current_tick = self.current_tick.max(now_tick);
+    }
+
+    /// Returns the earliest deadline of all timers in the wheel
+    pub fn next_deadline(&self) -> Option<Deadline> {
+        self.slots.iter().flatten().map(|entry| entry.deadline).min()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        TimerWheel::new()
+    }
+}
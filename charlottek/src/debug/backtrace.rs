@@ -0,0 +1,76 @@
+//! # Stack Backtraces
+//!
+//! The kernel is built with frame pointers, so on entry every function pushes the frame pointer of
+//! its caller right below its return address and points the frame pointer at that frame record.
+//! The records form a chain from the innermost function outwards that can be followed without any
+//! unwind tables.
+//!
+//! The chain can only be trusted as far as the stack is intact. A record is only followed if it is
+//! aligned, lies above the previous one within the span of a kernel stack and holds a return
+//! address into the kernel's code, so a corrupted stack ends the backtrace instead of faulting.
+
+use super::symbols::{Symbolized, is_kernel_text};
+use crate::isa::lp::ops::frame_pointer;
+use crate::logln;
+
+const MAX_FRAMES: usize = 64;
+/// No kernel stack spans more than this
+const MAX_STACK_SPAN: usize = 1 << 20;
+const HIGHER_HALF_START: usize = 0xffff_8000_0000_0000;
+
+/// Calls `f` with the return address of each frame in the chain starting at `frame_pointer`,
+/// innermost first
+pub fn walk(frame_pointer: usize, mut f: impl FnMut(usize)) {
+    let mut current = frame_pointer;
+    for _ in 0..MAX_FRAMES {
+        if current < HIGHER_HALF_START
+            || !current.is_multiple_of(align_of::<usize>())
+            || current - frame_pointer > MAX_STACK_SPAN
+        {
+            return;
+        }
+        let record = current as *const usize;
+        let (next, return_address) = unsafe { (record.read(), record.add(1).read()) };
+        if !is_kernel_text(return_address) {
+            return;
+        }
+        f(return_address);
+        if next <= current {
+            return;
+        }
+        current = next;
+    }
+}
+
+fn log_frame(index: usize, address: usize) {
+    // A return address follows the call instruction, which may be the last one of its function.
+    logln!("  #{:<2} {:#018x} {}", index, address, (Symbolized(address.saturating_sub(1))));
+}
+
+/// Logs a backtrace of the code that was interrupted at `instruction_pointer` with the given frame
+/// pointer
+pub fn log_backtrace_from(instruction_pointer: usize, frame_pointer: usize) {
+    logln!("Backtrace:");
+    logln!("  #0  {:#018x} {}", instruction_pointer, (Symbolized(instruction_pointer)));
+    let mut index = 1;
+    walk(frame_pointer, |address| {
+        log_frame(index, address);
+        index += 1;
+    });
+}
+
+/// Logs a backtrace of the callers of the function with the given frame pointer
+pub fn log_backtrace_of(frame_pointer: usize) {
+    logln!("Backtrace:");
+    let mut index = 0;
+    walk(frame_pointer, |address| {
+        log_frame(index, address);
+        index += 1;
+    });
+}
+
+/// Logs a backtrace of the calling function's callers
+#[inline(never)]
+pub fn log_backtrace() {
+    log_backtrace_of(frame_pointer());
+}
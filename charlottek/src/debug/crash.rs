@@ -0,0 +1,248 @@
+//! # Crash Reports
+//!
+//! The first LP to panic stops every other LP before it reports the panic, so that their output
+//! does not interleave with the report and none of them keeps running on state the panic may have
+//! left inconsistent. The other LPs are stopped with an NMI since it is delivered even while they
+//! have interrupts masked or spin on a lock. Each stopped LP records the state it was interrupted
+//! in and the thread it was running and halts for good. The panicking LP then takes over the log
+//! and the console, whose locks a stopped LP may have been holding, and reports the panic along
+//! with the state of every stopped LP.
+//!
+//! An LP that panics while another one is already reporting a crash stops in the same way. Hard
+//! lockups detected by the [watchdog](super::watchdog) are reported like panics.
+
+use core::fmt;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::time::Duration;
+
+use spin::Once;
+
+use super::backtrace;
+use super::symbols::Symbolized;
+use crate::cpu::multiprocessor::MAX_LP_COUNT;
+use crate::cpu::multiprocessor::lp_mask::{AtomicLpMask, LpMask};
+use crate::cpu::scheduler::GLOBAL_SCHEDULER;
+use crate::cpu::sync::Deadline;
+use crate::cpu::threads::ThreadId;
+use crate::isa::interrupts::exceptions::frame::ExceptionFrame;
+use crate::isa::interrupts::exceptions::report::ControlRegisters;
+use crate::isa::interrupts::x2apic::ipi::{DeliveryMode, Destination, send};
+use crate::isa::interrupts::x2apic::lapic_id_of;
+use crate::isa::lp::LpId;
+use crate::isa::lp::ops::{frame_pointer, get_lp_id, halt};
+use crate::log::persist;
+use crate::{logln, percpu};
+
+/// How long the panicking LP waits for the others to stop before it reports without them
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+const NO_LP: u32 = u32::MAX;
+
+/// The LP reporting a crash or [`NO_LP`] while the kernel is running normally
+static CRASHING_LP: AtomicU32 = AtomicU32::new(NO_LP);
+/// The LPs that have recorded their state and halted
+static STOPPED_LPS: AtomicLpMask = AtomicLpMask::empty();
+
+percpu! {
+    /// Set once the LP has started to stop so that a second NMI does not record its state again
+    static STOPPING: AtomicBool = AtomicBool::new(false);
+    static STOPPED_STATE: Once<StoppedState> = Once::new();
+}
+
+/// Where an LP was when it stopped
+enum StopPoint {
+    /// The LP was interrupted by the stop NMI
+    Interrupted(ExceptionFrame),
+    /// The LP was panicking as well and stopped in its panic handler with the given frame pointer
+    Panicking(usize),
+}
+
+struct StoppedState {
+    stop_point: StopPoint,
+    control_registers: ControlRegisters,
+    /// `None` if the LP was not running a thread or its scheduler was locked
+    thread: Option<ThreadId>,
+}
+
+/// Returns true if an LP is reporting a crash
+pub fn is_crashing() -> bool {
+    CRASHING_LP.load(Ordering::Acquire) != NO_LP
+}
+
+/// Stops the current LP if another LP is reporting a panic and returns otherwise. Called by the
+/// NMI handler with the state the NMI interrupted.
+pub fn stop_if_crashing(frame: &ExceptionFrame) {
+    let crashing_lp = CRASHING_LP.load(Ordering::Acquire);
+    if crashing_lp != NO_LP && crashing_lp != get_lp_id!() {
+        stop_local(StopPoint::Interrupted(*frame));
+    }
+}
+
+fn stop_local(stop_point: StopPoint) -> ! {
+    let lp_id = get_lp_id!();
+    if !STOPPING.get().swap(true, Ordering::Relaxed) {
+        STOPPED_STATE.get().call_once(|| StoppedState {
+            stop_point,
+            control_registers: ControlRegisters::read(),
+            thread: current_thread(lp_id),
+        });
+        STOPPED_LPS.insert(lp_id);
+    }
+    halt!()
+}
+
+fn current_thread(lp_id: LpId) -> Option<ThreadId> {
+    // The LP may have been stopped while holding its scheduler's lock.
+    GLOBAL_SCHEDULER.try_get_lp_scheduler(lp_id)?.try_lock()?.current_thread()
+}
+
+/// Sends the stop NMI to every other LP that can receive IPIs and waits for them to stop. Returns
+/// the LPs that did not stop in time.
+fn stop_other_lps() -> LpMask {
+    let lp_id = get_lp_id!();
+    let mut targets = LpMask::empty();
+    // IPIs can only be sent once the current LP's local APIC has been set up.
+    if lapic_id_of(lp_id).is_some() {
+        for target in (0..MAX_LP_COUNT as LpId).filter(|target| *target != lp_id) {
+            if let Some(lapic_id) = lapic_id_of(target) {
+                send(Destination::Physical(lapic_id.physical), 0, DeliveryMode::Nmi);
+                targets.insert(target);
+            }
+        }
+    }
+    let deadline = Deadline::after(STOP_TIMEOUT);
+    let unresponsive = || {
+        let mut unresponsive = targets;
+        STOPPED_LPS.load().iter().for_each(|lp_id| unresponsive.remove(lp_id));
+        unresponsive
+    };
+    while !unresponsive().is_empty() && !deadline.has_expired() {
+        core::hint::spin_loop();
+    }
+    unresponsive()
+}
+
+/// Makes the log sinks usable by the current LP regardless of which LP was writing to them and
+/// has every record written out as soon as it is logged
+///
+/// # Safety
+/// Every other LP that may be writing to a sink must have been stopped.
+pub unsafe fn release_output() {
+    unsafe { crate::log::enter_crash_mode() };
+}
+
+/// Claims the crash report for the current LP or stops it if another LP has already claimed it
+fn claim_report(stop_point: impl FnOnce() -> StopPoint) {
+    let lp_id = get_lp_id!();
+    if CRASHING_LP.compare_exchange(NO_LP, lp_id, Ordering::AcqRel, Ordering::Acquire).is_err() {
+        stop_local(stop_point());
+    }
+}
+
+/// Stops every other LP, then logs `report` for the current LP followed by the state of the other
+/// LPs. `summary` is kept for the next boot along with the log.
+fn report(summary: fmt::Arguments, report: impl FnOnce()) -> ! {
+    let unresponsive = stop_other_lps();
+    // The locks are released without their holders, so acquiring them out of order from here on
+    // is expected.
+    crate::cpu::sync::lockdep::disable();
+    unsafe { release_output() };
+    persist::record_crash(summary);
+    report();
+    for stopped_lp in STOPPED_LPS.load().iter() {
+        if let Some(state) = STOPPED_STATE.get_for(stopped_lp).and_then(Once::get) {
+            log_stopped_lp(stopped_lp, state);
+        }
+    }
+    if !unresponsive.is_empty() {
+        logln!("LPs that did not respond to the stop NMI: {:?}", unresponsive);
+    }
+    crate::isa::mca::log_error_log();
+    persist::seal();
+    super::monitor::enter_after_crash();
+    halt!()
+}
+
+/// Stops every other LP and reports the panic along with their state. An LP that panics while
+/// another LP is already reporting a crash stops instead.
+pub fn report_panic(info: &PanicInfo) -> ! {
+    let lp_id = get_lp_id!();
+    if CRASHING_LP.load(Ordering::Acquire) == lp_id {
+        // The report itself panicked and the other LPs have been stopped already.
+        unsafe { release_output() };
+        logln!("LP{} {}", lp_id, info);
+        persist::seal();
+        halt!()
+    }
+    claim_report(|| StopPoint::Panicking(frame_pointer()));
+    report(format_args!("LP{} panicked: {}", lp_id, info.message()), || {
+        logln!("LP{} {}", lp_id, info);
+        backtrace::log_backtrace();
+    })
+}
+
+/// Stops every other LP and reports that the current LP has been running with interrupts masked
+/// for `stalled_for` when the NMI that interrupted it at `frame` was delivered
+pub fn report_hard_lockup(frame: &ExceptionFrame, stalled_for: Duration) -> ! {
+    claim_report(|| StopPoint::Interrupted(*frame));
+    let lp_id = get_lp_id!();
+    let control_registers = ControlRegisters::read();
+    report(format_args!("LP{} hard lockup after {:?}", lp_id, stalled_for), || {
+        logln!(
+            "LP{} hard lockup: no scheduler progress for {:?} with interrupts masked",
+            lp_id,
+            stalled_for
+        );
+        log_interrupted_state(lp_id, frame, &control_registers, current_thread(lp_id));
+    })
+}
+
+fn log_interrupted_state(
+    lp_id: LpId,
+    frame: &ExceptionFrame,
+    control_registers: &ControlRegisters,
+    thread: Option<ThreadId>,
+) {
+    let rip = frame.interrupt_frame.rip as usize;
+    logln!(
+        "LP{} stopped in {} at RIP {:#018x} {} {}",
+        lp_id,
+        (if frame.is_kernel_mode() {
+            "the kernel"
+        } else {
+            "userspace"
+        }),
+        rip,
+        (Symbolized(rip)),
+        (RunningThread(thread))
+    );
+    logln!("{}", frame);
+    logln!("{}", control_registers);
+    if frame.is_kernel_mode() {
+        backtrace::log_backtrace_from(rip, frame.gprs.rbp as usize);
+    }
+}
+
+fn log_stopped_lp(lp_id: LpId, state: &StoppedState) {
+    match &state.stop_point {
+        StopPoint::Interrupted(frame) => {
+            log_interrupted_state(lp_id, frame, &state.control_registers, state.thread)
+        }
+        StopPoint::Panicking(frame_pointer) => {
+            logln!("LP{} stopped while panicking {}", lp_id, (RunningThread(state.thread)));
+            logln!("{}", (state.control_registers));
+            backtrace::log_backtrace_of(*frame_pointer);
+        }
+    }
+}
+
+struct RunningThread(Option<ThreadId>);
+
+impl fmt::Display for RunningThread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(tid) => write!(f, "running thread {}", tid),
+            None => write!(f, "outside of any known thread"),
+        }
+    }
+}
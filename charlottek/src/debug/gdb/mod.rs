@@ -0,0 +1,625 @@
+//! # GDB Remote Stub
+//!
+//! Lets GDB debug the kernel over the second UART, or whichever port `gdb.port` names on the kernel
+//! command line, using the GDB Remote Serial Protocol, which works on bare metal unlike QEMU's
+//! built-in stub. `gdb=off` disables the stub. Under QEMU the port can be exposed with e.g.
+//! `-serial stdio -serial tcp::1234,server,nowait` and attached to with `target remote :1234`.
+//!
+//! The kernel stops for the debugger when it executes a breakpoint, either one GDB inserted or an
+//! explicit call to [`breakpoint`], or finishes a single step. The LP that stopped becomes the
+//! debugger LP and stops every other LP with an NMI, which parks them in the NMI handler with their
+//! interrupted state available to GDB until the debugger LP resumes. Every LP is presented to GDB
+//! as a thread whose ID is the LP ID plus one, since GDB reserves thread ID zero.
+//!
+//! The stub runs in exception context with the other LPs stopped wherever they were, so it does not
+//! allocate, log or take any lock another LP may hold. Memory is accessed through
+//! [`super::memory`], which only touches mapped pages.
+
+pub mod packet;
+pub mod registers;
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use packet::{MAX_PACKET_SIZE, Packet, decode_hex, parse_hex};
+use registers::REGISTER_COUNT;
+
+use super::{memory, watchdog};
+use crate::cpu::multiprocessor::MAX_LP_COUNT;
+use crate::cpu::multiprocessor::lp_mask::{AtomicLpMask, LpMask};
+use crate::cpu::scheduler::GLOBAL_SCHEDULER;
+use crate::cpu::sync::{Deadline, IrqSpinlock};
+use crate::drivers::uart::ns16550::{DEBUG_PORT, GDB_PORT, Uart16550};
+use crate::isa::interrupts::exceptions::frame::ExceptionFrame;
+use crate::isa::interrupts::x2apic::ipi::{DeliveryMode, Destination, send};
+use crate::isa::interrupts::x2apic::lapic_id_of;
+use crate::isa::lp::LpId;
+use crate::isa::lp::ops::get_lp_id;
+use crate::{kernel_param, lock_class, logln, percpu};
+
+const NO_LP: u32 = u32::MAX;
+const MAX_BREAKPOINTS: usize = 32;
+/// How long the debugger LP waits for the other LPs to stop or to resume
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+const INT3: u8 = 0xcc;
+const RFLAGS_TF: u64 = 1 << 8;
+/// The debug exception was caused by single-stepping
+const DR6_BS: u64 = 1 << 14;
+const SIGTRAP: u8 = 5;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Called instead of running a GDB session when the kernel stops, or null
+static STOP_HOOK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+static DEBUGGER_LP: AtomicU32 = AtomicU32::new(NO_LP);
+/// The LPs waiting in the NMI handler for the debugger LP to resume
+static PARKED_LPS: AtomicLpMask = AtomicLpMask::empty();
+/// Incremented whenever the debugger LP resumes, which releases the parked LPs
+static RESUME_GENERATION: AtomicUsize = AtomicUsize::new(0);
+/// Set while GDB waits for the kernel to stop after resuming it
+static GDB_AWAITS_STOP: AtomicBool = AtomicBool::new(false);
+/// Only accessed by the debugger LP
+static BREAKPOINTS: IrqSpinlock<[Option<Breakpoint>; MAX_BREAKPOINTS]> =
+    IrqSpinlock::with_class(lock_class!("GDB_BREAKPOINTS"), [None; MAX_BREAKPOINTS]);
+
+kernel_param! {
+    static STUB_PARAM: bool = ("gdb", true);
+}
+
+percpu! {
+    /// Set before the debugger LP sends the stop NMI so that the NMI handler can tell it apart
+    static PARK_REQUESTED: AtomicBool = AtomicBool::new(false);
+    /// The state the stop NMI interrupted while the LP is parked
+    static PARKED_FRAME: AtomicPtr<ExceptionFrame> = AtomicPtr::new(core::ptr::null_mut());
+    static SINGLE_STEPPING: AtomicBool = AtomicBool::new(false);
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Breakpoint {
+    address:  usize,
+    /// The byte the breakpoint instruction replaced
+    original: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Error {
+    /// The memory is not mapped
+    Fault,
+    NoSpace,
+    Invalid,
+}
+
+impl Error {
+    /// The error number GDB is sent, following the POSIX numbering GDB uses
+    fn code(self) -> u8 {
+        match self {
+            Error::Fault => 0x0e,
+            Error::NoSpace => 0x1c,
+            Error::Invalid => 0x16,
+        }
+    }
+}
+
+/// Enables the stub unless it is disabled on the command line or its port is unavailable.
+/// Breakpoints are fatal until then.
+pub fn init() {
+    if !STUB_PARAM.get() {
+        logln!("GDB stub: disabled on the kernel command line.");
+        return;
+    }
+    if DEBUG_PORT.is_none() {
+        logln!(
+            "GDB stub: {} is missing or used as the console, so the stub is disabled.",
+            (GDB_PORT.get())
+        );
+        return;
+    }
+    ENABLED.store(true, Ordering::Release);
+    logln!("GDB stub: listening on {}.", (GDB_PORT.get()));
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// Has every stop call `hook` with the stopped LP's state instead of waiting for GDB, until it is
+/// cleared with `None`. Breakpoints and single steps are handled while a hook is set even if the
+/// stub is disabled, which lets them be tested without a debugger.
+pub fn set_stop_hook(hook: Option<fn(&mut ExceptionFrame)>) {
+    let hook = hook.map_or(core::ptr::null_mut(), |hook| hook as *mut ());
+    STOP_HOOK.store(hook, Ordering::Release);
+}
+
+fn stop_hook() -> Option<fn(&mut ExceptionFrame)> {
+    let hook = STOP_HOOK.load(Ordering::Acquire);
+    // Only ever stored from a function pointer of this type.
+    (!hook.is_null())
+        .then(|| unsafe { core::mem::transmute::<*mut (), fn(&mut ExceptionFrame)>(hook) })
+}
+
+fn is_active() -> bool {
+    is_enabled() || stop_hook().is_some()
+}
+
+/// Inserts a breakpoint the way GDB does. Returns false if the address cannot be written or too
+/// many breakpoints are inserted.
+pub fn set_breakpoint(address: usize) -> bool {
+    insert_breakpoint(address).is_ok()
+}
+
+/// Removes a breakpoint inserted by [`set_breakpoint`] or GDB
+pub fn clear_breakpoint(address: usize) -> bool {
+    remove_breakpoint(address).is_ok()
+}
+
+/// Stops the kernel for the debugger if the stub is enabled
+#[inline(always)]
+pub fn breakpoint() {
+    if is_enabled() {
+        unsafe { core::arch::asm!("int3", options(nomem, nostack)) };
+    }
+}
+
+/// Stops the kernel for the debugger if the stub is enabled and the breakpoint was hit in the
+/// kernel. Returns false if the breakpoint is not the stub's to handle.
+pub fn handle_breakpoint(frame: &mut ExceptionFrame) -> bool {
+    if !is_active() || !frame.is_kernel_mode() {
+        return false;
+    }
+    claim();
+    // The processor reports the address after the breakpoint instruction.
+    let address = frame.interrupt_frame.rip as usize - 1;
+    let is_inserted = BREAKPOINTS.lock().iter().flatten().any(|bp| bp.address == address);
+    let mut byte = [0];
+    let still_trapping = read_memory(address, &mut byte) == 1 && byte[0] == INT3;
+    if is_inserted || !still_trapping {
+        frame.interrupt_frame.rip = address as u64;
+    }
+    if !is_inserted && !still_trapping {
+        // GDB removed the breakpoint while this LP was waiting for another LP's stop to end.
+        release();
+        return true;
+    }
+    stop(frame);
+    true
+}
+
+/// Stops the kernel for the debugger if the current LP has finished a single step. Returns false
+/// if the debug exception is not the stub's to handle.
+pub fn handle_debug(frame: &mut ExceptionFrame, dr6: u64) -> bool {
+    if !is_active() || dr6 & DR6_BS == 0 || !SINGLE_STEPPING.get().swap(false, Ordering::Relaxed) {
+        return false;
+    }
+    frame.interrupt_frame.rflags &= !RFLAGS_TF;
+    claim();
+    stop(frame);
+    true
+}
+
+/// Parks the current LP until the debugger LP resumes if the NMI being handled is the debugger's
+/// stop NMI. Returns false if the NMI came from elsewhere.
+pub fn park_if_requested(frame: &mut ExceptionFrame) -> bool {
+    if !PARK_REQUESTED.get().swap(false, Ordering::AcqRel) {
+        return false;
+    }
+    let generation = RESUME_GENERATION.load(Ordering::Acquire);
+    let lp_id = get_lp_id!();
+    let debugger_lp = DEBUGGER_LP.load(Ordering::Acquire);
+    if debugger_lp == NO_LP || debugger_lp == lp_id {
+        // The stop the NMI was sent for has already ended.
+        return true;
+    }
+    PARKED_FRAME.get().store(frame, Ordering::Release);
+    PARKED_LPS.insert(lp_id);
+    while RESUME_GENERATION.load(Ordering::Acquire) == generation {
+        core::hint::spin_loop();
+    }
+    PARKED_FRAME.get().store(core::ptr::null_mut(), Ordering::Release);
+    PARKED_LPS.remove(lp_id);
+    watchdog::touch();
+    true
+}
+
+/// Makes the current LP the debugger LP, waiting for any other LP's stop to end first
+fn claim() {
+    let lp_id = get_lp_id!();
+    while DEBUGGER_LP.compare_exchange(NO_LP, lp_id, Ordering::AcqRel, Ordering::Acquire).is_err() {
+        watchdog::touch();
+        core::hint::spin_loop();
+    }
+}
+
+fn release() {
+    watchdog::touch();
+    DEBUGGER_LP.store(NO_LP, Ordering::Release);
+}
+
+/// Returns true if the LP has been started
+fn is_online(lp_id: LpId) -> bool {
+    lp_id == get_lp_id!() || lapic_id_of(lp_id).is_some()
+}
+
+/// Stops the other LPs, lets GDB inspect and modify the kernel until it resumes it and then resumes
+/// the other LPs
+fn stop(frame: &mut ExceptionFrame) {
+    let lp_id = get_lp_id!();
+    let mut targets = LpMask::empty();
+    // IPIs can only be sent once the current LP's local APIC has been set up.
+    if lapic_id_of(lp_id).is_some() {
+        for target in (0..MAX_LP_COUNT as LpId).filter(|target| *target != lp_id) {
+            if let Some(lapic_id) = lapic_id_of(target) {
+                let Some(park_requested) = PARK_REQUESTED.get_for(target) else {
+                    continue;
+                };
+                park_requested.store(true, Ordering::Release);
+                send(Destination::Physical(lapic_id.physical), 0, DeliveryMode::Nmi);
+                targets.insert(target);
+            }
+        }
+    }
+    let deadline = Deadline::after(STOP_TIMEOUT);
+    while PARKED_LPS.load().intersection(&targets).count() != targets.count()
+        && !deadline.has_expired()
+    {
+        core::hint::spin_loop();
+    }
+
+    if let Some(hook) = stop_hook() {
+        hook(frame);
+    } else if let Some(port) = &*DEBUG_PORT {
+        let mut session = Session {
+            lp_id,
+            frame,
+            register_lp: lp_id,
+            resume_lp: lp_id,
+        };
+        session.run(&mut port.lock());
+    }
+
+    RESUME_GENERATION.fetch_add(1, Ordering::AcqRel);
+    let deadline = Deadline::after(STOP_TIMEOUT);
+    while !PARKED_LPS.load().is_empty() && !deadline.has_expired() {
+        core::hint::spin_loop();
+    }
+    release();
+}
+
+fn read_memory(address: usize, buffer: &mut [u8]) -> usize {
+    memory::read(address, buffer)
+}
+
+fn write_memory(address: usize, data: &[u8]) -> Result<(), Error> {
+    if memory::write(address, data) {
+        Ok(())
+    } else {
+        Err(Error::Fault)
+    }
+}
+
+fn insert_breakpoint(address: usize) -> Result<(), Error> {
+    let mut breakpoints = BREAKPOINTS.lock();
+    if breakpoints.iter().flatten().any(|bp| bp.address == address) {
+        return Ok(());
+    }
+    let slot = breakpoints.iter_mut().find(|slot| slot.is_none()).ok_or(Error::NoSpace)?;
+    let mut original = [0];
+    if read_memory(address, &mut original) != 1 {
+        return Err(Error::Fault);
+    }
+    write_memory(address, &[INT3])?;
+    *slot = Some(Breakpoint {
+        address,
+        original: original[0],
+    });
+    Ok(())
+}
+
+fn remove_breakpoint(address: usize) -> Result<(), Error> {
+    let mut breakpoints = BREAKPOINTS.lock();
+    if let Some(slot) =
+        breakpoints.iter_mut().find(|slot| slot.is_some_and(|bp| bp.address == address))
+    {
+        let breakpoint = slot.take().unwrap();
+        write_memory(breakpoint.address, &[breakpoint.original])?;
+    }
+    Ok(())
+}
+
+fn remove_all_breakpoints() {
+    for slot in BREAKPOINTS.lock().iter_mut() {
+        if let Some(breakpoint) = slot.take() {
+            let _ = write_memory(breakpoint.address, &[breakpoint.original]);
+        }
+    }
+}
+
+fn thread_id(lp_id: LpId) -> u64 {
+    lp_id as u64 + 1
+}
+
+/// Parses a thread ID. `Some(None)` stands for any thread.
+fn parse_thread_id(bytes: &[u8]) -> Option<Option<LpId>> {
+    if bytes == b"-1" || bytes == b"0" {
+        return Some(None);
+    }
+    let lp_id = parse_hex(bytes)?.checked_sub(1)?;
+    (lp_id < MAX_LP_COUNT as u64 && is_online(lp_id as LpId)).then_some(Some(lp_id as LpId))
+}
+
+/// Splits `bytes` at the first occurrence of `separator`
+fn split(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let position = bytes.iter().position(|byte| *byte == separator)?;
+    Some((&bytes[..position], &bytes[position + 1..]))
+}
+
+/// Parses `address,length`
+fn parse_range(bytes: &[u8]) -> Option<(usize, usize)> {
+    let (address, length) = split(bytes, b',')?;
+    Some((parse_hex(address)? as usize, parse_hex(length)? as usize))
+}
+
+/// What to do once a request has been handled
+enum Action {
+    Reply,
+    /// Resume the kernel without a reply, GDB waits for it to stop again
+    Resume,
+    /// Resume the kernel, sending the reply first if there is one
+    Detach,
+}
+
+/// The conversation with GDB during one stop
+struct Session<'a> {
+    /// The debugger LP
+    lp_id: LpId,
+    frame: &'a mut ExceptionFrame,
+    /// The LP whose registers are read and written
+    register_lp: LpId,
+    /// The LP that is continued or single-stepped
+    resume_lp: LpId,
+}
+
+impl Session<'_> {
+    fn frame_of(&mut self, lp_id: LpId) -> Option<&mut ExceptionFrame> {
+        if lp_id == self.lp_id {
+            Some(self.frame)
+        } else if PARKED_LPS.contains(lp_id) {
+            unsafe { PARKED_FRAME.get_for(lp_id)?.load(Ordering::Acquire).as_mut() }
+        } else {
+            None
+        }
+    }
+
+    fn stop_reply(&self, reply: &mut Packet) {
+        let _ = write!(reply, "T{:02x}thread:{:x};", SIGTRAP, thread_id(self.lp_id));
+    }
+
+    /// Serves GDB's requests until it resumes the kernel
+    fn run(&mut self, port: &mut Uart16550) {
+        let mut request = Packet::new();
+        let mut reply = Packet::new();
+        if GDB_AWAITS_STOP.swap(false, Ordering::AcqRel) {
+            self.stop_reply(&mut reply);
+            packet::send(port, &reply);
+        }
+        loop {
+            packet::receive(port, &mut request);
+            reply.clear();
+            let action = self.handle(request.as_bytes(), &mut reply).unwrap_or_else(|error| {
+                reply.clear();
+                let _ = write!(reply, "E{:02x}", error.code());
+                Action::Reply
+            });
+            match action {
+                Action::Reply => packet::send(port, &reply),
+                Action::Resume => {
+                    GDB_AWAITS_STOP.store(true, Ordering::Release);
+                    return;
+                }
+                Action::Detach => {
+                    if !reply.as_bytes().is_empty() {
+                        packet::send(port, &reply);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Handles a request and writes the reply. Requests the stub does not support get an empty
+    /// reply.
+    fn handle(&mut self, request: &[u8], reply: &mut Packet) -> Result<Action, Error> {
+        let Some((&command, arguments)) = request.split_first() else {
+            return Ok(Action::Reply);
+        };
+        match command {
+            b'?' => self.stop_reply(reply),
+            b'g' => self.read_registers(reply)?,
+            b'G' => {
+                self.write_registers(arguments)?;
+                let _ = write!(reply, "OK");
+            }
+            b'p' => self.read_register(arguments, reply)?,
+            b'P' => {
+                self.write_register(arguments)?;
+                let _ = write!(reply, "OK");
+            }
+            b'm' => {
+                let (address, length) = parse_range(arguments).ok_or(Error::Invalid)?;
+                let mut buffer = [0; MAX_PACKET_SIZE / 2];
+                let length = length.min(buffer.len());
+                let read = read_memory(address, &mut buffer[..length]);
+                if read == 0 && length != 0 {
+                    return Err(Error::Fault);
+                }
+                reply.push_hex(&buffer[..read]);
+            }
+            b'M' => {
+                let (range, data) = split(arguments, b':').ok_or(Error::Invalid)?;
+                let (address, length) = parse_range(range).ok_or(Error::Invalid)?;
+                let mut buffer = [0; MAX_PACKET_SIZE / 2];
+                if decode_hex(data, &mut buffer) != Some(length) {
+                    return Err(Error::Invalid);
+                }
+                write_memory(address, &buffer[..length])?;
+                let _ = write!(reply, "OK");
+            }
+            // Only software breakpoints are supported.
+            b'Z' | b'z' => {
+                if let Some(range) = arguments.strip_prefix(b"0,") {
+                    let (address, _) = parse_range(range).ok_or(Error::Invalid)?;
+                    if command == b'Z' {
+                        insert_breakpoint(address)?;
+                    } else {
+                        remove_breakpoint(address)?;
+                    }
+                    let _ = write!(reply, "OK");
+                }
+            }
+            b'c' | b's' => {
+                self.resume(command == b's', arguments)?;
+                return Ok(Action::Resume);
+            }
+            b'D' => {
+                remove_all_breakpoints();
+                let _ = write!(reply, "OK");
+                return Ok(Action::Detach);
+            }
+            b'k' => {
+                remove_all_breakpoints();
+                return Ok(Action::Detach);
+            }
+            b'H' => {
+                let (&operation, thread) = arguments.split_first().ok_or(Error::Invalid)?;
+                let lp_id = parse_thread_id(thread).ok_or(Error::Invalid)?.unwrap_or(self.lp_id);
+                if operation == b'g' {
+                    self.register_lp = lp_id;
+                } else {
+                    self.resume_lp = lp_id;
+                }
+                let _ = write!(reply, "OK");
+            }
+            b'T' => {
+                parse_thread_id(arguments).ok_or(Error::Invalid)?;
+                let _ = write!(reply, "OK");
+            }
+            b'q' => self.query(arguments, reply)?,
+            _ => {}
+        }
+        Ok(Action::Reply)
+    }
+
+    fn query(&mut self, query: &[u8], reply: &mut Packet) -> Result<(), Error> {
+        if query.starts_with(b"Supported") {
+            let _ = write!(reply, "PacketSize={:x}", MAX_PACKET_SIZE);
+        } else if query == b"Attached" {
+            // Detaching leaves the kernel running rather than killing it.
+            let _ = write!(reply, "1");
+        } else if query == b"C" {
+            let _ = write!(reply, "QC{:x}", thread_id(self.lp_id));
+        } else if query == b"fThreadInfo" {
+            let _ = write!(reply, "m");
+            let mut separator = "";
+            for lp_id in (0..MAX_LP_COUNT as LpId).filter(|lp_id| is_online(*lp_id)) {
+                let _ = write!(reply, "{}{:x}", separator, thread_id(lp_id));
+                separator = ",";
+            }
+        } else if query == b"sThreadInfo" {
+            let _ = write!(reply, "l");
+        } else if let Some(thread) = query.strip_prefix(b"ThreadExtraInfo,") {
+            let lp_id = parse_thread_id(thread).ok_or(Error::Invalid)?.unwrap_or(self.lp_id);
+            let mut info = Packet::new();
+            let state = if lp_id == self.lp_id {
+                "stopped"
+            } else if PARKED_LPS.contains(lp_id) {
+                "parked"
+            } else {
+                "did not stop"
+            };
+            let _ = write!(info, "LP{} {}", lp_id, state);
+            // The LP may have been stopped while holding its scheduler's lock.
+            let thread = GLOBAL_SCHEDULER
+                .try_get_lp_scheduler(lp_id)
+                .and_then(|scheduler| scheduler.try_lock()?.current_thread());
+            if let Some(thread) = thread {
+                let _ = write!(info, ", running thread {:?}", thread);
+            }
+            reply.push_hex(info.as_bytes());
+        }
+        Ok(())
+    }
+
+    fn register_frame(&mut self) -> Result<&mut ExceptionFrame, Error> {
+        let lp_id = self.register_lp;
+        self.frame_of(lp_id).ok_or(Error::Invalid)
+    }
+
+    fn read_registers(&mut self, reply: &mut Packet) -> Result<(), Error> {
+        let frame = self.register_frame()?;
+        for number in 0..REGISTER_COUNT {
+            let value = registers::read(frame, number).ok_or(Error::Invalid)?;
+            reply.push_hex(&value.to_le_bytes()[..registers::size_of(number)]);
+        }
+        Ok(())
+    }
+
+    fn write_registers(&mut self, mut hex: &[u8]) -> Result<(), Error> {
+        let frame = self.register_frame()?;
+        let mut updated = *frame;
+        for number in 0..REGISTER_COUNT {
+            let size = registers::size_of(number);
+            let Some((value, rest)) = hex.split_at_checked(size * 2) else {
+                break;
+            };
+            let mut bytes = [0; 8];
+            decode_hex(value, &mut bytes[..size]).ok_or(Error::Invalid)?;
+            // The segment registers cannot be written and are sent back unchanged.
+            registers::write(&mut updated, number, u64::from_le_bytes(bytes));
+            hex = rest;
+        }
+        *frame = updated;
+        Ok(())
+    }
+
+    fn read_register(&mut self, number: &[u8], reply: &mut Packet) -> Result<(), Error> {
+        let number = parse_hex(number).ok_or(Error::Invalid)? as usize;
+        let value = registers::read(self.register_frame()?, number).ok_or(Error::Invalid)?;
+        reply.push_hex(&value.to_le_bytes()[..registers::size_of(number)]);
+        Ok(())
+    }
+
+    fn write_register(&mut self, arguments: &[u8]) -> Result<(), Error> {
+        let (number, value) = split(arguments, b'=').ok_or(Error::Invalid)?;
+        let number = parse_hex(number).ok_or(Error::Invalid)? as usize;
+        let mut bytes = [0; 8];
+        if number >= REGISTER_COUNT
+            || decode_hex(value, &mut bytes) != Some(registers::size_of(number))
+        {
+            return Err(Error::Invalid);
+        }
+        let frame = self.register_frame()?;
+        if registers::write(frame, number, u64::from_le_bytes(bytes)) {
+            Ok(())
+        } else {
+            Err(Error::Invalid)
+        }
+    }
+
+    /// Prepares the LP selected for resuming to continue at `address`, if given, and to stop again
+    /// after one instruction if `step` is set
+    fn resume(&mut self, step: bool, address: &[u8]) -> Result<(), Error> {
+        let lp_id = self.resume_lp;
+        let address = match address {
+            [] => None,
+            _ => Some(parse_hex(address).ok_or(Error::Invalid)?),
+        };
+        let frame = self.frame_of(lp_id).ok_or(Error::Invalid)?;
+        if let Some(address) = address {
+            frame.interrupt_frame.rip = address;
+        }
+        if step {
+            frame.interrupt_frame.rflags |= RFLAGS_TF;
+            SINGLE_STEPPING.get_for(lp_id).ok_or(Error::Invalid)?.store(true, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+//! # Remote Serial Protocol Packets
+//!
+//! A packet is sent as `$data#cc`, where `cc` is the sum of the data bytes modulo 256 in hex. The
+//! receiver acknowledges every packet with `+`, or with `-` to have it sent again if the checksum
+//! does not match. Packets are built in fixed size buffers since the stub runs in exception context
+//! where the heap may be unusable.
+
+use core::fmt::{self, Write};
+
+use crate::debug::watchdog;
+use crate::drivers::uart::ns16550::Uart16550;
+
+/// The largest packet the stub sends or accepts, without the framing
+pub const MAX_PACKET_SIZE: usize = 1024;
+
+const INTERRUPT: u8 = 0x03;
+
+pub struct Packet {
+    bytes:  [u8; MAX_PACKET_SIZE],
+    length: usize,
+}
+
+impl Packet {
+    pub const fn new() -> Self {
+        Packet {
+            bytes:  [0; MAX_PACKET_SIZE],
+            length: 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.length]
+    }
+
+    pub fn clear(&mut self) {
+        self.length = 0;
+    }
+
+    pub fn remaining(&self) -> usize {
+        MAX_PACKET_SIZE - self.length
+    }
+
+    /// Appends bytes, dropping whatever does not fit
+    pub fn push(&mut self, bytes: &[u8]) {
+        let length = bytes.len().min(self.remaining());
+        self.bytes[self.length..self.length + length].copy_from_slice(&bytes[..length]);
+        self.length += length;
+    }
+
+    /// Appends bytes as pairs of hex digits
+    pub fn push_hex(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.push(&[hex_digit(byte >> 4), hex_digit(byte & 0xf)]);
+        }
+    }
+}
+
+impl Default for Packet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Write for Packet {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+fn hex_digit(value: u8) -> u8 {
+    b"0123456789abcdef"[value as usize]
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+/// Decodes pairs of hex digits into `output` and returns the number of bytes decoded
+pub fn decode_hex(hex: &[u8], output: &mut [u8]) -> Option<usize> {
+    if hex.len() % 2 != 0 || hex.len() / 2 > output.len() {
+        return None;
+    }
+    for ([high, low], byte) in hex.as_chunks::<2>().0.iter().zip(output.iter_mut()) {
+        *byte = hex_value(*high)? << 4 | hex_value(*low)?;
+    }
+    Some(hex.len() / 2)
+}
+
+/// Parses a hex number, which GDB sends most significant digit first
+pub fn parse_hex(hex: &[u8]) -> Option<u64> {
+    if hex.is_empty() || hex.len() > 16 {
+        return None;
+    }
+    hex.iter().try_fold(0, |value, digit| Some(value << 4 | hex_value(*digit)? as u64))
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn read_byte(port: &mut Uart16550) -> u8 {
+    loop {
+        if let Some(byte) = port.try_read_byte() {
+            return byte;
+        }
+        // The stub waits for the debugger with interrupts masked.
+        watchdog::touch();
+        core::hint::spin_loop();
+    }
+}
+
+/// Receives the next packet into `packet`, acknowledging it once its checksum matches. Interrupt
+/// requests and anything outside of a packet are ignored.
+pub fn receive(port: &mut Uart16550, packet: &mut Packet) {
+    'packet: loop {
+        while read_byte(port) != b'$' {}
+        packet.clear();
+        loop {
+            match read_byte(port) {
+                b'#' => break,
+                b'$' => continue 'packet,
+                INTERRUPT => {}
+                _ if packet.remaining() == 0 => {
+                    port.write_byte(b'-');
+                    continue 'packet;
+                }
+                byte => packet.push(&[byte]),
+            }
+        }
+        let high = hex_value(read_byte(port));
+        let low = hex_value(read_byte(port));
+        match (high, low) {
+            (Some(high), Some(low)) if high << 4 | low == checksum(packet.as_bytes()) => {
+                port.write_byte(b'+');
+                return;
+            }
+            _ => port.write_byte(b'-'),
+        }
+    }
+}
+
+/// Sends a packet and waits for the debugger to acknowledge it
+pub fn send(port: &mut Uart16550, packet: &Packet) {
+    let data = packet.as_bytes();
+    let checksum = checksum(data);
+    loop {
+        port.write_byte(b'$');
+        data.iter().for_each(|byte| port.write_byte(*byte));
+        port.write_byte(b'#');
+        port.write_byte(hex_digit(checksum >> 4));
+        port.write_byte(hex_digit(checksum & 0xf));
+        loop {
+            match read_byte(port) {
+                b'+' => return,
+                b'-' => break,
+                _ => {}
+            }
+        }
+    }
+}
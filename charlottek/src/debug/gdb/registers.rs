@@ -0,0 +1,75 @@
+//! # Register Layout
+//!
+//! Without a target description GDB expects the x86_64 registers in a fixed order: the sixteen
+//! general purpose registers and RIP as 64 bit values, followed by RFLAGS and the six segment
+//! registers as 32 bit values. The floating point and vector registers that would follow are not
+//! sent, which GDB treats as them being unavailable.
+//!
+//! Registers are read from and written to the exception frame of the stopped LP, so writes take
+//! effect when it resumes. The exception entry stubs do not save the data segment registers, and
+//! the code and stack segments cannot be changed, so writes to segment registers are rejected.
+
+use crate::isa::interrupts::exceptions::frame::ExceptionFrame;
+
+/// The number of registers the stub sends
+pub const REGISTER_COUNT: usize = 24;
+pub const RIP: usize = 16;
+pub const RFLAGS: usize = 17;
+const CS: usize = 18;
+const SS: usize = 19;
+
+/// The size of a register in bytes
+pub fn size_of(number: usize) -> usize {
+    if number <= RIP {
+        8
+    } else {
+        4
+    }
+}
+
+fn gpr(frame: &mut ExceptionFrame, number: usize) -> Option<&mut u64> {
+    let gprs = &mut frame.gprs;
+    Some(match number {
+        0 => &mut gprs.rax,
+        1 => &mut gprs.rbx,
+        2 => &mut gprs.rcx,
+        3 => &mut gprs.rdx,
+        4 => &mut gprs.rsi,
+        5 => &mut gprs.rdi,
+        6 => &mut gprs.rbp,
+        7 => &mut frame.interrupt_frame.rsp,
+        8 => &mut gprs.r8,
+        9 => &mut gprs.r9,
+        10 => &mut gprs.r10,
+        11 => &mut gprs.r11,
+        12 => &mut gprs.r12,
+        13 => &mut gprs.r13,
+        14 => &mut gprs.r14,
+        15 => &mut gprs.r15,
+        RIP => &mut frame.interrupt_frame.rip,
+        RFLAGS => &mut frame.interrupt_frame.rflags,
+        _ => return None,
+    })
+}
+
+/// Returns the value of a register, or `None` if there is no such register
+pub fn read(frame: &ExceptionFrame, number: usize) -> Option<u64> {
+    let mut frame = *frame;
+    match number {
+        CS => Some(frame.interrupt_frame.cs),
+        SS => Some(frame.interrupt_frame.ss),
+        _ if number < REGISTER_COUNT => Some(gpr(&mut frame, number).map_or(0, |value| *value)),
+        _ => None,
+    }
+}
+
+/// Sets the value of a register. Returns false if the register cannot be written.
+pub fn write(frame: &mut ExceptionFrame, number: usize, value: u64) -> bool {
+    match gpr(frame, number) {
+        Some(register) => {
+            *register = value;
+            true
+        }
+        None => false,
+    }
+}
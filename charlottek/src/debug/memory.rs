@@ -0,0 +1,73 @@
+//! # Debugger Memory Access
+//!
+//! The debugging tools read and write memory at addresses typed in by a person, so every access is
+//! checked against the current address space first instead of risking a page fault in a context
+//! that may not survive one. Writes go through even if the page is mapped read-only, so that
+//! breakpoints can be placed in the kernel's code.
+
+use core::arch::asm;
+
+use crate::isa::interface::memory::{AddressSpaceInterface, MemoryInterface};
+use crate::isa::lp::ops::{
+    interrupts_enabled,
+    mask_interrupts,
+    unmask_interrupts,
+    without_write_protection,
+};
+use crate::isa::memory::MemoryInterfaceImpl;
+use crate::memory::{AddressSpace, VAddr};
+
+const PAGE_SIZE: usize = MemoryInterfaceImpl::PAGE_SIZE;
+
+fn is_mapped(address_space: &mut AddressSpace, page: usize) -> bool {
+    let vaddr = VAddr::from(page);
+    // Non-canonical addresses are not rejected by the conversion but sign extended.
+    <VAddr as Into<usize>>::into(vaddr) == page && address_space.is_mapped(vaddr).unwrap_or(false)
+}
+
+/// Reads as much of `buffer` as is mapped from `address` on and returns the number of bytes read
+pub fn read(address: usize, buffer: &mut [u8]) -> usize {
+    let mut address_space = AddressSpace::get_current();
+    let mut mapped_page = None;
+    for (i, byte) in buffer.iter_mut().enumerate() {
+        let Some(address) = address.checked_add(i) else {
+            return i;
+        };
+        let page = address & !(PAGE_SIZE - 1);
+        if mapped_page != Some(page) {
+            if !is_mapped(&mut address_space, page) {
+                return i;
+            }
+            mapped_page = Some(page);
+        }
+        *byte = unsafe { (address as *const u8).read_volatile() };
+    }
+    buffer.len()
+}
+
+/// Writes `data` to `address` if all of it is mapped. Returns false without writing anything
+/// otherwise.
+pub fn write(address: usize, data: &[u8]) -> bool {
+    let Some(end) = address.checked_add(data.len()) else {
+        return false;
+    };
+    let mut address_space = AddressSpace::get_current();
+    let first_page = address & !(PAGE_SIZE - 1);
+    if !(first_page..end).step_by(PAGE_SIZE).all(|page| is_mapped(&mut address_space, page)) {
+        return false;
+    }
+    // Write protection must not stay off for whatever an interrupt handler would run.
+    let interrupts_were_enabled = interrupts_enabled();
+    mask_interrupts!();
+    unsafe {
+        without_write_protection(|| {
+            for (i, byte) in data.iter().enumerate() {
+                ((address + i) as *mut u8).write_volatile(*byte);
+            }
+        })
+    };
+    if interrupts_were_enabled {
+        unmask_interrupts!();
+    }
+    true
+}
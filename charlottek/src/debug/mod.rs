@@ -0,0 +1,13 @@
+//! # Kernel Debugging Facilities
+//!
+//! Tools for finding out what the kernel was doing when something went wrong, such as the
+//! backtraces printed on a panic, for noticing when it has stopped doing anything at all, and for
+//! inspecting it with GDB or the built-in monitor.
+
+pub mod backtrace;
+pub mod crash;
+pub mod gdb;
+pub mod memory;
+pub mod monitor;
+pub mod symbols;
+pub mod watchdog;
@@ -0,0 +1,609 @@
+//! # Monitor Commands
+//!
+//! Every command only reads the state it shows, apart from `poke`, and uses `try_lock` on any lock
+//! it needs, since the monitor may run while the holder of a lock is stopped or halted.
+
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::str::SplitWhitespace;
+
+use limine::memory_map::EntryType;
+
+use super::Terminal;
+use crate::cpu::multiprocessor::{MAX_LP_COUNT, is_lp_online};
+use crate::cpu::scheduler::{GLOBAL_SCHEDULER, TrackedThread};
+use crate::debug::memory;
+use crate::debug::symbols::Symbolized;
+use crate::environment::boot_protocol::limine::MEMORY_MAP_REQUEST;
+use crate::environment::cmdline;
+use crate::isa::init::gdt::current_gdt;
+use crate::isa::interrupts::idt::current_idt;
+use crate::isa::interrupts::x2apic::lapic_id_of;
+use crate::isa::lp::LpId;
+use crate::isa::lp::ops::{get_lp_id, try_read_msr};
+use crate::isa::memory::paging::pte::PageTableEntry;
+use crate::isa::memory::paging::pth_walker::PthWalker;
+use crate::log::sink::MEMORY_SINK;
+use crate::memory::{AddressSpace, AddressSpaceInterface, PHYSICAL_FRAME_ALLOCATOR, VAddr};
+
+const PAGE_SIZE: usize = 4096;
+const DEFAULT_PEEK_LENGTH: usize = 64;
+const MAX_PEEK_LENGTH: usize = 4096;
+const BYTES_PER_ROW: usize = 16;
+const DEFAULT_LOG_LINES: usize = 20;
+
+pub(super) struct Session {
+    pub(super) terminal:  Terminal,
+    /// Whether the kernel can be resumed when the monitor is left
+    pub(super) resumable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Flow {
+    Continue,
+    Exit,
+}
+
+enum Error {
+    Usage,
+    Failed(&'static str),
+}
+
+type Arguments<'a> = SplitWhitespace<'a>;
+
+struct Command {
+    name: &'static str,
+    arguments: &'static str,
+    summary: &'static str,
+    run: fn(&mut Session, &mut Arguments) -> Result<Flow, Error>,
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        arguments: "",
+        summary: "list the commands",
+        run: help,
+    },
+    Command {
+        name: "exit",
+        arguments: "",
+        summary: "leave the monitor and resume the kernel",
+        run: exit,
+    },
+    Command {
+        name: "mmap",
+        arguments: "",
+        summary: "show the memory map the bootloader passed",
+        run: memory_map,
+    },
+    Command {
+        name: "frames",
+        arguments: "",
+        summary: "show how many physical frames are free",
+        run: frames,
+    },
+    Command {
+        name: "walk",
+        arguments: "<address>",
+        summary: "walk the page tables for a virtual address",
+        run: walk,
+    },
+    Command {
+        name: "lps",
+        arguments: "",
+        summary: "list the LPs and what they are running",
+        run: lps,
+    },
+    Command {
+        name: "threads",
+        arguments: "",
+        summary: "list the threads the schedulers know of",
+        run: threads,
+    },
+    Command {
+        name: "peek",
+        arguments: "<address> [length]",
+        summary: "dump memory",
+        run: peek,
+    },
+    Command {
+        name: "poke",
+        arguments: "<address> <value> [size]",
+        summary: "write a 1, 2, 4 or 8 byte value to memory",
+        run: poke,
+    },
+    Command {
+        name: "rdmsr",
+        arguments: "<msr>",
+        summary: "read a model specific register of this LP",
+        run: rdmsr,
+    },
+    Command {
+        name: "gdt",
+        arguments: "",
+        summary: "show the GDT of this LP",
+        run: gdt,
+    },
+    Command {
+        name: "idt",
+        arguments: "",
+        summary: "show the present gates of the IDT of this LP",
+        run: idt,
+    },
+    Command {
+        name: "log",
+        arguments: "[lines]",
+        summary: "show the most recent lines of the log",
+        run: log,
+    },
+    Command {
+        name: "params",
+        arguments: "",
+        summary: "show the kernel command line and parameters",
+        run: params,
+    },
+];
+
+/// Runs a command line and returns whether to stay in the monitor
+pub(super) fn run(session: &mut Session, line: &str) -> Flow {
+    let mut arguments = line.split_whitespace();
+    let Some(name) = arguments.next() else {
+        return Flow::Continue;
+    };
+    let Some(command) = COMMANDS.iter().find(|command| command.name == name) else {
+        let _ = writeln!(session.terminal, "Unknown command \"{}\", try \"help\".", name);
+        return Flow::Continue;
+    };
+    let result = (command.run)(session, &mut arguments).and_then(|flow| match arguments.next() {
+        Some(_) => Err(Error::Usage),
+        None => Ok(flow),
+    });
+    match result {
+        Ok(flow) => flow,
+        Err(Error::Usage) => {
+            let _ = writeln!(session.terminal, "usage: {} {}", command.name, command.arguments);
+            Flow::Continue
+        }
+        Err(Error::Failed(message)) => {
+            let _ = writeln!(session.terminal, "{}: {}", command.name, message);
+            Flow::Continue
+        }
+    }
+}
+
+/// Parses a number, which is hex if it starts with 0x and decimal otherwise
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn number(arguments: &mut Arguments) -> Result<u64, Error> {
+    arguments.next().and_then(parse_number).ok_or(Error::Usage)
+}
+
+fn optional_number(arguments: &mut Arguments, default: u64) -> Result<u64, Error> {
+    match arguments.next() {
+        Some(text) => parse_number(text).ok_or(Error::Usage),
+        None => Ok(default),
+    }
+}
+
+/// Formats a size in bytes with a binary unit
+struct Size(u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+        let mut value = self.0;
+        let mut unit = 0;
+        while value >= 1024 && value % 1024 == 0 && unit < UNITS.len() - 1 {
+            value /= 1024;
+            unit += 1;
+        }
+        write!(f, "{} {}", value, UNITS[unit])
+    }
+}
+
+fn help(session: &mut Session, _: &mut Arguments) -> Result<Flow, Error> {
+    let terminal = &mut session.terminal;
+    for command in COMMANDS {
+        let _ = writeln!(
+            terminal,
+            "  {:<7} {:<25} {}",
+            command.name, command.arguments, command.summary
+        );
+    }
+    let _ = writeln!(terminal, "Numbers are decimal unless they start with 0x.");
+    Ok(Flow::Continue)
+}
+
+fn exit(session: &mut Session, _: &mut Arguments) -> Result<Flow, Error> {
+    if !session.resumable {
+        return Err(Error::Failed("the kernel cannot be resumed after a crash"));
+    }
+    Ok(Flow::Exit)
+}
+
+const ENTRY_TYPE_NAMES: [(EntryType, &str); 8] = [
+    (EntryType::USABLE, "usable"),
+    (EntryType::RESERVED, "reserved"),
+    (EntryType::ACPI_RECLAIMABLE, "ACPI reclaimable"),
+    (EntryType::ACPI_NVS, "ACPI NVS"),
+    (EntryType::BAD_MEMORY, "bad memory"),
+    (EntryType::BOOTLOADER_RECLAIMABLE, "bootloader reclaimable"),
+    (EntryType::EXECUTABLE_AND_MODULES, "kernel and modules"),
+    (EntryType::FRAMEBUFFER, "framebuffer"),
+];
+
+fn memory_map(session: &mut Session, _: &mut Arguments) -> Result<Flow, Error> {
+    let mmap = MEMORY_MAP_REQUEST.get_response().ok_or(Error::Failed("there is no memory map"))?;
+    let terminal = &mut session.terminal;
+    let mut usable = 0;
+    for entry in mmap.entries() {
+        let name = ENTRY_TYPE_NAMES
+            .iter()
+            .find(|(entry_type, _)| *entry_type == entry.entry_type)
+            .map_or("unknown", |(_, name)| name);
+        let _ = writeln!(
+            terminal,
+            "{:#018x}-{:#018x} {:>10} {}",
+            entry.base,
+            entry.base + entry.length - 1,
+            (Size(entry.length)),
+            name
+        );
+        if entry.entry_type == EntryType::USABLE {
+            usable += entry.length;
+        }
+    }
+    let _ = writeln!(terminal, "{} usable", (Size(usable)));
+    Ok(Flow::Continue)
+}
+
+fn frames(session: &mut Session, _: &mut Arguments) -> Result<Flow, Error> {
+    let stats = PHYSICAL_FRAME_ALLOCATOR
+        .try_lock()
+        .ok_or(Error::Failed("the frame allocator is locked"))?
+        .stats();
+    let _ = writeln!(
+        session.terminal,
+        "{} frames tracked, {} free ({}), {} allocated or unusable",
+        stats.total,
+        stats.free,
+        (Size((stats.free * PAGE_SIZE) as u64)),
+        (stats.total - stats.free)
+    );
+    Ok(Flow::Continue)
+}
+
+/// Formats the flags of a page table entry
+struct EntryFlags<'a>(&'a PageTableEntry);
+
+impl fmt::Display for EntryFlags<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entry = self.0;
+        let flags = [
+            (entry.is_present(), "P"),
+            (entry.is_writable(), "W"),
+            (entry.is_user_accessible(), "U"),
+            (entry.is_accessed(), "A"),
+            (entry.is_dirty(), "D"),
+            (entry.get_page_size(), "PS"),
+            (entry.is_global(), "G"),
+            (entry.is_execute_disabled(), "NX"),
+        ];
+        for (_, name) in flags.iter().filter(|(is_set, _)| *is_set) {
+            write!(f, " {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+fn walk(session: &mut Session, arguments: &mut Arguments) -> Result<Flow, Error> {
+    const LEVELS: [(&str, u64); 4] =
+        [("PML4E", 0), ("PDPTE", 1 << 30), ("PDE", 1 << 21), ("PTE", 1 << 12)];
+    let address = number(arguments)?;
+    let vaddr = VAddr::from(address as usize);
+    if <VAddr as Into<usize>>::into(vaddr) as u64 != address {
+        return Err(Error::Failed("the address is not canonical"));
+    }
+    let mut address_space = AddressSpace::get_current();
+    let terminal = &mut session.terminal;
+    let _ = writeln!(terminal, "CR3 {:#018x}", (address_space.get_cr3()));
+    let walker = PthWalker::new(&mut address_space, vaddr);
+    let entries = walker.translation_entries();
+    for (level, entry) in entries.iter().enumerate() {
+        let Some(entry) = entry else {
+            break;
+        };
+        let (name, page_size) = LEVELS[level];
+        let _ = writeln!(terminal, "{:<5} {:#018x}{}", name, (entry.bits()), (EntryFlags(entry)));
+        if !entry.is_present() {
+            let _ = writeln!(terminal, "{:#x} is not mapped", address);
+            return Ok(Flow::Continue);
+        }
+        let maps_page = level == LEVELS.len() - 1 || (level != 0 && entry.get_page_size());
+        if maps_page {
+            let frame = entry.try_get_frame().map_err(|_| Error::Failed("invalid frame"))?;
+            let frame = <crate::memory::PAddr as Into<usize>>::into(frame) as u64;
+            let paddr = (frame & !(page_size - 1)) | (address & (page_size - 1));
+            let _ = writeln!(
+                terminal,
+                "{:#x} maps to {:#x} in a {} page",
+                address,
+                paddr,
+                (Size(page_size))
+            );
+            return Ok(Flow::Continue);
+        }
+    }
+    Ok(Flow::Continue)
+}
+
+/// Describes what the scheduler of an LP is running
+fn lp_activity(lp_id: LpId) -> &'static str {
+    match GLOBAL_SCHEDULER.try_get_lp_scheduler(lp_id) {
+        None => "no scheduler",
+        Some(scheduler) => match scheduler.try_lock() {
+            None => "scheduler locked",
+            Some(scheduler) if scheduler.current_thread().is_some() => "running a thread",
+            Some(scheduler) if scheduler.is_idle() => "idle",
+            Some(_) => "outside of any thread",
+        },
+    }
+}
+
+fn current_thread(lp_id: LpId) -> Option<usize> {
+    GLOBAL_SCHEDULER.try_get_lp_scheduler(lp_id)?.try_lock()?.current_thread()
+}
+
+fn lps(session: &mut Session, _: &mut Arguments) -> Result<Flow, Error> {
+    let current_lp = get_lp_id!();
+    let terminal = &mut session.terminal;
+    for lp_id in 0..MAX_LP_COUNT as LpId {
+        let Some(lapic_id) = lapic_id_of(lp_id) else {
+            continue;
+        };
+        let _ = write!(
+            terminal,
+            "{}LP{:<3} APIC {:<4} {:<8} {}",
+            (if lp_id == current_lp {
+                '*'
+            } else {
+                ' '
+            }),
+            lp_id,
+            (lapic_id.physical),
+            (if is_lp_online(lp_id) {
+                "online"
+            } else {
+                "offline"
+            }),
+            (lp_activity(lp_id))
+        );
+        match current_thread(lp_id) {
+            Some(tid) => {
+                let _ = writeln!(terminal, " {}", tid);
+            }
+            None => {
+                let _ = writeln!(terminal);
+            }
+        }
+    }
+    Ok(Flow::Continue)
+}
+
+fn threads(session: &mut Session, _: &mut Arguments) -> Result<Flow, Error> {
+    let terminal = &mut session.terminal;
+    for lp_id in 0..MAX_LP_COUNT as LpId {
+        if let Some(tid) = current_thread(lp_id) {
+            let _ = writeln!(terminal, "thread {:<6} running on LP{}", tid, lp_id);
+        }
+    }
+    let tracked = GLOBAL_SCHEDULER
+        .try_list_tracked_threads()
+        .ok_or(Error::Failed("the global scheduler is locked"))?;
+    for (tid, state) in tracked {
+        let _ = match state {
+            TrackedThread::Blocked(lp_id) => {
+                writeln!(terminal, "thread {:<6} blocked on LP{}", tid, lp_id)
+            }
+            TrackedThread::ReadyUnassigned => {
+                writeln!(terminal, "thread {:<6} ready, not assigned to an LP", tid)
+            }
+            TrackedThread::Parked(lp_id) => {
+                writeln!(terminal, "thread {:<6} parked until LP{} is online", tid, lp_id)
+            }
+        };
+    }
+    Ok(Flow::Continue)
+}
+
+fn peek(session: &mut Session, arguments: &mut Arguments) -> Result<Flow, Error> {
+    let address = number(arguments)? as usize;
+    let length =
+        (optional_number(arguments, DEFAULT_PEEK_LENGTH as u64)? as usize).min(MAX_PEEK_LENGTH);
+    let terminal = &mut session.terminal;
+    let mut row = [0; BYTES_PER_ROW];
+    for offset in (0..length).step_by(BYTES_PER_ROW) {
+        let row_address = address.wrapping_add(offset);
+        let row_length = BYTES_PER_ROW.min(length - offset);
+        let read = memory::read(row_address, &mut row[..row_length]);
+        if read == 0 {
+            let _ = writeln!(terminal, "{:#018x}: not mapped", row_address);
+            break;
+        }
+        let _ = write!(terminal, "{:#018x}:", row_address);
+        for byte in &row[..read] {
+            let _ = write!(terminal, " {:02x}", byte);
+        }
+        for _ in read..BYTES_PER_ROW {
+            let _ = write!(terminal, "   ");
+        }
+        let _ = write!(terminal, "  ");
+        for &byte in &row[..read] {
+            let shown = if byte.is_ascii_graphic() {
+                byte as char
+            } else {
+                '.'
+            };
+            let _ = terminal.write_char(shown);
+        }
+        let _ = writeln!(terminal);
+        if read < row_length {
+            let _ = writeln!(terminal, "{:#018x}: not mapped", row_address + read);
+            break;
+        }
+    }
+    Ok(Flow::Continue)
+}
+
+fn poke(session: &mut Session, arguments: &mut Arguments) -> Result<Flow, Error> {
+    let address = number(arguments)? as usize;
+    let value = number(arguments)?;
+    let size = optional_number(arguments, 8)? as usize;
+    if !matches!(size, 1 | 2 | 4 | 8) {
+        return Err(Error::Usage);
+    }
+    if size < 8 && value >> (size * 8) != 0 {
+        return Err(Error::Failed("the value does not fit"));
+    }
+    if !memory::write(address, &value.to_le_bytes()[..size]) {
+        return Err(Error::Failed("the memory is not mapped"));
+    }
+    let _ = writeln!(session.terminal, "Wrote {:#x} to {:#x}.", value, address);
+    Ok(Flow::Continue)
+}
+
+fn rdmsr(session: &mut Session, arguments: &mut Arguments) -> Result<Flow, Error> {
+    let msr = u32::try_from(number(arguments)?).map_err(|_| Error::Usage)?;
+    let value = try_read_msr(msr).ok_or(Error::Failed("the MSR is not implemented"))?;
+    let _ = writeln!(session.terminal, "MSR {:#x} = {:#018x}", msr, value);
+    Ok(Flow::Continue)
+}
+
+/// Reads a descriptor table entry, which the monitor only does through the checked memory access
+/// since the table register may point anywhere
+fn read_descriptor<const N: usize>(address: usize) -> Result<[u8; N], Error> {
+    let mut bytes = [0; N];
+    if memory::read(address, &mut bytes) != N {
+        return Err(Error::Failed("the table is not mapped"));
+    }
+    Ok(bytes)
+}
+
+fn gdt(session: &mut Session, _: &mut Arguments) -> Result<Flow, Error> {
+    const DESCRIPTOR_SIZE: usize = 8;
+    const ACCESS_PRESENT: u8 = 1 << 7;
+    const ACCESS_NOT_SYSTEM: u8 = 1 << 4;
+    const ACCESS_EXECUTABLE: u8 = 1 << 3;
+    const FLAGS_LONG_MODE: u8 = 1 << 1;
+    let (base, size) = current_gdt();
+    let terminal = &mut session.terminal;
+    let _ = writeln!(terminal, "GDT at {:#018x}, {} bytes", base, size);
+    let mut offset = 0;
+    while offset + DESCRIPTOR_SIZE <= size {
+        let bytes: [u8; DESCRIPTOR_SIZE] = read_descriptor(base + offset)?;
+        let raw = u64::from_le_bytes(bytes);
+        let access = bytes[5];
+        let flags = bytes[6] >> 4;
+        let mut base_address = (raw >> 16 & 0xff_ffff | (raw >> 56) << 24) as usize;
+        let limit = (raw & 0xffff | (raw >> 48 & 0xf) << 16) as usize;
+        let is_system = access & ACCESS_PRESENT != 0 && access & ACCESS_NOT_SYSTEM == 0;
+        // System descriptors such as the TSS take up two slots in long mode.
+        let mut length = DESCRIPTOR_SIZE;
+        if is_system && offset + 2 * DESCRIPTOR_SIZE <= size {
+            let high: [u8; DESCRIPTOR_SIZE] = read_descriptor(base + offset + DESCRIPTOR_SIZE)?;
+            base_address |= (u32::from_le_bytes(high[..4].try_into().unwrap()) as usize) << 32;
+            length = 2 * DESCRIPTOR_SIZE;
+        }
+        let kind = match () {
+            _ if access & ACCESS_PRESENT == 0 => "not present",
+            _ if is_system => "system (TSS)",
+            _ if access & ACCESS_EXECUTABLE != 0 && flags & FLAGS_LONG_MODE != 0 => "64 bit code",
+            _ if access & ACCESS_EXECUTABLE != 0 => "code",
+            _ => "data",
+        };
+        let _ = writeln!(
+            terminal,
+            "{:#06x} {:#018x} base {:#x} limit {:#x} access {:#04x} flags {:#x} DPL{} {}",
+            offset,
+            raw,
+            base_address,
+            limit,
+            access,
+            flags,
+            (access >> 5 & 0b11),
+            kind
+        );
+        offset += length;
+    }
+    Ok(Flow::Continue)
+}
+
+fn idt(session: &mut Session, _: &mut Arguments) -> Result<Flow, Error> {
+    const GATE_SIZE: usize = 16;
+    const GATE_PRESENT: u8 = 1 << 7;
+    const TRAP_GATE: u8 = 0xf;
+    let (base, size) = current_idt();
+    let terminal = &mut session.terminal;
+    let _ = writeln!(terminal, "IDT at {:#018x}, {} bytes", base, size);
+    for vector in 0..size / GATE_SIZE {
+        let bytes: [u8; GATE_SIZE] = read_descriptor(base + vector * GATE_SIZE)?;
+        let flags = bytes[5];
+        if flags & GATE_PRESENT == 0 {
+            continue;
+        }
+        let handler = u16::from_le_bytes([bytes[0], bytes[1]]) as usize
+            | (u16::from_le_bytes([bytes[6], bytes[7]]) as usize) << 16
+            | (u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize) << 32;
+        let selector = u16::from_le_bytes([bytes[2], bytes[3]]);
+        let _ = writeln!(
+            terminal,
+            "{:>3} {:#018x} {:#06x} {} DPL{} IST{} {}",
+            vector,
+            handler,
+            selector,
+            (if flags & 0xf == TRAP_GATE {
+                "trap"
+            } else {
+                "intr"
+            }),
+            (flags >> 5 & 0b11),
+            (bytes[4] & 0b111),
+            (Symbolized(handler))
+        );
+    }
+    Ok(Flow::Continue)
+}
+
+fn log(session: &mut Session, arguments: &mut Arguments) -> Result<Flow, Error> {
+    let lines = optional_number(arguments, DEFAULT_LOG_LINES as u64)? as usize;
+    let mut text = Vec::new();
+    MEMORY_SINK.read(|piece| text.extend_from_slice(piece));
+    let text = alloc::string::String::from_utf8_lossy(&text);
+    let all_lines: Vec<_> = text.lines().collect();
+    for line in &all_lines[all_lines.len().saturating_sub(lines)..] {
+        let _ = writeln!(session.terminal, "{}", line);
+    }
+    Ok(Flow::Continue)
+}
+
+fn params(session: &mut Session, _: &mut Arguments) -> Result<Flow, Error> {
+    let terminal = &mut session.terminal;
+    let _ = writeln!(terminal, "Command line: \"{}\"", (cmdline::cmdline()));
+    let mut params: Vec<_> = cmdline::params().to_vec();
+    params.sort_unstable_by_key(|param| param.name());
+    for param in params {
+        let origin = if param.is_set() {
+            ""
+        } else {
+            " (default)"
+        };
+        let _ = writeln!(terminal, "  {:<15} {}{}", (param.name()), (param.value()), origin);
+    }
+    Ok(Flow::Continue)
+}
@@ -0,0 +1,265 @@
+//! # Kernel Monitor
+//!
+//! An interactive shell for inspecting a kernel that has crashed or stopped making progress. It
+//! reads commands from the log's serial port and writes its output both there and to the
+//! framebuffer console. The monitor is entered:
+//!
+//! - after a crash report, unless `monitor.panic=off` is given on the kernel command line or
+//!   [`set_enter_on_panic`] has turned it off, with every other LP already stopped. The kernel
+//!   cannot be resumed from there.
+//! - when the magic key, Ctrl-], arrives on the serial port. The bootstrap processor polls for it
+//!   from a timer, which queues the monitor on the system work queue. The monitor runs there with
+//!   interrupts enabled, so its LP keeps answering TLB shootdowns and other IPIs while it waits for
+//!   input and the rest of the kernel keeps running. `exit` returns to the work queue.
+//! - at the end of initialization, if `monitor.boot` is given on the kernel command line or
+//!   [`set_enter_at_boot`] has been called.
+//!
+//! There is no PS/2 keyboard driver yet, so the magic key can only be sent over the serial port.
+//! While the monitor runs, the serial and console log sinks only write errors so that log records
+//! do not interleave with its output.
+
+mod commands;
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::time::Duration;
+
+use super::watchdog;
+use crate::cpu::deferred::work_queue::SYSTEM_WORK_QUEUE;
+use crate::cpu::sync::Deadline;
+use crate::cpu::timers;
+use crate::drivers::uart::ns16550::LOG_PORT;
+use crate::framebuffer::console::{CONSOLE, Console};
+use crate::isa::lp::ops::get_lp_id;
+use crate::kernel_param;
+use crate::klib::io::Read;
+use crate::log::Level;
+use crate::log::sink::{for_each_sink, set_sink_level};
+
+/// Ctrl-]
+pub const MAGIC_KEY: u8 = 0x1d;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_LINE_LENGTH: usize = 128;
+const NO_LP: u32 = u32::MAX;
+/// The sinks whose output would interleave with the monitor's
+const MUTED_SINKS: [&str; 2] = ["serial", "console"];
+
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+static ENTER_ON_PANIC: AtomicBool = AtomicBool::new(cfg!(debug_assertions));
+static ENTER_AT_BOOT: AtomicBool = AtomicBool::new(false);
+/// The LP running the monitor or [`NO_LP`]. Claimed by the LP that polls for the magic key until
+/// the monitor it queued starts running.
+static ACTIVE_LP: AtomicU32 = AtomicU32::new(NO_LP);
+
+/// Why the monitor was entered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reason {
+    Crash,
+    MagicKey,
+    Boot,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Reason::Crash => "after a crash",
+            Reason::MagicKey => "on the magic key",
+            Reason::Boot => "at boot",
+        })
+    }
+}
+
+/// Sets whether the monitor is entered once a crash has been reported, which is the default in
+/// debug builds
+pub fn set_enter_on_panic(enter: bool) {
+    ENTER_ON_PANIC.store(enter, Ordering::Relaxed);
+}
+
+/// Has the monitor entered once the kernel has been initialized
+pub fn set_enter_at_boot(enter: bool) {
+    ENTER_AT_BOOT.store(enter, Ordering::Relaxed);
+}
+
+kernel_param! {
+    static ENTER_ON_PANIC_PARAM: bool = ("monitor.panic", cfg!(debug_assertions));
+    static ENTER_AT_BOOT_PARAM: bool = ("monitor.boot", false);
+}
+
+/// Applies the kernel parameters and starts polling the serial port for the magic key on the
+/// current LP
+pub fn init() {
+    set_enter_on_panic(ENTER_ON_PANIC_PARAM.get());
+    set_enter_at_boot(ENTER_AT_BOOT_PARAM.get());
+    start_polling();
+}
+
+fn start_polling() {
+    timers::add_timer(Deadline::after(POLL_INTERVAL), || {
+        // The monitor reads the serial port itself while it runs. Nothing else does, so other
+        // input can be dropped.
+        if ACTIVE_LP.load(Ordering::Acquire) == NO_LP
+            && LOG_PORT.lock().try_read_byte() == Some(MAGIC_KEY)
+        {
+            queue_entry();
+        }
+        start_polling();
+    });
+}
+
+/// Queues the monitor on the system work queue unless it is running already
+fn queue_entry() {
+    let lp_id = get_lp_id!();
+    if ACTIVE_LP.compare_exchange(NO_LP, lp_id, Ordering::AcqRel, Ordering::Acquire).is_err() {
+        return;
+    }
+    SYSTEM_WORK_QUEUE.queue(|| {
+        ACTIVE_LP.store(get_lp_id!(), Ordering::Release);
+        run(Reason::MagicKey);
+        ACTIVE_LP.store(NO_LP, Ordering::Release);
+    });
+}
+
+/// Enters the monitor if it was requested for the end of initialization
+pub fn enter_at_boot_if_requested() {
+    if ENTER_AT_BOOT.load(Ordering::Relaxed) {
+        enter(Reason::Boot);
+    }
+}
+
+/// Enters the monitor for good if it is to be entered on a crash. Called by the crash report once
+/// it is complete and every other LP has been stopped.
+pub fn enter_after_crash() {
+    if ENTER_ON_PANIC.load(Ordering::Relaxed) {
+        // Whichever LP was running the monitor has been stopped with the rest.
+        ACTIVE_LP.store(get_lp_id!(), Ordering::Release);
+        run(Reason::Crash);
+    }
+}
+
+fn enter(reason: Reason) {
+    let lp_id = get_lp_id!();
+    if ACTIVE_LP.compare_exchange(NO_LP, lp_id, Ordering::AcqRel, Ordering::Acquire).is_err() {
+        return;
+    }
+    run(reason);
+    ACTIVE_LP.store(NO_LP, Ordering::Release);
+}
+
+fn run(reason: Reason) {
+    let saved_levels = mute_sinks();
+    let mut session = commands::Session {
+        terminal:  Terminal::default(),
+        resumable: reason != Reason::Crash,
+    };
+    let terminal = &mut session.terminal;
+    let _ = writeln!(
+        terminal,
+        "\nEntered the kernel monitor on LP{} {}. Type \"help\" for a list of commands.",
+        (get_lp_id!()),
+        reason
+    );
+    let mut buffer = [0; MAX_LINE_LENGTH];
+    loop {
+        let _ = write!(session.terminal, "monitor> ");
+        let Some(line) = session.terminal.read_command(&mut buffer) else {
+            continue;
+        };
+        if commands::run(&mut session, line) == commands::Flow::Exit {
+            break;
+        }
+    }
+    let _ = writeln!(session.terminal, "Leaving the kernel monitor.");
+    restore_sinks(saved_levels);
+}
+
+fn mute_sinks() -> [Option<Level>; MUTED_SINKS.len()] {
+    let mut saved_levels = [None; MUTED_SINKS.len()];
+    for_each_sink(|name, level| {
+        if let Some(i) = MUTED_SINKS.iter().position(|muted| *muted == name) {
+            saved_levels[i] = Some(level);
+        }
+    });
+    for name in MUTED_SINKS {
+        set_sink_level(name, Level::Error);
+    }
+    saved_levels
+}
+
+fn restore_sinks(saved_levels: [Option<Level>; MUTED_SINKS.len()]) {
+    for (name, level) in MUTED_SINKS.iter().zip(saved_levels) {
+        if let Some(level) = level {
+            set_sink_level(name, level);
+        }
+    }
+}
+
+/// The monitor's side of the serial port, with its output mirrored to the framebuffer console
+#[derive(Default)]
+struct Terminal {
+    /// Set after a carriage return so that the line feed following it does not end another line
+    after_carriage_return: bool,
+}
+
+impl Terminal {
+    /// Reads a line into `buffer`, echoing it as it is typed. Returns `None` if the line was
+    /// cancelled with Ctrl-C or is not valid UTF-8.
+    fn read_command<'a>(&mut self, buffer: &'a mut [u8]) -> Option<&'a str> {
+        let mut length = 0;
+        loop {
+            let byte = self.read_byte();
+            let after_carriage_return = core::mem::replace(&mut self.after_carriage_return, false);
+            match byte {
+                b'\n' if after_carriage_return => {}
+                b'\r' | b'\n' => {
+                    self.after_carriage_return = byte == b'\r';
+                    let _ = writeln!(self);
+                    return core::str::from_utf8(&buffer[..length]).ok();
+                }
+                CTRL_C => {
+                    let _ = writeln!(self, "^C");
+                    return None;
+                }
+                BACKSPACE | DELETE if length > 0 => {
+                    length -= 1;
+                    let _ = write!(self, "\x08 \x08");
+                }
+                b' '..=b'~' if length < buffer.len() => {
+                    buffer[length] = byte;
+                    length += 1;
+                    let _ = self.write_char(byte as char);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Read for Terminal {
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        for byte in buf.iter_mut() {
+            *byte = loop {
+                if let Some(byte) = LOG_PORT.lock().try_read_byte() {
+                    break byte;
+                }
+                // The monitor may wait for input for as long as it likes.
+                watchdog::touch();
+                core::hint::spin_loop();
+            };
+        }
+        buf.len()
+    }
+}
+
+impl Write for Terminal {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let _ = LOG_PORT.lock().write_str(s);
+        // Another LP may be stopped while holding the console.
+        if let Some(mut console) = CONSOLE.try_lock() {
+            Console::write_str(&mut console, s, None, None);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+//! # Kernel Symbols
+//!
+//! Addresses are symbolized with the symbol table of the kernel's own ELF file, which the
+//! bootloader leaves in memory for the lifetime of the kernel. The table is looked up in place so
+//! that symbolization neither allocates nor takes locks and works in any context, including a
+//! panic.
+//!
+//! The kernel may have been loaded at a different address than it was linked at. The difference is
+//! derived from the linked and actual addresses of the start of its code.
+
+use core::fmt;
+
+use spin::Lazy;
+
+use crate::environment::boot_protocol::limine::EXECUTABLE_FILE_REQUEST;
+use crate::logln;
+
+unsafe extern "C" {
+    static __text_start: u8;
+    static __text_end: u8;
+}
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const ELF_CLASS_64: u8 = 2;
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct ElfHeader {
+    ident: [u8; 16],
+    file_type: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    program_header_offset: u64,
+    section_header_offset: u64,
+    flags: u32,
+    header_size: u16,
+    program_header_size: u16,
+    n_program_headers: u16,
+    section_header_size: u16,
+    n_section_headers: u16,
+    section_names_index: u16,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SectionHeader {
+    name: u32,
+    section_type: u32,
+    flags: u64,
+    address: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    alignment: u64,
+    entry_size: u64,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct ElfSymbol {
+    name: u32,
+    info: u8,
+    other: u8,
+    section_index: u16,
+    value: u64,
+    size: u64,
+}
+
+struct SymbolTable {
+    symbols: &'static [ElfSymbol],
+    strings: &'static [u8],
+    /// The difference between the address the kernel was loaded at and the one it was linked at
+    slide: usize,
+}
+
+impl SymbolTable {
+    fn load() -> Option<Self> {
+        let file = EXECUTABLE_FILE_REQUEST.get_response()?.file();
+        let image = unsafe { core::slice::from_raw_parts(file.addr(), file.size() as usize) };
+        let header = read_at::<ElfHeader>(image, 0)?;
+        if header.ident[..4] != ELF_MAGIC || header.ident[4] != ELF_CLASS_64 {
+            return None;
+        }
+        let section = |index: usize| {
+            read_at::<SectionHeader>(
+                image,
+                header.section_header_offset as usize + index * header.section_header_size as usize,
+            )
+        };
+        let symtab = (0..header.n_section_headers as usize)
+            .filter_map(section)
+            .find(|section| section.section_type == SHT_SYMTAB)?;
+        let strtab = section(symtab.link as usize)?;
+        let symbols = slice_at::<ElfSymbol>(
+            image,
+            symtab.offset as usize,
+            symtab.size as usize / size_of::<ElfSymbol>(),
+        )?;
+        let strings = image.get(strtab.offset as usize..(strtab.offset + strtab.size) as usize)?;
+        let mut table = SymbolTable {
+            symbols,
+            strings,
+            slide: 0,
+        };
+        let linked_text_start =
+            table.symbols.iter().find(|symbol| table.name_of(symbol) == Some("__text_start"))?;
+        table.slide = text_start().wrapping_sub(linked_text_start.value as usize);
+        Some(table)
+    }
+
+    fn name_of(&self, symbol: &ElfSymbol) -> Option<&'static str> {
+        let strings = self.strings;
+        let start = strings.get(symbol.name as usize..)?;
+        let length = start.iter().position(|byte| *byte == 0)?;
+        core::str::from_utf8(&start[..length]).ok()
+    }
+
+    /// Finds the function containing the given address or the closest one below it
+    fn resolve(&self, address: usize) -> Option<(&'static str, usize)> {
+        let linked = address.wrapping_sub(self.slide) as u64;
+        let function = self
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.info & 0xf == STT_FUNC && symbol.value <= linked)
+            .max_by_key(|symbol| {
+                // Prefer a function that actually covers the address over one that merely starts
+                // closer to it.
+                (linked < symbol.value + symbol.size, symbol.value)
+            })?;
+        Some((self.name_of(function)?, (linked - function.value) as usize))
+    }
+}
+
+fn read_at<T: Copy>(image: &[u8], offset: usize) -> Option<T> {
+    let bytes = image.get(offset..offset.checked_add(size_of::<T>())?)?;
+    Some(unsafe { bytes.as_ptr().cast::<T>().read_unaligned() })
+}
+
+fn slice_at<T>(image: &'static [u8], offset: usize, count: usize) -> Option<&'static [T]> {
+    let bytes = image.get(offset..offset.checked_add(count.checked_mul(size_of::<T>())?)?)?;
+    if !(bytes.as_ptr() as usize).is_multiple_of(align_of::<T>()) {
+        return None;
+    }
+    Some(unsafe { core::slice::from_raw_parts(bytes.as_ptr().cast(), count) })
+}
+
+static SYMBOL_TABLE: Lazy<Option<SymbolTable>> = Lazy::new(SymbolTable::load);
+
+fn text_start() -> usize {
+    &raw const __text_start as usize
+}
+
+/// Locates the kernel's symbol table. Symbolization works without calling this first but looking
+/// the table up ahead of time keeps a panic from having to do it.
+pub fn init() {
+    match SYMBOL_TABLE.as_ref() {
+        Some(table) => logln!(
+            "Found {} kernel symbols, the kernel is loaded {:#x} bytes from its link address.",
+            (table.symbols.len()),
+            (table.slide)
+        ),
+        None => {
+            logln!("The kernel's symbol table is unavailable, addresses will not be symbolized.")
+        }
+    }
+}
+
+/// Returns true if the address lies within the kernel's code
+pub fn is_kernel_text(address: usize) -> bool {
+    (text_start()..&raw const __text_end as usize).contains(&address)
+}
+
+/// Returns the name of the function containing the address and the offset of the address within it
+pub fn resolve(address: usize) -> Option<(&'static str, usize)> {
+    if !is_kernel_text(address) {
+        return None;
+    }
+    SYMBOL_TABLE.as_ref()?.resolve(address)
+}
+
+/// Formats an address as the demangled name of the function containing it and an offset
+#[derive(Debug, Clone, Copy)]
+pub struct Symbolized(pub usize);
+
+impl fmt::Display for Symbolized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match resolve(self.0) {
+            // The alternate format leaves out the hash that makes each symbol unique.
+            Some((name, offset)) => write!(f, "{:#}+{:#x}", rustc_demangle::demangle(name), offset),
+            None => write!(f, "<unknown>"),
+        }
+    }
+}
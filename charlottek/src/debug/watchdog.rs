@@ -0,0 +1,108 @@
+//! # NMI Watchdog
+//!
+//! Detects LPs that spin with interrupts masked, which would otherwise hang silently. Every LP
+//! keeps a heartbeat, the monotonic time at which it last made scheduler progress, i.e. handled its
+//! local timer interrupt or went through its idle loop. The LP's performance counter periodically
+//! delivers an NMI while it is busy, and an NMI that finds the heartbeat older than the watchdog
+//! window while the interrupted code had interrupts masked reports a hard lockup. The report goes
+//! through the [crash report](super::crash) so that the other LPs are stopped and dumped as well.
+//!
+//! An LP that is halted waiting for an interrupt does not count cycles and so receives no NMIs.
+//! Its heartbeat is marked as idle instead so that the wait is not counted against the window
+//! once it is woken.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use super::crash;
+use crate::isa::interrupts::exceptions::frame::ExceptionFrame;
+use crate::isa::interrupts::x2apic::perf_counter;
+use crate::isa::lp::ops::get_lp_id;
+use crate::{logln, percpu, time};
+
+/// The window used unless [`set_window`] is called
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(10);
+/// The number of NMIs per window, which bounds how late a lockup is detected
+const SAMPLES_PER_WINDOW: u64 = 4;
+const RFLAGS_IF: u64 = 1 << 9;
+const IDLE: u64 = u64::MAX;
+
+static WINDOW_NANOS: AtomicU64 = AtomicU64::new(DEFAULT_WINDOW.as_nanos() as u64);
+
+percpu! {
+    /// The monotonic time of the LP's last scheduler progress or [`IDLE`]
+    static HEARTBEAT: AtomicU64 = AtomicU64::new(IDLE);
+    /// The number of watchdog NMIs the LP has handled
+    static NMI_COUNT: AtomicU64 = AtomicU64::new(0);
+}
+
+/// Sets how long an LP may go without scheduler progress before it is considered locked up. Takes
+/// effect on each LP the next time its watchdog NMI is armed.
+pub fn set_window(window: Duration) {
+    WINDOW_NANOS.store(u64::try_from(window.as_nanos()).unwrap_or(u64::MAX), Ordering::Relaxed);
+}
+
+pub fn window() -> Duration {
+    Duration::from_nanos(WINDOW_NANOS.load(Ordering::Relaxed))
+}
+
+/// The number of busy cycles between NMIs, assuming the core runs at the timestamp frequency
+fn sample_cycles() -> u64 {
+    let cycles = WINDOW_NANOS.load(Ordering::Relaxed) as u128 * time::timestamp_frequency() as u128
+        / 1_000_000_000
+        / SAMPLES_PER_WINDOW as u128;
+    u64::try_from(cycles).unwrap_or(u64::MAX)
+}
+
+/// Starts the watchdog on the current LP
+pub fn init_local() {
+    let lp_id = get_lp_id!();
+    if !perf_counter::is_supported() {
+        logln!("LP{}: No performance counter is available, the NMI watchdog is disabled.", lp_id);
+        return;
+    }
+    touch();
+    perf_counter::arm(sample_cycles());
+    logln!("LP{}: NMI watchdog armed with a {:?} window.", lp_id, (window()));
+}
+
+/// Returns the number of watchdog NMIs the current LP has handled
+pub fn nmi_count() -> u64 {
+    NMI_COUNT.get().load(Ordering::Relaxed)
+}
+
+/// Records scheduler progress on the current LP
+#[inline]
+pub fn touch() {
+    HEARTBEAT.get().store(time::monotonic_now(), Ordering::Relaxed);
+}
+
+/// Marks the current LP as waiting for an interrupt. The window starts over once it is woken.
+#[inline]
+pub fn touch_idle() {
+    HEARTBEAT.get().store(IDLE, Ordering::Relaxed);
+}
+
+/// Checks the current LP for a hard lockup if the NMI being handled was the watchdog's. Returns
+/// false if the NMI came from elsewhere.
+pub fn handle_nmi(frame: &ExceptionFrame) -> bool {
+    if !perf_counter::has_overflowed() {
+        return false;
+    }
+    NMI_COUNT.get().fetch_add(1, Ordering::Relaxed);
+    // The LP reporting a crash may keep interrupts masked for as long as it takes.
+    if crash::is_crashing() {
+        return true;
+    }
+    let heartbeat = HEARTBEAT.get();
+    let now = time::monotonic_now();
+    let last_progress = heartbeat.load(Ordering::Relaxed);
+    if last_progress == IDLE || frame.interrupt_frame.rflags & RFLAGS_IF != 0 {
+        // Code that runs with interrupts unmasked is not locked up even if it never yields.
+        heartbeat.store(now, Ordering::Relaxed);
+    } else if now.saturating_sub(last_progress) >= WINDOW_NANOS.load(Ordering::Relaxed) {
+        crash::report_hard_lockup(frame, Duration::from_nanos(now - last_progress));
+    }
+    perf_counter::arm(sample_cycles());
+    true
+}
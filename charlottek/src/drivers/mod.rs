@@ -1,2 +1,4 @@
 pub mod keyboard;
+pub mod pci;
+pub mod rtc;
 pub mod uart;
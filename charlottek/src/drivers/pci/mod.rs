@@ -0,0 +1,243 @@
+//! # PCI Express Configuration Space
+//!
+//! The configuration space of every PCI function is memory mapped through the enhanced
+//! configuration access mechanism (ECAM). The ACPI MCFG table lists the ECAM regions, each of
+//! which covers a range of buses within one segment group and gives every function a 4 KiB window.
+//! The regions are mapped uncached when the first configuration space is opened.
+//! Device drivers locate their registers and capabilities through the [`ConfigSpace`] of their
+//! function.
+
+pub mod msi;
+
+use alloc::vec::Vec;
+
+use spin::Lazy;
+
+use crate::environment::firmware::acpi;
+use crate::environment::firmware::acpi::tables::{Mcfg, McfgAllocation};
+use crate::isa::interface::memory::address::VirtualAddress;
+use crate::memory::PAddr;
+use crate::memory::vmem::{VAddr, mmio};
+use crate::warn;
+
+const REG_VENDOR_ID: u16 = 0x00;
+const REG_DEVICE_ID: u16 = 0x02;
+const REG_COMMAND: u16 = 0x04;
+const REG_STATUS: u16 = 0x06;
+const REG_HEADER_TYPE: u16 = 0x0e;
+const REG_BAR0: u16 = 0x10;
+const REG_CAPABILITIES_POINTER: u16 = 0x34;
+
+const STATUS_CAPABILITIES_LIST: u16 = 1 << 4;
+const HEADER_TYPE_MASK: u8 = 0x7f;
+const HEADER_TYPE_GENERAL: u8 = 0x00;
+const N_BARS: u8 = 6;
+const BAR_IO_SPACE: u32 = 1 << 0;
+const BAR_TYPE_MASK: u32 = 0b11 << 1;
+const BAR_TYPE_64BIT: u32 = 0b10 << 1;
+const BAR_ADDRESS_MASK: u32 = !0xf;
+/// The vendor ID read from functions that do not exist
+const INVALID_VENDOR_ID: u16 = 0xffff;
+
+/// An ECAM region of the MCFG, which is mapped as a whole the first time it is needed
+struct EcamRegion {
+    segment_group: u16,
+    start_bus: u8,
+    end_bus: u8,
+    base: VAddr,
+}
+
+impl EcamRegion {
+    fn map(allocation: McfgAllocation) -> Option<Self> {
+        let n_buses = (allocation.end_bus - allocation.start_bus) as usize + 1;
+        match mmio::map(PAddr::from(allocation.base_address), n_buses << 20) {
+            Ok(base) => Some(EcamRegion {
+                segment_group: allocation.segment_group,
+                start_bus: allocation.start_bus,
+                end_bus: allocation.end_bus,
+                base,
+            }),
+            Err(error) => {
+                let segment_group = allocation.segment_group;
+                warn!(
+                    "Failed to map the ECAM region of segment group {}: {:?}",
+                    segment_group, error
+                );
+                None
+            }
+        }
+    }
+}
+
+static ECAM_REGIONS: Lazy<Vec<EcamRegion>> = Lazy::new(|| match acpi::find::<Mcfg>() {
+    Some(mcfg) => mcfg.allocations().filter_map(EcamRegion::map).collect(),
+    None => Vec::new(),
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub segment_group: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+/// The command register bits
+pub struct Command;
+
+impl Command {
+    pub const BUS_MASTER: u16 = 1 << 2;
+    /// Stops the function from asserting its INTx pin
+    pub const INTX_DISABLE: u16 = 1 << 10;
+    pub const MEMORY_SPACE: u16 = 1 << 1;
+}
+
+/// The memory mapped configuration space of a PCI function
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigSpace {
+    address: PciAddress,
+    base: *mut u8,
+}
+
+unsafe impl Send for ConfigSpace {}
+unsafe impl Sync for ConfigSpace {}
+
+impl ConfigSpace {
+    /// Returns the configuration space of a function or `None` if the function does not exist or no
+    /// ECAM region covers it
+    pub fn open(address: PciAddress) -> Option<Self> {
+        if address.device >= 32 || address.function >= 8 {
+            return None;
+        }
+        let region = ECAM_REGIONS.iter().find(|region| {
+            region.segment_group == address.segment_group
+                && (region.start_bus..=region.end_bus).contains(&address.bus)
+        })?;
+        let offset = ((address.bus - region.start_bus) as usize) << 20
+            | (address.device as usize) << 15
+            | (address.function as usize) << 12;
+        let config = ConfigSpace {
+            address,
+            base: unsafe { region.base.into_mut::<u8>().add(offset) },
+        };
+        if config.vendor_id() == INVALID_VENDOR_ID {
+            None
+        } else {
+            Some(config)
+        }
+    }
+
+    pub fn address(&self) -> PciAddress {
+        self.address
+    }
+
+    pub fn read_u8(&self, offset: u16) -> u8 {
+        unsafe { self.base.add(offset as usize).read_volatile() }
+    }
+
+    pub fn read_u16(&self, offset: u16) -> u16 {
+        unsafe { self.base.add(offset as usize).cast::<u16>().read_volatile() }
+    }
+
+    pub fn read_u32(&self, offset: u16) -> u32 {
+        unsafe { self.base.add(offset as usize).cast::<u32>().read_volatile() }
+    }
+
+    pub fn write_u16(&self, offset: u16, value: u16) {
+        unsafe { self.base.add(offset as usize).cast::<u16>().write_volatile(value) }
+    }
+
+    pub fn write_u32(&self, offset: u16, value: u32) {
+        unsafe { self.base.add(offset as usize).cast::<u32>().write_volatile(value) }
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read_u16(REG_VENDOR_ID)
+    }
+
+    pub fn device_id(&self) -> u16 {
+        self.read_u16(REG_DEVICE_ID)
+    }
+
+    /// Sets or clears the given [`Command`] bits
+    pub fn set_command(&self, bits: u16, set: bool) {
+        let command = self.read_u16(REG_COMMAND);
+        self.write_u16(
+            REG_COMMAND,
+            if set {
+                command | bits
+            } else {
+                command & !bits
+            },
+        );
+    }
+
+    /// Iterates over the IDs and offsets of the function's capabilities
+    pub fn capabilities(&self) -> impl Iterator<Item = (u8, u16)> + '_ {
+        let mut next = if self.read_u16(REG_STATUS) & STATUS_CAPABILITIES_LIST != 0 {
+            self.read_u8(REG_CAPABILITIES_POINTER) & 0xfc
+        } else {
+            0
+        };
+        // A malformed list could otherwise loop forever; at most 48 capabilities fit.
+        let mut remaining = 48;
+        core::iter::from_fn(move || {
+            if next == 0 || remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+            let offset = next as u16;
+            next = self.read_u8(offset + 1) & 0xfc;
+            Some((self.read_u8(offset), offset))
+        })
+    }
+
+    /// Returns the offset of the first capability with the given ID
+    pub fn find_capability(&self, id: u8) -> Option<u16> {
+        self.capabilities().find(|(cap_id, _)| *cap_id == id).map(|(_, offset)| offset)
+    }
+
+    /// Returns the physical address a memory BAR of a general device is mapped at
+    pub fn bar_address(&self, index: u8) -> Option<PAddr> {
+        if index >= N_BARS
+            || self.read_u8(REG_HEADER_TYPE) & HEADER_TYPE_MASK != HEADER_TYPE_GENERAL
+        {
+            return None;
+        }
+        let register = REG_BAR0 + index as u16 * 4;
+        let low = self.read_u32(register);
+        if low & BAR_IO_SPACE != 0 {
+            return None;
+        }
+        let high = if low & BAR_TYPE_MASK == BAR_TYPE_64BIT {
+            if index + 1 >= N_BARS {
+                return None;
+            }
+            self.read_u32(register + 4)
+        } else {
+            0
+        };
+        Some(PAddr::from((high as u64) << 32 | (low & BAR_ADDRESS_MASK) as u64))
+    }
+}
+
+/// Calls `f` with the configuration space of every function in the ECAM regions
+pub fn for_each_function(mut f: impl FnMut(ConfigSpace)) {
+    for region in ECAM_REGIONS.iter() {
+        for bus in region.start_bus..=region.end_bus {
+            for device in 0..32 {
+                for function in 0..8 {
+                    let address = PciAddress {
+                        segment_group: region.segment_group,
+                        bus,
+                        device,
+                        function,
+                    };
+                    if let Some(config) = ConfigSpace::open(address) {
+                        f(config);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,335 @@
+//! # MSI and MSI-X Capabilities
+//!
+//! A function that supports MSI has a single message address and data register pair in its
+//! configuration space and can send up to 32 messages which differ only in the low bits of the
+//! data. All of them are therefore delivered to the same LP. MSI-X instead keeps a table of up to
+//! 2048 independently programmed messages in one of the function's BARs, which lets a device with
+//! several queues direct the interrupt of each queue to a different LP.
+//!
+//! Enabling either capability disables the function's INTx pin.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::{Command, ConfigSpace, PciAddress};
+use crate::cpu::multiprocessor::lp_mask::LpMask;
+use crate::cpu::sync::Spinlock;
+use crate::isa::interface::memory::address::VirtualAddress;
+use crate::isa::interrupts::irq::IrqError;
+use crate::isa::interrupts::msi::{self, MsiMessage, MsiVectors};
+use crate::isa::lp::LpId;
+use crate::lock_class;
+use crate::memory::vmem::mmio;
+use crate::memory::{PAddr, VAddr};
+
+pub const CAPABILITY_ID_MSI: u8 = 0x05;
+pub const CAPABILITY_ID_MSIX: u8 = 0x11;
+
+const MSI_CONTROL: u16 = 0x02;
+const MSI_ADDRESS_LOW: u16 = 0x04;
+const MSI_ADDRESS_HIGH: u16 = 0x08;
+const MSI_CONTROL_ENABLE: u16 = 1 << 0;
+const MSI_CONTROL_MULTIPLE_CAPABLE_SHIFT: u16 = 1;
+const MSI_CONTROL_MULTIPLE_ENABLE_SHIFT: u16 = 4;
+const MSI_CONTROL_MULTIPLE_MASK: u16 = 0b111;
+const MSI_CONTROL_64BIT: u16 = 1 << 7;
+const MSI_CONTROL_PER_VECTOR_MASKING: u16 = 1 << 8;
+
+const MSIX_CONTROL: u16 = 0x02;
+const MSIX_TABLE: u16 = 0x04;
+const MSIX_CONTROL_TABLE_SIZE_MASK: u16 = 0x7ff;
+const MSIX_CONTROL_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_CONTROL_ENABLE: u16 = 1 << 15;
+const MSIX_BIR_MASK: u32 = 0b111;
+const MSIX_ENTRY_SIZE: usize = 16;
+const MSIX_ENTRY_ADDRESS_LOW: usize = 0x0;
+const MSIX_ENTRY_ADDRESS_HIGH: usize = 0x4;
+const MSIX_ENTRY_DATA: usize = 0x8;
+const MSIX_ENTRY_VECTOR_CONTROL: usize = 0xc;
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+
+/// The MSI-X table mapping of each function, with the physical address it maps. MMIO mappings are
+/// never removed, so a function's table is only mapped again if its BAR has been moved.
+static MSIX_TABLES: Spinlock<BTreeMap<PciAddress, (u64, usize)>> =
+    Spinlock::with_class(lock_class!("MSIX_TABLES"), BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsiError {
+    /// The function does not have the capability
+    NoCapability,
+    /// The function cannot send that many messages or the count is not a power of two
+    InvalidVectorCount,
+    /// The BAR that holds the MSI-X table is not a memory BAR or the table could not be mapped
+    UnmappedTable,
+    Irq(IrqError),
+}
+
+impl From<IrqError> for MsiError {
+    fn from(error: IrqError) -> Self {
+        MsiError::Irq(error)
+    }
+}
+
+/// The MSI capability of a function
+pub struct Msi {
+    config: ConfigSpace,
+    offset: u16,
+}
+
+impl Msi {
+    pub fn find(config: ConfigSpace) -> Option<Self> {
+        let offset = config.find_capability(CAPABILITY_ID_MSI)?;
+        Some(Msi {
+            config,
+            offset,
+        })
+    }
+
+    fn control(&self) -> u16 {
+        self.config.read_u16(self.offset + MSI_CONTROL)
+    }
+
+    fn data_register(&self) -> u16 {
+        if self.control() & MSI_CONTROL_64BIT != 0 {
+            self.offset + 0x0c
+        } else {
+            self.offset + 0x08
+        }
+    }
+
+    fn mask_register(&self) -> Option<u16> {
+        if self.control() & MSI_CONTROL_PER_VECTOR_MASKING != 0 {
+            Some(self.data_register() + 4)
+        } else {
+            None
+        }
+    }
+
+    /// The largest number of messages the function can send
+    pub fn max_vectors(&self) -> usize {
+        1 << ((self.control() >> MSI_CONTROL_MULTIPLE_CAPABLE_SHIFT) & MSI_CONTROL_MULTIPLE_MASK)
+    }
+
+    /// Programs the function to send `count` messages starting with `message`. The capability
+    /// must be disabled.
+    pub fn program(&self, message: MsiMessage, count: usize) -> Result<(), MsiError> {
+        if !count.is_power_of_two() || count > self.max_vectors() {
+            return Err(MsiError::InvalidVectorCount);
+        }
+        let control = self.control();
+        assert!(control & MSI_CONTROL_ENABLE == 0, "MSI was reprogrammed while enabled");
+        self.config.write_u32(self.offset + MSI_ADDRESS_LOW, message.address as u32);
+        if control & MSI_CONTROL_64BIT != 0 {
+            self.config.write_u32(self.offset + MSI_ADDRESS_HIGH, (message.address >> 32) as u32);
+        }
+        self.config.write_u16(self.data_register(), message.data as u16);
+        let multiple_enable = (count.trailing_zeros() as u16) << MSI_CONTROL_MULTIPLE_ENABLE_SHIFT;
+        self.config.write_u16(
+            self.offset + MSI_CONTROL,
+            control & !(MSI_CONTROL_MULTIPLE_MASK << MSI_CONTROL_MULTIPLE_ENABLE_SHIFT)
+                | multiple_enable,
+        );
+        Ok(())
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        let control = self.control();
+        if enabled {
+            self.config.set_command(Command::INTX_DISABLE, true);
+            self.config.write_u16(self.offset + MSI_CONTROL, control | MSI_CONTROL_ENABLE);
+        } else {
+            self.config.write_u16(self.offset + MSI_CONTROL, control & !MSI_CONTROL_ENABLE);
+        }
+    }
+
+    /// Masks or unmasks a single message. Returns false if the function does not support per
+    /// message masking.
+    pub fn set_masked(&self, index: usize, masked: bool) -> bool {
+        let Some(register) = self.mask_register() else {
+            return false;
+        };
+        let mask = self.config.read_u32(register);
+        self.config.write_u32(
+            register,
+            if masked {
+                mask | 1 << index
+            } else {
+                mask & !(1 << index)
+            },
+        );
+        true
+    }
+
+    /// Allocates `count` vectors on the given LP, directs the function's messages to them and
+    /// enables MSI. The handler is passed the index of the message that was raised.
+    pub fn enable(
+        &self,
+        lp_id: LpId,
+        count: usize,
+        handler: impl Fn(usize) + Send + Sync + 'static,
+    ) -> Result<MsiVectors, MsiError> {
+        if !count.is_power_of_two() || count > self.max_vectors() {
+            return Err(MsiError::InvalidVectorCount);
+        }
+        self.set_enabled(false);
+        let vectors = msi::allocate(lp_id, count, handler)?;
+        self.program(vectors.message(0), count)?;
+        self.set_enabled(true);
+        Ok(vectors)
+    }
+
+    /// Disables MSI and frees the vectors returned by [`Msi::enable`]
+    pub fn disable(&self, vectors: MsiVectors) {
+        self.set_enabled(false);
+        msi::free(vectors);
+    }
+}
+
+/// The MSI-X capability of a function
+pub struct MsiX {
+    config: ConfigSpace,
+    offset: u16,
+    table:  *mut u8,
+}
+
+unsafe impl Send for MsiX {}
+unsafe impl Sync for MsiX {}
+
+impl MsiX {
+    pub fn find(config: ConfigSpace) -> Result<Self, MsiError> {
+        let offset = config.find_capability(CAPABILITY_ID_MSIX).ok_or(MsiError::NoCapability)?;
+        let table_location = config.read_u32(offset + MSIX_TABLE);
+        let bar = config
+            .bar_address((table_location & MSIX_BIR_MASK) as u8)
+            .ok_or(MsiError::UnmappedTable)?;
+        let table_address =
+            <PAddr as Into<u64>>::into(bar) + (table_location & !MSIX_BIR_MASK) as u64;
+        let table_size =
+            (config.read_u16(offset + MSIX_CONTROL) & MSIX_CONTROL_TABLE_SIZE_MASK) as usize + 1;
+        let table = {
+            let mut tables = MSIX_TABLES.lock();
+            match tables.get(&config.address()) {
+                Some(&(mapped_address, table)) if mapped_address == table_address => table,
+                _ => {
+                    let table: usize =
+                        mmio::map(PAddr::from(table_address), table_size * MSIX_ENTRY_SIZE)
+                            .map_err(|_| MsiError::UnmappedTable)?
+                            .into();
+                    tables.insert(config.address(), (table_address, table));
+                    table
+                }
+            }
+        };
+        Ok(MsiX {
+            config,
+            offset,
+            table: VAddr::from(table).into_mut(),
+        })
+    }
+
+    fn control(&self) -> u16 {
+        self.config.read_u16(self.offset + MSIX_CONTROL)
+    }
+
+    fn entry_register(&self, index: usize, register: usize) -> *mut u32 {
+        assert!(index < self.table_size(), "MSI-X entry {} does not exist", index);
+        unsafe { self.table.add(index * MSIX_ENTRY_SIZE + register).cast() }
+    }
+
+    /// The number of entries in the MSI-X table
+    pub fn table_size(&self) -> usize {
+        (self.control() & MSIX_CONTROL_TABLE_SIZE_MASK) as usize + 1
+    }
+
+    /// Programs an entry of the table. The entry is left masked.
+    pub fn set_entry(&self, index: usize, message: MsiMessage) {
+        self.set_masked(index, true);
+        unsafe {
+            self.entry_register(index, MSIX_ENTRY_ADDRESS_LOW)
+                .write_volatile(message.address as u32);
+            self.entry_register(index, MSIX_ENTRY_ADDRESS_HIGH)
+                .write_volatile((message.address >> 32) as u32);
+            self.entry_register(index, MSIX_ENTRY_DATA).write_volatile(message.data);
+        }
+    }
+
+    pub fn set_masked(&self, index: usize, masked: bool) {
+        let register = self.entry_register(index, MSIX_ENTRY_VECTOR_CONTROL);
+        unsafe {
+            let control = register.read_volatile();
+            register.write_volatile(
+                if masked {
+                    control | MSIX_ENTRY_MASKED
+                } else {
+                    control & !MSIX_ENTRY_MASKED
+                },
+            );
+        }
+    }
+
+    /// Enables or disables MSI-X. Entries are only delivered while they are unmasked.
+    pub fn set_enabled(&self, enabled: bool) {
+        let control = self.control() & !MSIX_CONTROL_FUNCTION_MASK;
+        if enabled {
+            self.config.set_command(Command::INTX_DISABLE, true);
+            self.config.write_u16(self.offset + MSIX_CONTROL, control | MSIX_CONTROL_ENABLE);
+        } else {
+            self.config.write_u16(self.offset + MSIX_CONTROL, control & !MSIX_CONTROL_ENABLE);
+        }
+    }
+
+    /// Allocates a vector on the given LP, directs an entry of the table to it and unmasks the
+    /// entry
+    pub fn request_vector(
+        &self,
+        index: usize,
+        lp_id: LpId,
+        handler: impl Fn() + Send + Sync + 'static,
+    ) -> Result<MsiVectors, MsiError> {
+        if index >= self.table_size() {
+            return Err(MsiError::InvalidVectorCount);
+        }
+        let vectors = msi::allocate(lp_id, 1, move |_| handler())?;
+        self.set_entry(index, vectors.message(0));
+        self.set_masked(index, false);
+        Ok(vectors)
+    }
+
+    /// Directs the first `count` entries of the table to the LPs in `lps` in turn, so that the
+    /// interrupts of a device with several queues are spread across them. The handler is passed
+    /// the index of the entry that was raised. Returns the vectors of the entries in order.
+    pub fn request_spread(
+        &self,
+        count: usize,
+        lps: LpMask,
+        handler: impl Fn(usize) + Send + Sync + 'static,
+    ) -> Result<Vec<MsiVectors>, MsiError> {
+        if count == 0 || count > self.table_size() || lps.is_empty() {
+            return Err(MsiError::InvalidVectorCount);
+        }
+        let handler = Arc::new(handler);
+        let mut requested = Vec::with_capacity(count);
+        let lps: Vec<LpId> = lps.iter().collect();
+        for index in 0..count {
+            let handler = handler.clone();
+            match self.request_vector(index, lps[index % lps.len()], move || handler(index)) {
+                Ok(vectors) => requested.push(vectors),
+                Err(error) => {
+                    requested
+                        .into_iter()
+                        .enumerate()
+                        .for_each(|(index, vectors)| self.free_vector(index, vectors));
+                    return Err(error);
+                }
+            }
+        }
+        Ok(requested)
+    }
+
+    /// Masks an entry and frees the vector returned for it by [`MsiX::request_vector`]
+    pub fn free_vector(&self, index: usize, vectors: MsiVectors) {
+        self.set_masked(index, true);
+        msi::free(vectors);
+    }
+}
@@ -0,0 +1,154 @@
+//! # CMOS Real Time Clock Driver
+//!
+//! The PC real time clock is accessed through the CMOS index and data ports. Its registers may be
+//! updated at any time, so they are read until two consecutive reads agree while no update is in
+//! progress. Depending on the configuration in status register B the values are either binary or
+//! BCD and the hour is in either 12 or 24 hour format.
+
+use crate::cpu::sync::IrqSpinlock;
+use crate::environment::firmware::acpi;
+use crate::environment::firmware::acpi::tables::Fadt;
+use crate::isa::interface::io::{IReg8Ifce, OReg8Ifce};
+use crate::isa::io::IoReg8;
+use crate::lock_class;
+use crate::time::calendar::DateTime;
+
+const INDEX_PORT: u16 = 0x70;
+const DATA_PORT: u16 = 0x71;
+// Setting bit 7 of the index disables NMIs while the index is selected.
+const NMI_DISABLE: u8 = 0x80;
+
+const REG_SECOND: u8 = 0x00;
+const REG_MINUTE: u8 = 0x02;
+const REG_HOUR: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 0x80;
+const STATUS_B_24_HOUR: u8 = 0x02;
+const STATUS_B_BINARY: u8 = 0x04;
+const HOUR_PM: u8 = 0x80;
+
+const MAX_READ_ATTEMPTS: usize = 16;
+
+/// Selecting a register and accessing it must not be interleaved with other accesses
+static CMOS: IrqSpinlock<Cmos> = IrqSpinlock::with_class(
+    lock_class!("CMOS"),
+    Cmos {
+        index: IoReg8::IoPort(INDEX_PORT),
+        data:  IoReg8::IoPort(DATA_PORT),
+    },
+);
+
+struct Cmos {
+    index: IoReg8,
+    data:  IoReg8,
+}
+
+unsafe impl Send for Cmos {}
+
+/// The raw values of the clock registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Registers {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+impl Cmos {
+    fn read(&self, register: u8) -> u8 {
+        self.index.write(NMI_DISABLE | register);
+        self.data.read()
+    }
+
+    fn is_update_in_progress(&self) -> bool {
+        self.read(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+    }
+
+    fn read_registers(&self, century_register: Option<u8>) -> Registers {
+        while self.is_update_in_progress() {
+            core::hint::spin_loop();
+        }
+        Registers {
+            second: self.read(REG_SECOND),
+            minute: self.read(REG_MINUTE),
+            hour: self.read(REG_HOUR),
+            day: self.read(REG_DAY),
+            month: self.read(REG_MONTH),
+            year: self.read(REG_YEAR),
+            century: century_register.map_or(0, |register| self.read(register)),
+        }
+    }
+
+    fn read_consistent_registers(&self, century_register: Option<u8>) -> Option<Registers> {
+        let mut registers = self.read_registers(century_register);
+        for _ in 0..MAX_READ_ATTEMPTS {
+            let again = self.read_registers(century_register);
+            if again == registers {
+                return Some(registers);
+            }
+            registers = again;
+        }
+        None
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+/// Returns false if the firmware reports that there is no CMOS RTC
+pub fn is_present() -> bool {
+    acpi::find::<Fadt>().is_none_or(|fadt| {
+        let iapc_boot_arch = fadt.iapc_boot_arch;
+        iapc_boot_arch & Fadt::IAPC_BOOT_ARCH_CMOS_RTC_NOT_PRESENT == 0
+    })
+}
+
+/// Reads the current date and time. The clock is assumed to be kept in UTC.
+pub fn read_date_time() -> Option<DateTime> {
+    // The FADT may name a register holding the century, otherwise the 21st century is assumed.
+    let century_register =
+        acpi::find::<Fadt>().map(|fadt| fadt.century).filter(|register| *register != 0);
+    let (registers, status_b) = {
+        let cmos = CMOS.lock();
+        let registers = cmos.read_consistent_registers(century_register)?;
+        (registers, cmos.read(REG_STATUS_B))
+    };
+    let decode = |value: u8| {
+        if status_b & STATUS_B_BINARY != 0 {
+            value
+        } else {
+            from_bcd(value)
+        }
+    };
+    let mut hour = decode(registers.hour & !HOUR_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // In 12 hour format midnight and noon are hour 12.
+        hour %= 12;
+        if registers.hour & HOUR_PM != 0 {
+            hour += 12;
+        }
+    }
+    let century = match century_register {
+        Some(_) => decode(registers.century),
+        None => 20,
+    };
+    let date_time = DateTime {
+        year: century as u16 * 100 + decode(registers.year) as u16,
+        month: decode(registers.month),
+        day: decode(registers.day),
+        hour,
+        minute: decode(registers.minute),
+        second: decode(registers.second),
+        nanosecond: 0,
+    };
+    date_time.is_valid().then_some(date_time)
+}
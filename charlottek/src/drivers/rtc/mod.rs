@@ -0,0 +1,7 @@
+//! # Real Time Clock Drivers
+//!
+//! Real time clocks keep the wall clock time while the system is powered off. The kernel reads one
+//! once during boot and keeps time with the timestamp counter afterwards.
+
+#[cfg(target_arch = "x86_64")]
+pub mod cmos;
@@ -4,17 +4,36 @@ use core::fmt::{self, Write};
 use core::result::Result;
 
 use lazy_static::lazy_static;
-use spin::Mutex;
 
+use crate::cpu::sync::IrqSpinlock;
 use crate::drivers::uart::Uart;
+use crate::environment::cmdline::{ParamValue, ParseError};
 use crate::isa::interface::io::{IReg8Ifce, OReg8Ifce};
 use crate::isa::io::{self, IoReg8};
+use crate::kernel_param;
 use crate::klib::io::Read;
 
+kernel_param! {
+    /// The port the log and the kernel monitor use
+    pub static CONSOLE: ComPort = ("console", ComPort(0));
+    /// The port the GDB stub talks to
+    pub static GDB_PORT: ComPort = ("gdb.port", ComPort(1));
+}
+
 #[cfg(target_arch = "x86_64")]
 lazy_static! {
-    pub static ref LOG_PORT: Mutex<Uart16550> =
-        Mutex::new(Uart16550::try_new(io::IoReg8::IoPort(COM1)).unwrap());
+    // COM1 is tried as well since a bad parameter must not take the log down with it.
+    pub static ref LOG_PORT: IrqSpinlock<Uart16550> = IrqSpinlock::with_class(
+        crate::lock_class!("LOG_PORT"),
+        Uart16550::try_new(io::IoReg8::IoPort(CONSOLE.get().base()))
+            .or_else(|_| Uart16550::try_new(io::IoReg8::IoPort(COM1)))
+            .unwrap()
+    );
+    /// The port the GDB stub talks to, if the machine has it and it is not the console
+    pub static ref DEBUG_PORT: Option<IrqSpinlock<Uart16550>> = (GDB_PORT.get() != CONSOLE.get())
+        .then(|| Uart16550::try_new(io::IoReg8::IoPort(GDB_PORT.get().base())).ok())
+        .flatten()
+        .map(|port| IrqSpinlock::with_class(crate::lock_class!("DEBUG_PORT"), port));
 }
 
 /*
@@ -41,6 +60,40 @@ static COM7: u16 = 0x5e8;
 #[allow(unused)]
 static COM8: u16 = 0x4e8;
 
+/// One of the first four COM ports, which are named `ttyS0` to `ttyS3` on the command line as they
+/// are on Linux
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ComPort(u8);
+
+impl ComPort {
+    const COUNT: u8 = 4;
+
+    fn base(self) -> u16 {
+        match self.0 {
+            0 => COM1,
+            1 => COM2,
+            2 => COM3,
+            _ => COM4,
+        }
+    }
+}
+
+impl ParamValue for ComPort {
+    fn parse(value: Option<&'static str>) -> Result<Self, ParseError> {
+        let index = value.ok_or(ParseError::MissingValue)?.strip_prefix("ttyS");
+        match index.and_then(|index| index.parse::<u8>().ok()) {
+            Some(index) if index < Self::COUNT => Ok(ComPort(index)),
+            _ => Err(ParseError::InvalidValue),
+        }
+    }
+}
+
+impl fmt::Display for ComPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ttyS{}", self.0)
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 #[repr(transparent)]
 pub struct Uart16550 {
@@ -64,6 +117,18 @@ impl Uart16550 {
         while !self.received() {}
         (self.base).read() as char
     }
+
+    /// Sends a byte as is, unlike the [`Write`] implementation which only sends ASCII and
+    /// translates line endings
+    pub fn write_byte(&mut self, byte: u8) {
+        while self.is_transmit_empty() == 0 {}
+        (self.base).write(byte);
+    }
+
+    /// Returns the next received byte if one has arrived
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        self.received().then(|| (self.base).read())
+    }
 }
 
 impl Uart for Uart16550 {
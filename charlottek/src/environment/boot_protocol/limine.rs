@@ -1,6 +1,10 @@
 use limine::BaseRevision;
 use limine::request::{
+    EfiMemoryMapRequest,
+    EfiSystemTableRequest,
     ExecutableAddressRequest,
+    ExecutableCmdlineRequest,
+    ExecutableFileRequest,
     FramebufferRequest,
     HhdmRequest,
     MemoryMapRequest,
@@ -16,9 +20,13 @@ pub static BASE_REVISION: BaseRevision = BaseRevision::new();
 pub static MEMORY_MAP_REQUEST: MemoryMapRequest = MemoryMapRequest::new();
 pub static HHDM_REQUEST: HhdmRequest = HhdmRequest::new();
 pub static EXECUTABLE_ADDRESS_REQUEST: ExecutableAddressRequest = ExecutableAddressRequest::new();
+pub static EXECUTABLE_FILE_REQUEST: ExecutableFileRequest = ExecutableFileRequest::new();
+pub static EXECUTABLE_CMDLINE_REQUEST: ExecutableCmdlineRequest = ExecutableCmdlineRequest::new();
 pub static FRAMEBUFFER_REQUEST: FramebufferRequest = FramebufferRequest::new();
 pub static SMP_REQUEST: MpRequest = MpRequest::new();
 pub static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
+pub static EFI_SYSTEM_TABLE_REQUEST: EfiSystemTableRequest = EfiSystemTableRequest::new();
+pub static EFI_MEMORY_MAP_REQUEST: EfiMemoryMapRequest = EfiMemoryMapRequest::new();
 pub static STACK_SIZE: StackSizeRequest =
     StackSizeRequest::new().with_size((MemoryInterfaceImpl::PAGE_SIZE * 4) as u64);
 pub static MP: MpRequest = MpRequest::new().with_flags(limine::mp::RequestFlags::X2APIC);
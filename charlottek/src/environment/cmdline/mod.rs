@@ -0,0 +1,264 @@
+//! # Kernel Command Line
+//!
+//! The bootloader passes the kernel a command line, which is set with `CMDLINE:` in `limine.conf`.
+//! It is a whitespace separated list of `name=value` parameters and bare flags, e.g.
+//! `log.level=debug selftest=off heap.max=512M console=ttyS1`. A value containing whitespace can
+//! be put in double quotes.
+//!
+//! Subsystems declare the parameters they accept with [`kernel_param!`](crate::kernel_param),
+//! which gives each of them a type and a default. Declared parameters are placed in the
+//! `.kernel_params` linker section, so the parser finds all of them without a central list.
+//!
+//! The command line is parsed first thing on the BSP, before anything is logged, so that even the
+//! log's serial port can be chosen on it. Parameters that are not declared or have invalid values
+//! are reported once logging is possible. If a parameter is given more than once, the last valid
+//! occurrence wins.
+
+pub mod value;
+
+use core::fmt;
+
+use spin::Once;
+pub use value::{ParamValue, ParseError, Size};
+
+use crate::environment::boot_protocol::limine::{
+    EXECUTABLE_CMDLINE_REQUEST,
+    EXECUTABLE_FILE_REQUEST,
+};
+use crate::{logln, warn};
+
+/// The longest command line that is kept, which is the limit Linux has on x86_64
+pub const MAX_CMDLINE_LENGTH: usize = 2048;
+
+unsafe extern "C" {
+    static __kernel_params_start: u8;
+    static __kernel_params_end: u8;
+}
+
+/// The command line copied out of bootloader memory
+struct Cmdline {
+    bytes: [u8; MAX_CMDLINE_LENGTH],
+    length: usize,
+    truncated: bool,
+    not_utf8: bool,
+}
+
+static CMDLINE: Once<Cmdline> = Once::new();
+
+/// Declares one or more kernel parameters with their name on the command line and their default
+///
+/// ```ignore
+/// kernel_param! {
+///     /// Whether the self tests are run
+///     static SELF_TESTS: bool = ("selftest", true);
+/// }
+/// ```
+#[macro_export]
+macro_rules! kernel_param {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = ($key:literal, $default:expr);)*) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::environment::cmdline::Param<$ty> =
+                $crate::environment::cmdline::Param::new($key, $default);
+
+            const _: () = {
+                #[used]
+                #[unsafe(link_section = ".kernel_params")]
+                static ENTRY: &'static dyn $crate::environment::cmdline::ParamEntry = &$name;
+            };
+        )*
+    };
+}
+pub use kernel_param;
+
+/// A parameter declared with [`kernel_param!`](crate::kernel_param)
+pub struct Param<T> {
+    name: &'static str,
+    default: T,
+    value: Once<T>,
+}
+
+impl<T: ParamValue> Param<T> {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str, default: T) -> Self {
+        Param {
+            name,
+            default,
+            value: Once::new(),
+        }
+    }
+
+    /// Returns the value given on the command line or the default
+    pub fn get(&self) -> T {
+        *self.value.get().unwrap_or(&self.default)
+    }
+}
+
+/// The type independent view of a [`Param`] the parser and queries work with
+pub trait ParamEntry: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Whether a value was given on the command line
+    fn is_set(&self) -> bool;
+
+    /// The current value
+    fn value(&self) -> &dyn fmt::Display;
+
+    /// Parses `value` without storing it
+    fn check(&self, value: Option<&'static str>) -> Result<(), ParseError>;
+
+    /// Parses `value` and stores it unless a value has been stored already
+    fn set(&self, value: Option<&'static str>) -> Result<(), ParseError>;
+}
+
+impl<T: ParamValue> ParamEntry for Param<T> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn is_set(&self) -> bool {
+        self.value.is_completed()
+    }
+
+    fn value(&self) -> &dyn fmt::Display {
+        self.value.get().unwrap_or(&self.default)
+    }
+
+    fn check(&self, value: Option<&'static str>) -> Result<(), ParseError> {
+        T::parse(value).map(|_| ())
+    }
+
+    fn set(&self, value: Option<&'static str>) -> Result<(), ParseError> {
+        let value = T::parse(value)?;
+        self.value.call_once(|| value);
+        Ok(())
+    }
+}
+
+/// Returns every declared parameter
+pub fn params() -> &'static [&'static dyn ParamEntry] {
+    let start = (&raw const __kernel_params_start).cast::<&'static dyn ParamEntry>();
+    let end = (&raw const __kernel_params_end).cast::<&'static dyn ParamEntry>();
+    unsafe { core::slice::from_raw_parts(start, end.offset_from_unsigned(start)) }
+}
+
+/// Returns the declared parameter with the given name
+pub fn find(name: &str) -> Option<&'static dyn ParamEntry> {
+    params().iter().find(|param| param.name() == name).copied()
+}
+
+/// Returns the command line as it was passed, or an empty string before it has been parsed
+pub fn cmdline() -> &'static str {
+    CMDLINE.get().map_or("", |cmdline| {
+        // Invalid command lines are replaced by an empty one when they are copied.
+        core::str::from_utf8(&cmdline.bytes[..cmdline.length]).unwrap_or_default()
+    })
+}
+
+/// Splits a command line into its parameters, each of which is a name and the value after the `=`
+/// if it has one. The double quotes around a quoted value are removed.
+pub fn tokens(cmdline: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+    let mut rest = cmdline;
+    core::iter::from_fn(move || {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let mut in_quotes = false;
+        let end = rest
+            .char_indices()
+            .find(|(_, c)| {
+                if *c == '"' {
+                    in_quotes = !in_quotes;
+                }
+                c.is_whitespace() && !in_quotes
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        let (token, remainder) = rest.split_at(end);
+        rest = remainder;
+        Some(match token.split_once('=') {
+            Some((name, value)) => {
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                (name, Some(value))
+            }
+            None => (token, None),
+        })
+    })
+}
+
+fn copy_cmdline() -> Cmdline {
+    let source = EXECUTABLE_CMDLINE_REQUEST
+        .get_response()
+        .map(|response| response.cmdline())
+        .or_else(|| EXECUTABLE_FILE_REQUEST.get_response().map(|response| response.file().string()))
+        .map_or(&[][..], |cmdline| cmdline.to_bytes());
+    let mut cmdline = Cmdline {
+        bytes: [0; MAX_CMDLINE_LENGTH],
+        length: source.len().min(MAX_CMDLINE_LENGTH),
+        truncated: source.len() > MAX_CMDLINE_LENGTH,
+        not_utf8: false,
+    };
+    if cmdline.truncated {
+        // Drop the parameter that was cut off.
+        cmdline.length = source[..cmdline.length]
+            .iter()
+            .rposition(|byte| byte.is_ascii_whitespace())
+            .unwrap_or(0);
+    }
+    if core::str::from_utf8(&source[..cmdline.length]).is_ok() {
+        cmdline.bytes[..cmdline.length].copy_from_slice(&source[..cmdline.length]);
+    } else {
+        cmdline.length = 0;
+        cmdline.not_utf8 = true;
+    }
+    cmdline
+}
+
+/// Copies and parses the command line. Nothing is logged so that parameters which configure
+/// logging take effect before the first record is written.
+pub fn init() {
+    CMDLINE.call_once(copy_cmdline);
+    for param in params() {
+        let last_valid = tokens(cmdline())
+            .filter(|(name, value)| *name == param.name() && param.check(*value).is_ok())
+            .last();
+        if let Some((_, value)) = last_valid {
+            let _ = param.set(value);
+        }
+    }
+}
+
+/// Logs the command line and warns about the parameters that were ignored
+pub fn report() {
+    let Some(copied) = CMDLINE.get() else {
+        return;
+    };
+    logln!("Kernel command line: \"{}\"", (cmdline()));
+    if copied.not_utf8 {
+        warn!("The kernel command line is not valid UTF-8 and has been ignored.");
+    }
+    if copied.truncated {
+        warn!(
+            "The kernel command line is longer than {} bytes and has been truncated.",
+            MAX_CMDLINE_LENGTH
+        );
+    }
+    for (name, value) in tokens(cmdline()) {
+        match find(name) {
+            None => warn!("Ignoring unknown kernel parameter \"{}\".", name),
+            Some(param) => {
+                if let Err(error) = param.check(value) {
+                    warn!(
+                        "Ignoring kernel parameter \"{}\": {}. Its value is {}.",
+                        name,
+                        error,
+                        (param.value())
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! # Parameter Values
+//!
+//! The types a kernel parameter can have and how each of them is spelled on the command line.
+
+use core::fmt;
+
+use crate::log::Level;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// The parameter was given as a bare flag but needs a value
+    MissingValue,
+    InvalidValue,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ParseError::MissingValue => "a value is required",
+            ParseError::InvalidValue => "the value is invalid",
+        })
+    }
+}
+
+/// A type that can be given on the kernel command line
+pub trait ParamValue: Copy + Send + Sync + fmt::Display + 'static {
+    /// Parses the text after the `=`, which is `None` for a bare flag such as `selftest`
+    fn parse(value: Option<&'static str>) -> Result<Self, ParseError>;
+}
+
+/// `on`, `yes`, `true` or `1` and `off`, `no`, `false` or `0`. A bare flag means `on`.
+impl ParamValue for bool {
+    fn parse(value: Option<&'static str>) -> Result<Self, ParseError> {
+        let Some(value) = value else {
+            return Ok(true);
+        };
+        if ["on", "yes", "true", "1"].iter().any(|text| text.eq_ignore_ascii_case(value)) {
+            Ok(true)
+        } else if ["off", "no", "false", "0"].iter().any(|text| text.eq_ignore_ascii_case(value)) {
+            Ok(false)
+        } else {
+            Err(ParseError::InvalidValue)
+        }
+    }
+}
+
+impl ParamValue for Level {
+    fn parse(value: Option<&'static str>) -> Result<Self, ParseError> {
+        value.ok_or(ParseError::MissingValue)?.parse().map_err(|_| ParseError::InvalidValue)
+    }
+}
+
+impl ParamValue for &'static str {
+    fn parse(value: Option<&'static str>) -> Result<Self, ParseError> {
+        value.ok_or(ParseError::MissingValue)
+    }
+}
+
+/// A size in bytes, written as a decimal number with an optional `K`, `M` or `G` binary suffix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Size(pub usize);
+
+impl Size {
+    const UNITS: [(char, usize); 3] = [('G', 1 << 30), ('M', 1 << 20), ('K', 1 << 10)];
+
+    pub const fn from_mib(mib: usize) -> Self {
+        Size(mib << 20)
+    }
+
+    pub const fn bytes(self) -> usize {
+        self.0
+    }
+}
+
+impl ParamValue for Size {
+    fn parse(value: Option<&'static str>) -> Result<Self, ParseError> {
+        let value = value.ok_or(ParseError::MissingValue)?;
+        let (digits, multiplier) = match Self::UNITS
+            .iter()
+            .find(|(suffix, _)| value.ends_with([*suffix, suffix.to_ascii_lowercase()]))
+        {
+            Some((_, multiplier)) => (&value[..value.len() - 1], *multiplier),
+            None => (value, 1),
+        };
+        digits
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| number.checked_mul(multiplier))
+            .map(Size)
+            .ok_or(ParseError::InvalidValue)
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match Self::UNITS
+            .iter()
+            .find(|(_, multiplier)| self.0 != 0 && self.0.is_multiple_of(*multiplier))
+        {
+            Some((suffix, multiplier)) => write!(f, "{}{}", self.0 / multiplier, suffix),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
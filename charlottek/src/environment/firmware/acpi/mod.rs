@@ -1 +1,147 @@
+//! # Advanced Configuration and Power Interface (ACPI)
+//!
+//! This module locates the static ACPI tables through the RSDP provided by the bootloader. Tables
+//! are accessed in place through the higher half direct mapping and only those whose checksum is
+//! valid are returned. Interpreting AML is out of scope for the kernel itself.
 
+pub mod tables;
+
+use core::mem::size_of;
+
+use spin::Lazy;
+
+use crate::environment::boot_protocol::limine::RSDP_REQUEST;
+use crate::logln;
+use crate::memory::{HHDM_BASE, PAddr};
+
+/// The Root System Description Pointer
+#[repr(C, packed)]
+pub struct Rsdp {
+    pub signature: [u8; 8],
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub revision: u8,
+    pub rsdt_address: u32,
+    // The following fields are only present from ACPI 2.0 onwards i.e. if `revision` >= 2.
+    pub length: u32,
+    pub xsdt_address: u64,
+    pub extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+const ACPI_1_RSDP_SIZE: usize = 20;
+
+/// The header common to all system description tables
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+/// The root table listing all other tables. The XSDT is used if the firmware provides one.
+enum RootTable {
+    Rsdt(&'static SdtHeader),
+    Xsdt(&'static SdtHeader),
+}
+
+static ROOT_TABLE: Lazy<Option<RootTable>> = Lazy::new(|| {
+    let root_table = find_root_table();
+    if root_table.is_none() {
+        logln!("ACPI: No valid root system description table was found.");
+    }
+    root_table
+});
+
+/// Returns true if the bytes of `length` bytes starting at `table` sum to zero
+fn is_checksum_valid(table: *const u8, length: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts(table, length) };
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+/// Depending on the boot protocol revision the RSDP address is either physical or already
+/// translated into the higher half direct mapping.
+fn rsdp_ptr(address: usize) -> *const Rsdp {
+    let hhdm_base: usize = (*HHDM_BASE).into();
+    if address >= hhdm_base {
+        address as *const Rsdp
+    } else {
+        PAddr::from(address as u64).into()
+    }
+}
+
+fn find_root_table() -> Option<RootTable> {
+    let rsdp = rsdp_ptr(RSDP_REQUEST.get_response()?.address());
+    if unsafe { (*rsdp).signature } != *b"RSD PTR " {
+        return None;
+    }
+    if !is_checksum_valid(rsdp.cast(), ACPI_1_RSDP_SIZE) {
+        return None;
+    }
+    let rsdp = unsafe { &*rsdp };
+    if rsdp.revision >= 2
+        && is_checksum_valid((rsdp as *const Rsdp).cast(), rsdp.length as usize)
+        && rsdp.xsdt_address != 0
+    {
+        let xsdt = map_table(rsdp.xsdt_address)?;
+        if xsdt.signature == *b"XSDT" {
+            return Some(RootTable::Xsdt(xsdt));
+        }
+    }
+    let rsdt = map_table(rsdp.rsdt_address as u64)?;
+    (rsdt.signature == *b"RSDT").then_some(RootTable::Rsdt(rsdt))
+}
+
+/// Returns the table at the given physical address if its checksum is valid
+fn map_table(paddr: u64) -> Option<&'static SdtHeader> {
+    let header: *const SdtHeader = PAddr::from(paddr).into();
+    let length = unsafe { (*header).length } as usize;
+    if length < size_of::<SdtHeader>() || !is_checksum_valid(header.cast(), length) {
+        return None;
+    }
+    Some(unsafe { &*header })
+}
+
+impl RootTable {
+    fn entries(&self) -> impl Iterator<Item = u64> {
+        let (header, entry_size) = match *self {
+            RootTable::Rsdt(header) => (header, size_of::<u32>()),
+            RootTable::Xsdt(header) => (header, size_of::<u64>()),
+        };
+        let first_entry = unsafe { (header as *const SdtHeader).add(1).cast::<u8>() };
+        let n_entries = (header.length as usize - size_of::<SdtHeader>()) / entry_size;
+        (0..n_entries).map(move |index| unsafe {
+            let entry = first_entry.add(index * entry_size);
+            if entry_size == size_of::<u32>() {
+                entry.cast::<u32>().read_unaligned() as u64
+            } else {
+                entry.cast::<u64>().read_unaligned()
+            }
+        })
+    }
+}
+
+/// Returns the first valid table with the given signature
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    ROOT_TABLE
+        .as_ref()?
+        .entries()
+        .filter(|&paddr| paddr != 0)
+        .filter_map(map_table)
+        .find(|table| table.signature == *signature)
+}
+
+/// Returns the first valid table of type `T`
+pub fn find<T: tables::AcpiTable>() -> Option<&'static T> {
+    let header = find_table(&T::SIGNATURE)?;
+    if (header.length as usize) < size_of::<T>() {
+        return None;
+    }
+    Some(unsafe { &*(header as *const SdtHeader).cast::<T>() })
+}
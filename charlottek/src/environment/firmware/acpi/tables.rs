@@ -0,0 +1,269 @@
+//! # ACPI Table Layouts
+//!
+//! Only the fields the kernel currently makes use of are described. Fields that were added in later
+//! revisions of a table are read through accessors that check the length of the table first.
+
+use core::mem::offset_of;
+
+use super::SdtHeader;
+
+/// A system description table with a fixed signature
+///
+/// # Safety
+/// The type must be `repr(C, packed)` and start with an [`SdtHeader`].
+pub unsafe trait AcpiTable {
+    const SIGNATURE: [u8; 4];
+}
+
+/// The address spaces a [`GenericAddress`] can refer to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    Other(u8),
+}
+
+/// The Generic Address Structure (GAS) used to describe the location of registers
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct GenericAddress {
+    pub address_space_id: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub fn address_space(&self) -> AddressSpace {
+        match self.address_space_id {
+            0 => AddressSpace::SystemMemory,
+            1 => AddressSpace::SystemIo,
+            other => AddressSpace::Other(other),
+        }
+    }
+}
+
+/// The Fixed ACPI Description Table (FADT) up to and including the ACPI 1.0 fields
+#[repr(C, packed)]
+pub struct Fadt {
+    pub header: SdtHeader,
+    pub firmware_ctrl: u32,
+    pub dsdt: u32,
+    reserved0: u8,
+    pub preferred_pm_profile: u8,
+    pub sci_int: u16,
+    pub smi_cmd: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub s4bios_req: u8,
+    pub pstate_cnt: u8,
+    pub pm1a_evt_blk: u32,
+    pub pm1b_evt_blk: u32,
+    pub pm1a_cnt_blk: u32,
+    pub pm1b_cnt_blk: u32,
+    pub pm2_cnt_blk: u32,
+    pub pm_tmr_blk: u32,
+    pub gpe0_blk: u32,
+    pub gpe1_blk: u32,
+    pub pm1_evt_len: u8,
+    pub pm1_cnt_len: u8,
+    pub pm2_cnt_len: u8,
+    pub pm_tmr_len: u8,
+    pub gpe0_blk_len: u8,
+    pub gpe1_blk_len: u8,
+    pub gpe1_base: u8,
+    pub cst_cnt: u8,
+    pub p_lvl2_lat: u16,
+    pub p_lvl3_lat: u16,
+    pub flush_size: u16,
+    pub flush_stride: u16,
+    pub duty_offset: u8,
+    pub duty_width: u8,
+    pub day_alrm: u8,
+    pub mon_alrm: u8,
+    pub century: u8,
+    pub iapc_boot_arch: u16,
+    reserved1: u8,
+    pub flags: u32,
+}
+
+unsafe impl AcpiTable for Fadt {
+    const SIGNATURE: [u8; 4] = *b"FACP";
+}
+
+impl Fadt {
+    /// The platform implements none of the fixed ACPI hardware such as the PM timer
+    pub const FLAG_HW_REDUCED_ACPI: u32 = 1 << 20;
+    /// The PM timer counts with 32 instead of 24 bits
+    pub const FLAG_TMR_VAL_EXT: u32 = 1 << 8;
+    /// The platform has no CMOS RTC at the legacy I/O ports
+    pub const IAPC_BOOT_ARCH_CMOS_RTC_NOT_PRESENT: u16 = 1 << 5;
+    // Offset of X_PM_TMR_BLK in FADT revisions 3 and later
+    const X_PM_TMR_BLK_OFFSET: usize = 208;
+
+    /// Returns the extended PM timer block if the table is recent enough to contain it and the
+    /// firmware filled it in
+    pub fn x_pm_tmr_blk(&self) -> Option<GenericAddress> {
+        let length = self.header.length as usize;
+        if length < Self::X_PM_TMR_BLK_OFFSET + size_of::<GenericAddress>() {
+            return None;
+        }
+        let block = unsafe {
+            (self as *const Self)
+                .cast::<u8>()
+                .add(Self::X_PM_TMR_BLK_OFFSET)
+                .cast::<GenericAddress>()
+                .read_unaligned()
+        };
+        (block.address != 0).then_some(block)
+    }
+}
+
+/// The High Precision Event Timer (HPET) description table
+#[repr(C, packed)]
+pub struct Hpet {
+    pub header: SdtHeader,
+    pub event_timer_block_id: u32,
+    pub base_address: GenericAddress,
+    pub hpet_number: u8,
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+unsafe impl AcpiTable for Hpet {
+    const SIGNATURE: [u8; 4] = *b"HPET";
+}
+
+/// The Multiple APIC Description Table (MADT). The fixed fields are followed by a list of
+/// variable length interrupt controller structures.
+#[repr(C, packed)]
+pub struct Madt {
+    pub header: SdtHeader,
+    pub local_interrupt_controller_address: u32,
+    pub flags: u32,
+}
+
+unsafe impl AcpiTable for Madt {
+    const SIGNATURE: [u8; 4] = *b"APIC";
+}
+
+/// The interrupt controller structures of the MADT the kernel makes use of
+#[derive(Debug, Clone, Copy)]
+pub enum MadtEntry {
+    IoApic {
+        id: u8,
+        address: u32,
+        gsi_base: u32,
+    },
+    /// An ISA interrupt that is not identity mapped to a GSI or whose polarity or trigger mode
+    /// differs from the ISA defaults
+    InterruptSourceOverride {
+        bus: u8,
+        source: u8,
+        gsi: u32,
+        flags: u16,
+    },
+    /// A GSI that is connected to the NMI input of an IOAPIC
+    NmiSource {
+        flags: u16,
+        gsi: u32,
+    },
+    Other(u8),
+}
+
+impl Madt {
+    /// The system also has a pair of 8259 PICs which have to be disabled
+    pub const FLAG_PCAT_COMPAT: u32 = 1 << 0;
+    pub const INTI_POLARITY_ACTIVE_HIGH: u16 = 0b01;
+    pub const INTI_POLARITY_ACTIVE_LOW: u16 = 0b11;
+    /// The polarity bits of the MPS INTI flags of overrides and NMI sources
+    pub const INTI_POLARITY_MASK: u16 = 0b11;
+    pub const INTI_TRIGGER_EDGE: u16 = 0b01 << 2;
+    pub const INTI_TRIGGER_LEVEL: u16 = 0b11 << 2;
+    /// The trigger mode bits of the MPS INTI flags of overrides and NMI sources
+    pub const INTI_TRIGGER_MASK: u16 = 0b11 << 2;
+    const TYPE_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+    const TYPE_IO_APIC: u8 = 1;
+    const TYPE_NMI_SOURCE: u8 = 3;
+
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> + '_ {
+        let base = (self as *const Self).cast::<u8>();
+        let length = self.header.length as usize;
+        let mut offset = size_of::<Self>();
+        core::iter::from_fn(move || {
+            if offset + 2 > length {
+                return None;
+            }
+            let (entry_type, entry_length) =
+                unsafe { (base.add(offset).read(), base.add(offset + 1).read() as usize) };
+            if entry_length < 2 || offset + entry_length > length {
+                return None;
+            }
+            let entry = unsafe { base.add(offset) };
+            let read_u16 = |at: usize| unsafe { entry.add(at).cast::<u16>().read_unaligned() };
+            let read_u32 = |at: usize| unsafe { entry.add(at).cast::<u32>().read_unaligned() };
+            let parsed = match entry_type {
+                Self::TYPE_IO_APIC if entry_length >= 12 => MadtEntry::IoApic {
+                    id: unsafe { entry.add(2).read() },
+                    address: read_u32(4),
+                    gsi_base: read_u32(8),
+                },
+                Self::TYPE_INTERRUPT_SOURCE_OVERRIDE if entry_length >= 10 => {
+                    MadtEntry::InterruptSourceOverride {
+                        bus: unsafe { entry.add(2).read() },
+                        source: unsafe { entry.add(3).read() },
+                        gsi: read_u32(4),
+                        flags: read_u16(8),
+                    }
+                }
+                Self::TYPE_NMI_SOURCE if entry_length >= 8 => MadtEntry::NmiSource {
+                    flags: read_u16(2),
+                    gsi: read_u32(4),
+                },
+                other => MadtEntry::Other(other),
+            };
+            offset += entry_length;
+            Some(parsed)
+        })
+    }
+}
+
+/// The PCI Express memory mapped configuration space description table (MCFG). The fixed fields
+/// are followed by a list of [`McfgAllocation`]s.
+#[repr(C, packed)]
+pub struct Mcfg {
+    pub header: SdtHeader,
+    reserved: u64,
+}
+
+unsafe impl AcpiTable for Mcfg {
+    const SIGNATURE: [u8; 4] = *b"MCFG";
+}
+
+/// A region through which the configuration space of a range of buses in one PCI segment group is
+/// memory mapped
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct McfgAllocation {
+    pub base_address: u64,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+    reserved: u32,
+}
+
+impl Mcfg {
+    pub fn allocations(&self) -> impl Iterator<Item = McfgAllocation> + '_ {
+        let first = unsafe { (self as *const Self).add(1).cast::<McfgAllocation>() };
+        let count = (self.header.length as usize).saturating_sub(size_of::<Self>())
+            / size_of::<McfgAllocation>();
+        (0..count).map(move |index| unsafe { first.add(index).read_unaligned() })
+    }
+}
+
+const _: () = assert!(offset_of!(Fadt, flags) == 112);
+const _: () = assert!(offset_of!(Hpet, base_address) == 40);
+const _: () = assert!(size_of::<Madt>() == 44);
+const _: () = assert!(size_of::<Mcfg>() == 44);
+const _: () = assert!(size_of::<McfgAllocation>() == 16);
@@ -1 +1,298 @@
+//! # UEFI Runtime Services
+//!
+//! The bootloader passes the physical address of the EFI system table, through which the runtime
+//! services are located. The kernel never calls `SetVirtualAddressMap` so the runtime services
+//! expect to be called through their physical addresses. Those are only reachable if the firmware
+//! regions happen to be identity mapped in the current address space. A service is only called if
+//! its entry point lies in one of the regions the firmware's memory map marks as needed at runtime
+//! and every one of those regions is identity mapped, since the service may touch any of them. The
+//! services must not be reentered, so only one LP calls them at a time.
 
+use core::ffi::c_void;
+use core::mem::MaybeUninit;
+use core::time::Duration;
+
+use spin::Lazy;
+
+use crate::cpu::sync::IrqSpinlock;
+use crate::environment::boot_protocol::limine::{EFI_MEMORY_MAP_REQUEST, EFI_SYSTEM_TABLE_REQUEST};
+use crate::isa::interface::memory::{AddressSpaceInterface, MemoryInterface};
+use crate::isa::memory::MemoryInterfaceImpl;
+use crate::lock_class;
+use crate::memory::{HHDM_BASE, PAddr, VAddr};
+use crate::time::calendar::DateTime;
+
+const EFI_SYSTEM_TABLE_SIGNATURE: u64 = 0x5453_5953_2049_4249; // "IBI SYST"
+const EFI_RUNTIME_SERVICES_SIGNATURE: u64 = 0x5652_4553_544e_5552; // "RUNTSERV"
+const EFI_SUCCESS: usize = 0;
+/// The time zone of an [`EfiTime`] that is not relative to UTC
+const EFI_UNSPECIFIED_TIMEZONE: i16 = 0x07ff;
+
+/// Marks the memory map entries of regions the runtime services use
+const EFI_MEMORY_RUNTIME: u64 = 1 << 63;
+const EFI_PAGE_SIZE: usize = 4096;
+
+/// Held while a runtime service is being called
+static SERVICES_LOCK: IrqSpinlock<()> =
+    IrqSpinlock::with_class(lock_class!("UEFI_RUNTIME_SERVICES"), ());
+
+/// The header common to all EFI tables
+#[repr(C)]
+pub struct EfiTableHeader {
+    pub signature: u64,
+    pub revision: u32,
+    pub header_size: u32,
+    pub crc32: u32,
+    reserved: u32,
+}
+
+#[repr(C)]
+pub struct EfiSystemTable {
+    pub header: EfiTableHeader,
+    pub firmware_vendor: *const u16,
+    pub firmware_revision: u32,
+    pub console_in_handle: *const c_void,
+    pub con_in: *const c_void,
+    pub console_out_handle: *const c_void,
+    pub con_out: *const c_void,
+    pub standard_error_handle: *const c_void,
+    pub std_err: *const c_void,
+    pub runtime_services: *const EfiRuntimeServices,
+    pub boot_services: *const c_void,
+    pub number_of_table_entries: usize,
+    pub configuration_table: *const c_void,
+}
+
+#[repr(C)]
+pub struct EfiTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pad1: u8,
+    pub nanosecond: u32,
+    /// The offset from UTC in minutes
+    pub time_zone: i16,
+    pub daylight: u8,
+    pad2: u8,
+}
+
+pub type EfiGetTime =
+    unsafe extern "efiapi" fn(time: *mut EfiTime, capabilities: *mut c_void) -> usize;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EfiGuid {
+    pub data1: u32,
+    pub data2: u16,
+    pub data3: u16,
+    pub data4: [u8; 8],
+}
+
+/// The variable is kept across resets
+pub const EFI_VARIABLE_NON_VOLATILE: u32 = 1 << 0;
+pub const EFI_VARIABLE_BOOTSERVICE_ACCESS: u32 = 1 << 1;
+pub const EFI_VARIABLE_RUNTIME_ACCESS: u32 = 1 << 2;
+
+pub type EfiGetVariable = unsafe extern "efiapi" fn(
+    name: *const u16,
+    vendor: *const EfiGuid,
+    attributes: *mut u32,
+    data_size: *mut usize,
+    data: *mut c_void,
+) -> usize;
+pub type EfiSetVariable = unsafe extern "efiapi" fn(
+    name: *const u16,
+    vendor: *const EfiGuid,
+    attributes: u32,
+    data_size: usize,
+    data: *const c_void,
+) -> usize;
+
+/// The runtime services table. Only the services the kernel calls are typed.
+#[repr(C)]
+pub struct EfiRuntimeServices {
+    pub header: EfiTableHeader,
+    pub get_time: EfiGetTime,
+    pub set_time: usize,
+    pub get_wakeup_time: usize,
+    pub set_wakeup_time: usize,
+    pub set_virtual_address_map: usize,
+    pub convert_pointer: usize,
+    pub get_variable: EfiGetVariable,
+    pub get_next_variable_name: usize,
+    pub set_variable: EfiSetVariable,
+    pub get_next_high_monotonic_count: usize,
+    pub reset_system: usize,
+    pub update_capsule: usize,
+    pub query_capsule_capabilities: usize,
+    pub query_variable_info: usize,
+}
+
+/// The start of an entry of the firmware's memory map. Entries may be larger than this.
+#[repr(C)]
+struct EfiMemoryDescriptor {
+    memory_type: u32,
+    physical_start: u64,
+    virtual_start: u64,
+    number_of_pages: u64,
+    attribute: u64,
+}
+
+/// Depending on the boot protocol revision the system table address is either physical or already
+/// translated into the higher half direct mapping.
+fn to_hhdm<T>(address: usize) -> *const T {
+    let hhdm_base: usize = (*HHDM_BASE).into();
+    if address >= hhdm_base {
+        address as *const T
+    } else {
+        PAddr::from(address as u64).into()
+    }
+}
+
+/// Returns the runtime services table if the firmware passed a valid system table
+pub fn runtime_services() -> Option<&'static EfiRuntimeServices> {
+    let response = EFI_SYSTEM_TABLE_REQUEST.get_response()?;
+    let system_table = unsafe { &*to_hhdm::<EfiSystemTable>(response.address()) };
+    if system_table.header.signature != EFI_SYSTEM_TABLE_SIGNATURE
+        || system_table.runtime_services.is_null()
+    {
+        return None;
+    }
+    let runtime_services =
+        unsafe { &*to_hhdm::<EfiRuntimeServices>(system_table.runtime_services as usize) };
+    (runtime_services.header.signature == EFI_RUNTIME_SERVICES_SIGNATURE)
+        .then_some(runtime_services)
+}
+
+/// Returns the base and length of every region the firmware's memory map marks as used by the
+/// runtime services
+fn runtime_regions() -> impl Iterator<Item = (usize, usize)> {
+    let response = EFI_MEMORY_MAP_REQUEST.get_response();
+    let (memmap, size, stride) = response.map_or((core::ptr::null(), 0, 1), |response| {
+        (
+            to_hhdm::<u8>(response.memmap() as usize),
+            response.memmap_size() as usize,
+            (response.desc_size() as usize).max(size_of::<EfiMemoryDescriptor>()),
+        )
+    });
+    (0..size / stride)
+        .map(move |index| unsafe { &*memmap.add(index * stride).cast::<EfiMemoryDescriptor>() })
+        .filter(|descriptor| descriptor.attribute & EFI_MEMORY_RUNTIME != 0)
+        .map(|descriptor| {
+            (
+                descriptor.physical_start as usize,
+                descriptor.number_of_pages as usize * EFI_PAGE_SIZE,
+            )
+        })
+}
+
+/// Whether every runtime services region is identity mapped in the kernel's address space. The
+/// kernel never unmaps them, so this is only checked once.
+static RUNTIME_REGIONS_MAPPED: Lazy<bool> = Lazy::new(|| {
+    let mut address_space = <MemoryInterfaceImpl as MemoryInterface>::AddressSpace::get_current();
+    let mut regions = runtime_regions().peekable();
+    regions.peek().is_some()
+        && regions.all(|(base, length)| {
+            (base..base + length).step_by(EFI_PAGE_SIZE).all(|address| {
+                address_space.translate_address(VAddr::from(address)).ok()
+                    == Some(PAddr::from(address as u64))
+            })
+        })
+});
+
+/// Returns true if the runtime service at `address` can be called from the current address space
+fn is_callable(address: usize) -> bool {
+    *RUNTIME_REGIONS_MAPPED
+        && runtime_regions().any(|(base, length)| (base..base + length).contains(&address))
+}
+
+/// Reads the current date and time from the firmware, converted to UTC
+pub fn get_time() -> Option<DateTime> {
+    let get_time = runtime_services()?.get_time;
+    if !is_callable(get_time as usize) {
+        return None;
+    }
+    let mut time = MaybeUninit::<EfiTime>::uninit();
+    let status = {
+        let _guard = SERVICES_LOCK.lock();
+        unsafe { get_time(time.as_mut_ptr(), core::ptr::null_mut()) }
+    };
+    if status != EFI_SUCCESS {
+        return None;
+    }
+    let time = unsafe { time.assume_init() };
+    let date_time = DateTime {
+        year: time.year,
+        month: time.month,
+        day: time.day,
+        hour: time.hour,
+        minute: time.minute,
+        second: time.second,
+        nanosecond: time.nanosecond,
+    };
+    let local = date_time.to_unix_time()?;
+    if time.time_zone == EFI_UNSPECIFIED_TIMEZONE {
+        return Some(date_time);
+    }
+    // The time zone is the offset of local time from UTC.
+    let offset = Duration::from_secs(time.time_zone.unsigned_abs() as u64 * 60);
+    let utc = if time.time_zone < 0 {
+        local.checked_add(offset)?
+    } else {
+        local.checked_sub(offset)?
+    };
+    Some(DateTime::from_unix_time(utc))
+}
+
+/// Reads a variable into `data` and returns its length. `name` must be null terminated.
+pub fn get_variable(name: &[u16], vendor: &EfiGuid, data: &mut [u8]) -> Option<usize> {
+    let get_variable = runtime_services()?.get_variable;
+    if !is_callable(get_variable as usize) {
+        return None;
+    }
+    let mut data_size = data.len();
+    let _guard = SERVICES_LOCK.lock();
+    let status = unsafe {
+        get_variable(
+            name.as_ptr(),
+            vendor,
+            core::ptr::null_mut(),
+            &mut data_size,
+            data.as_mut_ptr().cast(),
+        )
+    };
+    (status == EFI_SUCCESS).then_some(data_size)
+}
+
+/// Writes a variable, or deletes it if `data` is empty. `name` must be null terminated. Returns
+/// false if the firmware could not be called or refused the write.
+pub fn set_variable(name: &[u16], vendor: &EfiGuid, attributes: u32, data: &[u8]) -> bool {
+    let _guard = SERVICES_LOCK.lock();
+    call_set_variable(name, vendor, attributes, data)
+}
+
+/// Like [`set_variable`] but returns false instead of waiting if another LP is calling a runtime
+/// service, which may never return if that LP has been stopped
+pub fn try_set_variable(name: &[u16], vendor: &EfiGuid, attributes: u32, data: &[u8]) -> bool {
+    let Some(_guard) = SERVICES_LOCK.try_lock() else {
+        return false;
+    };
+    call_set_variable(name, vendor, attributes, data)
+}
+
+fn call_set_variable(name: &[u16], vendor: &EfiGuid, attributes: u32, data: &[u8]) -> bool {
+    let Some(runtime_services) = runtime_services() else {
+        return false;
+    };
+    let set_variable = runtime_services.set_variable;
+    if !is_callable(set_variable as usize) {
+        return false;
+    }
+    let status = unsafe {
+        set_variable(name.as_ptr(), vendor, attributes, data.len(), data.as_ptr().cast())
+    };
+    status == EFI_SUCCESS
+}
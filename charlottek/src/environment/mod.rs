@@ -7,4 +7,5 @@
 //! interface over the supported boot protocols.
 
 pub mod boot_protocol;
+pub mod cmdline;
 pub mod firmware;
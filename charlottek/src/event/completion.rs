@@ -0,0 +1,167 @@
+//! # Completions
+//!
+//! A completion records that something has happened exactly once. Any number of threads can wait
+//! for it and all of them are released when it completes. Continuations registered with
+//! [`Completion::on_complete`] and async tasks awaiting [`Completion::wait_async`] are run and
+//! woken by whoever completes it.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+
+use super::Observer;
+use crate::cpu::sync::context::debug_assert_can_block;
+use crate::cpu::sync::{Deadline, IrqSpinlock, WaitQueue};
+use crate::lock_class;
+
+type Continuation = Box<dyn FnOnce() + Send>;
+
+/// What has to happen once the completion completes
+struct Pending {
+    continuations: Vec<Continuation>,
+    wakers: Vec<Waker>,
+}
+
+pub struct Completion {
+    is_complete: AtomicBool,
+    waiters: WaitQueue,
+    // Only ever set while this is locked so registering cannot race with completing.
+    pending: IrqSpinlock<Pending>,
+}
+
+impl Completion {
+    pub const fn new() -> Self {
+        Completion {
+            is_complete: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            pending: IrqSpinlock::with_class(
+                lock_class!("Completion.pending"),
+                Pending {
+                    continuations: Vec::new(),
+                    wakers: Vec::new(),
+                },
+            ),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.is_complete.load(Ordering::Acquire)
+    }
+
+    /// Completes the completion, releasing all waiters and running all continuations in the
+    /// current context. Returns false if it had already completed, in which case nothing happens.
+    pub fn complete(&self) -> bool {
+        let pending = {
+            let mut pending = self.pending.lock();
+            if self.is_complete.swap(true, Ordering::AcqRel) {
+                return false;
+            }
+            Pending {
+                continuations: core::mem::take(&mut pending.continuations),
+                wakers: core::mem::take(&mut pending.wakers),
+            }
+        };
+        self.waiters.wake_all();
+        pending.wakers.into_iter().for_each(Waker::wake);
+        for continuation in pending.continuations {
+            continuation();
+        }
+        true
+    }
+
+    /// Makes the completion available to be completed again. Must not be called while anything is
+    /// waiting for it.
+    pub fn reset(&self) {
+        let _pending = self.pending.lock();
+        self.is_complete.store(false, Ordering::Release);
+    }
+
+    /// Runs `continuation` once the completion completes, or right away if it already has. It
+    /// runs in the context of whoever completes it and must not block.
+    pub fn on_complete(&self, continuation: impl FnOnce() + Send + 'static) {
+        {
+            let mut pending = self.pending.lock();
+            if !self.is_complete() {
+                pending.continuations.push(Box::new(continuation));
+                return;
+            }
+        }
+        continuation();
+    }
+
+    /// Blocks the current thread until the completion completes
+    pub fn wait(&self) {
+        debug_assert_can_block();
+        self.wait_until(None);
+    }
+
+    /// Blocks the current thread until the completion completes or `timeout` elapses. Returns
+    /// false if it timed out.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        debug_assert_can_block();
+        self.wait_until(Some(Deadline::after(timeout)))
+    }
+
+    fn wait_until(&self, deadline: Option<Deadline>) -> bool {
+        while !self.is_complete() {
+            let result = self.waiters.wait_if(|| !self.is_complete(), deadline);
+            if result.timed_out() {
+                return self.is_complete();
+            }
+        }
+        true
+    }
+
+    /// Returns a future that resolves once the completion completes
+    pub fn wait_async(&self) -> CompletionFuture<'_> {
+        CompletionFuture {
+            completion: self,
+        }
+    }
+
+    /// Registers `waker` to be woken once the completion completes. Returns true instead if it
+    /// already has.
+    pub fn register_waker(&self, waker: &Waker) -> bool {
+        let mut pending = self.pending.lock();
+        if self.is_complete() {
+            return true;
+        }
+        if !pending.wakers.iter().any(|registered| registered.will_wake(waker)) {
+            pending.wakers.push(waker.clone());
+        }
+        false
+    }
+}
+
+impl Default for Completion {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Observer for Completion {
+    fn notify(&self) {
+        self.complete();
+    }
+}
+
+/// Resolves once a [`Completion`] completes
+pub struct CompletionFuture<'a> {
+    completion: &'a Completion,
+}
+
+impl Future for CompletionFuture<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.completion.register_waker(cx.waker()) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
@@ -1,69 +1,52 @@
-pub trait Event {
-    fn register_observer(&mut self, observer: &dyn Observer);
-}
+//! # Events
+//!
+//! An [`Event`] notifies the [`Observer`]s registered with it whenever it occurs. Observers are
+//! shared with the event through an [`Arc`] and are notified through a shared reference, so they
+//! keep their state in atomics or behind locks. Notifications may be delivered in interrupt context
+//! and observers must not block while handling them.
+//!
+//! [`EventSource`] is the event implementation drivers signal, including from their interrupt
+//! handlers. Threads wait for events through one of the provided observers: a [`Completion`] for
+//! something that happens once and a [`Sentinel`] for something that happens repeatedly. Both can
+//! also be awaited from async code.
 
-pub trait Observer {
-    fn notify(&mut self);
-}
+pub mod completion;
+pub mod sentinel;
+pub mod source;
 
-pub struct Completion {
-    completed: bool,
-    callback:  Option<fn()>,
-}
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU64, Ordering};
 
-impl Completion {
-    pub fn new(callback: Option<fn()>) -> Self {
-        Completion {
-            completed: false,
-            callback,
-        }
-    }
+pub use completion::Completion;
+pub use sentinel::Sentinel;
+pub use source::EventSource;
 
-    pub fn poll(&self) -> bool {
-        self.completed
-    }
+static NEXT_OBSERVER_ID: AtomicU64 = AtomicU64::new(0);
 
-    pub fn register_callback(&mut self, callback: fn()) {
-        self.callback.replace(callback);
-    }
-}
+/// Identifies the registration of an observer with an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObserverId(u64);
 
-impl Observer for Completion {
-    fn notify(&mut self) {
-        self.completed = true;
-        if let Some(cb) = self.callback {
-            cb();
-        }
+impl ObserverId {
+    pub fn new() -> Self {
+        ObserverId(NEXT_OBSERVER_ID.fetch_add(1, Ordering::Relaxed))
     }
 }
 
-pub struct Sentinel {
-    times_notified: u64,
-    callback: Option<fn()>,
-}
-
-impl Sentinel {
-    pub fn new(callback: Option<fn()>) -> Self {
-        Sentinel {
-            times_notified: 0,
-            callback,
-        }
-    }
-
-    pub fn get_times_notified(&self) -> u64 {
-        self.times_notified
+impl Default for ObserverId {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    pub fn register_callback(&mut self, callback: fn()) {
-        self.callback.replace(callback);
-    }
+pub trait Event {
+    /// Notifies `observer` every time the event occurs until it is unregistered
+    fn register_observer(&self, observer: Arc<dyn Observer>) -> ObserverId;
+    /// Stops notifying an observer. Returns false if it was not registered.
+    fn unregister_observer(&self, id: ObserverId) -> bool;
 }
 
-impl Observer for Sentinel {
-    fn notify(&mut self) {
-        self.times_notified += 1;
-        if let Some(cb) = self.callback {
-            cb();
-        }
-    }
+pub trait Observer: Send + Sync {
+    /// Called every time an event the observer is registered with occurs. Must not block.
+    fn notify(&self);
 }
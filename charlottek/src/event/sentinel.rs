@@ -0,0 +1,107 @@
+//! # Sentinels
+//!
+//! A sentinel counts how often the events it observes have occurred. Threads wait for the count to
+//! reach a given value, which lets a waiter that falls behind catch up without missing any
+//! occurrence.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+
+use super::Observer;
+use crate::cpu::sync::context::debug_assert_can_block;
+use crate::cpu::sync::{Deadline, IrqSpinlock, WaitQueue};
+use crate::lock_class;
+
+type Callback = Box<dyn Fn() + Send + Sync>;
+
+pub struct Sentinel {
+    times_notified: AtomicU64,
+    waiters: WaitQueue,
+    wakers: IrqSpinlock<Vec<Waker>>,
+    callback: Option<Callback>,
+}
+
+impl Sentinel {
+    pub const fn new() -> Self {
+        Sentinel {
+            times_notified: AtomicU64::new(0),
+            waiters: WaitQueue::new(),
+            wakers: IrqSpinlock::with_class(lock_class!("Sentinel.wakers"), Vec::new()),
+            callback: None,
+        }
+    }
+
+    /// Creates a sentinel that also runs `callback` on every notification. The callback runs in
+    /// the context of the notifier and must not block.
+    pub fn with_callback(callback: impl Fn() + Send + Sync + 'static) -> Self {
+        Sentinel {
+            callback: Some(Box::new(callback)),
+            ..Self::new()
+        }
+    }
+
+    pub fn get_times_notified(&self) -> u64 {
+        self.times_notified.load(Ordering::Acquire)
+    }
+
+    /// Blocks the current thread until the sentinel has been notified at least `count` times
+    pub fn wait_for(&self, count: u64) {
+        debug_assert_can_block();
+        self.wait_until(count, None);
+    }
+
+    /// Blocks the current thread until the sentinel has been notified at least `count` times or
+    /// `timeout` elapses. Returns false if it timed out.
+    pub fn wait_for_timeout(&self, count: u64, timeout: Duration) -> bool {
+        debug_assert_can_block();
+        self.wait_until(count, Some(Deadline::after(timeout)))
+    }
+
+    fn wait_until(&self, count: u64, deadline: Option<Deadline>) -> bool {
+        while self.get_times_notified() < count {
+            let result = self.waiters.wait_if(|| self.get_times_notified() < count, deadline);
+            if result.timed_out() {
+                return self.get_times_notified() >= count;
+            }
+        }
+        true
+    }
+
+    /// Polls whether the sentinel has been notified at least `count` times, registering the
+    /// context's waker to be woken on the next notification if it has not
+    pub fn poll_count(&self, count: u64, cx: &mut Context<'_>) -> Poll<u64> {
+        let mut wakers = self.wakers.lock();
+        let times_notified = self.get_times_notified();
+        if times_notified >= count {
+            return Poll::Ready(times_notified);
+        }
+        if !wakers.iter().any(|registered| registered.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+impl Default for Sentinel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Observer for Sentinel {
+    fn notify(&self) {
+        let wakers = {
+            let mut wakers = self.wakers.lock();
+            self.times_notified.fetch_add(1, Ordering::AcqRel);
+            core::mem::take(&mut *wakers)
+        };
+        self.waiters.wake_all();
+        wakers.into_iter().for_each(Waker::wake);
+        if let Some(callback) = &self.callback {
+            callback();
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! # Event Sources
+//!
+//! An event source is signalled by whatever produces the event, typically a driver's interrupt
+//! handler. Signalling only takes a lock for long enough to take a reference to the current list of
+//! observers, which registering and unregistering replace rather than modify in place, so observers
+//! may register and unregister themselves while being notified.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::{Event, Observer, ObserverId};
+use crate::cpu::sync::IrqSpinlock;
+use crate::lock_class;
+
+type Observers = Arc<Vec<(ObserverId, Arc<dyn Observer>)>>;
+
+pub struct EventSource {
+    // Signalled from interrupt handlers.
+    observers: IrqSpinlock<Option<Observers>>,
+    times_signalled: AtomicU64,
+}
+
+impl EventSource {
+    pub const fn new() -> Self {
+        EventSource {
+            observers: IrqSpinlock::with_class(lock_class!("EventSource.observers"), None),
+            times_signalled: AtomicU64::new(0),
+        }
+    }
+
+    /// Notifies every registered observer in the current context. Safe to call from interrupt
+    /// handlers. Should the last reference to an unregistered observer be dropped here, the
+    /// observer is dropped in the current context as well.
+    pub fn signal(&self) {
+        self.times_signalled.fetch_add(1, Ordering::Relaxed);
+        let observers = self.observers.lock().clone();
+        for (_, observer) in observers.iter().flat_map(|observers| observers.iter()) {
+            observer.notify();
+        }
+    }
+
+    pub fn get_times_signalled(&self) -> u64 {
+        self.times_signalled.load(Ordering::Relaxed)
+    }
+
+    pub fn has_observers(&self) -> bool {
+        self.observers.lock().as_ref().is_some_and(|observers| !observers.is_empty())
+    }
+}
+
+impl Default for EventSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Event for EventSource {
+    fn register_observer(&self, observer: Arc<dyn Observer>) -> ObserverId {
+        let id = ObserverId::new();
+        let mut observers = self.observers.lock();
+        let mut updated = observers.as_deref().cloned().unwrap_or_default();
+        updated.push((id, observer));
+        *observers = Some(Arc::new(updated));
+        id
+    }
+
+    fn unregister_observer(&self, id: ObserverId) -> bool {
+        let mut observers = self.observers.lock();
+        let Some(current) = observers.as_deref() else {
+            return false;
+        };
+        let Some(index) = current.iter().position(|(registered, _)| *registered == id) else {
+            return false;
+        };
+        let mut updated = current.clone();
+        let (_, observer) = updated.remove(index);
+        *observers = Some(Arc::new(updated));
+        drop(observers);
+        // The observer may run arbitrary code when dropped, so not while the lock is held.
+        drop(observer);
+        true
+    }
+}
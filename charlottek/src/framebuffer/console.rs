@@ -2,6 +2,7 @@ use core::fmt;
 
 use spin::mutex::TicketMutex;
 
+use crate::cpu::sync::IrqSpinlock;
 use crate::framebuffer::chars::{FONT_HEIGHT, FONT_WIDTH};
 use crate::framebuffer::colors::Color;
 use crate::framebuffer::framebuffer::FRAMEBUFFER;
@@ -9,7 +10,8 @@ use crate::framebuffer::framebuffer::FRAMEBUFFER;
 pub const CONSOLE_WIDTH: usize = 80;
 pub const CONSOLE_HEIGHT: usize = 50;
 
-pub static CONSOLE: TicketMutex<Console> = TicketMutex::new(Console::new());
+pub static CONSOLE: IrqSpinlock<Console> =
+    IrqSpinlock::with_class(crate::lock_class!("CONSOLE"), Console::new());
 
 /// Represents a single character on the framebuffer console
 #[derive(Copy, Clone)]
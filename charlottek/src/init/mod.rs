@@ -1,5 +1,11 @@
+use alloc::boxed::Box;
+
+use crate::cpu::scheduler::GLOBAL_SCHEDULER;
+use crate::cpu::scheduler::round_robin::RoundRobin;
 use crate::isa::init::IsaInitializer;
 use crate::isa::interface::init::InitInterface;
+use crate::isa::interface::interrupts::InterruptManagerIfce;
+use crate::isa::interrupts::InterruptManager;
 use crate::isa::lp;
 use crate::logln;
 use crate::memory::PHYSICAL_FRAME_ALLOCATOR;
@@ -31,6 +37,25 @@ pub fn bsp_init() {
         }
     }
     logln!("Intialized kernel allocator.");
+    crate::log::persist::init();
+    logln!("Locating kernel symbols...");
+    crate::debug::symbols::init();
+    logln!("Initializing external interrupt routing...");
+    if let Err(e) = InterruptManager::init_external_interrupts() {
+        logln!("External interrupts are unavailable: {:?}", e);
+    }
+    logln!("Initializing time keeping...");
+    crate::time::init();
+    logln!("Calibrating the LP local timer...");
+    crate::cpu::timers::calibrate();
+    crate::cpu::timers::init_local();
+    crate::debug::watchdog::init_local();
+    crate::isa::mca::start_polling();
+    crate::debug::gdb::init();
+    crate::debug::monitor::init();
+    crate::cpu::multiprocessor::set_local_lp_online();
+    logln!("Starting the LP scheduler...");
+    GLOBAL_SCHEDULER.start_local_lp_scheduler(Box::new(RoundRobin::new()));
     logln!("ISA independent initialization complete.");
     logln!("BSP initialization complete.");
 }
@@ -46,4 +71,10 @@ pub fn ap_init() {
             panic!("LP {}: ISA specific initialization failed: {:?}", lp_id, e);
         }
     }
+    crate::time::lp_sync::synchronize_local();
+    crate::cpu::timers::init_local();
+    crate::debug::watchdog::init_local();
+    crate::isa::mca::start_polling();
+    crate::cpu::multiprocessor::set_local_lp_online();
+    GLOBAL_SCHEDULER.start_local_lp_scheduler(Box::new(RoundRobin::new()));
 }
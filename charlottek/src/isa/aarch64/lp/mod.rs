@@ -0,0 +1,4 @@
+// aarch64 Logical Processor Operations
+pub mod ops;
+
+pub type LpId = u32;
@@ -0,0 +1,45 @@
+use core::arch::asm;
+
+/// Returns the address of the current LP's local data area, which is kept in TPIDR_EL1
+#[inline(always)]
+pub fn lp_local_base() -> usize {
+    let base: usize;
+    unsafe {
+        asm!("mrs {}, tpidr_el1", out(reg) base, options(nomem, nostack, preserves_flags));
+    }
+    base
+}
+
+/// Points TPIDR_EL1 at the current LP's local data area
+///
+/// # Safety
+/// `base` must point to an initialized local data area that is never freed.
+pub unsafe fn set_lp_local_base(base: usize) {
+    unsafe {
+        asm!("msr tpidr_el1, {}", in(reg) base, options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Returns true if IRQs are unmasked on the current LP
+#[inline(always)]
+pub fn interrupts_enabled() -> bool {
+    const DAIF_IRQ_MASK: u64 = 1 << 7;
+    let daif: u64;
+    unsafe {
+        asm!("mrs {}, daif", out(reg) daif, options(nomem, nostack, preserves_flags));
+    }
+    daif & DAIF_IRQ_MASK == 0
+}
+
+/// Unmasks IRQs and waits until one has been handled. IRQs are masked again on return if they were
+/// masked on entry.
+#[inline(always)]
+pub fn wait_for_interrupt() {
+    let were_enabled = interrupts_enabled();
+    unsafe {
+        asm!("msr daifclr, #2", "wfi", options(nomem, nostack));
+        if !were_enabled {
+            asm!("msr daifset, #2", options(nomem, nostack));
+        }
+    }
+}
@@ -120,6 +120,18 @@ impl CpuInfoIfce for CpuInfo {
         }
     }
 
+    fn get_timestamp_frequency() -> Option<u64> {
+        let mut cntfrq_el0: u64;
+        unsafe {
+            core::arch::asm!("mrs {}, cntfrq_el0", out(reg) cntfrq_el0);
+        }
+        if cntfrq_el0 == 0 {
+            None
+        } else {
+            Some(cntfrq_el0)
+        }
+    }
+
     fn is_extension_supported(_extension: Self::IsaExtension) -> bool {
         false
     }
@@ -1,8 +1,32 @@
-use crate::isa::lp;
+//! # Interrupt Management Interfaces
+//!
+//! Every LP sets up its own interrupt controller and records how other LPs can address it. IPIs
+//! are delivered through each target's mailbox, so an IPI is a value of the ISA specific request
+//! type rather than a bare vector. External interrupts are identified by their ACPI global system
+//! interrupt (GSI) number and can be directed to any LP.
+
+use crate::cpu::multiprocessor::lp_mask::LpMask;
+use crate::isa::lp::LpId;
+
 pub trait InterruptManagerIfce {
-    type Error;
+    type Error: core::fmt::Debug;
     type Ipi;
 
+    /// Sets up the current LP's interrupt controller. Must be called on every LP before any IPI is
+    /// sent to it.
     fn init_interrupt_structures() -> Result<(), Self::Error>;
-    fn send_ipi(lp_list: &[LpControl::LpId], ipi: Self::Ipi);
+    /// Discovers the controllers external interrupts arrive through. Must be called once on the BSP
+    /// after the kernel allocator has been initialized.
+    fn init_external_interrupts() -> Result<(), Self::Error>;
+    /// Calls `handler` on `target_lp` whenever the given global system interrupt is raised
+    fn request_irq(
+        gsi: u32,
+        handler: impl Fn() + Send + Sync + 'static,
+        target_lp: LpId,
+    ) -> Result<(), Self::Error>;
+    fn free_irq(gsi: u32) -> Result<(), Self::Error>;
+    /// Sends `ipi` to every LP in `targets`
+    fn send_ipi(targets: LpMask, ipi: Self::Ipi);
+    /// Sends `ipi` to every online LP other than the current one
+    fn broadcast_ipi(ipi: Self::Ipi);
 }
@@ -14,6 +14,12 @@ pub trait CoreStateIfce {
  * unmask_interrupts!() enables interrupts on the current logical processor.
  * curr_lic_id!() evaluates to the ID of the current local interrupt controller.
  * curr_lp_id!() evaluates to the ID of the current logical processor.
+ * The following functions must also be defined:
+ * interrupts_enabled() returns true if interrupts are unmasked on the current logical processor.
+ * read_timestamp() returns the current value of the logical processor's timestamp counter.
+ * lp_local_base() returns the address of the current logical processor's local data area.
+ * set_lp_local_base(base) sets the address of the current logical processor's local data area.
+ * wait_for_interrupt() unmasks interrupts, waits for one to be handled and restores the mask.
  * The following type aliases must also be defined:
  * LpId: The type used for logical processor IDs.
  *
@@ -12,5 +12,7 @@ pub trait CpuInfoIfce {
     fn get_model() -> Self::Model;
     fn get_vaddr_sig_bits() -> u8;
     fn get_paddr_sig_bits() -> u8;
+    /// The frequency of the timestamp counter in Hz, if the processor reports it
+    fn get_timestamp_frequency() -> Option<u64>;
     fn is_extension_supported(extension: Self::IsaExtension) -> bool;
 }
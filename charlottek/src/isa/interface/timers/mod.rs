@@ -0,0 +1,55 @@
+//! # Timer Interfaces
+//!
+//! Every LP has a timer of its own which raises an interrupt on that LP only. The kernel uses it to
+//! generate the scheduler tick and to expire kernel timers. Every LP also has a free running
+//! timestamp counter, read with `read_timestamp()`, which the kernel keeps time with.
+
+use core::time::Duration;
+
+/// The ways in which an LP local timer can be armed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    /// The timer fires repeatedly with a fixed period
+    Periodic,
+    /// The timer fires once after a delay
+    OneShot,
+    /// The timer fires once the LP's timestamp counter reaches a given value
+    TimestampDeadline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// There is no clock with a known frequency to calibrate the timer against
+    NoReferenceClock,
+    /// The timer did not count during calibration
+    CalibrationFailed,
+}
+
+pub trait TimerIfce {
+    /// Determines the frequency of the timer. Must be called once on the BSP before any LP sets up
+    /// its timer.
+    fn calibrate() -> Result<(), Error>;
+    /// Sets up the current LP's timer. The timer is left disarmed.
+    fn init_local() -> Result<(), Error>;
+    /// The frequency the timer counts at in Hz
+    fn get_frequency() -> u64;
+    fn is_mode_supported(mode: TimerMode) -> bool;
+    /// Arms the timer to fire every `period`
+    fn arm_periodic(period: Duration);
+    /// Arms the timer to fire once after `delay`. Delays longer than the timer can count are
+    /// clamped, in which case it fires early.
+    fn arm_one_shot(delay: Duration);
+    /// Arms the timer to fire once the timestamp counter reaches `timestamp`. Only available if
+    /// [`TimerMode::TimestampDeadline`] is supported.
+    fn arm_timestamp_deadline(timestamp: u64);
+    fn disarm();
+}
+
+/// The free running counter read by `read_timestamp()`
+pub trait TimestampCounterIfce {
+    /// Returns true if the counter runs at a constant rate in all power and performance states
+    fn is_invariant() -> bool;
+    /// Measures the frequency of the counter in Hz against a reference clock. Returns `None` if
+    /// there is no reference clock.
+    fn measure_frequency() -> Option<u64>;
+}
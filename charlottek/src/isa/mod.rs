@@ -7,6 +7,7 @@
 //! - [`Logical Processor Control`](lp): logical processor operating state control
 //! - [`Memory`](memory): wrappers over ISA specific memory management structures
 //! - [`System Information`](system_info): ISA specific system information
+//! - [`Timers`](timers): LP local timers
 
 #[cfg(target_arch = "aarch64")]
 mod aarch64;
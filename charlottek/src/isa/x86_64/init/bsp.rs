@@ -2,14 +2,24 @@ use spin::Lazy;
 
 use super::INTERRUPT_STACK_SIZE;
 use super::gdt::*;
+use crate::cpu::percpu;
 use crate::isa::interrupts::idt::Idt;
 use crate::isa::interrupts::register_fixed_isr_gates;
 use crate::logln;
 
 static mut BSP_INTERRUPT_STACK: [u8; INTERRUPT_STACK_SIZE] = [0u8; INTERRUPT_STACK_SIZE];
 static mut BSP_DF_STACK: [u8; INTERRUPT_STACK_SIZE] = [0u8; INTERRUPT_STACK_SIZE];
+static mut BSP_NMI_STACK: [u8; INTERRUPT_STACK_SIZE] = [0u8; INTERRUPT_STACK_SIZE];
+static mut BSP_MC_STACK: [u8; INTERRUPT_STACK_SIZE] = [0u8; INTERRUPT_STACK_SIZE];
 static BSP_TSS: Lazy<Tss> = Lazy::new(|| {
-    Tss::new((&raw const BSP_INTERRUPT_STACK) as u64, (&raw const BSP_DF_STACK) as u64)
+    Tss::new(
+        stack_top(&raw const BSP_INTERRUPT_STACK),
+        [
+            stack_top(&raw const BSP_DF_STACK),
+            stack_top(&raw const BSP_NMI_STACK),
+            stack_top(&raw const BSP_MC_STACK),
+        ],
+    )
 });
 static BSP_GDT: Lazy<Gdt> = Lazy::new(|| Gdt::new(&BSP_TSS));
 static BSP_IDT: Lazy<Idt> = Lazy::new(|| {
@@ -18,11 +28,17 @@ static BSP_IDT: Lazy<Idt> = Lazy::new(|| {
     idt
 });
 
+/// Stacks grow down, so the TSS takes the address just past their end.
+fn stack_top(stack: *const [u8; INTERRUPT_STACK_SIZE]) -> u64 {
+    stack as u64 + INTERRUPT_STACK_SIZE as u64
+}
+
 pub fn init_bsp() {
     BSP_GDT.load();
     unsafe {
         reload_segment_regs();
     }
+    percpu::init_bsp_area();
     BSP_IDT.load();
     logln!("BSP: x86-64 logical processor initialization complete");
 }
@@ -132,7 +132,7 @@ core::arch::global_asm!(
     "mov ds, ax",
     "mov es, ax",
     "mov fs, ax",
-    "mov gs, ax",
+    // GS is not reloaded since doing so would clear the base of the per-LP data area.
     "mov ss, ax",
     "ret"
 );
@@ -141,6 +141,17 @@ unsafe extern "C" {
     pub fn reload_segment_regs();
 }
 
+/// Returns the address and size in bytes of the GDT loaded on the current LP
+pub fn current_gdt() -> (usize, usize) {
+    let mut gdtr = [0u8; 10];
+    unsafe {
+        asm!("sgdt [{}]", in(reg) gdtr.as_mut_ptr(), options(nostack, preserves_flags));
+    }
+    let limit = u16::from_le_bytes([gdtr[0], gdtr[1]]);
+    let base = u64::from_le_bytes(gdtr[2..].try_into().unwrap());
+    (base as usize, limit as usize + 1)
+}
+
 #[repr(C, packed(1))]
 struct GdtRegister {
     limit: u16,
@@ -203,6 +214,12 @@ pub static USER_DATA_SELECTOR: SegmentSelector = make_segment_selector(4, true);
 #[unsafe(no_mangle)]
 pub static TSS_SELECTOR: SegmentSelector = make_segment_selector(5, false);
 
+/// The interrupt stack table slots of the exceptions that must not run on the interrupted stack,
+/// which may be exhausted or in the middle of being switched
+pub const DOUBLE_FAULT_IST: u8 = 1;
+pub const NMI_IST: u8 = 2;
+pub const MACHINE_CHECK_IST: u8 = 3;
+
 #[derive(Debug)]
 #[repr(C, packed(1))]
 pub struct Tss {
@@ -224,16 +241,18 @@ pub struct Tss {
 }
 
 impl Tss {
-    pub fn new(rsp0: u64, ist1: u64) -> Self {
+    /// Takes the top of the ring 0 stack and of the stacks for [`DOUBLE_FAULT_IST`], [`NMI_IST`]
+    /// and [`MACHINE_CHECK_IST`] in that order
+    pub fn new(rsp0: u64, ist: [u64; 3]) -> Self {
         Tss {
             res0: 0,
             rsp0: rsp0,
             rsp1: 0,
             rsp2: 0,
             res1: 0,
-            ist1: ist1,
-            ist2: 0,
-            ist3: 0,
+            ist1: ist[0],
+            ist2: ist[1],
+            ist3: ist[2],
             ist4: 0,
             ist5: 0,
             ist6: 0,
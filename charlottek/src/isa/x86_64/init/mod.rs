@@ -3,8 +3,12 @@ mod bsp;
 pub mod gdt;
 
 use crate::isa::interface::init::InitInterface;
+use crate::isa::interface::interrupts::InterruptManagerIfce;
+use crate::isa::interrupts::InterruptManager;
 use crate::isa::lp::ops::get_lp_id;
+use crate::isa::mca;
 use crate::isa::memory::paging::PAGE_SIZE;
+use crate::isa::memory::tlb::shootdown;
 use crate::logln;
 
 const INTERRUPT_STACK_SIZE: usize = PAGE_SIZE * 4;
@@ -19,6 +23,9 @@ impl InitInterface for IsaInitializer {
         logln!("LP{}: Starting x86-64 bootstrap processor initialization", lp_id);
         // Initialize TSS, GDT, and IDT
         bsp::init_bsp();
+        InterruptManager::init_interrupt_structures().expect("Failed to set up the local APIC");
+        shootdown::init_local();
+        mca::init_local();
         logln!("LP{}: x86-64 bootstrap processor initialization complete", lp_id);
         // return success
         Ok(())
@@ -29,6 +36,9 @@ impl InitInterface for IsaInitializer {
         logln!("LP{}: Starting x86-64 application processor initialization", lp_id);
         // Initialize TSS, GDT, and IDT
         ap::init_ap();
+        InterruptManager::init_interrupt_structures().expect("Failed to set up the local APIC");
+        shootdown::init_local();
+        mca::init_local();
         logln!("LP{}: x86-64 logical processor initialization complete", lp_id);
         Ok(())
     }
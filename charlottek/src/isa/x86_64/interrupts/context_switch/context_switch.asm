@@ -3,63 +3,71 @@
 .section .text
 .global isr_switch_thread_context
 isr_switch_thread_context:
-cli
+// Entered through an interrupt gate, so interrupts stay masked until the final iretq.
 /* Save the current context if needed */
 push r15
-// Load the pointer to the ThreadContext to be saved from TLS which located at gs:[0].
-mov r15, gs:[0]
-// If no context to save, skip saving and just load the new context.
-cmp r15, 0
-je load_context
+// Load the pointer to the ThreadContext to be saved from the LP's local data area header.
+mov r15, [rip + PERCPU_OFFSET_SAVE_CONTEXT]
+mov r15, gs:[r15]
+// If no context to save, drop the saved r15 and just load the new context.
+test r15, r15
+jnz .Lsave_context
+add rsp, 8
+jmp .Lload_context
+.Lsave_context:
 // Point r15 to the start of the gprs array in the ThreadContext.
 add r15, [rip + TC_GPRS_OFFSET]
 // Save all general-purpose registers into the ThreadContext.
-mov [rip + r15 + 8 * 0], rax
-mov [rip + r15 + 8 * 1], rbx
-mov [rip + r15 + 8 * 2], rcx
-mov [rip + r15 + 8 * 3], rdx
-mov [rip + r15 + 8 * 4], rsi
-mov [rip + r15 + 8 * 5], rdi
-mov [rip + r15 + 8 * 6], rbp
-mov [rip + r15 + 8 * 7], rsp
-mov [rip + r15 + 8 * 8], r8
-mov [rip + r15 + 8 * 9], r9
-mov [rip + r15 + 8 * 10], r10
-mov [rip + r15 + 8 * 11], r11
-mov [rip + r15 + 8 * 12], r12
-mov [rip + r15 + 8 * 13], r13
-mov [rip + r15 + 8 * 14], r14
-pop rax                       // Restore r15 from the stack.
-mov [rip + r15 + 8 * 15], rax // Save the original r15 value.
-mov r15, gs:[0]
+mov [r15 + 8 * 0], rax
+mov [r15 + 8 * 1], rbx
+mov [r15 + 8 * 2], rcx
+mov [r15 + 8 * 3], rdx
+mov [r15 + 8 * 4], rsi
+mov [r15 + 8 * 5], rdi
+mov [r15 + 8 * 6], rbp
+mov [r15 + 8 * 8], r8
+mov [r15 + 8 * 9], r9
+mov [r15 + 8 * 10], r10
+mov [r15 + 8 * 11], r11
+mov [r15 + 8 * 12], r12
+mov [r15 + 8 * 13], r13
+mov [r15 + 8 * 14], r14
+pop rax                  // Restore r15 from the stack.
+mov [r15 + 8 * 15], rax  // Save the original r15 value.
+mov [r15 + 8 * 7], rsp   // The saved rsp points at the interrupt frame.
+sub r15, [rip + TC_GPRS_OFFSET]
 add r15, [rip + TC_CR3_OFFSET] // Point r15 to the cr3 field.
 mov rax, cr3           // Read the current CR3 value.
-mov [rip + r15], rax   // Save CR3 into the ThreadContext.
+mov [r15], rax         // Save CR3 into the ThreadContext.
 /* Load the new context */
-load_context:
-// Load the pointer to the ThreadContext to be loaded from TLS located at gs:[8].
-mov r15, gs:[8]
-add r15, [rip + TC_CR3_OFFSET] // Point r15 to the cr3 field.
-mov rax, [rip + r15]   // Load CR3 from the ThreadContext.
-mov cr3, rax           // Update CR3 to switch address spaces.
-mov r15, gs:[8]
-add r15, [rip + TC_GPRS_OFFSET] // Point r15 to the start of
+.Lload_context:
+// Load the pointer to the ThreadContext to be loaded from the LP's local data area header.
+mov r15, [rip + PERCPU_OFFSET_LOAD_CONTEXT]
+mov r15, gs:[r15]
+// Only switch address spaces if needed, since writing CR3 flushes the TLB.
+mov rax, [rip + TC_CR3_OFFSET]
+mov rax, [r15 + rax]
+mov rbx, cr3
+cmp rax, rbx
+je .Lload_gprs
+mov cr3, rax
+.Lload_gprs:
+add r15, [rip + TC_GPRS_OFFSET] // Point r15 to the start of the gprs array.
 // Load all general-purpose registers from the ThreadContext.
-mov rax, [rip + r15 + 8 * 0]
-mov rbx, [rip + r15 + 8 * 1]
-mov rcx, [rip + r15 + 8 * 2]
-mov rdx, [rip + r15 + 8 * 3]
-mov rsi, [rip + r15 + 8 * 4]
-mov rdi, [rip + r15 + 8 * 5]
-mov rbp, [rip + r15 + 8 * 6]
-mov rsp, [rip + r15 + 8 * 7]
-mov r8,  [rip + r15 + 8 * 8]
-mov r9,  [rip + r15 + 8 * 9]
-mov r10, [rip + r15 + 8 * 10]
-mov r11, [rip + r15 + 8 * 11]
-mov r12, [rip + r15 + 8 * 12]
-mov r13, [rip + r15 + 8 * 13]
-mov r14, [rip + r15 + 8 * 14]
-mov r15, [rip + r15 + 8 * 15] // Restore the original r15 value.
-sti
-iret
\ No newline at end of file
+mov rax, [r15 + 8 * 0]
+mov rbx, [r15 + 8 * 1]
+mov rcx, [r15 + 8 * 2]
+mov rdx, [r15 + 8 * 3]
+mov rsi, [r15 + 8 * 4]
+mov rdi, [r15 + 8 * 5]
+mov rbp, [r15 + 8 * 6]
+mov rsp, [r15 + 8 * 7]
+mov r8,  [r15 + 8 * 8]
+mov r9,  [r15 + 8 * 9]
+mov r10, [r15 + 8 * 10]
+mov r11, [r15 + 8 * 11]
+mov r12, [r15 + 8 * 12]
+mov r13, [r15 + 8 * 13]
+mov r14, [r15 + 8 * 14]
+mov r15, [r15 + 8 * 15] // Restore the original r15 value.
+iretq
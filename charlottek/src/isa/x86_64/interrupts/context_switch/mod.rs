@@ -3,3 +3,14 @@ core::arch::global_asm!(include_str!("context_switch.asm"));
 unsafe extern "C" {
     pub fn isr_switch_thread_context();
 }
+
+pub const CONTEXT_SWITCH_VECTOR: u8 = 32;
+
+/// Switches to the thread most recently selected by the local LP scheduler by raising the context
+/// switch interrupt on the current LP
+#[inline(always)]
+pub fn request_context_switch() {
+    unsafe {
+        core::arch::asm!("int {vector}", vector = const CONTEXT_SWITCH_VECTOR);
+    }
+}
@@ -0,0 +1,87 @@
+use core::fmt;
+
+/// The general purpose registers at the time of the exception in the order the entry stubs push
+/// them
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct GprSnapshot {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9:  u64,
+    pub r8:  u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+}
+
+/// The frame the processor pushes when it delivers an interrupt or exception
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct InterruptFrame {
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// Everything the exception entry stubs save. Handlers that return resume the interrupted code with
+/// the register values in the frame.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct ExceptionFrame {
+    pub gprs: GprSnapshot,
+    /// Zero for exceptions that do not have an error code
+    pub error_code: u64,
+    pub interrupt_frame: InterruptFrame,
+}
+
+impl ExceptionFrame {
+    /// Whether the exception interrupted the kernel rather than userspace
+    pub fn is_kernel_mode(&self) -> bool {
+        // The privilege level of the interrupted code is that of its code segment selector.
+        self.interrupt_frame.cs & 0b11 == 0
+    }
+}
+
+impl fmt::Display for ExceptionFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gprs = &self.gprs;
+        let frame = &self.interrupt_frame;
+        writeln!(
+            f,
+            "RAX={:016x} RBX={:016x} RCX={:016x} RDX={:016x}",
+            gprs.rax, gprs.rbx, gprs.rcx, gprs.rdx
+        )?;
+        writeln!(
+            f,
+            "RSI={:016x} RDI={:016x} RBP={:016x} RSP={:016x}",
+            gprs.rsi, gprs.rdi, gprs.rbp, frame.rsp
+        )?;
+        writeln!(
+            f,
+            "R8 ={:016x} R9 ={:016x} R10={:016x} R11={:016x}",
+            gprs.r8, gprs.r9, gprs.r10, gprs.r11
+        )?;
+        writeln!(
+            f,
+            "R12={:016x} R13={:016x} R14={:016x} R15={:016x}",
+            gprs.r12, gprs.r13, gprs.r14, gprs.r15
+        )?;
+        write!(
+            f,
+            "RIP={:016x} RFLAGS={:08x} CS={:04x} SS={:04x} ERROR={:x}",
+            frame.rip, frame.rflags, frame.cs, frame.ss, self.error_code
+        )
+    }
+}
+
+const _: () = assert!(size_of::<ExceptionFrame>() == 21 * 8);
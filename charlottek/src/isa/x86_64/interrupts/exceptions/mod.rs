@@ -1,31 +1,79 @@
+pub mod frame;
+pub mod report;
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use frame::ExceptionFrame;
+use report::{ControlProtectionError, ControlRegisters, PageFaultError, SelectorError};
+
 use crate::isa::init::gdt;
 use crate::isa::interrupts::idt::Idt;
-use crate::logln;
+use crate::isa::interrupts::x2apic::ipi::{DeliveryMode, Destination, send};
+use crate::isa::interrupts::x2apic::lapic_id_of;
+use crate::isa::lp::ops::get_lp_id;
+use crate::percpu;
+
+percpu! {
+    /// The number of NMIs the LP has received
+    static NMI_COUNT: AtomicU64 = AtomicU64::new(0);
+    /// The number of NMIs sent with [`send_self_nmi`] the LP has not handled yet
+    static EXPECTED_SELF_NMIS: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Returns the number of NMIs the current LP has received, whatever their source
+pub fn nmi_count() -> u64 {
+    NMI_COUNT.get().load(Ordering::Relaxed)
+}
+
+/// Sends the current LP an NMI that its handler ignores. The shorthand for the sender itself
+/// cannot deliver NMIs, so it is addressed by its local APIC ID.
+pub fn send_self_nmi() {
+    let lapic_id = lapic_id_of(get_lp_id!()).expect("The local APIC has not been set up");
+    EXPECTED_SELF_NMIS.get().fetch_add(1, Ordering::Relaxed);
+    send(Destination::Physical(lapic_id.physical), 0, DeliveryMode::Nmi);
+}
 
 pub fn load_exceptions(idt: &mut Idt) {
-    idt.set_gate(0, isr_divide_by_zero, gdt::KERNEL_CODE_SELECTOR, true, true);
-    idt.set_gate(1, isr_debug, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(2, isr_non_maskable_interrupt, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(3, isr_breakpoint, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(4, isr_overflow, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(5, isr_bound_range_exceeded, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(6, isr_invalid_opcode, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(7, isr_device_not_available, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(8, isr_double_fault, gdt::KERNEL_CODE_SELECTOR, true, true);
-    idt.set_gate(10, isr_invalid_tss, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(11, isr_segment_not_present, gdt::KERNEL_CODE_SELECTOR, true, true);
-    idt.set_gate(12, isr_stack_segment_fault, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(13, isr_general_protection_fault, gdt::KERNEL_CODE_SELECTOR, true, true);
-    idt.set_gate(14, isr_page_fault, gdt::KERNEL_CODE_SELECTOR, true, true);
-    idt.set_gate(16, isr_x87_floating_point, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(17, isr_alignment_check, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(18, isr_machine_check, gdt::KERNEL_CODE_SELECTOR, true, true);
-    idt.set_gate(19, isr_simd_floating_point, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(20, isr_virtualization, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(21, isr_control_protection, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(28, isr_hypervisor_injection, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(29, isr_vmm_communication, gdt::KERNEL_CODE_SELECTOR, true, false);
-    idt.set_gate(30, isr_security_exception, gdt::KERNEL_CODE_SELECTOR, true, false);
+    let exceptions: [(usize, unsafe extern "C" fn()); 23] = [
+        (0, isr_divide_by_zero),
+        (1, isr_debug),
+        (2, isr_non_maskable_interrupt),
+        (3, isr_breakpoint),
+        (4, isr_overflow),
+        (5, isr_bound_range_exceeded),
+        (6, isr_invalid_opcode),
+        (7, isr_device_not_available),
+        (8, isr_double_fault),
+        (10, isr_invalid_tss),
+        (11, isr_segment_not_present),
+        (12, isr_stack_segment_fault),
+        (13, isr_general_protection_fault),
+        (14, isr_page_fault),
+        (16, isr_x87_floating_point),
+        (17, isr_alignment_check),
+        (18, isr_machine_check),
+        (19, isr_simd_floating_point),
+        (20, isr_virtualization),
+        (21, isr_control_protection),
+        (28, isr_hypervisor_injection),
+        (29, isr_vmm_communication),
+        (30, isr_security_exception),
+    ];
+    for (vector, isr) in exceptions {
+        idt.set_gate(vector, isr, gdt::KERNEL_CODE_SELECTOR, true, true);
+    }
+    // These can arrive at any instruction, including one that has just exhausted or replaced the
+    // stack, so they get stacks of their own and mask interrupts until they return. An NMI that
+    // interrupts the NMI handler would overwrite its stack, but the processor holds further NMIs
+    // back until the next IRET, which the NMI handler must not execute before its own.
+    for (vector, isr, ist) in [
+        (2, isr_non_maskable_interrupt as unsafe extern "C" fn(), gdt::NMI_IST),
+        (8, isr_double_fault, gdt::DOUBLE_FAULT_IST),
+        (18, isr_machine_check, gdt::MACHINE_CHECK_IST),
+    ] {
+        idt.set_gate(vector, isr, gdt::KERNEL_CODE_SELECTOR, false, true);
+        idt.set_ist(vector, ist);
+    }
 }
 
 core::arch::global_asm! {
@@ -58,144 +106,175 @@ unsafe extern "C" {
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_double_fault(_error_code: u64) {
-    logln!("A double fault has occurred in kernelspace! Panicking!");
-    panic!("Double fault");
+extern "C" fn ih_double_fault(frame: &mut ExceptionFrame) {
+    report::fatal("#DF Double fault", frame, None);
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_divide_by_zero() {
-    logln!("Divide by zero exception occurred!");
-    panic!("Divide by zero");
+extern "C" fn ih_divide_by_zero(frame: &mut ExceptionFrame) {
+    report::fatal("#DE Divide error", frame, None);
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_debug() {
-    logln!("Debug exception occurred!");
-    panic!("Debug exception");
+extern "C" fn ih_debug(frame: &mut ExceptionFrame) {
+    let dr6: u64;
+    unsafe { core::arch::asm!("mov {}, dr6", out(reg) dr6, options(nomem, nostack)) };
+    if crate::debug::gdb::handle_debug(frame, dr6) {
+        // The processor never clears DR6 itself.
+        unsafe { core::arch::asm!("mov dr6, {}", in(reg) 0u64, options(nomem, nostack)) };
+        return;
+    }
+    report::fatal("#DB Debug exception", frame, Some(format_args!("DR6: {:#x}", dr6)));
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_non_maskable_interrupt() {
-    logln!("Non-maskable interrupt occurred!");
-    panic!("Non-maskable interrupt");
+extern "C" fn ih_non_maskable_interrupt(frame: &mut ExceptionFrame) {
+    NMI_COUNT.get().fetch_add(1, Ordering::Relaxed);
+    crate::debug::crash::stop_if_crashing(frame);
+    // The debugger's stop NMI, the watchdog NMI and a self NMI may be handled as one.
+    let expected = EXPECTED_SELF_NMIS
+        .get()
+        .try_update(Ordering::Relaxed, Ordering::Relaxed, |count| count.checked_sub(1))
+        .is_ok();
+    let parked = crate::debug::gdb::park_if_requested(frame);
+    if crate::debug::watchdog::handle_nmi(frame) || parked || expected {
+        return;
+    }
+    report::fatal("NMI Non-maskable interrupt", frame, None);
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_breakpoint() {
-    logln!("Breakpoint exception occurred!");
-    panic!("Breakpoint exception");
+extern "C" fn ih_breakpoint(frame: &mut ExceptionFrame) {
+    if crate::debug::gdb::handle_breakpoint(frame) {
+        return;
+    }
+    report::fatal("#BP Breakpoint", frame, None);
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_overflow() {
-    logln!("Overflow exception occurred!");
-    panic!("Overflow exception");
+extern "C" fn ih_overflow(frame: &mut ExceptionFrame) {
+    report::fatal("#OF Overflow", frame, None);
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_bound_range_exceeded() {
-    logln!("Bound range exceeded exception occurred!");
-    panic!("Bound range exceeded");
+extern "C" fn ih_bound_range_exceeded(frame: &mut ExceptionFrame) {
+    report::fatal("#BR Bound range exceeded", frame, None);
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_invalid_opcode() {
-    logln!("Invalid opcode exception occurred!");
-    panic!("Invalid opcode");
+extern "C" fn ih_invalid_opcode(frame: &mut ExceptionFrame) {
+    report::fatal("#UD Invalid opcode", frame, None);
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_device_not_available() {
-    logln!("Device not available exception occurred!");
-    panic!("Device not available");
+extern "C" fn ih_device_not_available(frame: &mut ExceptionFrame) {
+    report::fatal("#NM Device not available", frame, None);
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_invalid_tss() {
-    logln!("Invalid TSS exception occurred!");
-    panic!("Invalid TSS");
+extern "C" fn ih_invalid_tss(frame: &mut ExceptionFrame) {
+    report::fatal(
+        "#TS Invalid TSS",
+        frame,
+        Some(format_args!("Error code: {}", SelectorError(frame.error_code))),
+    );
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_segment_not_present() {
-    logln!("Segment not present exception occurred!");
-    panic!("Segment not present");
+extern "C" fn ih_segment_not_present(frame: &mut ExceptionFrame) {
+    report::fatal(
+        "#NP Segment not present",
+        frame,
+        Some(format_args!("Error code: {}", SelectorError(frame.error_code))),
+    );
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_stack_segment_fault() {
-    logln!("Stack segment fault occurred!");
-    panic!("Stack segment fault");
+extern "C" fn ih_stack_segment_fault(frame: &mut ExceptionFrame) {
+    report::fatal(
+        "#SS Stack segment fault",
+        frame,
+        Some(format_args!("Error code: {}", SelectorError(frame.error_code))),
+    );
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_general_protection_fault(_error_code: u64) {
-    logln!("General protection fault occurred!");
-    panic!("General protection fault");
+extern "C" fn ih_general_protection_fault(frame: &mut ExceptionFrame) {
+    if crate::isa::lp::ops::recover_msr_probe(frame) {
+        return;
+    }
+    report::fatal(
+        "#GP General protection fault",
+        frame,
+        Some(format_args!("Error code: {}", SelectorError(frame.error_code))),
+    );
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_page_fault(error_code: u64) {
-    logln!("Page fault occurred with error code {:X}!", error_code);
-    let pf_addr: u64;
-    unsafe {
-        core::arch::asm!("mov {0}, cr2", out(reg) pf_addr);
-    }
-    logln!("Page fault address: {:x}", pf_addr);
-    panic!("Page fault");
+extern "C" fn ih_page_fault(frame: &mut ExceptionFrame) {
+    let cr2 = ControlRegisters::read().cr2;
+    report::fatal(
+        "#PF Page fault",
+        frame,
+        Some(format_args!("{} at {:#018x}", PageFaultError(frame.error_code), cr2)),
+    );
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_x87_floating_point() {
-    logln!("x87 floating point exception occurred!");
-    panic!("x87 floating point exception");
+extern "C" fn ih_x87_floating_point(frame: &mut ExceptionFrame) {
+    report::fatal("#MF x87 floating point exception", frame, None);
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_alignment_check() {
-    logln!("Alignment check exception occurred!");
-    panic!("Alignment check");
+extern "C" fn ih_alignment_check(frame: &mut ExceptionFrame) {
+    report::fatal("#AC Alignment check", frame, None);
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_machine_check() {
-    logln!("Machine check exception occurred!");
-    panic!("Machine check");
+extern "C" fn ih_machine_check(frame: &mut ExceptionFrame) {
+    crate::isa::mca::handle_machine_check(frame);
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_simd_floating_point() {
-    logln!("SIMD floating point exception occurred!");
-    panic!("SIMD floating point exception");
+extern "C" fn ih_simd_floating_point(frame: &mut ExceptionFrame) {
+    let mut mxcsr = 0u32;
+    unsafe { core::arch::asm!("stmxcsr [{}]", in(reg) &raw mut mxcsr, options(nostack)) };
+    report::fatal(
+        "#XM SIMD floating point exception",
+        frame,
+        Some(format_args!("MXCSR: {:#x}", mxcsr)),
+    );
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_virtualization() {
-    logln!("Virtualization exception occurred!");
-    panic!("Virtualization exception");
+extern "C" fn ih_virtualization(frame: &mut ExceptionFrame) {
+    report::fatal("#VE Virtualization exception", frame, None);
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_control_protection() {
-    logln!("Control protection exception occurred!");
-    panic!("Control protection exception");
+extern "C" fn ih_control_protection(frame: &mut ExceptionFrame) {
+    report::fatal(
+        "#CP Control protection exception",
+        frame,
+        Some(format_args!("Cause: {}", ControlProtectionError(frame.error_code))),
+    );
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_hypervisor_injection() {
-    logln!("Hypervisor injection exception occurred!");
-    panic!("Hypervisor injection");
+extern "C" fn ih_hypervisor_injection(frame: &mut ExceptionFrame) {
+    report::fatal("#HV Hypervisor injection exception", frame, None);
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_vmm_communication() {
-    logln!("VMM communication exception occurred!");
-    panic!("VMM communication");
+extern "C" fn ih_vmm_communication(frame: &mut ExceptionFrame) {
+    report::fatal(
+        "#VC VMM communication exception",
+        frame,
+        Some(format_args!("Exit code: {:#x}", frame.error_code)),
+    );
 }
 
 #[unsafe(no_mangle)]
-extern "C" fn ih_security_exception() {
-    logln!("Security exception occurred!");
-    panic!("Security exception");
+extern "C" fn ih_security_exception(frame: &mut ExceptionFrame) {
+    report::fatal("#SX Security exception", frame, None);
 }
@@ -0,0 +1,170 @@
+//! # Exception Reports
+//!
+//! Decoders for the error codes the processor pushes and the report logged before a fatal
+//! exception panics.
+
+use core::arch::asm;
+use core::fmt;
+
+use super::frame::ExceptionFrame;
+use crate::debug::symbols::Symbolized;
+use crate::isa::lp::ops::get_lp_id;
+use crate::logln;
+
+/// The control registers that determine how the faulting code was executing
+#[derive(Debug, Clone, Copy)]
+pub struct ControlRegisters {
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+}
+
+impl ControlRegisters {
+    pub fn read() -> Self {
+        let (cr0, cr2, cr3, cr4): (u64, u64, u64, u64);
+        unsafe {
+            asm!(
+                "mov {cr0}, cr0",
+                "mov {cr2}, cr2",
+                "mov {cr3}, cr3",
+                "mov {cr4}, cr4",
+                cr0 = out(reg) cr0,
+                cr2 = out(reg) cr2,
+                cr3 = out(reg) cr3,
+                cr4 = out(reg) cr4,
+                options(nomem, nostack, preserves_flags),
+            );
+        }
+        ControlRegisters {
+            cr0,
+            cr2,
+            cr3,
+            cr4,
+        }
+    }
+}
+
+impl fmt::Display for ControlRegisters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CR0={:016x} CR2={:016x} CR3={:016x} CR4={:016x}",
+            self.cr0, self.cr2, self.cr3, self.cr4
+        )
+    }
+}
+
+/// The error code of a page fault
+#[derive(Debug, Clone, Copy)]
+pub struct PageFaultError(pub u64);
+
+impl PageFaultError {
+    pub const INSTRUCTION_FETCH: u64 = 1 << 4;
+    pub const PRESENT: u64 = 1 << 0;
+    pub const PROTECTION_KEY: u64 = 1 << 5;
+    pub const RESERVED_BIT: u64 = 1 << 3;
+    pub const SHADOW_STACK: u64 = 1 << 6;
+    pub const USER: u64 = 1 << 2;
+    pub const WRITE: u64 = 1 << 1;
+}
+
+impl fmt::Display for PageFaultError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let is_set = |bit| self.0 & bit != 0;
+        write!(
+            f,
+            "{} {} by {}",
+            if is_set(Self::PRESENT) {
+                "protection violation on"
+            } else {
+                "not present page on"
+            },
+            if is_set(Self::INSTRUCTION_FETCH) {
+                "instruction fetch"
+            } else if is_set(Self::WRITE) {
+                "write"
+            } else {
+                "read"
+            },
+            if is_set(Self::USER) {
+                "userspace"
+            } else {
+                "the kernel"
+            }
+        )?;
+        if is_set(Self::RESERVED_BIT) {
+            write!(f, ", reserved bit set in a paging structure")?;
+        }
+        if is_set(Self::PROTECTION_KEY) {
+            write!(f, ", protection key violation")?;
+        }
+        if is_set(Self::SHADOW_STACK) {
+            write!(f, ", shadow stack access")?;
+        }
+        Ok(())
+    }
+}
+
+/// The error code of the exceptions caused by loading a segment or gate descriptor
+#[derive(Debug, Clone, Copy)]
+pub struct SelectorError(pub u64);
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "not caused by a segment selector");
+        }
+        let table = match (self.0 >> 1) & 0b11 {
+            0b00 => "GDT",
+            0b10 => "LDT",
+            _ => "IDT",
+        };
+        write!(f, "{} entry {:#x} (selector {:#06x})", table, self.0 >> 3, self.0 & !0b111)?;
+        if self.0 & 1 != 0 {
+            write!(f, ", raised while delivering an external event")?;
+        }
+        Ok(())
+    }
+}
+
+/// The error code of a control protection exception
+#[derive(Debug, Clone, Copy)]
+pub struct ControlProtectionError(pub u64);
+
+impl fmt::Display for ControlProtectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cause = match self.0 & 0x7fff {
+            1 => "near return address mismatch",
+            2 => "far return or interrupt return address mismatch",
+            3 => "missing ENDBRANCH",
+            4 => "invalid shadow stack restore token",
+            5 => "invalid supervisor shadow stack token",
+            _ => "unknown cause",
+        };
+        write!(f, "{}", cause)
+    }
+}
+
+/// Logs a report of an exception the kernel cannot recover from and panics. `details` is an
+/// optional line describing the exception specific state.
+pub fn fatal(name: &str, frame: &ExceptionFrame, details: Option<fmt::Arguments>) -> ! {
+    logln!(
+        "LP{}: {} in {} at RIP {:#018x} {}",
+        (get_lp_id!()),
+        name,
+        (if frame.is_kernel_mode() {
+            "the kernel"
+        } else {
+            "userspace"
+        }),
+        (frame.interrupt_frame.rip),
+        (Symbolized(frame.interrupt_frame.rip as usize))
+    );
+    if let Some(details) = details {
+        logln!("{}", details);
+    }
+    logln!("{}", frame);
+    logln!("{}", (ControlRegisters::read()));
+    panic!("{}", name);
+}
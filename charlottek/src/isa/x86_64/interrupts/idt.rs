@@ -1,10 +1,58 @@
 use core::arch::asm;
 use core::mem::MaybeUninit;
 
+use super::context_switch::CONTEXT_SWITCH_VECTOR;
+use super::ipis::IPI_VECTOR;
+use super::pic::{PIC1_VECTOR_BASE, PIC2_SPURIOUS_VECTOR};
+use super::x2apic::SPURIOUS_VECTOR;
+use super::x2apic::timer::TIMER_VECTOR;
+use crate::cpu::sync::IrqSpinlock;
+use crate::isa::lp::LpId;
+use crate::{lock_class, percpu};
+
 static mut IDTR: MaybeUninit<Idtr> = MaybeUninit::uninit();
 
 const N_INTERRUPT_VECTORS: usize = 256;
 
+/// The vectors above the exceptions, which are handed out at runtime unless a fixed gate uses them.
+/// Every IDT points them at the same entry stubs, but each LP allocates them separately since it
+/// has its own IDT.
+pub const FIRST_DYNAMIC_VECTOR: u8 = 32;
+pub const LAST_DYNAMIC_VECTOR: u8 = 0xff;
+/// The largest block of vectors that can be allocated at once, which is the most MSI can use
+pub const MAX_VECTOR_BLOCK: usize = 32;
+
+type VectorBitmap = [u64; N_INTERRUPT_VECTORS / 64];
+
+const fn reserve(mut bitmap: VectorBitmap, first: u8, last: u8) -> VectorBitmap {
+    let mut vector = first as usize;
+    while vector <= last as usize {
+        bitmap[vector / 64] |= 1 << (vector % 64);
+        vector += 1;
+    }
+    bitmap
+}
+
+/// The exceptions and the vectors of the fixed gates
+const RESERVED_VECTORS: VectorBitmap = {
+    let bitmap = reserve([0; N_INTERRUPT_VECTORS / 64], 0, FIRST_DYNAMIC_VECTOR - 1);
+    let bitmap = reserve(bitmap, CONTEXT_SWITCH_VECTOR, CONTEXT_SWITCH_VECTOR);
+    let bitmap = reserve(bitmap, IPI_VECTOR, IPI_VECTOR);
+    let bitmap = reserve(bitmap, TIMER_VECTOR, TIMER_VECTOR);
+    let bitmap = reserve(bitmap, PIC1_VECTOR_BASE, PIC2_SPURIOUS_VECTOR);
+    reserve(bitmap, SPURIOUS_VECTOR, SPURIOUS_VECTOR)
+};
+
+percpu! {
+    /// One bit per vector which is set while the vector is allocated or reserved on the LP
+    static ALLOCATED_VECTORS: IrqSpinlock<VectorBitmap> =
+        IrqSpinlock::with_class(lock_class!("IDT_ALLOCATED_VECTORS"), RESERVED_VECTORS);
+}
+
+fn is_allocated(bitmap: &VectorBitmap, vector: usize) -> bool {
+    bitmap[vector / 64] & 1 << (vector % 64) != 0
+}
+
 #[derive(Debug)]
 #[repr(C, align(16))]
 pub struct Idt {
@@ -31,7 +79,7 @@ impl Idt {
 
         gate.addr0 = u16::try_from(isr_addr & 0xffff).unwrap();
         gate.segment_selector = segment_selector;
-        gate.reserved_ist_index = 0u8; // the IST is not used
+        gate.reserved_ist_index = 0u8; // the current stack unless `set_ist` is called
         gate.flags = if is_trap {
             0b1111u8
         } else {
@@ -49,6 +97,12 @@ impl Idt {
         gate.reserved = 0u32;
     }
 
+    /// Has the gate switch to the given interrupt stack table slot of the TSS, or stay on the
+    /// current stack if `ist` is zero
+    pub fn set_ist(&mut self, index: usize, ist: u8) {
+        self.gates[index].reserved_ist_index = ist & 0b111;
+    }
+
     #[allow(unused)]
     pub fn set_present(&mut self, index: usize) {
         if index < 256 {
@@ -63,6 +117,53 @@ impl Idt {
         }
     }
 
+    /// Reserves an unused vector on the given LP. Returns `None` if every vector is in use or the
+    /// LP has not been started.
+    pub fn allocate_vector(lp_id: LpId) -> Option<u8> {
+        Self::allocate_vectors(lp_id, 1)
+    }
+
+    /// Reserves a block of `count` unused vectors on the given LP and returns the first of them.
+    /// `count` must be a power of two no larger than [`MAX_VECTOR_BLOCK`] and the block is aligned
+    /// to its size as multiple message MSI requires.
+    pub fn allocate_vectors(lp_id: LpId, count: usize) -> Option<u8> {
+        assert!(
+            count.is_power_of_two() && count <= MAX_VECTOR_BLOCK,
+            "Cannot allocate a block of {} vectors",
+            count
+        );
+        let mut allocated = ALLOCATED_VECTORS.get_for(lp_id)?.lock();
+        let first =
+            (FIRST_DYNAMIC_VECTOR as usize..N_INTERRUPT_VECTORS).step_by(count).find(|first| {
+                (*first..*first + count).all(|vector| !is_allocated(&allocated, vector))
+            })?;
+        for vector in first..first + count {
+            allocated[vector / 64] |= 1 << (vector % 64);
+        }
+        Some(first as u8)
+    }
+
+    /// Returns a vector obtained from [`Idt::allocate_vector`]
+    pub fn free_vector(lp_id: LpId, vector: u8) {
+        Self::free_vectors(lp_id, vector, 1)
+    }
+
+    /// Returns a block of vectors obtained from [`Idt::allocate_vectors`]
+    pub fn free_vectors(lp_id: LpId, first: u8, count: usize) {
+        let mut allocated = ALLOCATED_VECTORS
+            .get_for(lp_id)
+            .expect("Vectors were freed on an LP that has not been started")
+            .lock();
+        for vector in first as usize..first as usize + count {
+            assert!(
+                !is_allocated(&RESERVED_VECTORS, vector),
+                "Vector {} is reserved for a fixed gate",
+                vector
+            );
+            allocated[vector / 64] &= !(1 << (vector % 64));
+        }
+    }
+
     pub fn load(&self) {
         unsafe {
             IDTR.write(Idtr::new(
@@ -114,6 +215,17 @@ impl Idtr {
     }
 }
 
+/// Returns the address and size in bytes of the IDT loaded on the current LP
+pub fn current_idt() -> (usize, usize) {
+    let mut idtr = [0u8; 10];
+    unsafe {
+        asm!("sidt [{}]", in(reg) idtr.as_mut_ptr(), options(nostack, preserves_flags));
+    }
+    let limit = u16::from_le_bytes([idtr[0], idtr[1]]);
+    let base = u64::from_le_bytes(idtr[2..].try_into().unwrap());
+    (base as usize, limit as usize + 1)
+}
+
 #[inline(always)]
 unsafe fn asm_load_idt(idtr: *const Idtr) {
     unsafe {
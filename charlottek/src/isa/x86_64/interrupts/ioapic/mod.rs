@@ -0,0 +1,273 @@
+//! # I/O Advanced Programmable Interrupt Controllers (IOAPICs)
+//!
+//! Each IOAPIC receives a contiguous range of global system interrupts (GSIs) starting at the base
+//! the MADT assigns it and has one redirection entry per GSI, which selects the vector, the
+//! destination LP, the polarity and the trigger mode and can mask the GSI. Its registers are
+//! mapped uncached and accessed indirectly through a register select and a data window register.
+//!
+//! The 16 ISA IRQs are connected to the first 16 GSIs as active high and edge triggered unless the
+//! MADT contains an interrupt source override for them. Every other GSI is assumed to be a PCI
+//! interrupt, which is active low and level triggered.
+
+use alloc::vec::Vec;
+
+use super::irq::IrqError;
+use super::x2apic::PhysicalLapicId;
+use crate::cpu::sync::IrqSpinlock;
+use crate::environment::firmware::acpi;
+use crate::environment::firmware::acpi::tables::{Madt, MadtEntry};
+use crate::isa::interface::memory::address::VirtualAddress;
+use crate::memory::PAddr;
+use crate::memory::vmem::mmio;
+use crate::{lock_class, logln};
+
+const REGISTER_SELECT: usize = 0x00;
+const REGISTER_WINDOW: usize = 0x10;
+const REGISTERS_SIZE: usize = 0x20;
+
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION_TABLE: u32 = 0x10;
+
+const ENTRY_VECTOR_MASK: u64 = 0xff;
+const ENTRY_ACTIVE_LOW: u64 = 1 << 13;
+const ENTRY_LEVEL_TRIGGERED: u64 = 1 << 15;
+const ENTRY_MASKED: u64 = 1 << 16;
+const ENTRY_DESTINATION_SHIFT: u64 = 56;
+
+const N_ISA_IRQS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IsaRoute {
+    gsi: u32,
+    polarity: Polarity,
+    trigger_mode: TriggerMode,
+}
+
+struct IoApic {
+    registers: *mut u32,
+    gsi_base:  u32,
+    n_entries: u32,
+}
+
+unsafe impl Send for IoApic {}
+
+impl IoApic {
+    fn new(address: u32, gsi_base: u32) -> Self {
+        let mut ioapic = IoApic {
+            registers: mmio::map(PAddr::from(address as u64), REGISTERS_SIZE)
+                .expect("Failed to map the IOAPIC registers")
+                .into_mut(),
+            gsi_base,
+            n_entries: 0,
+        };
+        // The version register holds the index of the last redirection entry.
+        ioapic.n_entries = ((ioapic.read(REG_VERSION) >> 16) & 0xff) + 1;
+        ioapic
+    }
+
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            self.registers.byte_add(REGISTER_SELECT).write_volatile(register);
+            self.registers.byte_add(REGISTER_WINDOW).read_volatile()
+        }
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        unsafe {
+            self.registers.byte_add(REGISTER_SELECT).write_volatile(register);
+            self.registers.byte_add(REGISTER_WINDOW).write_volatile(value);
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi - self.gsi_base < self.n_entries
+    }
+
+    fn read_entry(&self, gsi: u32) -> u64 {
+        let register = REG_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+        self.read(register) as u64 | (self.read(register + 1) as u64) << 32
+    }
+
+    /// Writes a redirection entry. The high half is written while the entry is masked so that the
+    /// GSI is never delivered to a mix of the old and new destination.
+    fn write_entry(&self, gsi: u32, entry: u64) {
+        let register = REG_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+        self.write(register, (entry as u32) | ENTRY_MASKED as u32);
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+}
+
+struct Routing {
+    ioapics: Vec<IoApic>,
+    isa_routes: [IsaRoute; N_ISA_IRQS],
+}
+
+impl Routing {
+    const fn new() -> Self {
+        let mut isa_routes = [IsaRoute {
+            gsi: 0,
+            polarity: Polarity::ActiveHigh,
+            trigger_mode: TriggerMode::Edge,
+        }; N_ISA_IRQS];
+        let mut irq = 0;
+        while irq < N_ISA_IRQS {
+            isa_routes[irq].gsi = irq as u32;
+            irq += 1;
+        }
+        Routing {
+            ioapics: Vec::new(),
+            isa_routes,
+        }
+    }
+
+    fn ioapic_for(&self, gsi: u32) -> Result<&IoApic, IrqError> {
+        self.ioapics.iter().find(|ioapic| ioapic.handles(gsi)).ok_or(IrqError::NoSuchGsi)
+    }
+
+    fn default_config(&self, gsi: u32) -> (Polarity, TriggerMode) {
+        match self.isa_routes.iter().find(|route| route.gsi == gsi) {
+            Some(route) => (route.polarity, route.trigger_mode),
+            None => (Polarity::ActiveLow, TriggerMode::Level),
+        }
+    }
+}
+
+static ROUTING: IrqSpinlock<Routing> =
+    IrqSpinlock::with_class(lock_class!("IOAPIC_ROUTING"), Routing::new());
+
+/// Decodes the MPS INTI flags of an interrupt source override. Flags that conform to the bus
+/// specification keep the ISA defaults.
+fn decode_inti_flags(flags: u16, route: &mut IsaRoute) {
+    match flags & Madt::INTI_POLARITY_MASK {
+        Madt::INTI_POLARITY_ACTIVE_HIGH => route.polarity = Polarity::ActiveHigh,
+        Madt::INTI_POLARITY_ACTIVE_LOW => route.polarity = Polarity::ActiveLow,
+        _ => {}
+    }
+    match flags & Madt::INTI_TRIGGER_MASK {
+        Madt::INTI_TRIGGER_EDGE => route.trigger_mode = TriggerMode::Edge,
+        Madt::INTI_TRIGGER_LEVEL => route.trigger_mode = TriggerMode::Level,
+        _ => {}
+    }
+}
+
+/// Discovers the IOAPICs and interrupt source overrides in the MADT and masks every GSI. Returns
+/// true if the system also has 8259 PICs.
+pub fn init() -> Result<bool, IrqError> {
+    let madt = acpi::find::<Madt>().ok_or(IrqError::NoInterruptControllerTable)?;
+    let mut routing = ROUTING.lock();
+    for entry in madt.entries() {
+        match entry {
+            MadtEntry::IoApic {
+                id,
+                address,
+                gsi_base,
+            } => {
+                let ioapic = IoApic::new(address, gsi_base);
+                logln!(
+                    "IOAPIC {}: GSIs {} to {} at {:#x}",
+                    id,
+                    gsi_base,
+                    (gsi_base + ioapic.n_entries - 1),
+                    address
+                );
+                for gsi in gsi_base..gsi_base + ioapic.n_entries {
+                    ioapic.write_entry(gsi, ENTRY_MASKED);
+                }
+                routing.ioapics.push(ioapic);
+            }
+            // Bus 0 is the ISA bus, which is the only one overrides are defined for.
+            MadtEntry::InterruptSourceOverride {
+                bus: 0,
+                source,
+                gsi,
+                flags,
+            } if (source as usize) < N_ISA_IRQS => {
+                let route = &mut routing.isa_routes[source as usize];
+                route.gsi = gsi;
+                decode_inti_flags(flags, route);
+                logln!("IOAPIC: ISA IRQ {} is routed to {:?}", source, route);
+            }
+            _ => {}
+        }
+    }
+    if routing.ioapics.is_empty() {
+        return Err(IrqError::NoInterruptControllerTable);
+    }
+    let flags = madt.flags;
+    Ok(flags & Madt::FLAG_PCAT_COMPAT != 0)
+}
+
+/// Returns the GSI an ISA IRQ is connected to
+pub fn isa_irq_gsi(irq: u8) -> Option<u32> {
+    ROUTING.lock().isa_routes.get(irq as usize).map(|route| route.gsi)
+}
+
+/// Directs a GSI to the given vector and LP with its default polarity and trigger mode. The GSI is
+/// left masked.
+pub fn route(gsi: u32, vector: u8, destination: PhysicalLapicId) -> Result<(), IrqError> {
+    // Without interrupt remapping only 8-bit APIC IDs can be addressed.
+    let destination = u8::try_from(destination).map_err(|_| IrqError::UnreachableLp)?;
+    let routing = ROUTING.lock();
+    let ioapic = routing.ioapic_for(gsi)?;
+    let (polarity, trigger_mode) = routing.default_config(gsi);
+    let mut entry = ENTRY_MASKED | vector as u64 | (destination as u64) << ENTRY_DESTINATION_SHIFT;
+    if polarity == Polarity::ActiveLow {
+        entry |= ENTRY_ACTIVE_LOW;
+    }
+    if trigger_mode == TriggerMode::Level {
+        entry |= ENTRY_LEVEL_TRIGGERED;
+    }
+    ioapic.write_entry(gsi, entry);
+    Ok(())
+}
+
+/// Masks or unmasks a GSI
+pub fn set_masked(gsi: u32, masked: bool) -> Result<(), IrqError> {
+    let routing = ROUTING.lock();
+    let ioapic = routing.ioapic_for(gsi)?;
+    let entry = ioapic.read_entry(gsi);
+    ioapic.write_entry(
+        gsi,
+        if masked {
+            entry | ENTRY_MASKED
+        } else {
+            entry & !ENTRY_MASKED
+        },
+    );
+    Ok(())
+}
+
+/// Overrides the polarity and trigger mode of a GSI
+pub fn configure(gsi: u32, polarity: Polarity, trigger_mode: TriggerMode) -> Result<(), IrqError> {
+    let routing = ROUTING.lock();
+    let ioapic = routing.ioapic_for(gsi)?;
+    let mut entry = ioapic.read_entry(gsi) & !(ENTRY_ACTIVE_LOW | ENTRY_LEVEL_TRIGGERED);
+    if polarity == Polarity::ActiveLow {
+        entry |= ENTRY_ACTIVE_LOW;
+    }
+    if trigger_mode == TriggerMode::Level {
+        entry |= ENTRY_LEVEL_TRIGGERED;
+    }
+    ioapic.write_entry(gsi, entry);
+    Ok(())
+}
+
+/// Returns the vector a GSI is routed to and whether it is masked
+pub fn routing_of(gsi: u32) -> Result<(u8, bool), IrqError> {
+    let routing = ROUTING.lock();
+    let entry = routing.ioapic_for(gsi)?.read_entry(gsi);
+    Ok(((entry & ENTRY_VECTOR_MASK) as u8, entry & ENTRY_MASKED != 0))
+}
@@ -3,6 +3,24 @@
 .section .text
 .global isr_interprocessor_interrupt
 isr_interprocessor_interrupt:
-mov rdi, gs:[rip + GS_OFFSET_IPI_QUEUE]
+// Preserve the registers the System V ABI does not require the handler to preserve.
+push rax
+push rcx
+push rdx
+push rsi
+push rdi
+push r8
+push r9
+push r10
+push r11
 call ih_interprocessor_interrupt
-iretq
\ No newline at end of file
+pop r11
+pop r10
+pop r9
+pop r8
+pop rdi
+pop rsi
+pop rdx
+pop rcx
+pop rax
+iretq
@@ -5,28 +5,47 @@
 //! The protocol supports both unicast (single target) and multicast (multiple targets) IPIs.
 //! The implementation is kept as similar as possible across different architectures within reason.
 //!
-//! Each logical processor (LP) has it's own IPI mailbox, which is contains an enum with the IPI
-//! type and arguments. Sending an IPI involves writing to the target LP's mailbox and then
-//! triggering the IPI via the architecture-specific mechanism. Receiving an IPI involves checking
-//! the mailbox and executing the corresponding handler. If the IPI is multicast, the first argument
-//! passed is a pointer to the completion barrier, which is used to signal when all target LPs have
-//! completed handling the IPI. This is important for ensuring that all target LPs have completed
-//! the requested operation before any of them return from the ISR.
+//! Each logical processor (LP) has it's own IPI mailbox, which is a queue of enums holding the IPI
+//! type and arguments. Sending an IPI involves writing to the target LPs' mailboxes and then
+//! triggering the IPI via the architecture-specific mechanism. Receiving an IPI involves draining
+//! the mailbox and executing the corresponding handlers. A synchronous RPC made with
+//! [`call_on_lps`] carries a completion barrier which every target LP arrives at once it has run
+//! the call, and the sender does not return until all of them have arrived. While it waits the
+//! sender drains its own mailbox so that two LPs calling each other at the same time cannot
+//! deadlock even with interrupts masked.
+//!
+//! Only TLB invalidations and synchronous calls are carried out in the ISR itself. Requests that
+//! need the LP scheduler are deferred to tasklets so that its lock is not taken with interrupts
+//! masked.
 
 use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::arch::global_asm;
-
-use spin::Mutex;
+use core::arch::{asm, global_asm};
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
 
+use super::x2apic::ipi::{DeliveryMode, Destination, send};
+use super::x2apic::{LogicalLapicId, lapic_id_of};
+use crate::cpu::deferred::schedule_tasklet;
+use crate::cpu::multiprocessor::lp_mask::LpMask;
+use crate::cpu::multiprocessor::{get_lp_count, online_lps};
 use crate::cpu::scheduler::GLOBAL_SCHEDULER;
+use crate::cpu::sync::IrqSpinlock;
+use crate::cpu::sync::context::InterruptContextGuard;
 use crate::cpu::threads::ThreadId;
+use crate::cpu::timers;
+use crate::isa::lp::LpId;
+use crate::isa::lp::ops::{get_lp_id, interrupts_enabled, mask_interrupts, unmask_interrupts};
 use crate::isa::memory::tlb;
 use crate::memory::vmem::VAddr;
 use crate::memory::{AddressSpaceId, KERNEL_ASID};
+use crate::percpu;
 
-#[unsafe(no_mangle)]
-pub static GS_OFFSET_IPI_QUEUE: usize = 16;
+percpu! {
+    /// The IPI mailbox of each LP
+    pub static IPI_QUEUE: IrqSpinlock<VecDeque<Ipi>> = IrqSpinlock::new(VecDeque::new());
+}
 
 global_asm!(include_str!("ipis.asm"));
 
@@ -34,6 +53,8 @@ unsafe extern "C" {
     pub fn isr_interprocessor_interrupt();
 }
 
+pub const IPI_VECTOR: u8 = 33;
+
 #[derive(Clone, Debug)]
 pub enum Ipi {
     VMemInval(AddressSpaceId, VAddr, usize),
@@ -41,29 +62,201 @@ pub enum Ipi {
     TerminateThreads(Vec<ThreadId>),
     AbortThreads(Vec<ThreadId>),
     AbortAsThreads(AddressSpaceId),
+    /// Moves a thread off the receiving LP to the given LP
+    MigrateThread(ThreadId, LpId),
+    /// Wakes the receiving LP to run threads that have become runnable on it
+    Reschedule,
+    /// Runs a function on behalf of [`call_on_lps`]
+    Call(Arc<RemoteCall>),
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn ih_interprocessor_interrupt(ipi_queue: &'static mut Mutex<VecDeque<Ipi>>) {
+/// Counts down the target LPs of a multicast IPI that have yet to handle it
+pub struct CompletionBarrier {
+    remaining: AtomicU32,
+}
+
+impl CompletionBarrier {
+    pub const fn new(participants: u32) -> Self {
+        CompletionBarrier {
+            remaining: AtomicU32::new(participants),
+        }
+    }
+
+    /// Signals that one target has handled the IPI
+    pub fn arrive(&self) {
+        let previous = self.remaining.fetch_sub(1, Ordering::AcqRel);
+        debug_assert!(previous > 0, "More LPs arrived at a completion barrier than it expected");
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining.load(Ordering::Acquire) == 0
+    }
+
+    /// Waits until every target has arrived. The current LP's own mailbox is drained meanwhile.
+    pub fn wait(&self) {
+        while !self.is_complete() {
+            handle_pending_ipis();
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// A function to be run on other LPs by [`call_on_lps`]
+pub struct RemoteCall {
+    /// Borrowed from the caller's stack frame, which outlives every use because the caller does
+    /// not return before all targets have arrived at the barrier
+    function: *const (dyn Fn() + Sync),
+    barrier:  CompletionBarrier,
+}
+
+// SAFETY: The function is `Sync` and is kept alive by the caller until the call completes.
+unsafe impl Send for RemoteCall {}
+unsafe impl Sync for RemoteCall {}
+
+impl RemoteCall {
+    fn run(&self) {
+        unsafe { (*self.function)() };
+        // Nothing may be accessed through `function` after arriving.
+        self.barrier.arrive();
+    }
+}
+
+impl fmt::Debug for RemoteCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteCall")
+            .field("remaining", &self.barrier.remaining.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+fn post_ipi(lp_id: LpId, ipi: Ipi) {
+    let Some(ipi_queue) = IPI_QUEUE.get_for(lp_id) else {
+        panic!("An IPI was posted to LP{} which has not been initialized", lp_id);
+    };
+    ipi_queue.lock().push_back(ipi);
+}
+
+/// Interrupts the given LPs. A lone target is addressed by its physical ID while several targets
+/// are addressed with one logical destination per cluster they span.
+fn trigger_ipi(targets: LpMask) {
+    let lapic_id = |lp_id| {
+        lapic_id_of(lp_id)
+            .unwrap_or_else(|| panic!("LP{} has not registered its local APIC ID", lp_id))
+    };
+    if targets.count() == 1 {
+        let lp_id = targets.iter().next().unwrap();
+        send(Destination::Physical(lapic_id(lp_id).physical), IPI_VECTOR, DeliveryMode::Fixed);
+        return;
+    }
+    let mut clusters: Vec<LogicalLapicId> = Vec::new();
+    for lp_id in targets.iter() {
+        let logical = lapic_id(lp_id).logical;
+        match clusters.iter_mut().find(|cluster| cluster.cluster_id == logical.cluster_id) {
+            Some(cluster) => cluster.apic_bitmask |= logical.apic_bitmask,
+            None => clusters.push(logical),
+        }
+    }
+    for cluster in clusters {
+        send(Destination::LogicalCluster(cluster), IPI_VECTOR, DeliveryMode::Fixed);
+    }
+}
+
+/// Places `ipi` in the mailbox of every LP in `targets` and interrupts them
+pub fn send_ipi(targets: LpMask, ipi: Ipi) {
+    if targets.is_empty() {
+        return;
+    }
+    for lp_id in targets.iter() {
+        post_ipi(lp_id, ipi.clone());
+    }
+    trigger_ipi(targets);
+}
+
+/// Sends `ipi` to every online LP other than the current one
+pub fn broadcast_ipi(ipi: Ipi) {
+    let mut targets = online_lps();
+    targets.remove(get_lp_id!());
+    if targets.is_empty() {
+        return;
+    }
+    for lp_id in targets.iter() {
+        post_ipi(lp_id, ipi.clone());
+    }
+    // The shorthand also reaches LPs that are not online, which is only harmless once all are.
+    if targets.count() + 1 == get_lp_count() {
+        send(Destination::AllExcludingSelf, IPI_VECTOR, DeliveryMode::Fixed);
+    } else {
+        trigger_ipi(targets);
+    }
+}
+
+/// Runs `function` on every LP in `targets` and returns once all of them have run it. Remote LPs
+/// run it in their IPI handler and the current LP, if it is a target, runs it directly with
+/// interrupts masked.
+pub fn call_on_lps<F: Fn() + Sync>(targets: LpMask, function: F) {
+    let lp_id = get_lp_id!();
+    let mut remote_targets = targets;
+    remote_targets.remove(lp_id);
+    let function: &(dyn Fn() + Sync) = &function;
+    // SAFETY: Only the lifetime is erased. See `RemoteCall::function`.
+    let function: *const (dyn Fn() + Sync) = unsafe { core::mem::transmute(function) };
+    let call = Arc::new(RemoteCall {
+        function,
+        barrier: CompletionBarrier::new(remote_targets.count()),
+    });
+    send_ipi(remote_targets, Ipi::Call(call.clone()));
+    if targets.contains(lp_id) {
+        let interrupts_were_enabled = interrupts_enabled();
+        mask_interrupts!();
+        unsafe { (*function)() };
+        if interrupts_were_enabled {
+            unmask_interrupts!();
+        }
+    }
+    call.barrier.wait();
+}
+
+/// Handles every IPI in the current LP's mailbox
+fn handle_pending_ipis() {
+    let ipi_queue = IPI_QUEUE.get();
     while let Some(ipi) = ipi_queue.lock().pop_front() {
-        match ipi {
-            Ipi::VMemInval(asid, base, size) => {
-                if asid == KERNEL_ASID {
-                    tlb::inval_range_kernel(base, size);
-                } else {
-                    tlb::inval_range_user(asid, base, size);
-                }
-            }
-            Ipi::AsidInval(asid) => tlb::inval_asid(asid),
-            Ipi::TerminateThreads(tids) => {
-                GLOBAL_SCHEDULER.get_local_lp_scheduler().lock().terminate_threads(tids)
-            }
-            Ipi::AbortThreads(tids) => {
-                GLOBAL_SCHEDULER.get_local_lp_scheduler().lock().abort_threads(tids)
-            }
-            Ipi::AbortAsThreads(asid) => {
-                GLOBAL_SCHEDULER.get_local_lp_scheduler().lock().abort_as_threads(asid)
+        handle_ipi(ipi);
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ih_interprocessor_interrupt() {
+    let _context = InterruptContextGuard::enter();
+    super::x2apic::signal_eoi();
+    handle_pending_ipis();
+}
+
+fn handle_ipi(ipi: Ipi) {
+    match ipi {
+        // The sender may be waiting for the invalidation so it is not deferred.
+        Ipi::VMemInval(asid, base, size) => {
+            if asid == KERNEL_ASID {
+                tlb::inval_range_kernel(base, size);
+            } else {
+                tlb::inval_range_user(asid, base, size);
             }
         }
+        Ipi::AsidInval(asid) => tlb::inval_asid(asid),
+        Ipi::TerminateThreads(tids) => schedule_tasklet(move || {
+            GLOBAL_SCHEDULER.get_local_lp_scheduler().lock().terminate_threads(tids)
+        }),
+        Ipi::AbortThreads(tids) => schedule_tasklet(move || {
+            GLOBAL_SCHEDULER.get_local_lp_scheduler().lock().abort_threads(tids)
+        }),
+        Ipi::AbortAsThreads(asid) => schedule_tasklet(move || {
+            GLOBAL_SCHEDULER.get_local_lp_scheduler().lock().abort_as_threads(asid)
+        }),
+        Ipi::MigrateThread(tid, destination) => {
+            schedule_tasklet(move || GLOBAL_SCHEDULER.migrate_local_thread(tid, destination))
+        }
+        // Taking the interrupt gets an idle LP out of its wait and the restarted tick preempts
+        // whatever it is running.
+        Ipi::Reschedule => timers::restart_tick(),
+        Ipi::Call(call) => call.run(),
     }
 }
@@ -0,0 +1,46 @@
+.code64
+
+.section .text
+// One 16 byte aligned stub per dynamic vector which pushes the vector number. The address of the
+// stub for a vector is derived from its offset from the first dynamic vector.
+.balign 16
+.global isr_external_interrupt_stubs
+isr_external_interrupt_stubs:
+.set vector, {first}
+.rept {last} - {first} + 1
+.balign 16
+// push imm32
+.byte 0x68
+.long vector
+jmp isr_external_interrupt_common
+.set vector, vector + 1
+.endr
+
+isr_external_interrupt_common:
+// Preserve the registers the System V ABI does not require the handler to preserve.
+push rax
+push rcx
+push rdx
+push rsi
+push rdi
+push r8
+push r9
+push r10
+push r11
+mov rdi, [rsp + 72]
+// The pushed vector number leaves the stack misaligned for the call.
+sub rsp, 8
+call ih_external_interrupt
+add rsp, 8
+pop r11
+pop r10
+pop r9
+pop r8
+pop rdi
+pop rsi
+pop rdx
+pop rcx
+pop rax
+// Discard the vector number
+add rsp, 8
+iretq
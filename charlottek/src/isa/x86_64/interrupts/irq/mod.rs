@@ -0,0 +1,165 @@
+//! # External Interrupts
+//!
+//! Device interrupts enter either through the IOAPICs or as message signaled interrupts and are
+//! delivered on vectors allocated at runtime on the LP they are directed to. Every dynamic vector
+//! has an entry stub which passes its number to a common handler that looks up the handler the
+//! current LP has registered for the vector. Handlers run in interrupt context. The interrupt is
+//! acknowledged once the handler returns, so a level triggered device must have deasserted its
+//! interrupt by then.
+
+use alloc::boxed::Box;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::sync::Arc;
+use core::arch::global_asm;
+
+use super::idt::{FIRST_DYNAMIC_VECTOR, Idt, LAST_DYNAMIC_VECTOR};
+use super::ioapic::{Polarity, TriggerMode};
+use super::x2apic::{lapic_id_of, signal_eoi};
+use super::{ioapic, pic};
+use crate::cpu::sync::IrqSpinlock;
+use crate::cpu::sync::context::InterruptContextGuard;
+use crate::isa::lp::LpId;
+use crate::isa::lp::ops::get_lp_id;
+use crate::{lock_class, logln, percpu};
+
+global_asm!(
+    include_str!("irq.asm"),
+    first = const FIRST_DYNAMIC_VECTOR,
+    last = const LAST_DYNAMIC_VECTOR,
+);
+
+unsafe extern "C" {
+    static isr_external_interrupt_stubs: u8;
+}
+
+const N_DYNAMIC_VECTORS: usize = (LAST_DYNAMIC_VECTOR - FIRST_DYNAMIC_VECTOR) as usize + 1;
+const STUB_SIZE: usize = 16;
+
+pub type IrqHandler = Arc<dyn Fn() + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// The firmware does not describe any IOAPIC
+    NoInterruptControllerTable,
+    /// No IOAPIC receives the GSI
+    NoSuchGsi,
+    /// The GSI already has a handler
+    AlreadyRequested,
+    /// The GSI has no handler
+    NotRequested,
+    /// Every dynamic vector is in use
+    NoFreeVector,
+    /// The LP cannot be addressed by the IOAPIC or has not set up its local APIC yet
+    UnreachableLp,
+}
+
+percpu! {
+    /// The handler of each dynamic vector on the LP
+    static HANDLERS: IrqSpinlock<[Option<IrqHandler>; N_DYNAMIC_VECTORS]> =
+        IrqSpinlock::with_class(lock_class!("IRQ_HANDLERS"), [const { None }; N_DYNAMIC_VECTORS]);
+}
+
+/// The LP and vector each requested GSI is delivered on
+static REQUESTED_GSIS: IrqSpinlock<BTreeMap<u32, (LpId, u8)>> =
+    IrqSpinlock::with_class(lock_class!("IRQ_REQUESTED_GSIS"), BTreeMap::new());
+
+/// Returns the entry stub of a dynamic vector
+pub fn stub_for(vector: u8) -> unsafe extern "C" fn() {
+    let index = (vector - FIRST_DYNAMIC_VECTOR) as usize;
+    let stub = unsafe { (&raw const isr_external_interrupt_stubs).byte_add(index * STUB_SIZE) };
+    unsafe { core::mem::transmute::<*const u8, unsafe extern "C" fn()>(stub) }
+}
+
+/// Discovers the IOAPICs and disables the 8259 PICs. Must be called on the BSP once the kernel
+/// allocator is available.
+pub fn init() -> Result<(), IrqError> {
+    let has_pics = ioapic::init()?;
+    if has_pics {
+        logln!("Disabling the 8259 PICs.");
+        pic::disable();
+    }
+    Ok(())
+}
+
+/// Calls `handler` whenever the vector is raised on the given LP. The vector must have been
+/// allocated on that LP.
+pub fn set_vector_handler(
+    lp_id: LpId,
+    vector: u8,
+    handler: impl Fn() + Send + Sync + 'static,
+) -> Result<(), IrqError> {
+    let handlers = HANDLERS.get_for(lp_id).ok_or(IrqError::UnreachableLp)?;
+    let handler: Box<dyn Fn() + Send + Sync> = Box::new(handler);
+    handlers.lock()[(vector - FIRST_DYNAMIC_VECTOR) as usize] = Some(handler.into());
+    Ok(())
+}
+
+/// Removes the handler of a vector on the given LP. A call to the handler that is already running
+/// is allowed to finish.
+pub fn clear_vector_handler(lp_id: LpId, vector: u8) {
+    if let Some(handlers) = HANDLERS.get_for(lp_id) {
+        handlers.lock()[(vector - FIRST_DYNAMIC_VECTOR) as usize] = None;
+    }
+}
+
+/// Delivers the GSI to `handler` on the given LP and unmasks it
+pub fn request_irq(
+    gsi: u32,
+    handler: impl Fn() + Send + Sync + 'static,
+    target_lp: LpId,
+) -> Result<(), IrqError> {
+    let destination = lapic_id_of(target_lp).ok_or(IrqError::UnreachableLp)?.physical;
+    let mut requested = REQUESTED_GSIS.lock();
+    if requested.contains_key(&gsi) {
+        return Err(IrqError::AlreadyRequested);
+    }
+    let vector = Idt::allocate_vector(target_lp).ok_or(IrqError::NoFreeVector)?;
+    if let Err(error) = ioapic::route(gsi, vector, destination)
+        .and_then(|_| set_vector_handler(target_lp, vector, handler))
+    {
+        Idt::free_vector(target_lp, vector);
+        return Err(error);
+    }
+    requested.insert(gsi, (target_lp, vector));
+    ioapic::set_masked(gsi, false)
+}
+
+/// Masks the GSI and removes its handler
+pub fn free_irq(gsi: u32) -> Result<(), IrqError> {
+    let mut requested = REQUESTED_GSIS.lock();
+    let (lp_id, vector) = requested.remove(&gsi).ok_or(IrqError::NotRequested)?;
+    ioapic::set_masked(gsi, true)?;
+    clear_vector_handler(lp_id, vector);
+    Idt::free_vector(lp_id, vector);
+    Ok(())
+}
+
+pub fn mask_irq(gsi: u32) -> Result<(), IrqError> {
+    ioapic::set_masked(gsi, true)
+}
+
+pub fn unmask_irq(gsi: u32) -> Result<(), IrqError> {
+    ioapic::set_masked(gsi, false)
+}
+
+/// Overrides the polarity and trigger mode the GSI was given from the firmware's description
+pub fn configure_irq(
+    gsi: u32,
+    polarity: Polarity,
+    trigger_mode: TriggerMode,
+) -> Result<(), IrqError> {
+    ioapic::configure(gsi, polarity, trigger_mode)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ih_external_interrupt(vector: u64) {
+    let _context = InterruptContextGuard::enter();
+    let index = vector as usize - FIRST_DYNAMIC_VECTOR as usize;
+    // The handler is called without the lock held so that it can request and free IRQs itself.
+    let handler = HANDLERS.get().lock()[index].clone();
+    match handler {
+        Some(handler) => handler(),
+        None => logln!("LP{}: Received an interrupt on unused vector {}.", (get_lp_id!()), vector),
+    }
+    signal_eoi();
+}
@@ -1,20 +1,105 @@
 pub mod context_switch;
 pub mod exceptions;
 pub mod idt;
+pub mod ioapic;
 pub mod ipis;
+pub mod irq;
+pub mod msi;
+pub mod pic;
 pub mod x2apic;
 
-use context_switch::isr_switch_thread_context;
+use context_switch::{CONTEXT_SWITCH_VECTOR, isr_switch_thread_context};
 use idt::*;
-use ipis::isr_interprocessor_interrupt;
-use spin::Mutex;
+use ipis::{IPI_VECTOR, Ipi, isr_interprocessor_interrupt};
+use irq::IrqError;
+use pic::{PIC1_SPURIOUS_VECTOR, PIC2_SPURIOUS_VECTOR};
+use x2apic::timer::{TIMER_VECTOR, isr_lapic_timer};
+use x2apic::{SPURIOUS_VECTOR, isr_spurious_interrupt};
 
+use crate::cpu::multiprocessor::lp_mask::LpMask;
 use crate::isa::init::gdt;
-
-pub static IDT: Mutex<Idt> = Mutex::new(Idt::new());
+use crate::isa::interface::interrupts::InterruptManagerIfce;
+use crate::isa::lp::LpId;
 
 pub fn register_fixed_isr_gates(idt: &mut Idt) {
+    // The fixed gates below replace the stubs of the vectors they use.
+    for vector in FIRST_DYNAMIC_VECTOR..=LAST_DYNAMIC_VECTOR {
+        idt.set_gate(
+            vector as usize,
+            irq::stub_for(vector),
+            gdt::KERNEL_CODE_SELECTOR,
+            false,
+            true,
+        );
+    }
     exceptions::load_exceptions(idt);
-    idt.set_gate(32, isr_switch_thread_context, gdt::KERNEL_CODE_SELECTOR, false, true);
-    idt.set_gate(33, isr_interprocessor_interrupt, gdt::KERNEL_CODE_SELECTOR, false, true);
+    idt.set_gate(
+        CONTEXT_SWITCH_VECTOR as usize,
+        isr_switch_thread_context,
+        gdt::KERNEL_CODE_SELECTOR,
+        false,
+        true,
+    );
+    idt.set_gate(
+        IPI_VECTOR as usize,
+        isr_interprocessor_interrupt,
+        gdt::KERNEL_CODE_SELECTOR,
+        false,
+        true,
+    );
+    idt.set_gate(TIMER_VECTOR as usize, isr_lapic_timer, gdt::KERNEL_CODE_SELECTOR, false, true);
+    idt.set_gate(
+        SPURIOUS_VECTOR as usize,
+        isr_spurious_interrupt,
+        gdt::KERNEL_CODE_SELECTOR,
+        false,
+        true,
+    );
+    // A PIC can raise spurious interrupts even while all of its lines are masked.
+    for vector in [PIC1_SPURIOUS_VECTOR, PIC2_SPURIOUS_VECTOR] {
+        idt.set_gate(
+            vector as usize,
+            isr_spurious_interrupt,
+            gdt::KERNEL_CODE_SELECTOR,
+            false,
+            true,
+        );
+    }
+}
+
+pub struct InterruptManager;
+
+impl InterruptManagerIfce for InterruptManager {
+    type Error = IrqError;
+    type Ipi = Ipi;
+
+    fn init_interrupt_structures() -> Result<(), Self::Error> {
+        x2apic::enable_local();
+        x2apic::register_local();
+        Ok(())
+    }
+
+    fn init_external_interrupts() -> Result<(), Self::Error> {
+        irq::init()
+    }
+
+    fn request_irq(
+        gsi: u32,
+        handler: impl Fn() + Send + Sync + 'static,
+        target_lp: LpId,
+    ) -> Result<(), Self::Error> {
+        irq::request_irq(gsi, handler, target_lp)
+    }
+
+    fn free_irq(gsi: u32) -> Result<(), Self::Error> {
+        irq::free_irq(gsi)
+    }
+
+    fn send_ipi(targets: LpMask, ipi: Ipi) {
+        ipis::send_ipi(targets, ipi)
+    }
+
+    fn broadcast_ipi(ipi: Ipi) {
+        ipis::broadcast_ipi(ipi)
+    }
 }
@@ -0,0 +1,87 @@
+//! # Message Signaled Interrupts
+//!
+//! A device raises a message signaled interrupt by writing a data word to an address, both of which
+//! the kernel programs into the device. On x86_64 the address selects the destination local APIC
+//! and the data selects the vector, so every message is delivered to exactly one LP. Without
+//! interrupt remapping the address only has room for 8-bit APIC IDs even in x2APIC mode.
+//!
+//! Multiple message MSI derives the vector of each message from the low bits of the data, which is
+//! why its vectors are allocated as an aligned block on a single LP. MSI-X programs every message
+//! separately and can direct each of them to a different LP.
+
+use alloc::sync::Arc;
+
+use super::idt::Idt;
+use super::irq::{IrqError, clear_vector_handler, set_vector_handler};
+use super::x2apic::lapic_id_of;
+use crate::isa::lp::LpId;
+
+const ADDRESS_BASE: u64 = 0xfee0_0000;
+const ADDRESS_DESTINATION_SHIFT: u64 = 12;
+
+/// The address and data word a device writes to raise an interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiMessage {
+    pub address: u64,
+    pub data: u32,
+}
+
+/// A block of vectors allocated on one LP for the messages of a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiVectors {
+    pub lp_id: LpId,
+    pub first_vector: u8,
+    pub count: usize,
+    destination: u8,
+}
+
+impl MsiVectors {
+    /// Returns the message that raises the `index`th vector of the block
+    pub fn message(&self, index: usize) -> MsiMessage {
+        assert!(index < self.count, "Message {} is not part of the block", index);
+        MsiMessage {
+            address: ADDRESS_BASE | (self.destination as u64) << ADDRESS_DESTINATION_SHIFT,
+            // Edge triggered with fixed delivery
+            data: (self.first_vector as usize + index) as u32,
+        }
+    }
+}
+
+/// Allocates `count` vectors on the given LP and registers `handler` for them. The handler is
+/// passed the index of the message that was raised. `count` must be a power of two no larger than
+/// 32.
+pub fn allocate(
+    lp_id: LpId,
+    count: usize,
+    handler: impl Fn(usize) + Send + Sync + 'static,
+) -> Result<MsiVectors, IrqError> {
+    let physical = lapic_id_of(lp_id).ok_or(IrqError::UnreachableLp)?.physical;
+    let destination = u8::try_from(physical).map_err(|_| IrqError::UnreachableLp)?;
+    let first_vector = Idt::allocate_vectors(lp_id, count).ok_or(IrqError::NoFreeVector)?;
+    let handler = Arc::new(handler);
+    for index in 0..count {
+        let handler = handler.clone();
+        if let Err(error) =
+            set_vector_handler(lp_id, first_vector + index as u8, move || handler(index))
+        {
+            (0..index).for_each(|index| clear_vector_handler(lp_id, first_vector + index as u8));
+            Idt::free_vectors(lp_id, first_vector, count);
+            return Err(error);
+        }
+    }
+    Ok(MsiVectors {
+        lp_id,
+        first_vector,
+        count,
+        destination,
+    })
+}
+
+/// Removes the handlers of a block of vectors and frees them. The device must no longer be able to
+/// send the messages.
+pub fn free(vectors: MsiVectors) {
+    for index in 0..vectors.count {
+        clear_vector_handler(vectors.lp_id, vectors.first_vector + index as u8);
+    }
+    Idt::free_vectors(vectors.lp_id, vectors.first_vector, vectors.count);
+}
@@ -0,0 +1,48 @@
+//! # Legacy 8259 Programmable Interrupt Controllers
+//!
+//! PC compatible systems still have a pair of cascaded 8259 PICs which must not deliver interrupts
+//! once the IOAPICs are in use. Masking every line is not quite enough since a PIC can still raise
+//! a spurious interrupt on the vector of its lowest priority line, so both PICs are first
+//! reinitialized to deliver on vectors that are reserved for exactly that purpose.
+
+use crate::isa::interface::io::OReg8Ifce;
+use crate::isa::io::IoReg8;
+
+const PIC1_COMMAND: IoReg8 = IoReg8::IoPort(0x20);
+const PIC1_DATA: IoReg8 = IoReg8::IoPort(0x21);
+const PIC2_COMMAND: IoReg8 = IoReg8::IoPort(0xa0);
+const PIC2_DATA: IoReg8 = IoReg8::IoPort(0xa1);
+/// Writing to the POST code port takes long enough for a PIC to process the previous write
+const POST_PORT: IoReg8 = IoReg8::IoPort(0x80);
+
+const ICW1_INIT: u8 = 0x10;
+const ICW1_ICW4_NEEDED: u8 = 0x01;
+const ICW4_8086_MODE: u8 = 0x01;
+/// The line of the primary PIC the secondary one is cascaded to
+const CASCADE_IRQ: u8 = 2;
+const MASK_ALL: u8 = 0xff;
+
+pub const PIC1_VECTOR_BASE: u8 = 0xe0;
+pub const PIC2_VECTOR_BASE: u8 = 0xe8;
+/// The vectors the PICs raise spurious interrupts on
+pub const PIC1_SPURIOUS_VECTOR: u8 = PIC1_VECTOR_BASE + 7;
+pub const PIC2_SPURIOUS_VECTOR: u8 = PIC2_VECTOR_BASE + 7;
+
+fn write_slowly(register: IoReg8, value: u8) {
+    register.write(value);
+    POST_PORT.write(0);
+}
+
+/// Remaps both PICs to their reserved vectors and masks all of their lines
+pub fn disable() {
+    write_slowly(PIC1_COMMAND, ICW1_INIT | ICW1_ICW4_NEEDED);
+    write_slowly(PIC2_COMMAND, ICW1_INIT | ICW1_ICW4_NEEDED);
+    write_slowly(PIC1_DATA, PIC1_VECTOR_BASE);
+    write_slowly(PIC2_DATA, PIC2_VECTOR_BASE);
+    write_slowly(PIC1_DATA, 1 << CASCADE_IRQ);
+    write_slowly(PIC2_DATA, CASCADE_IRQ);
+    write_slowly(PIC1_DATA, ICW4_8086_MODE);
+    write_slowly(PIC2_DATA, ICW4_8086_MODE);
+    write_slowly(PIC1_DATA, MASK_ALL);
+    write_slowly(PIC2_DATA, MASK_ALL);
+}
@@ -0,0 +1,55 @@
+//! # Sending Inter-Processor Interrupts
+//!
+//! In x2APIC mode an IPI is sent with a single write to the interrupt command register MSR. The
+//! destination occupies the upper half of the register and is interpreted either as a physical
+//! x2APIC ID or as a logical ID, i.e. a cluster and a bitmask of LPs within it. Unlike in xAPIC
+//! mode there is no delivery status to poll before the next IPI can be sent.
+
+use super::{LogicalLapicId, PhysicalLapicId};
+use crate::isa::lp::ops::write_msr;
+
+pub const ICR_MSR: u32 = 0x830;
+
+const ICR_LOGICAL_DESTINATION: u64 = 1 << 11;
+const ICR_LEVEL_ASSERT: u64 = 1 << 14;
+const ICR_SHORTHAND_SHIFT: u64 = 18;
+const ICR_DESTINATION_SHIFT: u64 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum DeliveryMode {
+    /// Delivers the interrupt on the given vector
+    Fixed = 0b000 << 8,
+    /// Delivers a non-maskable interrupt. The vector is ignored.
+    Nmi = 0b100 << 8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Physical(PhysicalLapicId),
+    /// The LPs of one cluster whose bits are set in the bitmask
+    LogicalCluster(LogicalLapicId),
+    SelfOnly,
+    AllIncludingSelf,
+    AllExcludingSelf,
+}
+
+impl Destination {
+    const fn to_icr_bits(self) -> u64 {
+        match self {
+            Destination::Physical(id) => (id as u64) << ICR_DESTINATION_SHIFT,
+            Destination::LogicalCluster(id) => {
+                (id.to_bits() as u64) << ICR_DESTINATION_SHIFT | ICR_LOGICAL_DESTINATION
+            }
+            Destination::SelfOnly => 0b01 << ICR_SHORTHAND_SHIFT,
+            Destination::AllIncludingSelf => 0b10 << ICR_SHORTHAND_SHIFT,
+            Destination::AllExcludingSelf => 0b11 << ICR_SHORTHAND_SHIFT,
+        }
+    }
+}
+
+/// Sends an edge triggered IPI
+pub fn send(destination: Destination, vector: u8, mode: DeliveryMode) {
+    let icr = destination.to_icr_bits() | ICR_LEVEL_ASSERT | mode as u64 | vector as u64;
+    unsafe { write_msr(ICR_MSR, icr) }
+}
@@ -1,10 +1,68 @@
 //! # x2APIC Local Advanced Programmable Interrupt Controller
 
-use alloc::vec::Vec;
-use core::arch::asm;
+pub mod ipi;
+pub mod perf_counter;
+pub mod timer;
 
-pub static mut X2APIC_ID_TABLE: Vec<LapicId> = Vec::new();
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicU64, Ordering};
 
+use crate::cpu::multiprocessor::MAX_LP_COUNT;
+use crate::isa::lp::LpId;
+use crate::isa::lp::ops::{get_lp_id, read_msr, write_msr};
+
+global_asm!(include_str!("x2apic.asm"));
+
+unsafe extern "C" {
+    pub fn isr_spurious_interrupt();
+}
+
+pub const ID_MSR: u32 = 0x802;
+pub const EOI_MSR: u32 = 0x80b;
+pub const LOGICAL_DESTINATION_MSR: u32 = 0x80d;
+pub const SPURIOUS_INTERRUPT_VECTOR_MSR: u32 = 0x80f;
+
+/// The vector the local APIC delivers spurious interrupts on. Spurious interrupts must not be
+/// acknowledged.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+const SVR_APIC_SOFTWARE_ENABLE: u64 = 1 << 8;
+
+const UNREGISTERED: u64 = u64::MAX;
+
+/// The local APIC IDs of each LP packed with [`LapicId::to_bits`]
+static LAPIC_ID_TABLE: [AtomicU64; MAX_LP_COUNT] =
+    [const { AtomicU64::new(UNREGISTERED) }; MAX_LP_COUNT];
+
+/// Records the current LP's local APIC IDs so that other LPs can address IPIs to it
+pub fn register_local() {
+    let lp_id = get_lp_id!();
+    LAPIC_ID_TABLE[lp_id as usize].store(LapicId::get_local().to_bits(), Ordering::Release);
+}
+
+/// Returns the local APIC IDs of the given LP or `None` if it has not registered them yet
+pub fn lapic_id_of(lp_id: LpId) -> Option<LapicId> {
+    let bits = LAPIC_ID_TABLE.get(lp_id as usize)?.load(Ordering::Acquire);
+    (bits != UNREGISTERED).then(|| LapicId::from_bits(bits))
+}
+
+/// Software enables the current LP's local APIC so that it delivers local interrupts
+pub fn enable_local() {
+    unsafe {
+        let svr = read_msr(SPURIOUS_INTERRUPT_VECTOR_MSR);
+        write_msr(
+            SPURIOUS_INTERRUPT_VECTOR_MSR,
+            (svr & !0xff) | SVR_APIC_SOFTWARE_ENABLE | SPURIOUS_VECTOR as u64,
+        );
+    }
+}
+
+/// Signals the end of the interrupt currently being serviced to the current LP's local APIC
+#[inline(always)]
+pub fn signal_eoi() {
+    unsafe { write_msr(EOI_MSR, 0) }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct LapicId {
     pub physical: PhysicalLapicId,
     pub logical:  LogicalLapicId,
@@ -12,32 +70,45 @@ pub struct LapicId {
 
 impl LapicId {
     pub fn get_local() -> Self {
-        let physical: PhysicalLapicId;
-        let logical: u32;
-        unsafe {
-            asm! (
-                "mov ecx, 0x802", // x2APIC ID Register
-                "rdmsr",
-                "mov [{phys:e}], eax",
-                "mov ecx, 0x80d", // x2APIC Logical Destination Register
-                "rdmsr",
-                "mov [{log:e}], eax",
-                phys = out(reg) physical,
-                log = out(reg) logical,
-            )
-        }
+        let (physical, logical) =
+            unsafe { (read_msr(ID_MSR) as u32, read_msr(LOGICAL_DESTINATION_MSR) as u32) };
         LapicId {
             physical,
-            logical: LogicalLapicId {
-                cluster_id: ((logical >> 16) & (1 << 16 - 1)) as u16,
-                apic_bitmask: (logical & (1 << 16 - 1)) as u16,
-            },
+            logical: LogicalLapicId::from_bits(logical),
+        }
+    }
+
+    const fn to_bits(self) -> u64 {
+        (self.logical.to_bits() as u64) << 32 | self.physical as u64
+    }
+
+    const fn from_bits(bits: u64) -> Self {
+        LapicId {
+            physical: bits as u32,
+            logical:  LogicalLapicId::from_bits((bits >> 32) as u32),
         }
     }
 }
+
 pub type PhysicalLapicId = u32;
-#[repr(C, packed)]
+
+/// In x2APIC mode the logical ID is fixed by the hardware. The upper half of the physical ID
+/// selects a cluster of up to 16 LPs and each LP in the cluster is assigned one bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct LogicalLapicId {
-    cluster_id: u16,
-    apic_bitmask: u16,
+    pub cluster_id: u16,
+    pub apic_bitmask: u16,
+}
+
+impl LogicalLapicId {
+    pub const fn from_bits(bits: u32) -> Self {
+        LogicalLapicId {
+            cluster_id: (bits >> 16) as u16,
+            apic_bitmask: bits as u16,
+        }
+    }
+
+    pub const fn to_bits(self) -> u32 {
+        (self.cluster_id as u32) << 16 | self.apic_bitmask as u32
+    }
 }
@@ -0,0 +1,86 @@
+//! # Performance Counter Overflow NMIs
+//!
+//! The first general purpose performance counter of each LP is programmed to count unhalted core
+//! cycles starting from a negative value, so that it overflows once the LP has been busy for the
+//! given number of cycles. The local APIC delivers the overflow as an NMI through its performance
+//! counter LVT entry, which it masks on delivery, so the counter has to be armed again after
+//! every overflow. Only Intel's architectural performance monitoring is supported.
+
+use core::arch::x86_64::__cpuid_count;
+
+use spin::Lazy;
+
+use super::ipi::DeliveryMode;
+use crate::isa::interface::system_info::CpuInfoIfce;
+use crate::isa::lp::ops::{read_msr, write_msr};
+use crate::isa::system_info::{CpuInfo, IsaExtension};
+
+const LVT_PERFORMANCE_COUNTER_MSR: u32 = 0x834;
+const PMC0_MSR: u32 = 0xc1;
+const PERFEVTSEL0_MSR: u32 = 0x186;
+const PERF_GLOBAL_CTRL_MSR: u32 = 0x38f;
+const PERF_GLOBAL_OVF_CTRL_MSR: u32 = 0x390;
+
+const EVENT_UNHALTED_CORE_CYCLES: u64 = 0x3c;
+const EVTSEL_USR: u64 = 1 << 16;
+const EVTSEL_OS: u64 = 1 << 17;
+const EVTSEL_INT: u64 = 1 << 20;
+const EVTSEL_EN: u64 = 1 << 22;
+
+/// Writes to the counter only set its low 32 bits and sign extend them, so it cannot be armed for
+/// more cycles than this
+pub const MAX_CYCLES: u64 = i32::MAX as u64;
+
+struct PerfmonInfo {
+    version: u32,
+    counter_width: u32,
+}
+
+static PERFMON_INFO: Lazy<Option<PerfmonInfo>> = Lazy::new(|| {
+    if !CpuInfo::is_extension_supported(IsaExtension::ArchPerfmon) {
+        return None;
+    }
+    let cpuid_result = __cpuid_count(0x0000_000a, 0);
+    Some(PerfmonInfo {
+        version: cpuid_result.eax & 0xff,
+        counter_width: (cpuid_result.eax >> 16) & 0xff,
+    })
+});
+
+pub fn is_supported() -> bool {
+    PERFMON_INFO.is_some()
+}
+
+/// Arms the current LP's counter to deliver an NMI once it has executed `cycles` unhalted cycles,
+/// clamped to [`MAX_CYCLES`]. Does nothing if performance monitoring is not supported.
+pub fn arm(cycles: u64) {
+    let Some(info) = PERFMON_INFO.as_ref() else {
+        return;
+    };
+    let counter_mask = (1u64 << info.counter_width) - 1;
+    let start = cycles.clamp(1, MAX_CYCLES).wrapping_neg() & counter_mask;
+    unsafe {
+        write_msr(PERFEVTSEL0_MSR, 0);
+        write_msr(PMC0_MSR, start);
+        if info.version >= 2 {
+            write_msr(PERF_GLOBAL_OVF_CTRL_MSR, 1);
+            write_msr(PERF_GLOBAL_CTRL_MSR, read_msr(PERF_GLOBAL_CTRL_MSR) | 1);
+        }
+        write_msr(LVT_PERFORMANCE_COUNTER_MSR, DeliveryMode::Nmi as u64);
+        write_msr(
+            PERFEVTSEL0_MSR,
+            EVENT_UNHALTED_CORE_CYCLES | EVTSEL_USR | EVTSEL_OS | EVTSEL_INT | EVTSEL_EN,
+        );
+    }
+}
+
+/// Returns true if the current LP's counter has overflowed since it was last armed, i.e. if it
+/// could have caused the NMI being handled
+pub fn has_overflowed() -> bool {
+    let Some(info) = PERFMON_INFO.as_ref() else {
+        return false;
+    };
+    // The counter starts out with its top bit set and only clears it by wrapping around.
+    let top_bit = 1u64 << (info.counter_width - 1);
+    unsafe { read_msr(PERFEVTSEL0_MSR) & EVTSEL_EN != 0 && read_msr(PMC0_MSR) & top_bit == 0 }
+}
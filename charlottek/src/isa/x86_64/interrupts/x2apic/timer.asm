@@ -0,0 +1,26 @@
+.code64
+
+.section .text
+.global isr_lapic_timer
+isr_lapic_timer:
+// Preserve the registers the System V ABI does not require the handler to preserve.
+push rax
+push rcx
+push rdx
+push rsi
+push rdi
+push r8
+push r9
+push r10
+push r11
+call ih_lapic_timer
+pop r11
+pop r10
+pop r9
+pop r8
+pop rdi
+pop rsi
+pop rdx
+pop rcx
+pop rax
+iretq
@@ -0,0 +1,148 @@
+//! # x2APIC Timer
+//!
+//! Each LP's local APIC timer counts down from an initial count in periodic or one-shot mode, or
+//! fires once the timestamp counter reaches a deadline in TSC-deadline mode. The rate at which it
+//! counts is not architecturally defined. It is calibrated against a reference clock on the BSP
+//! and assumed to be the same on every LP. The divide configuration is always 1.
+
+use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use spin::Lazy;
+
+use crate::cpu::sync::context::InterruptContextGuard;
+use crate::cpu::timers;
+use crate::isa::interface::system_info::CpuInfoIfce;
+use crate::isa::interface::timers::{Error, TimerIfce, TimerMode};
+use crate::isa::lp::ops::{read_msr, write_msr};
+use crate::isa::system_info::{CpuInfo, IsaExtension};
+use crate::isa::timers::ReferenceClock;
+use crate::logln;
+
+global_asm!(include_str!("timer.asm"));
+
+unsafe extern "C" {
+    pub fn isr_lapic_timer();
+}
+
+/// The vector the local APIC timer interrupt is delivered on
+pub const TIMER_VECTOR: u8 = 34;
+
+const LVT_TIMER_MSR: u32 = 0x832;
+const INITIAL_COUNT_MSR: u32 = 0x838;
+const CURRENT_COUNT_MSR: u32 = 0x839;
+const DIVIDE_CONFIGURATION_MSR: u32 = 0x83e;
+const TSC_DEADLINE_MSR: u32 = 0x6e0;
+
+const LVT_MASKED: u64 = 1 << 16;
+const LVT_MODE_ONE_SHOT: u64 = 0b00 << 17;
+const LVT_MODE_PERIODIC: u64 = 0b01 << 17;
+const LVT_MODE_TSC_DEADLINE: u64 = 0b10 << 17;
+const DIVIDE_BY_1: u64 = 0b1011;
+
+const CALIBRATION_PERIOD: Duration = Duration::from_millis(10);
+
+/// The number of times the timer counts down per second
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+static TSC_DEADLINE_SUPPORTED: Lazy<bool> =
+    Lazy::new(|| CpuInfo::is_extension_supported(IsaExtension::TscDeadline));
+
+pub struct X2ApicTimer;
+
+impl X2ApicTimer {
+    fn set_lvt(flags: u64) {
+        unsafe { write_msr(LVT_TIMER_MSR, flags | TIMER_VECTOR as u64) }
+    }
+
+    /// Converts a duration to an initial count
+    fn count_for(duration: Duration) -> u64 {
+        let count = duration.as_nanos() * FREQUENCY.load(Ordering::Relaxed) as u128 / 1_000_000_000;
+        count.clamp(1, u32::MAX as u128) as u64
+    }
+}
+
+impl TimerIfce for X2ApicTimer {
+    fn calibrate() -> Result<(), Error> {
+        let reference = ReferenceClock::detect().ok_or(Error::NoReferenceClock)?;
+        unsafe {
+            write_msr(DIVIDE_CONFIGURATION_MSR, DIVIDE_BY_1);
+        }
+        Self::set_lvt(LVT_MASKED | LVT_MODE_ONE_SHOT);
+        unsafe {
+            write_msr(INITIAL_COUNT_MSR, u32::MAX as u64);
+        }
+        reference.busy_wait(CALIBRATION_PERIOD);
+        let remaining = unsafe { read_msr(CURRENT_COUNT_MSR) };
+        Self::disarm();
+        let elapsed = u32::MAX as u64 - remaining;
+        if elapsed == 0 {
+            return Err(Error::CalibrationFailed);
+        }
+        let frequency = elapsed * 1_000_000_000 / CALIBRATION_PERIOD.as_nanos() as u64;
+        FREQUENCY.store(frequency, Ordering::Relaxed);
+        logln!(
+            "x2APIC timer: Calibrated to {} Hz against a {} Hz reference clock.",
+            frequency,
+            (reference.get_frequency())
+        );
+        Ok(())
+    }
+
+    fn init_local() -> Result<(), Error> {
+        if FREQUENCY.load(Ordering::Relaxed) == 0 {
+            return Err(Error::CalibrationFailed);
+        }
+        unsafe {
+            write_msr(DIVIDE_CONFIGURATION_MSR, DIVIDE_BY_1);
+        }
+        Self::disarm();
+        Ok(())
+    }
+
+    fn get_frequency() -> u64 {
+        FREQUENCY.load(Ordering::Relaxed)
+    }
+
+    fn is_mode_supported(mode: TimerMode) -> bool {
+        match mode {
+            TimerMode::Periodic | TimerMode::OneShot => true,
+            TimerMode::TimestampDeadline => *TSC_DEADLINE_SUPPORTED,
+        }
+    }
+
+    fn arm_periodic(period: Duration) {
+        Self::set_lvt(LVT_MODE_PERIODIC);
+        unsafe { write_msr(INITIAL_COUNT_MSR, Self::count_for(period)) }
+    }
+
+    fn arm_one_shot(delay: Duration) {
+        Self::set_lvt(LVT_MODE_ONE_SHOT);
+        unsafe { write_msr(INITIAL_COUNT_MSR, Self::count_for(delay)) }
+    }
+
+    fn arm_timestamp_deadline(timestamp: u64) {
+        debug_assert!(*TSC_DEADLINE_SUPPORTED, "TSC-deadline mode is not supported");
+        Self::set_lvt(LVT_MODE_TSC_DEADLINE);
+        unsafe {
+            // The write to the LVT must be globally visible before the deadline is armed.
+            asm!("mfence", options(nostack, preserves_flags));
+            // Writing zero disarms the timer rather than firing it immediately.
+            write_msr(TSC_DEADLINE_MSR, timestamp.max(1));
+        }
+    }
+
+    fn disarm() {
+        // Leaving TSC-deadline mode also clears any armed deadline.
+        Self::set_lvt(LVT_MASKED | LVT_MODE_ONE_SHOT);
+        unsafe { write_msr(INITIAL_COUNT_MSR, 0) }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ih_lapic_timer() {
+    let context = InterruptContextGuard::enter();
+    // Acknowledged up front since the handler may switch to another thread before returning.
+    super::signal_eoi();
+    timers::handle_timer_interrupt(context);
+}
@@ -0,0 +1,7 @@
+.code64
+
+.section .text
+.global isr_spurious_interrupt
+isr_spurious_interrupt:
+// Spurious interrupts are not acknowledged and there is nothing to do.
+iretq
@@ -36,6 +36,18 @@ macro_rules! unmask_interrupts {
 #[rustfmt::skip]
 pub use unmask_interrupts;
 
+const RFLAGS_INTERRUPT_FLAG: u64 = 1 << 9;
+
+/// Returns true if maskable interrupts are enabled on the current LP
+#[inline(always)]
+pub fn interrupts_enabled() -> bool {
+    let rflags: u64;
+    unsafe {
+        core::arch::asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags));
+    }
+    rflags & RFLAGS_INTERRUPT_FLAG != 0
+}
+
 pub const LAPIC_ID_MSR: u32 = 0x802;
 
 #[rustfmt::skip]
@@ -60,10 +72,188 @@ macro_rules! get_lic_id {
 pub use get_lic_id;
 
 use core::arch::{asm, naked_asm};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use super::LpId;
+use crate::isa::interrupts::exceptions::frame::ExceptionFrame;
+use crate::percpu;
 
 pub const TSC_AUX_MSR: u32 = 0xc000_0103;
+pub const GS_BASE_MSR: u32 = 0xc000_0101;
+pub const KERNEL_GS_BASE_MSR: u32 = 0xc000_0102;
+
+/// Returns the frame pointer of the calling function, which heads its chain of frame records
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let frame_pointer: usize;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) frame_pointer, options(nomem, nostack, preserves_flags));
+    }
+    frame_pointer
+}
+
+/// Reads the current value of this LP's timestamp counter.
+#[inline(always)]
+pub fn read_timestamp() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Reads a model specific register of the current LP
+///
+/// # Safety
+/// `msr` must be implemented by the processor and reading it must have no side effects that
+/// violate the invariants of the kernel.
+#[inline(always)]
+pub unsafe fn read_msr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!(
+            "rdmsr",
+            in("ecx") msr,
+            out("eax") low,
+            out("edx") high,
+            options(nostack, preserves_flags)
+        );
+    }
+    (high as u64) << 32 | low as u64
+}
+
+percpu! {
+    /// The address of the `rdmsr` instruction in [`try_read_msr`] while it runs, so that the
+    /// general protection fault handler can tell a probe apart from a kernel bug
+    static MSR_PROBE_RIP: AtomicU64 = AtomicU64::new(0);
+    static MSR_PROBE_FAULTED: AtomicBool = AtomicBool::new(false);
+}
+
+/// Reads a model specific register of the current LP, or returns `None` if the processor does not
+/// implement it
+pub fn try_read_msr(msr: u32) -> Option<u64> {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!(
+            "lea {probe}, [rip + 2f]",
+            "mov qword ptr [{slot}], {probe}",
+            "2:",
+            "rdmsr",
+            slot = in(reg) MSR_PROBE_RIP.get().as_ptr(),
+            probe = out(reg) _,
+            in("ecx") msr,
+            out("eax") low,
+            out("edx") high,
+            options(nostack, preserves_flags)
+        );
+    }
+    MSR_PROBE_RIP.get().store(0, Ordering::Relaxed);
+    if MSR_PROBE_FAULTED.get().swap(false, Ordering::Relaxed) {
+        None
+    } else {
+        Some((high as u64) << 32 | low as u64)
+    }
+}
+
+/// Skips the `rdmsr` instruction of [`try_read_msr`] if it caused the general protection fault
+/// being handled. Returns false if the fault came from elsewhere.
+pub fn recover_msr_probe(frame: &mut ExceptionFrame) -> bool {
+    let probe_rip = MSR_PROBE_RIP.get().load(Ordering::Relaxed);
+    if probe_rip == 0 || frame.interrupt_frame.rip != probe_rip {
+        return false;
+    }
+    const RDMSR_LENGTH: u64 = 2;
+    frame.interrupt_frame.rip += RDMSR_LENGTH;
+    MSR_PROBE_FAULTED.get().store(true, Ordering::Relaxed);
+    true
+}
+
+/// Writes a model specific register of the current LP
+///
+/// # Safety
+/// `msr` must be implemented by the processor and `value` must be valid for it.
+#[inline(always)]
+pub unsafe fn write_msr(msr: u32, value: u64) {
+    unsafe {
+        asm!(
+            "wrmsr",
+            in("ecx") msr,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Unmasks interrupts and halts the current LP until one has been handled. `sti` takes effect
+/// after the following instruction so no interrupt can be taken between unmasking and halting,
+/// which lets a caller that masked interrupts to check for work wait without missing the interrupt
+/// that queues it. Interrupts are masked again on return if they were masked on entry.
+#[inline(always)]
+pub fn wait_for_interrupt() {
+    let were_enabled = interrupts_enabled();
+    unsafe {
+        asm!("sti", "hlt", options(nomem, nostack));
+        if !were_enabled {
+            asm!("cli", options(nomem, nostack));
+        }
+    }
+}
+
+/// Writes every modified cache line of the current LP back to memory, so that the data survives a
+/// reset that does not preserve the caches
+#[inline(always)]
+pub fn write_back_caches() {
+    unsafe {
+        asm!("wbinvd", options(nostack, preserves_flags));
+    }
+}
+
+/// Runs `f` with CR0.WP cleared so that the kernel can write to pages that are mapped read-only,
+/// such as its own code
+///
+/// # Safety
+/// Interrupts must be masked, and `f` must only write where the mapping being read-only does not
+/// protect an invariant the kernel relies on.
+pub unsafe fn without_write_protection<R>(f: impl FnOnce() -> R) -> R {
+    const CR0_WP: u64 = 1 << 16;
+    let cr0: u64;
+    unsafe {
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+        asm!("mov cr0, {}", in(reg) cr0 & !CR0_WP, options(nostack, preserves_flags));
+    }
+    let result = f();
+    unsafe {
+        asm!("mov cr0, {}", in(reg) cr0, options(nostack, preserves_flags));
+    }
+    result
+}
+
+/// Returns the address of the current LP's local data area. The area begins with its own address
+/// so it can be read relative to GS without FSGSBASE support.
+#[inline(always)]
+pub fn lp_local_base() -> usize {
+    let base: usize;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) base, options(nostack, readonly, preserves_flags));
+    }
+    base
+}
+
+/// Points IA32_GS_BASE at the current LP's local data area. IA32_KERNEL_GS_BASE holds the user
+/// mode GS base while the kernel is running and is cleared.
+///
+/// # Safety
+/// `base` must point to an initialized local data area that is never freed.
+pub unsafe fn set_lp_local_base(base: usize) {
+    for (msr, value) in [(GS_BASE_MSR, base as u64), (KERNEL_GS_BASE_MSR, 0)] {
+        unsafe {
+            asm!(
+                "wrmsr",
+                in("eax") value as u32,
+                in("edx") (value >> 32) as u32,
+                in("ecx") msr,
+                options(nostack, preserves_flags)
+            );
+        }
+    }
+}
 
 pub fn store_lp_id(id: LpId) {
     let id_upper = ((id as u64) >> 32) as u32;
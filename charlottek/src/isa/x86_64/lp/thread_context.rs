@@ -1,6 +1,17 @@
 use core::arch::naked_asm;
 use core::mem::offset_of;
 
+use crate::isa::init::gdt;
+use crate::isa::interface::memory::AddressSpaceInterface;
+use crate::memory::AddressSpace;
+
+/// The indices of the registers in [`ThreadContext::gprs`], in the order the context switch saves
+/// them
+const GPR_RDI: usize = 5;
+const GPR_RSP: usize = 7;
+const RFLAGS_RESERVED: u64 = 1 << 1;
+const RFLAGS_INTERRUPT_FLAG: u64 = 1 << 9;
+
 #[derive(Debug, Clone, Default)]
 #[repr(C)]
 pub struct ThreadContext {
@@ -8,6 +19,32 @@ pub struct ThreadContext {
     pub cr3:  u64,
 }
 
+impl ThreadContext {
+    /// Creates the context of a kernel thread that calls `entry` with `argument` on `stack` in the
+    /// current address space. A thread is resumed with `iretq`, so the stack starts out holding the
+    /// interrupt frame that returns into `entry`, followed by a null return address for `entry`.
+    pub fn new_kernel(entry: extern "C" fn(usize) -> !, argument: usize, stack: &mut [u8]) -> Self {
+        let top = stack.as_mut_ptr_range().end as u64 & !0xf;
+        let frame = [
+            entry as usize as u64,
+            gdt::KERNEL_CODE_SELECTOR as u64,
+            RFLAGS_INTERRUPT_FLAG | RFLAGS_RESERVED,
+            // Where the return address is, as if `entry` had been called
+            top - 8,
+            gdt::KERNEL_DATA_SELECTOR as u64,
+            0,
+        ];
+        let frame_start = top - size_of_val(&frame) as u64;
+        assert!(frame_start >= stack.as_ptr() as u64, "A kernel thread stack is too small");
+        unsafe { (frame_start as *mut [u64; 6]).write(frame) };
+        let mut context = ThreadContext::default();
+        context.gprs[GPR_RDI] = argument as u64;
+        context.gprs[GPR_RSP] = frame_start;
+        context.cr3 = AddressSpace::get_current().get_cr3();
+        context
+    }
+}
+
 #[unsafe(no_mangle)]
 pub static TC_GPRS_OFFSET: usize = offset_of!(ThreadContext, gprs);
 #[unsafe(no_mangle)]
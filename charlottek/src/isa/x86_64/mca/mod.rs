@@ -0,0 +1,233 @@
+//! # Machine Check Architecture (MCA)
+//!
+//! The processor reports hardware errors, such as ECC errors in memory or caches, through a set of
+//! error reporting banks. Each bank holds one error at a time in its status register along with
+//! the address and model specific information the error relates to, if any.
+//!
+//! Errors the processor cannot correct are signaled with a machine check exception, whose handler
+//! records the error of every bank and decides whether the kernel can continue. Corrected errors
+//! are not signaled, so the banks of every LP are polled for them periodically instead. All errors
+//! are kept in an [`ErrorLog`] that the crash report includes and new errors are logged by the next
+//! poll on any LP.
+
+pub mod record;
+
+use core::arch::asm;
+use core::arch::x86_64::__cpuid_count;
+use core::time::Duration;
+
+use record::{
+    ErrorLog,
+    ErrorRecord,
+    MCG_STATUS_RIPV,
+    STATUS_ADDRV,
+    STATUS_EN,
+    STATUS_MISCV,
+    STATUS_S,
+    STATUS_UC,
+    STATUS_VAL,
+    Severity,
+    Source,
+};
+
+use crate::cpu::sync::Deadline;
+use crate::cpu::timers;
+use crate::isa::interface::system_info::CpuInfoIfce;
+use crate::isa::interrupts::exceptions::frame::ExceptionFrame;
+use crate::isa::interrupts::exceptions::report;
+use crate::isa::lp::ops::{get_lp_id, read_msr, write_msr};
+use crate::isa::system_info::{CpuInfo, IsaExtension};
+use crate::{logln, time};
+
+const MCG_CAP_MSR: u32 = 0x179;
+const MCG_STATUS_MSR: u32 = 0x17a;
+const MCG_CTL_MSR: u32 = 0x17b;
+const MC0_CTL_MSR: u32 = 0x400;
+
+/// The number of error reporting banks
+const MCG_CAP_COUNT: u64 = 0xff;
+/// The processor implements the global control register
+const MCG_CAP_CTL_P: u64 = 1 << 8;
+const CR4_MCE: u64 = 1 << 6;
+
+/// How often every LP checks its banks for corrected errors
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The errors found on all LPs
+pub static ERROR_LOG: ErrorLog = ErrorLog::new();
+
+const fn bank_msr(bank: u8, register: u32) -> u32 {
+    MC0_CTL_MSR + 4 * bank as u32 + register
+}
+
+const fn ctl_msr(bank: u8) -> u32 {
+    bank_msr(bank, 0)
+}
+
+const fn status_msr(bank: u8) -> u32 {
+    bank_msr(bank, 1)
+}
+
+const fn addr_msr(bank: u8) -> u32 {
+    bank_msr(bank, 2)
+}
+
+const fn misc_msr(bank: u8) -> u32 {
+    bank_msr(bank, 3)
+}
+
+fn bank_count() -> u8 {
+    if !CpuInfo::is_extension_supported(IsaExtension::Mca) {
+        return 0;
+    }
+    (unsafe { read_msr(MCG_CAP_MSR) } & MCG_CAP_COUNT) as u8
+}
+
+/// On Intel P6 family processors bank 0 is configured by the firmware and must not be written.
+fn owns_bank_0_control() -> bool {
+    let vendor = __cpuid_count(0, 0);
+    let is_intel = (vendor.ebx, vendor.edx, vendor.ecx) == (0x756e_6547, 0x4965_6e69, 0x6c65_746e);
+    let family = (__cpuid_count(1, 0).eax >> 8) & 0xf;
+    !(is_intel && family == 6)
+}
+
+/// Reads the error held by a bank, if any
+fn read_bank(bank: u8, source: Source, mcg_status: Option<u64>) -> Option<ErrorRecord> {
+    let status = unsafe { read_msr(status_msr(bank)) };
+    if status & STATUS_VAL == 0 {
+        return None;
+    }
+    let address = (status & STATUS_ADDRV != 0).then(|| unsafe { read_msr(addr_msr(bank)) });
+    let misc = (status & STATUS_MISCV != 0).then(|| unsafe { read_msr(misc_msr(bank)) });
+    Some(ErrorRecord {
+        lp_id: get_lp_id!(),
+        bank,
+        severity: Severity::classify(status, mcg_status),
+        source,
+        status,
+        address,
+        misc,
+        timestamp: time::monotonic_now(),
+    })
+}
+
+fn clear_bank(bank: u8) {
+    unsafe { write_msr(status_msr(bank), 0) }
+}
+
+/// Enables machine check exceptions and error reporting in every bank of the current LP. Errors the
+/// banks already hold are recorded and cleared first.
+pub fn init_local() {
+    let lp_id = get_lp_id!();
+    let banks = bank_count();
+    if banks == 0 {
+        logln!("LP{}: The machine check architecture is not supported.", lp_id);
+        return;
+    }
+    unsafe {
+        if read_msr(MCG_CAP_MSR) & MCG_CAP_CTL_P != 0 {
+            write_msr(MCG_CTL_MSR, u64::MAX);
+        }
+        let first_controlled_bank = if owns_bank_0_control() {
+            0
+        } else {
+            1
+        };
+        for bank in 0..banks {
+            if let Some(record) = read_bank(bank, Source::Boot, None) {
+                ERROR_LOG.push(record);
+            }
+            if bank >= first_controlled_bank {
+                write_msr(ctl_msr(bank), u64::MAX);
+            }
+            clear_bank(bank);
+        }
+        write_msr(MCG_STATUS_MSR, 0);
+        asm!(
+            "mov {tmp}, cr4",
+            "or {tmp}, {mce}",
+            "mov cr4, {tmp}",
+            tmp = out(reg) _,
+            mce = in(reg) CR4_MCE,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    logln!("LP{}: Machine checks enabled with {} error reporting banks.", lp_id, banks);
+    log_new_errors();
+}
+
+/// Starts polling the current LP's banks for corrected errors. Requires the kernel timers.
+pub fn start_polling() {
+    if bank_count() != 0 {
+        schedule_poll();
+    }
+}
+
+fn schedule_poll() {
+    timers::add_timer(Deadline::after(POLL_INTERVAL), || {
+        poll_local();
+        schedule_poll();
+    });
+}
+
+/// Records the errors the current LP's banks hold that were not signaled with a machine check
+/// exception and logs every error that has not been logged yet
+pub fn poll_local() {
+    for bank in 0..bank_count() {
+        let Some(record) = read_bank(bank, Source::Poll, None) else {
+            continue;
+        };
+        // Uncorrected errors that are signaled are left to the exception handler.
+        if record.status & STATUS_UC != 0 && record.status & (STATUS_EN | STATUS_S) != 0 {
+            continue;
+        }
+        ERROR_LOG.push(record);
+        clear_bank(bank);
+    }
+    log_new_errors();
+}
+
+fn log_new_errors() {
+    let lost = ERROR_LOG.for_each_new(|record| logln!("Machine check: {}", record));
+    if lost != 0 {
+        logln!("Machine check: {} errors were overwritten before they were logged", lost);
+    }
+}
+
+/// Logs every error still held by the error log
+pub fn log_error_log() {
+    if ERROR_LOG.is_empty() {
+        return;
+    }
+    logln!("Machine check error log ({} errors in total):", (ERROR_LOG.len()));
+    ERROR_LOG.for_each(|record| logln!("  {}", record));
+}
+
+/// Handles a machine check exception on the current LP. Every bank is recorded and cleared and the
+/// kernel only continues if none of the errors is fatal.
+pub fn handle_machine_check(frame: &ExceptionFrame) {
+    let mcg_status = unsafe { read_msr(MCG_STATUS_MSR) };
+    let mut fatal_errors = 0;
+    for bank in 0..bank_count() {
+        let Some(record) = read_bank(bank, Source::Exception, Some(mcg_status)) else {
+            continue;
+        };
+        if record.severity == Severity::Fatal {
+            fatal_errors += 1;
+        }
+        ERROR_LOG.push(record);
+        clear_bank(bank);
+    }
+    if fatal_errors != 0 || mcg_status & MCG_STATUS_RIPV == 0 {
+        report::fatal(
+            "#MC Machine check",
+            frame,
+            Some(format_args!(
+                "{} fatal errors, MCG_STATUS={:#x}. See the machine check error log below.",
+                fatal_errors, mcg_status
+            )),
+        );
+    }
+    // Clearing MCIP allows the next machine check to be delivered instead of shutting down the LP.
+    unsafe { write_msr(MCG_STATUS_MSR, 0) };
+}
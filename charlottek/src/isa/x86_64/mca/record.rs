@@ -0,0 +1,258 @@
+//! # Machine Check Error Records
+//!
+//! Errors are kept in a fixed size ring that is written without locks, since the #MC handler may
+//! interrupt any code including a writer on the same LP, and that is read by the crash report after
+//! the other LPs have been stopped. A slot holds the sequence number of the record in it only while
+//! the record is complete, so a reader that sees the same sequence number before and after copying
+//! a record has a consistent copy. Once the ring is full the oldest records are overwritten.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering, fence};
+
+use crate::isa::lp::LpId;
+
+/// The error is valid
+pub const STATUS_VAL: u64 = 1 << 63;
+/// An earlier error was lost because the bank already held one
+pub const STATUS_OVER: u64 = 1 << 62;
+/// The error was not corrected
+pub const STATUS_UC: u64 = 1 << 61;
+/// Reporting the error through a machine check exception is enabled
+pub const STATUS_EN: u64 = 1 << 60;
+pub const STATUS_MISCV: u64 = 1 << 59;
+pub const STATUS_ADDRV: u64 = 1 << 58;
+/// The processor context may have been corrupted by the error
+pub const STATUS_PCC: u64 = 1 << 57;
+/// The error was signaled with a machine check exception
+pub const STATUS_S: u64 = 1 << 56;
+/// The error requires software to act before the interrupted code can be resumed
+pub const STATUS_AR: u64 = 1 << 55;
+
+/// The interrupted code can be restarted at the instruction pointer saved for the exception
+pub const MCG_STATUS_RIPV: u64 = 1 << 0;
+
+const LOG_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The hardware corrected the error
+    Corrected,
+    /// The error was not corrected but the kernel can continue running
+    UncorrectedRecoverable,
+    /// The error left the processor or the interrupted code in a state the kernel cannot recover
+    /// from
+    Fatal,
+}
+
+impl Severity {
+    /// Classifies an error. `mcg_status` is the global machine check status if the error was
+    /// reported by a machine check exception rather than found by polling.
+    pub fn classify(status: u64, mcg_status: Option<u64>) -> Self {
+        if status & STATUS_UC == 0 {
+            return Severity::Corrected;
+        }
+        let Some(mcg_status) = mcg_status else {
+            return Severity::UncorrectedRecoverable;
+        };
+        // Recovering from an error that requires action would mean abandoning the interrupted
+        // code, which the kernel does not support.
+        if status & (STATUS_PCC | STATUS_AR) != 0 || mcg_status & MCG_STATUS_RIPV == 0 {
+            Severity::Fatal
+        } else {
+            Severity::UncorrectedRecoverable
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Corrected => "corrected",
+            Severity::UncorrectedRecoverable => "uncorrected recoverable",
+            Severity::Fatal => "fatal",
+        })
+    }
+}
+
+/// How an error was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The bank held the error when the LP was initialized, e.g. from before a reset
+    Boot,
+    Poll,
+    Exception,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorRecord {
+    pub lp_id: LpId,
+    pub bank: u8,
+    pub severity: Severity,
+    pub source: Source,
+    pub status: u64,
+    pub address: Option<u64>,
+    pub misc: Option<u64>,
+    /// The monotonic time the error was recorded at in nanoseconds
+    pub timestamp: u64,
+}
+
+impl ErrorRecord {
+    /// The architecturally defined error code
+    pub fn mca_code(&self) -> u16 {
+        self.status as u16
+    }
+
+    /// The model specific error code
+    pub fn model_code(&self) -> u16 {
+        (self.status >> 16) as u16
+    }
+}
+
+/// Returns the class of the architectural error code
+fn describe_mca_code(code: u16) -> &'static str {
+    // Filtering is ignored, it only reports that further corrected errors are not being signaled.
+    let code = code & !(1 << 12);
+    match code {
+        0x0000 => "no error",
+        0x0001 => "unclassified error",
+        0x0002 => "microcode ROM parity error",
+        0x0003 => "external error",
+        0x0004 => "functional redundancy check error",
+        0x0005 => "internal parity error",
+        0x0006 => "SMM handler code access violation",
+        0x0400 => "internal timer error",
+        0x0e0b => "I/O error",
+        _ if code & 0xfc00 == 0x0400 => "internal unclassified error",
+        _ if code & 0xeffc == 0x000c => "generic cache hierarchy error",
+        _ if code & 0xeff0 == 0x0010 => "TLB error",
+        _ if code & 0xef80 == 0x0080 => "memory controller error",
+        _ if code & 0xef00 == 0x0100 => "cache hierarchy error",
+        _ if code & 0xe800 == 0x0800 => "bus or interconnect error",
+        _ => "unknown error",
+    }
+}
+
+impl fmt::Display for ErrorRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "LP{} bank {}: {} {} ({:?}) STATUS={:016x} MCA={:04x} MODEL={:04x}",
+            self.lp_id,
+            self.bank,
+            self.severity,
+            describe_mca_code(self.mca_code()),
+            self.source,
+            self.status,
+            self.mca_code(),
+            self.model_code()
+        )?;
+        if let Some(address) = self.address {
+            write!(f, " ADDR={:016x}", address)?;
+        }
+        if let Some(misc) = self.misc {
+            write!(f, " MISC={:016x}", misc)?;
+        }
+        if self.status & STATUS_OVER != 0 {
+            write!(f, ", earlier errors were lost")?;
+        }
+        Ok(())
+    }
+}
+
+struct Slot {
+    /// The sequence number of the record plus one, or zero while the slot is being written
+    sequence: AtomicUsize,
+    record: UnsafeCell<ErrorRecord>,
+}
+
+pub struct ErrorLog {
+    slots: [Slot; LOG_CAPACITY],
+    /// The sequence number of the next record
+    next: AtomicUsize,
+    /// The sequence number of the first record that has not been logged yet
+    next_to_log: AtomicUsize,
+}
+
+// Records are only accessed through the slot sequence protocol.
+unsafe impl Sync for ErrorLog {}
+
+impl ErrorLog {
+    pub const fn new() -> Self {
+        const EMPTY: ErrorRecord = ErrorRecord {
+            lp_id: 0,
+            bank: 0,
+            severity: Severity::Corrected,
+            source: Source::Boot,
+            status: 0,
+            address: None,
+            misc: None,
+            timestamp: 0,
+        };
+        ErrorLog {
+            slots: [const {
+                Slot {
+                    sequence: AtomicUsize::new(0),
+                    record: UnsafeCell::new(EMPTY),
+                }
+            }; LOG_CAPACITY],
+            next: AtomicUsize::new(0),
+            next_to_log: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn push(&self, record: ErrorRecord) {
+        let sequence = self.next.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[sequence % LOG_CAPACITY];
+        slot.sequence.store(0, Ordering::Relaxed);
+        fence(Ordering::Release);
+        unsafe { slot.record.get().write_volatile(record) };
+        slot.sequence.store(sequence + 1, Ordering::Release);
+    }
+
+    fn get(&self, sequence: usize) -> Option<ErrorRecord> {
+        let slot = &self.slots[sequence % LOG_CAPACITY];
+        if slot.sequence.load(Ordering::Acquire) != sequence + 1 {
+            return None;
+        }
+        let record = unsafe { slot.record.get().read_volatile() };
+        fence(Ordering::Acquire);
+        (slot.sequence.load(Ordering::Relaxed) == sequence + 1).then_some(record)
+    }
+
+    /// The total number of records ever pushed
+    pub fn len(&self) -> usize {
+        self.next.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Calls `f` with every record still held by the log, oldest first
+    pub fn for_each(&self, f: impl FnMut(ErrorRecord)) {
+        let end = self.len();
+        (end.saturating_sub(LOG_CAPACITY)..end)
+            .filter_map(|sequence| self.get(sequence))
+            .for_each(f)
+    }
+
+    /// Calls `f` with every record that has not been passed to this method before, oldest first.
+    /// Returns the number of records that were overwritten before they could be passed.
+    pub fn for_each_new(&self, f: impl FnMut(ErrorRecord)) -> usize {
+        let end = self.len();
+        let start = self.next_to_log.swap(end, Ordering::AcqRel);
+        if start >= end {
+            return 0;
+        }
+        let first_held = start.max(end.saturating_sub(LOG_CAPACITY));
+        (first_held..end).filter_map(|sequence| self.get(sequence)).for_each(f);
+        first_held - start
+    }
+}
+
+impl Default for ErrorLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -10,12 +10,14 @@ use spin::Mutex;
 
 use super::MemoryInterfaceImpl;
 use super::address::vaddr::VAddr;
+use super::tlb::shootdown::{self, ShootdownBatch};
 use crate::isa::interface::memory::{AddressSpaceInterface, MemoryInterface, MemoryMapping};
 use crate::logln;
 use crate::memory::{AddressSpaceId, PAddr};
 
 pub const PAGE_SIZE: usize = 4096;
 pub const N_PAGE_TABLE_ENTRIES: usize = 512;
+pub const CR3_ADDRESS_MASK: u64 = 0x000ffffffffff000;
 pub type PageTable = [pte::PageTableEntry; N_PAGE_TABLE_ENTRIES];
 
 pub fn is_pagetable_unused(table_ptr: NonNull<PageTable>) -> bool {
@@ -39,6 +41,32 @@ impl AddressSpace {
     pub fn get_cr3(&self) -> u64 {
         self.cr3
     }
+
+    /// The physical address of the top level page table, which identifies the address space
+    pub fn get_root_table(&self) -> u64 {
+        self.cr3 & CR3_ADDRESS_MASK
+    }
+
+    /// Unmaps a page like [`AddressSpaceInterface::unmap_page`] but leaves invalidating the stale
+    /// TLB entries and freeing emptied page tables to `batch`. The frame must not be reused before
+    /// the batch has been flushed.
+    pub fn unmap_page_batched(
+        &mut self,
+        vaddr: VAddr,
+        batch: &mut ShootdownBatch,
+    ) -> Result<PAddr, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        debug_assert_eq!(batch.root_table(), self.get_root_table());
+        if <VAddr as Into<usize>>::into(vaddr) == 0 {
+            return Err(<MemoryInterfaceImpl as MemoryInterface>::Error::NullVAddrNotAllowed);
+        }
+        if vaddr.page_offset() != 0 {
+            return Err(<MemoryInterfaceImpl as MemoryInterface>::Error::VAddrNotPageAligned);
+        }
+        let mut walker = pth_walker::PthWalker::new(self, vaddr);
+        let paddr = walker.unmap_page(batch)?;
+        batch.add_range(vaddr, 1);
+        Ok(paddr)
+    }
 }
 
 impl AddressSpaceInterface for AddressSpace {
@@ -53,6 +81,7 @@ impl AddressSpaceInterface for AddressSpace {
     }
 
     fn load(&self) -> Result<(), <MemoryInterfaceImpl as MemoryInterface>::Error> {
+        shootdown::track_load(self.get_root_table());
         unsafe {
             // Set the top level page table base register
             asm!("mov cr3, {}", in(reg) self.cr3);
@@ -106,6 +135,7 @@ impl AddressSpaceInterface for AddressSpace {
             mapping.page_type.is_writable(),
             mapping.page_type.is_user_accessible(),
             mapping.page_type.is_no_execute(),
+            mapping.page_type.is_uncacheable(),
         )?;
         Ok(())
     }
@@ -114,14 +144,8 @@ impl AddressSpaceInterface for AddressSpace {
         &mut self,
         vaddr: <MemoryInterfaceImpl as MemoryInterface>::VAddr,
     ) -> Result<PAddr, <MemoryInterfaceImpl as MemoryInterface>::Error> {
-        if <VAddr as Into<usize>>::into(vaddr) == 0 {
-            return Err(<MemoryInterfaceImpl as MemoryInterface>::Error::NullVAddrNotAllowed);
-        }
-        if vaddr.page_offset() != 0 {
-            return Err(<MemoryInterfaceImpl as MemoryInterface>::Error::VAddrNotPageAligned);
-        }
-        let mut walker = pth_walker::PthWalker::new(self, vaddr);
-        walker.unmap_page()
+        let mut batch = ShootdownBatch::new(self);
+        self.unmap_page_batched(vaddr, &mut batch)
     }
 
     fn is_mapped(
@@ -40,6 +40,11 @@ impl PageTableEntry {
         pte
     }
 
+    /// The raw value of the entry
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
     pub fn is_present(&self) -> bool {
         self.0 & (1 << PRESENT_BIT_INDEX) != 0
     }
@@ -88,6 +93,7 @@ impl PageTableEntry {
     }
 
     pub fn set_pat_index_bits(&mut self, pat_index: u8) -> &mut Self {
+        self.0 &= !(1 << PAT_INDEX_0 | 1 << PAT_INDEX_1 | 1 << PAT_INDEX_2_STANDARD);
         self.0 |= ((pat_index & 1) << PAT_INDEX_0) as u64;
         self.0 |= ((pat_index & 1 << 1) << PAT_INDEX_1 - 1) as u64;
         self.0 |= ((pat_index & 1 << 2) << PAT_INDEX_2_STANDARD - 2) as u64;
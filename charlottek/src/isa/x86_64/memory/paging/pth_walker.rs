@@ -7,14 +7,18 @@
 
 use core::ptr::NonNull;
 
-use super::{PAGE_SIZE, is_pagetable_unused};
+use super::{CR3_ADDRESS_MASK, PAGE_SIZE, is_pagetable_unused, pte};
 use crate::isa::interface::memory::address::VirtualAddress;
 use crate::isa::interface::memory::{AddressSpaceInterface, MemoryInterface};
 use crate::isa::x86_64::memory::address::paddr::PAddr;
 use crate::isa::x86_64::memory::address::vaddr::VAddr;
+use crate::isa::x86_64::memory::tlb::shootdown::ShootdownBatch;
 use crate::memory::PHYSICAL_FRAME_ALLOCATOR;
 
-const CR3_ADDRESS_MASK: u64 = 0x000ffffffffff000;
+/// The kernel leaves the power-on PAT in place, whose first entry is write-back and whose fourth,
+/// selected by PWT and PCD, is uncacheable.
+const PAT_INDEX_WRITE_BACK: u8 = 0;
+const PAT_INDEX_UNCACHEABLE: u8 = 3;
 
 pub struct PthWalker<'vas> {
     pub address_space: &'vas mut super::AddressSpace,
@@ -76,12 +80,42 @@ impl<'vas> PthWalker<'vas> {
         Ok(())
     }
 
+    /// Returns the entries that translate the address, from the PML4 entry down to the first one
+    /// that is not present or maps a large page. Unlike [`PthWalker::walk`] this does not modify
+    /// the walker and stops at large pages instead of treating them as page tables.
+    pub fn translation_entries(&self) -> [Option<&pte::PageTableEntry>; 4] {
+        let indices = [
+            self.vaddr.pml4_index(),
+            self.vaddr.pdpt_index(),
+            self.vaddr.pd_index(),
+            self.vaddr.pt_index(),
+        ];
+        let mut entries = [None; 4];
+        let mut table: *mut super::PageTable =
+            PAddr::try_from((self.address_space.cr3 & CR3_ADDRESS_MASK) as usize).unwrap().into();
+        for (level, index) in indices.into_iter().enumerate() {
+            let entry = unsafe { &(*table)[index] };
+            entries[level] = Some(entry);
+            // The page size bit is reserved in PML4 entries and selects the PAT in PTEs.
+            let is_large_page = (1..=2).contains(&level) && entry.get_page_size();
+            if !entry.is_present() || is_large_page {
+                break;
+            }
+            let Ok(frame) = entry.try_get_frame() else {
+                break;
+            };
+            table = frame.into();
+        }
+        entries
+    }
+
     pub fn map_page(
         &mut self,
         frame: PAddr,
         writable: bool,
         user_accessible: bool,
         no_execute: bool,
+        uncacheable: bool,
     ) -> Result<(), <super::MemoryInterfaceImpl as MemoryInterface>::Error> {
         match self.walk() {
             Ok(_) => Err(<super::MemoryInterfaceImpl as MemoryInterface>::Error::AlreadyMapped),
@@ -159,17 +193,26 @@ impl<'vas> PthWalker<'vas> {
                         .set_present(true)
                         .set_writable(writable)
                         .set_user_accessible(user_accessible)
-                        .set_execute_disabled(no_execute);
+                        .set_execute_disabled(no_execute)
+                        .set_pat_index_bits(
+                            if uncacheable {
+                                PAT_INDEX_UNCACHEABLE
+                            } else {
+                                PAT_INDEX_WRITE_BACK
+                            },
+                        );
                     // for those who may not immediately see it, this is the Rust equivalent of
-                    // memset being used to clear the newly mapped page
-                    core::ptr::write_bytes(<PAddr as Into<*mut u8>>::into(frame), 0, PAGE_SIZE);
+                    // memset being used to clear the newly mapped page. Uncacheable frames hold
+                    // device registers or buffers shared with a device, which are not ours to
+                    // clear.
+                    if !uncacheable {
+                        core::ptr::write_bytes(<PAddr as Into<*mut u8>>::into(frame), 0, PAGE_SIZE);
+                    }
                 }
                 self.address_space.load().expect("Failed to reload the address space");
                 unsafe {
                     // Get rid of any stale TLB entries referring to the linear address space
                     // aperture into which the newly allocated page frame has been mapped
-                    // This works as is in the single LP world but to operate with multiple
-                    // processors we need a proper TLB shootdown here.
                     core::arch::asm!("invlpg [{}]", in(reg) self.vaddr.into_ptr::<u8>());
                 }
 
@@ -179,8 +222,11 @@ impl<'vas> PthWalker<'vas> {
         }
     }
 
+    /// Unmaps the page and hands the page tables it leaves empty to `batch`, which frees them once
+    /// no LP can still be walking through them
     pub fn unmap_page(
         &mut self,
+        batch: &mut ShootdownBatch,
     ) -> Result<PAddr, <super::MemoryInterfaceImpl as MemoryInterface>::Error> {
         match self.walk() {
             Ok(_) => {
@@ -198,31 +244,23 @@ impl<'vas> PthWalker<'vas> {
 
                     let pde = &raw mut (*self.pd_ptr)[self.vaddr.pd_index()];
                     if is_pagetable_unused(NonNull::new_unchecked(self.pt_ptr)) {
-                        PHYSICAL_FRAME_ALLOCATOR
-                            .lock()
-                            .deallocate_frame((*pde).try_get_frame().unwrap())
-                            .unwrap();
+                        batch.free_table((*pde).try_get_frame().unwrap());
                         (*pde).set_present(false);
                     }
 
                     let pdpte = &raw mut (*self.pdpt_ptr)[self.vaddr.pdpt_index()];
                     if is_pagetable_unused(NonNull::new_unchecked(self.pd_ptr)) {
-                        PHYSICAL_FRAME_ALLOCATOR
-                            .lock()
-                            .deallocate_frame((*pdpte).try_get_frame().unwrap())
-                            .unwrap();
+                        batch.free_table((*pdpte).try_get_frame().unwrap());
                         (*pdpte).set_present(false);
                     }
 
                     let pml4e = &raw mut (*self.pml4_ptr)[self.vaddr.pml4_index()];
                     if is_pagetable_unused(NonNull::new_unchecked(self.pdpt_ptr)) {
-                        PHYSICAL_FRAME_ALLOCATOR
-                            .lock()
-                            .deallocate_frame((*pml4e).try_get_frame().unwrap())
-                            .unwrap();
+                        batch.free_table((*pml4e).try_get_frame().unwrap());
                         (*pml4e).set_present(false);
                     }
-                    //super::tlb::invalidate_page(self.address_space, self.vaddr);
+                    // Stale TLB entries are invalidated and the freed tables released by the
+                    // caller's shootdown batch.
                     Ok(paddr)
                 }
             }
@@ -0,0 +1,75 @@
+//! # Broadcast TLB Invalidation
+//!
+//! Processors that support INVLPGB can invalidate TLB entries on every LP in the system without
+//! interrupting any of them. An INVLPGB only starts the invalidation; TLBSYNC waits until every
+//! INVLPGB previously issued by the same LP has completed on all LPs. Both must therefore be
+//! executed on the same LP without being migrated in between.
+
+use core::arch::asm;
+use core::arch::x86_64::__cpuid_count;
+
+use spin::Lazy;
+
+use crate::isa::interface::system_info::CpuInfoIfce;
+use crate::isa::memory::paging::PAGE_SIZE;
+use crate::isa::system_info::{CpuInfo, IsaExtension};
+use crate::memory::VAddr;
+
+const INVLPGB_VALID_VADDR: u64 = 1 << 0;
+const INVLPGB_INCLUDE_GLOBAL: u64 = 1 << 3;
+
+static IS_SUPPORTED: Lazy<bool> =
+    Lazy::new(|| CpuInfo::is_extension_supported(IsaExtension::Invlpgb));
+
+/// The largest number of pages after the first one that a single INVLPGB can invalidate
+static MAX_ADDITIONAL_PAGES: Lazy<usize> =
+    Lazy::new(|| (__cpuid_count(0x8000_0008, 0).edx & 0xffff) as usize);
+
+pub fn is_supported() -> bool {
+    *IS_SUPPORTED
+}
+
+unsafe fn invlpgb(rax: u64, ecx: u32) {
+    unsafe {
+        asm!(
+            "invlpgb",
+            in("rax") rax,
+            in("ecx") ecx,
+            in("edx") 0u32,
+            options(nostack, preserves_flags),
+        );
+    }
+}
+
+/// Starts invalidating `num_pages` pages starting at `base` on every LP
+pub fn invalidate_range(base: VAddr, num_pages: usize, include_global: bool) {
+    let flags = INVLPGB_VALID_VADDR
+        | if include_global {
+            INVLPGB_INCLUDE_GLOBAL
+        } else {
+            0
+        };
+    let mut page = <VAddr as Into<usize>>::into(base);
+    let mut remaining = num_pages;
+    while remaining > 0 {
+        let count = remaining.min(*MAX_ADDITIONAL_PAGES + 1);
+        unsafe { invlpgb(page as u64 | flags, (count - 1) as u32) };
+        page += count * PAGE_SIZE;
+        remaining -= count;
+    }
+}
+
+/// Starts invalidating every TLB entry on every LP. Global entries are only included if requested.
+pub fn invalidate_all(include_global: bool) {
+    let flags = if include_global {
+        INVLPGB_INCLUDE_GLOBAL
+    } else {
+        0
+    };
+    unsafe { invlpgb(flags, 0) };
+}
+
+/// Waits until the invalidations started by the current LP have completed on every LP
+pub fn synchronize() {
+    unsafe { asm!("tlbsync", options(nostack, preserves_flags)) };
+}
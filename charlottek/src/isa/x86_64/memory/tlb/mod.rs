@@ -1,3 +1,6 @@
+pub mod invlpgb;
+pub mod shootdown;
+
 use core::arch::asm;
 
 use crate::cpu::scheduler::GLOBAL_SCHEDULER;
@@ -54,3 +57,30 @@ pub fn inval_range_kernel(base: VAddr, num_pages: usize) {
         }
     }
 }
+
+const CR4_PAGE_GLOBAL_ENABLE: u64 = 1 << 7;
+
+/// Flushes every entry from the current LP's TLB including global ones
+pub fn flush_all_local() {
+    unsafe {
+        let cr4: u64;
+        asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+        if cr4 & CR4_PAGE_GLOBAL_ENABLE != 0 {
+            // Toggling global pages off and on again also flushes the global entries.
+            asm!(
+                "mov cr4, {disabled}",
+                "mov cr4, {enabled}",
+                disabled = in(reg) cr4 & !CR4_PAGE_GLOBAL_ENABLE,
+                enabled = in(reg) cr4,
+                options(nostack, preserves_flags),
+            );
+        } else {
+            asm!(
+                "mov {cr3}, cr3",
+                "mov cr3, {cr3}",
+                cr3 = out(reg) _,
+                options(nostack, preserves_flags),
+            );
+        }
+    }
+}
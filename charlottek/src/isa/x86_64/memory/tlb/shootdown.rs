@@ -0,0 +1,224 @@
+//! # TLB Shootdowns
+//!
+//! Once a mapping has been removed every LP that may have cached it has to invalidate its TLB entry
+//! before the memory behind it can be reused. Lower half mappings can only be cached by the LPs
+//! that have their address space loaded, which the active-LP mask of each address space records.
+//! PCIDs are not enabled, so loading an address space flushes the entries of the previous one and
+//! an LP leaves the mask of an address space as soon as it loads another. Higher half mappings are
+//! shared by all address spaces and may be cached by any LP.
+//!
+//! Invalidations are collected in a [`ShootdownBatch`] so that unmapping many pages costs a single
+//! round of IPIs, and flushing a batch only returns once every target LP has carried it out. If
+//! the processor supports INVLPGB the invalidations are broadcast by the hardware instead and no
+//! IPIs are sent at all.
+
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::{flush_all_local, inval_range_kernel, invlpgb};
+use crate::cpu::multiprocessor::MAX_LP_COUNT;
+use crate::cpu::multiprocessor::lp_mask::{AtomicLpMask, LpMask};
+use crate::cpu::sync::IrqSpinlock;
+use crate::isa::interface::memory::AddressSpaceInterface;
+use crate::isa::interrupts::ipis::call_on_lps;
+use crate::isa::lp::ops::{get_lp_id, interrupts_enabled, mask_interrupts, unmask_interrupts};
+use crate::isa::memory::paging::{AddressSpace, PAGE_SIZE};
+use crate::memory::{PAddr, PHYSICAL_FRAME_ALLOCATOR, VAddr};
+use crate::{lock_class, percpu};
+
+/// Batches covering more pages than this flush the whole TLB instead
+const FULL_FLUSH_THRESHOLD: usize = 64;
+const MAX_RANGES: usize = 16;
+const NO_ROOT: u64 = u64::MAX;
+
+percpu! {
+    /// The top level page table of the address space loaded on each LP
+    static LOADED_ROOT: AtomicU64 = AtomicU64::new(NO_ROOT);
+}
+
+/// The active-LP mask of every address space that is loaded on at least one LP, paired with the
+/// physical address of its top level page table. Address spaces are loaded before the kernel
+/// allocator is available so the table has a fixed size, which suffices since no LP has more than
+/// one address space loaded.
+static ACTIVE_LPS: IrqSpinlock<[(u64, LpMask); MAX_LP_COUNT]> = IrqSpinlock::with_class(
+    lock_class!("TLB_ACTIVE_LPS"),
+    [(NO_ROOT, LpMask::empty()); MAX_LP_COUNT],
+);
+
+/// The LPs that may have cached higher half mappings
+static PARTICIPATING_LPS: AtomicLpMask = AtomicLpMask::empty();
+
+fn is_higher_half(vaddr: VAddr) -> bool {
+    <VAddr as Into<usize>>::into(vaddr) & (1 << 63) != 0
+}
+
+/// Includes the current LP in shootdowns. Must be called on every LP before it accesses any memory
+/// another LP might unmap.
+pub fn init_local() {
+    PARTICIPATING_LPS.insert(get_lp_id!());
+    track_load(AddressSpace::get_current().get_root_table());
+}
+
+/// Moves the current LP to the active-LP mask of the address space with the given top level page
+/// table. Must be called before the address space is loaded so that no shootdown misses the LP.
+pub fn track_load(root: u64) {
+    let lp_id = get_lp_id!();
+    let mut active_lps = ACTIVE_LPS.lock();
+    let previous = LOADED_ROOT.get().swap(root, Ordering::Relaxed);
+    if previous == root {
+        return;
+    }
+    if previous != NO_ROOT
+        && let Some((previous_root, mask)) = active_lps.iter_mut().find(|(r, _)| *r == previous)
+    {
+        mask.remove(lp_id);
+        if mask.is_empty() {
+            *previous_root = NO_ROOT;
+        }
+    }
+    let index = active_lps
+        .iter()
+        .position(|(r, _)| *r == root)
+        .or_else(|| active_lps.iter().position(|(r, _)| *r == NO_ROOT))
+        .expect("More address spaces are loaded than there are LPs");
+    active_lps[index].0 = root;
+    active_lps[index].1.insert(lp_id);
+}
+
+/// Returns the LPs that currently have the address space with the given top level page table
+/// loaded
+pub fn active_lps(root: u64) -> LpMask {
+    let active_lps = ACTIVE_LPS.lock();
+    active_lps.iter().find(|(r, _)| *r == root).map(|(_, mask)| *mask).unwrap_or_default()
+}
+
+/// Invalidations of one address space that are carried out together. Whatever has not been
+/// flushed yet is flushed when the batch is dropped.
+pub struct ShootdownBatch {
+    root: u64,
+    ranges: Vec<(VAddr, usize)>,
+    n_pages: usize,
+    flush_all: bool,
+    includes_higher_half: bool,
+    /// Page tables that were unlinked from the hierarchy and are freed by the next flush
+    freed_tables: Vec<PAddr>,
+}
+
+impl ShootdownBatch {
+    pub fn new(address_space: &AddressSpace) -> Self {
+        ShootdownBatch {
+            root: address_space.get_root_table(),
+            ranges: Vec::new(),
+            n_pages: 0,
+            flush_all: false,
+            includes_higher_half: false,
+            freed_tables: Vec::new(),
+        }
+    }
+
+    pub fn root_table(&self) -> u64 {
+        self.root
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.flush_all && self.ranges.is_empty()
+    }
+
+    /// Adds `num_pages` pages starting at `base` to the batch. Ranges that directly follow the
+    /// previous one are merged with it.
+    pub fn add_range(&mut self, base: VAddr, num_pages: usize) {
+        if num_pages == 0 {
+            return;
+        }
+        self.includes_higher_half |= is_higher_half(base);
+        if self.flush_all {
+            return;
+        }
+        self.n_pages += num_pages;
+        match self.ranges.last_mut() {
+            Some((last_base, last_pages))
+                if *last_base + (*last_pages * PAGE_SIZE) as isize == base =>
+            {
+                *last_pages += num_pages
+            }
+            _ => self.ranges.push((base, num_pages)),
+        }
+        if self.n_pages > FULL_FLUSH_THRESHOLD || self.ranges.len() > MAX_RANGES {
+            self.flush_all = true;
+            self.ranges.clear();
+        }
+    }
+
+    /// Frees `frame`, a page table that no longer has an entry pointing to it, once the batch has
+    /// been flushed. Until then other LPs may still walk through it.
+    pub fn free_table(&mut self, frame: PAddr) {
+        self.freed_tables.push(frame);
+    }
+
+    /// Carries out the invalidations on every LP that may have cached the affected mappings and
+    /// returns once all of them have done so
+    pub fn flush(&mut self) {
+        if !self.is_empty() {
+            if invlpgb::is_supported() {
+                self.flush_broadcast();
+            } else {
+                let targets = if self.includes_higher_half {
+                    PARTICIPATING_LPS.load()
+                } else {
+                    active_lps(self.root)
+                };
+                call_on_lps(targets, || self.invalidate_local());
+            }
+            self.ranges.clear();
+            self.n_pages = 0;
+            self.flush_all = false;
+            self.includes_higher_half = false;
+        }
+        if !self.freed_tables.is_empty() {
+            let mut allocator = PHYSICAL_FRAME_ALLOCATOR.lock();
+            for frame in self.freed_tables.drain(..) {
+                allocator.deallocate_frame(frame).expect("Failed to free an unused page table");
+            }
+        }
+    }
+
+    fn flush_broadcast(&self) {
+        // TLBSYNC only waits for the INVLPGBs issued by the LP executing it.
+        let interrupts_were_enabled = interrupts_enabled();
+        mask_interrupts!();
+        if self.flush_all {
+            invlpgb::invalidate_all(self.includes_higher_half);
+        } else {
+            for (base, num_pages) in self.ranges.iter() {
+                invlpgb::invalidate_range(*base, *num_pages, is_higher_half(*base));
+            }
+        }
+        invlpgb::synchronize();
+        self.invalidate_local();
+        if interrupts_were_enabled {
+            unmask_interrupts!();
+        }
+    }
+
+    fn invalidate_local(&self) {
+        // Lower half entries of an address space that is not loaded were flushed when it was
+        // switched away from.
+        if !self.includes_higher_half && LOADED_ROOT.get().load(Ordering::Relaxed) != self.root {
+            return;
+        }
+        if self.flush_all {
+            flush_all_local();
+        } else {
+            for (base, num_pages) in self.ranges.iter() {
+                inval_range_kernel(*base, *num_pages);
+            }
+        }
+    }
+}
+
+impl Drop for ShootdownBatch {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
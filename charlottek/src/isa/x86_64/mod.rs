@@ -7,5 +7,7 @@ pub mod init;
 pub mod interrupts;
 pub mod io;
 pub mod lp;
+pub mod mca;
 pub mod memory;
 pub mod system_info;
+pub mod timers;
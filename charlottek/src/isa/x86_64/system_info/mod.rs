@@ -11,6 +11,18 @@ pub enum IsaExtension {
     /* indicates support for `invlpgb` (Invalidate Page with Broadcast) and `tlbsync`
      * (TLB shootdown synchronization after `invlpgb`) */
     Invlpgb,
+    /* indicates support for arming the local APIC timer with an absolute timestamp counter
+     * value through the IA32_TSC_DEADLINE MSR */
+    TscDeadline,
+    /* indicates that the timestamp counter runs at a constant rate in all ACPI P-, C- and
+     * T-states */
+    InvariantTsc,
+    /* indicates architectural performance monitoring with at least one general purpose counter
+     * that can count unhalted core cycles */
+    ArchPerfmon,
+    /* indicates support for the machine check exception and the machine check architecture's
+     * error reporting banks */
+    Mca,
 }
 
 pub struct CpuInfo;
@@ -77,6 +89,25 @@ impl CpuInfoIfce for CpuInfo {
         }
     }
 
+    fn get_timestamp_frequency() -> Option<u64> {
+        // CPUID leaf 0x15 reports the TSC to core crystal clock ratio and, on most processors,
+        // the crystal clock frequency itself.
+        if __cpuid_count(0, 0).eax >= 0x15 {
+            let tsc_leaf = __cpuid_count(0x15, 0);
+            if tsc_leaf.eax != 0 && tsc_leaf.ebx != 0 && tsc_leaf.ecx != 0 {
+                return Some(tsc_leaf.ecx as u64 * tsc_leaf.ebx as u64 / tsc_leaf.eax as u64);
+            }
+        }
+        // Otherwise fall back to the processor base frequency in MHz from leaf 0x16.
+        if __cpuid_count(0, 0).eax >= 0x16 {
+            let freq_leaf = __cpuid_count(0x16, 0);
+            if freq_leaf.eax & 0xffff != 0 {
+                return Some((freq_leaf.eax & 0xffff) as u64 * 1_000_000);
+            }
+        }
+        None
+    }
+
     fn is_extension_supported(extension: Self::IsaExtension) -> bool {
         match extension {
             IsaExtension::La57 => unsafe {
@@ -87,6 +118,29 @@ impl CpuInfoIfce for CpuInfo {
                 let cpuid_result = __cpuid_count(0x8000_0008, 0);
                 (cpuid_result.ebx & 1 << 5) != 0
             },
+            IsaExtension::TscDeadline => {
+                let cpuid_result = __cpuid_count(0x0000_0001, 0);
+                (cpuid_result.ecx & 1 << 24) != 0
+            }
+            IsaExtension::InvariantTsc => {
+                let cpuid_result = __cpuid_count(0x8000_0007, 0);
+                (cpuid_result.edx & 1 << 8) != 0
+            }
+            IsaExtension::ArchPerfmon => {
+                if __cpuid_count(0, 0).eax < 0xa {
+                    return false;
+                }
+                let cpuid_result = __cpuid_count(0x0000_000a, 0);
+                let version = cpuid_result.eax & 0xff;
+                let counters = (cpuid_result.eax >> 8) & 0xff;
+                let events = (cpuid_result.eax >> 24) & 0xff;
+                // A set bit in EBX means the event is unavailable.
+                version != 0 && counters != 0 && events != 0 && cpuid_result.ebx & 1 == 0
+            }
+            IsaExtension::Mca => {
+                let cpuid_result = __cpuid_count(0x0000_0001, 0);
+                (cpuid_result.edx & 1 << 7) != 0 && (cpuid_result.edx & 1 << 14) != 0
+            }
         }
     }
 }
@@ -0,0 +1,83 @@
+//! # High Precision Event Timer (HPET)
+//!
+//! Only the main counter of the HPET is used. Its registers are mapped uncached.
+
+use crate::environment::firmware::acpi;
+use crate::environment::firmware::acpi::tables::{self, AddressSpace};
+use crate::isa::interface::memory::address::VirtualAddress;
+use crate::memory::PAddr;
+use crate::memory::vmem::mmio;
+
+const GENERAL_CAPABILITIES: usize = 0x000;
+const GENERAL_CONFIGURATION: usize = 0x010;
+const MAIN_COUNTER: usize = 0x0f0;
+const REGISTERS_SIZE: usize = 0x400;
+
+const CAPABILITIES_COUNT_SIZE_CAP: u64 = 1 << 13;
+const CONFIGURATION_ENABLE: u64 = 1 << 0;
+// The specification caps the counter period at 100 ns.
+const MAX_PERIOD_FS: u64 = 100_000_000;
+const FEMTOSECONDS_PER_SECOND: u64 = 1_000_000_000_000_000;
+
+pub struct Hpet {
+    registers: *mut u8,
+    period_fs: u64,
+    is_64_bit: bool,
+}
+
+impl Hpet {
+    /// Locates the first HPET through its ACPI table and enables its main counter
+    pub fn detect() -> Option<Self> {
+        let table = acpi::find::<tables::Hpet>()?;
+        let base_address = table.base_address;
+        if base_address.address_space() != AddressSpace::SystemMemory {
+            return None;
+        }
+        let mut hpet = Hpet {
+            registers: mmio::map(PAddr::from(base_address.address), REGISTERS_SIZE)
+                .ok()?
+                .into_mut(),
+            period_fs: 0,
+            is_64_bit: false,
+        };
+        let capabilities = hpet.read(GENERAL_CAPABILITIES);
+        hpet.period_fs = capabilities >> 32;
+        hpet.is_64_bit = capabilities & CAPABILITIES_COUNT_SIZE_CAP != 0;
+        if hpet.period_fs == 0 || hpet.period_fs > MAX_PERIOD_FS {
+            return None;
+        }
+        let configuration = hpet.read(GENERAL_CONFIGURATION);
+        if configuration & CONFIGURATION_ENABLE == 0 {
+            hpet.write(GENERAL_CONFIGURATION, configuration | CONFIGURATION_ENABLE);
+        }
+        Some(hpet)
+    }
+
+    pub fn get_frequency(&self) -> u64 {
+        FEMTOSECONDS_PER_SECOND / self.period_fs
+    }
+
+    pub fn read_counter(&self) -> u64 {
+        if self.is_64_bit {
+            self.read(MAIN_COUNTER)
+        } else {
+            unsafe { self.registers.add(MAIN_COUNTER).cast::<u32>().read_volatile() as u64 }
+        }
+    }
+
+    pub fn counter_mask(&self) -> u64 {
+        if self.is_64_bit {
+            u64::MAX
+        } else {
+            u32::MAX as u64
+        }
+    }
+
+    fn read(&self, offset: usize) -> u64 {
+        unsafe { self.registers.add(offset).cast::<u64>().read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u64) {
+        unsafe { self.registers.add(offset).cast::<u64>().write_volatile(value) }
+    }
+}
@@ -0,0 +1,69 @@
+//! # x86_64 Timers
+//!
+//! The LP local timer is the x2APIC timer and the timestamp counter is the TSC. The frequency of
+//! neither is architecturally defined so both are calibrated against one of the platform's fixed
+//! frequency reference clocks. The ACPI PM timer is preferred since it is accessed through port IO;
+//! the HPET is used on platforms without one.
+
+pub mod hpet;
+pub mod pm_timer;
+pub mod tsc;
+
+use core::time::Duration;
+
+use hpet::Hpet;
+use pm_timer::PmTimer;
+pub use tsc::Tsc as TimestampCounter;
+
+pub use crate::isa::interrupts::x2apic::timer::X2ApicTimer as LpTimer;
+
+/// A free running counter with a known frequency
+pub enum ReferenceClock {
+    PmTimer(PmTimer),
+    Hpet(Hpet),
+}
+
+impl ReferenceClock {
+    pub fn detect() -> Option<Self> {
+        PmTimer::detect()
+            .map(ReferenceClock::PmTimer)
+            .or_else(|| Hpet::detect().map(ReferenceClock::Hpet))
+    }
+
+    /// The frequency of the counter in Hz
+    pub fn get_frequency(&self) -> u64 {
+        match self {
+            ReferenceClock::PmTimer(_) => PmTimer::FREQUENCY,
+            ReferenceClock::Hpet(hpet) => hpet.get_frequency(),
+        }
+    }
+
+    pub fn read_counter(&self) -> u64 {
+        match self {
+            ReferenceClock::PmTimer(pm_timer) => pm_timer.read_counter(),
+            ReferenceClock::Hpet(hpet) => hpet.read_counter(),
+        }
+    }
+
+    /// The mask to apply to the difference of two counter values to account for wraparound
+    pub fn counter_mask(&self) -> u64 {
+        match self {
+            ReferenceClock::PmTimer(pm_timer) => pm_timer.counter_mask(),
+            ReferenceClock::Hpet(hpet) => hpet.counter_mask(),
+        }
+    }
+
+    /// Spins until `duration` has elapsed according to the counter
+    pub fn busy_wait(&self, duration: Duration) {
+        let ticks = duration.as_nanos() * self.get_frequency() as u128 / 1_000_000_000;
+        let mask = self.counter_mask();
+        let mut elapsed = 0u128;
+        let mut last = self.read_counter();
+        while elapsed < ticks {
+            core::hint::spin_loop();
+            let now = self.read_counter();
+            elapsed += (now.wrapping_sub(last) & mask) as u128;
+            last = now;
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! # ACPI Power Management Timer
+//!
+//! A free running 24 or 32 bit counter in the fixed ACPI hardware that counts at 3.579545 MHz.
+
+use core::arch::asm;
+
+use crate::environment::firmware::acpi;
+use crate::environment::firmware::acpi::tables::{AddressSpace, Fadt};
+
+pub struct PmTimer {
+    port: u16,
+    is_32_bit: bool,
+}
+
+impl PmTimer {
+    pub const FREQUENCY: u64 = 3_579_545;
+
+    /// Locates the PM timer through the FADT. Returns `None` if the platform does not have one.
+    pub fn detect() -> Option<Self> {
+        let fadt = acpi::find::<Fadt>()?;
+        let flags = fadt.flags;
+        if flags & Fadt::FLAG_HW_REDUCED_ACPI != 0 {
+            return None;
+        }
+        let port = match fadt.x_pm_tmr_blk() {
+            Some(block) if block.address_space() == AddressSpace::SystemIo => {
+                u16::try_from(block.address).ok()?
+            }
+            // The PM timer is only supported in IO space.
+            Some(_) => return None,
+            None if fadt.pm_tmr_len == 4 => u16::try_from(fadt.pm_tmr_blk).ok()?,
+            None => return None,
+        };
+        (port != 0).then_some(PmTimer {
+            port,
+            is_32_bit: flags & Fadt::FLAG_TMR_VAL_EXT != 0,
+        })
+    }
+
+    pub fn read_counter(&self) -> u64 {
+        let value: u32;
+        unsafe {
+            asm!(
+                "in eax, dx",
+                in("dx") self.port,
+                out("eax") value,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+        value as u64 & self.counter_mask()
+    }
+
+    pub fn counter_mask(&self) -> u64 {
+        if self.is_32_bit {
+            u32::MAX as u64
+        } else {
+            (1 << 24) - 1
+        }
+    }
+}
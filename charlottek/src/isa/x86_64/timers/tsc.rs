@@ -0,0 +1,41 @@
+//! # Timestamp Counter (TSC)
+
+use core::time::Duration;
+
+use super::ReferenceClock;
+use crate::isa::interface::system_info::CpuInfoIfce;
+use crate::isa::interface::timers::TimestampCounterIfce;
+use crate::isa::lp::ops::read_timestamp;
+use crate::isa::system_info::{CpuInfo, IsaExtension};
+
+const MEASUREMENT_PERIOD: Duration = Duration::from_millis(50);
+
+pub struct Tsc;
+
+impl TimestampCounterIfce for Tsc {
+    fn is_invariant() -> bool {
+        CpuInfo::is_extension_supported(IsaExtension::InvariantTsc)
+    }
+
+    fn measure_frequency() -> Option<u64> {
+        let reference = ReferenceClock::detect()?;
+        let reference_ticks =
+            MEASUREMENT_PERIOD.as_nanos() * reference.get_frequency() as u128 / 1_000_000_000;
+        let mask = reference.counter_mask();
+        // Both counters are sampled back to back at the start and end of the measurement so that
+        // the time spent polling the reference clock does not skew the result.
+        let reference_start = reference.read_counter();
+        let start = read_timestamp();
+        let mut reference_elapsed = 0u128;
+        let mut last = reference_start;
+        while reference_elapsed < reference_ticks {
+            core::hint::spin_loop();
+            let now = reference.read_counter();
+            reference_elapsed += (now.wrapping_sub(last) & mask) as u128;
+            last = now;
+        }
+        let end = read_timestamp();
+        let elapsed = (end - start) as u128;
+        Some((elapsed * reference.get_frequency() as u128 / reference_elapsed) as u64)
+    }
+}
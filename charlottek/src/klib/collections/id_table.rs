@@ -1,51 +1,78 @@
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use hashbrown::HashMap;
 use spin::{Mutex, RwLock};
 
+use crate::cpu::sync::{RcuCell, RcuReadGuard, rcu_read_lock};
+
+/// A table of elements indexed by reusable IDs. Lookups are lock free; the map is protected by RCU
+/// and replaced with an updated copy whenever an element is added or removed.
 pub struct IdTable<I, T>
 where
     I: From<usize> + Copy + core::cmp::Eq + core::hash::Hash,
 {
-    map: HashMap<I, Arc<RwLock<T>>>,
+    map: RcuCell<HashMap<I, Arc<RwLock<T>>>>,
+    /// IDs of removed elements. Only modified within map updates so that an ID is never handed
+    /// out while the element it was freed from is still in the published map.
     available_ids: Mutex<Vec<I>>,
+    /// The lowest ID that has never been handed out
+    next_id: AtomicUsize,
 }
 
 impl<I, T> IdTable<I, T>
 where
-    I: From<usize> + Copy + core::cmp::Eq + core::hash::Hash,
+    I: From<usize> + Copy + core::cmp::Eq + core::hash::Hash + Send + 'static,
+    T: Send + Sync + 'static,
 {
     pub fn new() -> Self {
         IdTable {
-            map: HashMap::new(),
+            map: RcuCell::new(HashMap::new()),
             available_ids: Mutex::new(Vec::new()),
+            next_id: AtomicUsize::new(0),
         }
     }
 
-    pub fn add_element(&mut self, element: T) -> I {
-        let element_id = {
-            if let Some(id) = self.available_ids.lock().pop() {
-                id
-            } else {
-                self.map.len().into()
-            }
-        };
-        self.map.insert(element_id, Arc::new(RwLock::new(element)));
-        element_id
+    pub fn add_element(&self, element: T) -> I {
+        let element = Arc::new(RwLock::new(element));
+        let mut element_id = None;
+        self.map.update(|map| {
+            let id = match self.available_ids.lock().pop() {
+                Some(id) => id,
+                None => self.next_id.fetch_add(1, Ordering::Relaxed).into(),
+            };
+            element_id = Some(id);
+            let mut map = map.clone();
+            map.insert(id, element);
+            map
+        });
+        element_id.unwrap()
     }
 
     pub fn try_get_element_arc(&self, element_id: I) -> Option<Arc<RwLock<T>>> {
-        if let Some(lock_ptr) = self.map.get(&element_id) {
-            Some(lock_ptr.clone())
-        } else {
-            None
-        }
+        let guard = rcu_read_lock();
+        self.get_element_rcu(element_id, &guard).cloned()
     }
 
-    pub fn remove_element(&mut self, element_id: I) {
-        self.map.remove(&element_id);
-        self.available_ids.lock().push(element_id);
+    /// Looks up an element without touching its reference count. The returned reference is valid
+    /// for the duration of the read-side critical section `guard` belongs to.
+    pub fn get_element_rcu<'a>(
+        &'a self,
+        element_id: I,
+        guard: &'a RcuReadGuard,
+    ) -> Option<&'a Arc<RwLock<T>>> {
+        self.map.read(guard).get(&element_id)
+    }
+
+    pub fn remove_element(&self, element_id: I) {
+        self.map.update(|map| {
+            let mut map = map.clone();
+            if map.remove(&element_id).is_some() {
+                self.available_ids.lock().push(element_id);
+            }
+            map
+        });
     }
 }
 
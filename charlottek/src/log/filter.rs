@@ -0,0 +1,101 @@
+//! # Log Filtering
+//!
+//! A record is written if its level is at or below the level that applies to the module that wrote
+//! it. That is the level set for the longest matching module prefix, or the global maximum level
+//! if no prefix matches. Module prefixes are matched on path segment boundaries, so a level set for
+//! `charlottek::cpu` applies to `charlottek::cpu::timers` but not to `charlottek::cpuid`. The crate
+//! name may be left out.
+//!
+//! The module levels are read under RCU since records are written from any context. Until the first
+//! module level is set only the global level is consulted, which lets the kernel log before its
+//! allocator is available.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use spin::Lazy;
+
+use super::Level;
+use crate::cpu::sync::rcu::{RcuCell, rcu_read_lock};
+use crate::kernel_param;
+
+const CRATE_PREFIX: &str = "charlottek::";
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::DEFAULT as u8);
+static HAS_MODULE_LEVELS: AtomicBool = AtomicBool::new(false);
+static MODULE_LEVELS: Lazy<RcuCell<Vec<(String, Level)>>> = Lazy::new(|| RcuCell::new(Vec::new()));
+
+kernel_param! {
+    /// The global maximum level the kernel starts with
+    static LEVEL_PARAM: Level = ("log.level", Level::DEFAULT);
+}
+
+/// Sets the global maximum level given on the kernel command line
+pub fn apply_params() {
+    set_max_level(LEVEL_PARAM.get());
+}
+
+/// Sets the level that applies to modules without a level of their own
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn max_level() -> Level {
+    Level::from_u8(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+fn normalize(module: &str) -> &str {
+    module.strip_prefix(CRATE_PREFIX).unwrap_or(module)
+}
+
+fn matches(prefix: &str, module: &str) -> bool {
+    prefix.is_empty()
+        || module.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+/// Sets the level for a module and its submodules, or removes it if `level` is `None`
+pub fn set_module_level(module: &str, level: Option<Level>) {
+    let module = normalize(module).to_string();
+    MODULE_LEVELS.update(|levels| {
+        let mut levels: Vec<_> =
+            levels.iter().filter(|(prefix, _)| *prefix != module).cloned().collect();
+        if let Some(level) = level {
+            levels.push((module, level));
+        }
+        levels
+    });
+    HAS_MODULE_LEVELS.store(true, Ordering::Release);
+}
+
+/// Calls `f` with every module that has a level of its own and that level
+pub fn for_each_module_level(mut f: impl FnMut(&str, Level)) {
+    if !HAS_MODULE_LEVELS.load(Ordering::Acquire) {
+        return;
+    }
+    let guard = rcu_read_lock();
+    for (module, level) in MODULE_LEVELS.read(&guard) {
+        f(module, *level);
+    }
+}
+
+/// Returns the level that applies to the given module
+pub fn level_for(module: &str) -> Level {
+    if !HAS_MODULE_LEVELS.load(Ordering::Acquire) {
+        return max_level();
+    }
+    let module = normalize(module);
+    let guard = rcu_read_lock();
+    MODULE_LEVELS
+        .read(&guard)
+        .iter()
+        .filter(|(prefix, _)| matches(prefix, module))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or_else(max_level, |(_, level)| *level)
+}
+
+/// Returns true if a record of the given level written by the given module would be logged
+#[inline]
+pub fn is_enabled(level: Level, module: &str) -> bool {
+    level <= level_for(module)
+}
@@ -1,32 +1,164 @@
-//! # Kernel Logging Macros
+//! # Kernel Log
 //!
-//! This module provides convenient macros for logging messages to the kernel
-//! log. They will be updated as the kernel develops to provide more
-//! functionality and use an actual kernel log that will reside in memory and be
-//! stored in a file. For now they print to the COM1 serial port on x86_64
-//! systems only.
+//! Every message logged with [`error!`], [`warn!`], [`info!`], [`debug!`] or [`trace!`] becomes a
+//! record in the in-memory [log ring](ring) along with the time it was logged at, the LP that
+//! logged it, its level and the module that logged it. Writing a record never takes a lock, so any
+//! code, interrupt handlers included, can log.
+//!
+//! Records are written out by the [sinks](sink) the kernel has: the COM1 serial port in debug
+//! builds, the framebuffer console, a plain text buffer in memory and the [persistent log](persist)
+//! that survives a warm reset. Outside of interrupt context the sinks are drained as soon as a
+//! record is written. Records written in interrupt context are drained once the LP leaves it or
+//! goes idle instead, so interrupt handlers never wait for a slow device.
+//!
+//! Which records are written to the ring is decided by a global level and optional per-module
+//! levels, see [`filter`]. [`logln!`] logs at the info level.
+
+pub mod filter;
+pub mod persist;
+pub mod ring;
+pub mod sink;
+
+use core::fmt;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use ring::LogRing;
+
+use crate::cpu::sync::context::in_interrupt_context;
+use crate::isa::lp::ops::get_lp_id;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// The level below which records are written unless configured otherwise
+    pub const DEFAULT: Level = if cfg!(debug_assertions) {
+        Level::Debug
+    } else {
+        Level::Info
+    };
+
+    /// Converts a level stored as its discriminant back. Out of range values are clamped.
+    pub const fn from_u8(value: u8) -> Self {
+        match value {
+            0 | 1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Padding is applied so records line up in columns.
+        f.pad(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLevelError;
+
+impl FromStr for Level {
+    type Err = ParseLevelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace]
+            .into_iter()
+            .find(|level| level.name().eq_ignore_ascii_case(s))
+            .ok_or(ParseLevelError)
+    }
+}
+
+/// The records logged by every LP
+pub static RING: LogRing = LogRing::new();
+
+/// Set once the kernel is crashing, after which the sinks are drained right away in every context
+static CRASH_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Writes a record to the log ring and drains it to the sinks if that is allowed in the current
+/// context. Use the logging macros instead.
+#[doc(hidden)]
+pub fn write_record(level: Level, module: &'static str, args: fmt::Arguments) {
+    RING.write(level, module, get_lp_id!(), args);
+    let crashing = CRASH_MODE.load(Ordering::Relaxed);
+    if crashing || !in_interrupt_context() {
+        sink::drain_all(crashing);
+    }
+}
+
+/// Writes every record the sinks have not written yet
+pub fn flush() {
+    sink::drain_all(CRASH_MODE.load(Ordering::Relaxed));
+}
 
+/// Makes the sinks usable by the current LP no matter where the other LPs were stopped and has
+/// every record written out immediately from here on
+///
+/// # Safety
+/// Every other LP must have been stopped.
+pub unsafe fn enter_crash_mode() {
+    CRASH_MODE.store(true, Ordering::Relaxed);
+    unsafe { sink::force_release_all() };
+}
+
+/// Logs a message at the given level if the filter for the calling module allows it
 #[macro_export]
 macro_rules! log {
-    ($text:expr $(, $arg:tt)*) => ({
-        if cfg!(all(target_arch = "x86_64", debug_assertions)) {
-            use core::fmt::Write;
-            use crate::drivers::uart::ns16550::LOG_PORT;
-            let _ = write!(LOG_PORT.lock(), $text $(, $arg)*);
+    ($level:expr, $($arg:tt)+) => ({
+        let level: $crate::log::Level = $level;
+        if $crate::log::filter::is_enabled(level, module_path!()) {
+            $crate::log::write_record(level, module_path!(), format_args!($($arg)+));
         }
-        use crate::print;
-        print!($text $(, $arg)*);
     })
 }
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Error, $($arg)+))
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Warn, $($arg)+))
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Info, $($arg)+))
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Debug, $($arg)+))
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Trace, $($arg)+))
+}
+
+/// Logs a message at the info level
 #[macro_export]
 macro_rules! logln {
-    ($text:expr $(, $arg:tt)*) => ({
-         if cfg!(all(target_arch = "x86_64", debug_assertions)) {
-            use core::fmt::Write;
-            use crate::drivers::uart::ns16550::LOG_PORT;
-            let _ = writeln!(LOG_PORT.lock(), $text $(, $arg)*);
-        }
-        use crate::println;
-        println!($text $(, $arg)*);
-    })
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Info, $($arg)+))
 }
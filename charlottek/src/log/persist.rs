@@ -0,0 +1,370 @@
+//! # Persistent Log
+//!
+//! The text of the log is also kept in a region of physical memory which the frame allocator never
+//! hands out, so that a warm reset after a crash leaves the last log of the crashed boot in memory
+//! for the next boot to pick up. No address is safe on every machine, so the region is only kept
+//! if its address is given with the `log.persist` kernel parameter, e.g. `log.persist=512M`. The
+//! address has to be usable memory according to the memory map and should be one that neither the
+//! firmware nor the bootloader writes to while booting on the machine at hand.
+//!
+//! Memory contents are not guaranteed to survive a reset, so a crash report seals the region with
+//! a SHA-256 checksum over the text and a one line summary of the crash once it is complete. Only
+//! a region with the right magic number and a matching checksum is recovered. A region that was
+//! never sealed, e.g. because the machine reset without a crash report, is discarded.
+//!
+//! If the region cannot be reserved only the crash summary is kept, in a non-volatile UEFI
+//! variable.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::mem::size_of;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use limine::memory_map::EntryType;
+use limine::response::MemoryMapResponse;
+use sha_256::Sha256;
+use spin::Once;
+
+use super::Level;
+use super::ring::Record;
+use super::sink::{self, Line, LogSink};
+use crate::environment::cmdline::Size;
+use crate::environment::firmware::uefi_rt::{
+    self,
+    EFI_VARIABLE_BOOTSERVICE_ACCESS,
+    EFI_VARIABLE_NON_VOLATILE,
+    EFI_VARIABLE_RUNTIME_ACCESS,
+    EfiGuid,
+};
+use crate::isa::lp::ops::write_back_caches;
+use crate::memory::PAddr;
+use crate::memory::pmem::PhysicalAddress;
+use crate::{kernel_param, logln};
+
+kernel_param! {
+    /// The physical address of the region, which has to be the same on every boot. The region is
+    /// not kept if it is zero.
+    static REGION_BASE: Size = ("log.persist", Size(0));
+}
+
+/// The alignment the address of the region must have
+const REGION_ALIGN: usize = 4096;
+pub const REGION_SIZE: usize = 64 * 1024;
+pub const MAX_SUMMARY_LENGTH: usize = 240;
+const TEXT_SIZE: usize = REGION_SIZE - size_of::<Header>();
+
+const MAGIC: u64 = u64::from_le_bytes(*b"CHKLOG\r\n");
+const VERSION: u32 = 1;
+/// The number of lines of a recovered log that are repeated in the current log
+const RECOVERED_TAIL_LINES: usize = 16;
+
+/// "CharlottekCrash" as a null terminated UTF-16 string
+const CRASH_VARIABLE_NAME: [u16; 16] = {
+    let name = b"CharlottekCrash\0";
+    let mut wide = [0; 16];
+    let mut i = 0;
+    while i < name.len() {
+        wide[i] = name[i] as u16;
+        i += 1;
+    }
+    wide
+};
+const CRASH_VARIABLE_VENDOR: EfiGuid = EfiGuid {
+    data1: 0x6c1b_5a0e,
+    data2: 0x3f2d,
+    data3: 0x4c8e,
+    data4: [0x9a, 0x41, 0x2b, 0x7d, 0x63, 0xe5, 0x18, 0xc4],
+};
+
+#[repr(C)]
+struct Header {
+    /// The SHA-256 digest of the rest of the region, valid only once the region is sealed
+    checksum: [u8; 32],
+    magic: u64,
+    version: u32,
+    sealed: u32,
+    /// The total number of bytes ever written to the text
+    written: u64,
+    summary_length: u32,
+    reserved: u32,
+    summary: [u8; MAX_SUMMARY_LENGTH],
+}
+
+/// The layout of the region
+#[repr(C)]
+pub struct Region {
+    header: Header,
+    /// The most recent log text, written as a ring
+    text: [u8; TEXT_SIZE],
+}
+
+const _: () = assert!(size_of::<Region>() == REGION_SIZE);
+
+impl Region {
+    /// The bytes covered by the checksum
+    fn checksummed(&self) -> &[u8] {
+        let bytes = unsafe {
+            core::slice::from_raw_parts((self as *const Region).cast::<u8>(), REGION_SIZE)
+        };
+        &bytes[size_of::<[u8; 32]>()..]
+    }
+
+    fn is_valid(&self) -> bool {
+        self.header.magic == MAGIC
+            && self.header.version == VERSION
+            && self.header.summary_length as usize <= MAX_SUMMARY_LENGTH
+    }
+
+    pub fn write_text(&mut self, text: &str) {
+        for byte in text.bytes() {
+            self.text[self.header.written as usize % TEXT_SIZE] = byte;
+            self.header.written += 1;
+        }
+    }
+
+    /// Returns the text the region holds, oldest first
+    fn read_text(&self) -> Vec<u8> {
+        let held = (self.header.written as usize).min(TEXT_SIZE);
+        let start = (self.header.written as usize - held) % TEXT_SIZE;
+        let mut text = Vec::with_capacity(held);
+        text.extend_from_slice(&self.text[start..(start + held).min(TEXT_SIZE)]);
+        text.extend_from_slice(&self.text[..held - text.len()]);
+        text
+    }
+
+    fn summary(&self) -> Option<String> {
+        let length = self.header.summary_length as usize;
+        (length != 0).then(|| String::from_utf8_lossy(&self.header.summary[..length]).into_owned())
+    }
+
+    pub fn set_summary(&mut self, summary: &[u8]) {
+        let length = summary.len().min(MAX_SUMMARY_LENGTH);
+        self.header.summary[..length].copy_from_slice(&summary[..length]);
+        self.header.summary_length = length as u32;
+    }
+
+    /// Checksums the text and summary so that the next boot can verify them
+    pub fn seal(&mut self) {
+        self.header.sealed = 1;
+        self.header.checksum = Sha256::new().digest(self.checksummed());
+    }
+
+    pub fn reset(&mut self) {
+        self.header.checksum = [0; 32];
+        self.header.magic = MAGIC;
+        self.header.version = VERSION;
+        self.header.sealed = 0;
+        self.header.written = 0;
+        self.header.summary_length = 0;
+        self.header.reserved = 0;
+    }
+}
+
+/// The log recovered from the previous boot
+#[derive(Debug)]
+pub struct PreviousLog {
+    /// The last part of the text of the log, if the region held one
+    pub text: Option<String>,
+    /// The summary of the crash that ended the boot, if any
+    pub crash_summary: Option<String>,
+}
+
+static RESERVED: Once<PAddr> = Once::new();
+static REGION: AtomicPtr<Region> = AtomicPtr::new(core::ptr::null_mut());
+static PREVIOUS_LOG: Once<Option<PreviousLog>> = Once::new();
+static PERSISTENT_SINK: PersistentSink = PersistentSink;
+
+fn region() -> Option<&'static mut Region> {
+    unsafe { REGION.load(Ordering::Acquire).as_mut() }
+}
+
+/// Reserves the region at the address given on the command line if it lies within a single entry
+/// of usable memory and outside the given range, which the frame allocator has already taken for
+/// itself. Called once by the frame allocator while it is being initialized.
+pub fn reserve_region(
+    mmap: &MemoryMapResponse,
+    excluded_base: PAddr,
+    excluded_length: usize,
+) -> Option<(PAddr, usize)> {
+    let region_base = REGION_BASE.get().bytes();
+    if region_base == 0 {
+        return None;
+    }
+    let end = region_base.checked_add(REGION_SIZE)?;
+    let is_usable = mmap.entries().iter().any(|entry| {
+        entry.entry_type == EntryType::USABLE
+            && entry.base as usize <= region_base
+            && (entry.base + entry.length) as usize >= end
+    });
+    let excluded_base: usize = excluded_base.into();
+    let overlaps = excluded_base < end && region_base < excluded_base + excluded_length;
+    if !region_base.is_multiple_of(REGION_ALIGN) || !is_usable || overlaps {
+        logln!("The persistent log region at {:#x} is not available.", region_base);
+        return None;
+    }
+    let base = PAddr::try_from(region_base).ok()?;
+    RESERVED.call_once(|| base);
+    Some((base, REGION_SIZE))
+}
+
+/// Recovers the log of the previous boot and starts writing the log of this boot to the region.
+/// Requires the kernel allocator.
+pub fn init() {
+    let previous_log = PREVIOUS_LOG.call_once(|| {
+        let region = RESERVED.get().map(|base| unsafe { &mut *base.into_hhdm_mut::<Region>() });
+        let mut previous_log = region.as_deref().and_then(recover);
+        if let Some(summary) = take_crash_variable() {
+            previous_log
+                .get_or_insert(PreviousLog {
+                    text: None,
+                    crash_summary: None,
+                })
+                .crash_summary
+                .get_or_insert(summary);
+        }
+        if let Some(region) = region {
+            region.reset();
+            REGION.store(region, Ordering::Release);
+            if sink::register_sink(&PERSISTENT_SINK, Level::Trace).is_err() {
+                logln!("The persistent log could not be registered as a log sink.");
+            }
+        }
+        previous_log
+    });
+    let Some(previous_log) = previous_log else {
+        return;
+    };
+    if let Some(text) = &previous_log.text {
+        logln!("Recovered {} bytes of the log of the previous boot.", (text.len()));
+    }
+    if let Some(summary) = &previous_log.crash_summary {
+        logln!("The previous boot crashed: {}", summary);
+    }
+    if let Some(text) = &previous_log.text {
+        let lines: Vec<_> = text.lines().collect();
+        for line in &lines[lines.len().saturating_sub(RECOVERED_TAIL_LINES)..] {
+            logln!("previous boot | {}", line);
+        }
+    }
+}
+
+/// Reads the log a region holds, unless the region was never initialized, was never sealed or
+/// does not match its checksum
+pub fn recover(region: &Region) -> Option<PreviousLog> {
+    if !region.is_valid() {
+        return None;
+    }
+    if region.header.sealed == 0 {
+        logln!("The persistent log of the previous boot was not sealed and is discarded.");
+        return None;
+    }
+    if Sha256::new().digest(region.checksummed()) != region.header.checksum {
+        logln!("The persistent log of the previous boot does not match its checksum.");
+        return None;
+    }
+    let text = String::from_utf8_lossy(&region.read_text()).into_owned();
+    Some(PreviousLog {
+        text: (!text.is_empty()).then_some(text),
+        crash_summary: region.summary(),
+    })
+}
+
+fn take_crash_variable() -> Option<String> {
+    let mut summary = [0; MAX_SUMMARY_LENGTH];
+    let length = uefi_rt::get_variable(&CRASH_VARIABLE_NAME, &CRASH_VARIABLE_VENDOR, &mut summary)?;
+    uefi_rt::set_variable(&CRASH_VARIABLE_NAME, &CRASH_VARIABLE_VENDOR, 0, &[]);
+    Some(String::from_utf8_lossy(&summary[..length.min(MAX_SUMMARY_LENGTH)]).into_owned())
+}
+
+/// Returns the log recovered from the previous boot, if there was one
+pub fn previous_log() -> Option<&'static PreviousLog> {
+    PREVIOUS_LOG.get()?.as_ref()
+}
+
+struct Summary {
+    bytes:  [u8; MAX_SUMMARY_LENGTH],
+    length: usize,
+}
+
+impl Write for Summary {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let length = s.len().min(MAX_SUMMARY_LENGTH - self.length);
+        self.bytes[self.length..self.length + length].copy_from_slice(&s.as_bytes()[..length]);
+        self.length += length;
+        Ok(())
+    }
+}
+
+/// Records a one line summary of the crash being reported, in the region or else in a UEFI
+/// variable. Only called by the crash report.
+pub fn record_crash(summary: fmt::Arguments) {
+    let mut buffer = Summary {
+        bytes:  [0; MAX_SUMMARY_LENGTH],
+        length: 0,
+    };
+    let _ = buffer.write_fmt(summary);
+    let summary = &buffer.bytes[..buffer.length];
+    match region() {
+        Some(region) => region.set_summary(summary),
+        None => {
+            // Another LP may have been stopped while calling into the firmware, in which case the
+            // summary is lost.
+            uefi_rt::try_set_variable(
+                &CRASH_VARIABLE_NAME,
+                &CRASH_VARIABLE_VENDOR,
+                EFI_VARIABLE_NON_VOLATILE
+                    | EFI_VARIABLE_BOOTSERVICE_ACCESS
+                    | EFI_VARIABLE_RUNTIME_ACCESS,
+                summary,
+            );
+        }
+    }
+}
+
+/// Checksums the region and writes it back to memory. Called once the crash report is complete.
+pub fn seal() {
+    let Some(region) = region() else {
+        return;
+    };
+    region.seal();
+    write_back_caches();
+}
+
+struct PersistentSink;
+
+impl LogSink for PersistentSink {
+    fn name(&self) -> &'static str {
+        "persistent"
+    }
+
+    fn write(&self, record: &Record) {
+        let Some(region) = region() else {
+            return;
+        };
+        let mut text = RegionText(region);
+        let _ = writeln!(
+            text,
+            "{}",
+            Line {
+                record,
+                verbose: true,
+            }
+        );
+    }
+
+    fn write_lost(&self, count: usize) {
+        if let Some(region) = region() {
+            let _ = writeln!(RegionText(region), "... {} log records were lost ...", count);
+        }
+    }
+}
+
+struct RegionText(&'static mut Region);
+
+impl Write for RegionText {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_text(s);
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+//! # Log Record Ring
+//!
+//! Records are kept in a fixed size ring that any number of LPs can write to at the same time
+//! without taking locks. A writer claims the next sequence number and then the slot it maps to,
+//! formats its message directly into the slot and finally publishes the record by storing its
+//! sequence number in the slot. A slot that is still being written by another writer, which can
+//! only happen if the ring has wrapped around while that writer was interrupted, is not waited for
+//! and the new record is dropped instead.
+//!
+//! Readers keep their own position in the ring. They copy a record and only accept the copy if the
+//! slot still holds the same sequence number afterwards, so a record overwritten while it was being
+//! read is detected and skipped like any other record that was lost to a reader falling behind.
+
+use core::cell::UnsafeCell;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering, fence};
+
+use super::Level;
+use crate::isa::lp::LpId;
+
+/// The number of records the ring holds
+pub const RING_CAPACITY: usize = 512;
+/// The maximum length of a message in bytes. Longer messages are truncated.
+pub const MAX_MESSAGE_LENGTH: usize = 200;
+
+const EMPTY: usize = 0;
+const WRITING: usize = usize::MAX;
+
+#[derive(Clone, Copy)]
+pub struct Record {
+    /// The monotonic time the record was written at in nanoseconds
+    pub timestamp: u64,
+    pub lp_id: LpId,
+    pub level: Level,
+    /// The path of the module that wrote the record
+    pub module: &'static str,
+    length: u8,
+    truncated: bool,
+    message: [u8; MAX_MESSAGE_LENGTH],
+}
+
+impl Record {
+    const fn empty() -> Self {
+        Record {
+            timestamp: 0,
+            lp_id: 0,
+            level: Level::Info,
+            module: "",
+            length: 0,
+            truncated: false,
+            message: [0; MAX_MESSAGE_LENGTH],
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        let bytes = &self.message[..self.length as usize];
+        // Truncation may have split a character at the end.
+        match core::str::from_utf8(bytes) {
+            Ok(message) => message,
+            Err(error) => unsafe { core::str::from_utf8_unchecked(&bytes[..error.valid_up_to()]) },
+        }
+    }
+
+    /// Whether the message was too long to be stored in full
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl Write for Record {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let start = self.length as usize;
+        let available = MAX_MESSAGE_LENGTH - start;
+        let length = s.len().min(available);
+        self.message[start..start + length].copy_from_slice(&s.as_bytes()[..length]);
+        self.length = (start + length) as u8;
+        self.truncated |= length < s.len();
+        Ok(())
+    }
+}
+
+const _: () = assert!(MAX_MESSAGE_LENGTH <= u8::MAX as usize);
+
+struct Slot {
+    /// The sequence number of the record in the slot plus one, [`EMPTY`] or [`WRITING`]
+    state:  AtomicUsize,
+    record: UnsafeCell<Record>,
+}
+
+/// The outcome of reading the record with a given sequence number
+// Readers may run where the allocator cannot be used, so the record is returned by value.
+#[allow(clippy::large_enum_variant)]
+pub enum ReadResult {
+    Record(Record),
+    /// The record has been claimed but not published yet
+    Pending,
+    /// The record has been overwritten or dropped
+    Lost,
+}
+
+pub struct LogRing {
+    slots: [Slot; RING_CAPACITY],
+    /// The sequence number the next record will be written with
+    next: AtomicUsize,
+    /// The number of records dropped because their slot was still being written
+    dropped: AtomicUsize,
+}
+
+// Slots are only accessed by the writer that claimed them or through the sequence check.
+unsafe impl Sync for LogRing {}
+
+impl LogRing {
+    pub const fn new() -> Self {
+        LogRing {
+            slots: [const {
+                Slot {
+                    state:  AtomicUsize::new(EMPTY),
+                    record: UnsafeCell::new(Record::empty()),
+                }
+            }; RING_CAPACITY],
+            next: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Writes a record with the formatted message
+    pub fn write(&self, level: Level, module: &'static str, lp_id: LpId, args: fmt::Arguments) {
+        let sequence = self.next.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[sequence % RING_CAPACITY];
+        let mut state = slot.state.load(Ordering::Relaxed);
+        loop {
+            if state == WRITING {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            match slot.state.compare_exchange_weak(
+                state,
+                WRITING,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => state = current,
+            }
+        }
+        let record = unsafe { &mut *slot.record.get() };
+        record.timestamp = crate::time::monotonic_now();
+        record.lp_id = lp_id;
+        record.level = level;
+        record.module = module;
+        record.length = 0;
+        record.truncated = false;
+        let _ = record.write_fmt(args);
+        slot.state.store(sequence + 1, Ordering::Release);
+    }
+
+    /// Returns the record with the given sequence number if it is still held by the ring
+    pub fn read(&self, sequence: usize) -> ReadResult {
+        let slot = &self.slots[sequence % RING_CAPACITY];
+        let state = slot.state.load(Ordering::Acquire);
+        if state != sequence + 1 {
+            return if state == WRITING || state == EMPTY || state < sequence + 1 {
+                ReadResult::Pending
+            } else {
+                ReadResult::Lost
+            };
+        }
+        let record = unsafe { slot.record.get().read_volatile() };
+        fence(Ordering::Acquire);
+        if slot.state.load(Ordering::Relaxed) == sequence + 1 {
+            ReadResult::Record(record)
+        } else {
+            ReadResult::Lost
+        }
+    }
+
+    /// The sequence number the next record will be written with
+    pub fn next_sequence(&self) -> usize {
+        self.next.load(Ordering::Acquire)
+    }
+
+    /// The sequence number of the oldest record that may still be held by the ring
+    pub fn oldest_sequence(&self) -> usize {
+        self.next_sequence().saturating_sub(RING_CAPACITY)
+    }
+
+    /// The number of records dropped because their slot was still being written
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for LogRing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,354 @@
+//! # Log Sinks
+//!
+//! Sinks receive the records written to the [log ring](super::ring) and write them somewhere. Each
+//! sink reads the ring at its own pace from its own position and is drained by at most one LP at a
+//! time. An LP that finds a sink already being drained leaves the new records to the LP draining
+//! it, so draining never waits and is safe from any context, including an NMI that interrupted the
+//! drain on the same LP. Records are drained right after being written outside of interrupt
+//! context, when the LP leaves interrupt context and whenever it goes idle.
+//!
+//! A sink that falls further behind than the ring holds misses the overwritten records and is told
+//! how many it missed.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+use spin::Once;
+
+use super::ring::{RING_CAPACITY, ReadResult, Record};
+use super::{Level, RING};
+use crate::cpu::sync::IrqSpinlock;
+use crate::drivers::uart::ns16550::LOG_PORT;
+use crate::framebuffer::console::CONSOLE;
+use crate::lock_class;
+use crate::time::LogTimestamp;
+
+pub const MAX_SINKS: usize = 8;
+/// How far a sink has to fall behind a record that is still being written before it gives up on it
+const STALL_DISTANCE: usize = RING_CAPACITY / 2;
+const CRATE_PREFIX: &str = "charlottek::";
+
+pub trait LogSink: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Writes a record. Calls for the same sink never overlap.
+    fn write(&self, record: &Record);
+
+    /// Tells the sink that `count` records were lost before it could write them
+    fn write_lost(&self, count: usize);
+
+    /// Releases any lock `write` may be holding
+    ///
+    /// # Safety
+    /// The LP that was writing to the sink must never resume.
+    unsafe fn force_release(&self) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkError {
+    /// Every sink slot is in use
+    NoFreeSlot,
+}
+
+struct SinkSlot {
+    claimed: AtomicBool,
+    sink: Once<&'static dyn LogSink>,
+    /// The sequence number of the next record to write to the sink
+    cursor: AtomicUsize,
+    draining: AtomicBool,
+    /// The most verbose level the sink writes
+    level: AtomicU8,
+}
+
+impl SinkSlot {
+    const fn new(sink: Option<&'static dyn LogSink>) -> Self {
+        SinkSlot {
+            claimed: AtomicBool::new(sink.is_some()),
+            sink: match sink {
+                Some(sink) => Once::initialized(sink),
+                None => Once::new(),
+            },
+            cursor: AtomicUsize::new(0),
+            draining: AtomicBool::new(false),
+            level: AtomicU8::new(Level::Trace as u8),
+        }
+    }
+
+    /// Writes every record that has been published to the sink. If `force` is set records that are
+    /// still being written are skipped rather than waited for.
+    fn drain(&self, force: bool) {
+        let Some(sink) = self.sink.get() else {
+            return;
+        };
+        loop {
+            if self.cursor.load(Ordering::Relaxed) >= RING.next_sequence()
+                || self.draining.swap(true, Ordering::Acquire)
+            {
+                return;
+            }
+            let start = self.cursor.load(Ordering::Relaxed);
+            let end = RING.next_sequence();
+            let mut cursor = start.max(RING.oldest_sequence());
+            let mut lost = cursor - start;
+            let level = self.level.load(Ordering::Relaxed);
+            while cursor < end {
+                match RING.read(cursor) {
+                    ReadResult::Record(record) => {
+                        if lost != 0 {
+                            sink.write_lost(lost);
+                            lost = 0;
+                        }
+                        if record.level as u8 <= level {
+                            sink.write(&record);
+                        }
+                    }
+                    ReadResult::Pending if !force && end - cursor < STALL_DISTANCE => break,
+                    ReadResult::Pending | ReadResult::Lost => lost += 1,
+                }
+                cursor += 1;
+            }
+            if lost != 0 {
+                sink.write_lost(lost);
+            }
+            self.cursor.store(cursor, Ordering::Relaxed);
+            self.draining.store(false, Ordering::Release);
+            // Records written by LPs that found the sink busy would otherwise wait for the next
+            // drain.
+            if cursor == start {
+                return;
+            }
+        }
+    }
+}
+
+static SERIAL_SINK: SerialSink = SerialSink;
+static CONSOLE_SINK: ConsoleSink = ConsoleSink;
+pub static MEMORY_SINK: MemorySink = MemorySink::new();
+
+static SINKS: [SinkSlot; MAX_SINKS] = [
+    // The serial port is only used for logging in debug builds.
+    SinkSlot::new(
+        if cfg!(all(target_arch = "x86_64", debug_assertions)) {
+            Some(&SERIAL_SINK)
+        } else {
+            None
+        },
+    ),
+    SinkSlot::new(Some(&CONSOLE_SINK)),
+    SinkSlot::new(Some(&MEMORY_SINK)),
+    SinkSlot::new(None),
+    SinkSlot::new(None),
+    SinkSlot::new(None),
+    SinkSlot::new(None),
+    SinkSlot::new(None),
+];
+
+/// Adds a sink that writes records of `level` and below. It starts with the oldest record the ring
+/// still holds.
+pub fn register_sink(sink: &'static dyn LogSink, level: Level) -> Result<(), SinkError> {
+    let slot = SINKS
+        .iter()
+        .find(|slot| !slot.claimed.swap(true, Ordering::AcqRel))
+        .ok_or(SinkError::NoFreeSlot)?;
+    slot.cursor.store(RING.oldest_sequence(), Ordering::Relaxed);
+    slot.level.store(level as u8, Ordering::Relaxed);
+    slot.sink.call_once(|| sink);
+    Ok(())
+}
+
+/// Sets the most verbose level the named sink writes. Returns false if there is no such sink.
+pub fn set_sink_level(name: &str, level: Level) -> bool {
+    SINKS
+        .iter()
+        .filter(|slot| slot.sink.get().is_some_and(|sink| sink.name() == name))
+        .map(|slot| slot.level.store(level as u8, Ordering::Relaxed))
+        .count()
+        != 0
+}
+
+/// Calls `f` with the name of every sink and the most verbose level it writes
+pub fn for_each_sink(mut f: impl FnMut(&'static str, Level)) {
+    for slot in SINKS.iter() {
+        if let Some(sink) = slot.sink.get() {
+            f(sink.name(), Level::from_u8(slot.level.load(Ordering::Relaxed)));
+        }
+    }
+}
+
+pub(super) fn drain_all(force: bool) {
+    for slot in SINKS.iter() {
+        slot.drain(force);
+    }
+}
+
+/// Makes every sink available to the current LP regardless of which LP was draining it
+///
+/// # Safety
+/// Every other LP must have been stopped.
+pub(super) unsafe fn force_release_all() {
+    for slot in SINKS.iter() {
+        if let Some(sink) = slot.sink.get() {
+            unsafe { sink.force_release() };
+            slot.draining.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Formats a record as a line of text, with its LP, level and module if `verbose` is set
+pub(super) struct Line<'a> {
+    pub(super) record:  &'a Record,
+    pub(super) verbose: bool,
+}
+
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let record = self.record;
+        write!(f, "{} ", LogTimestamp::from_nanos(record.timestamp))?;
+        if self.verbose {
+            let module = record.module.strip_prefix(CRATE_PREFIX).unwrap_or(record.module);
+            write!(f, "LP{} {:<5} {}: ", record.lp_id, record.level, module)?;
+        } else if record.level <= Level::Warn {
+            write!(f, "{}: ", record.level)?;
+        }
+        write!(f, "{}", record.message())?;
+        if record.is_truncated() {
+            write!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes records to the COM1 serial port
+pub struct SerialSink;
+
+impl LogSink for SerialSink {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn write(&self, record: &Record) {
+        let line = Line {
+            record,
+            verbose: true,
+        };
+        let _ = writeln!(LOG_PORT.lock(), "{}", line);
+    }
+
+    fn write_lost(&self, count: usize) {
+        let _ = writeln!(LOG_PORT.lock(), "... {} log records were lost ...", count);
+    }
+
+    unsafe fn force_release(&self) {
+        unsafe { LOG_PORT.force_unlock() };
+    }
+}
+
+/// Writes records to the framebuffer console
+pub struct ConsoleSink;
+
+impl LogSink for ConsoleSink {
+    fn name(&self) -> &'static str {
+        "console"
+    }
+
+    fn write(&self, record: &Record) {
+        let line = Line {
+            record,
+            verbose: false,
+        };
+        crate::println!("{}", line);
+    }
+
+    fn write_lost(&self, count: usize) {
+        crate::println!("... {} log records were lost ...", count);
+    }
+
+    unsafe fn force_release(&self) {
+        unsafe { CONSOLE.force_unlock() };
+    }
+}
+
+const MEMORY_SINK_SIZE: usize = 16 * 1024;
+
+/// The text of the most recent records
+struct TextRing {
+    bytes: [u8; MEMORY_SINK_SIZE],
+    /// The total number of bytes ever written
+    written: usize,
+}
+
+impl Write for TextRing {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.bytes[self.written % MEMORY_SINK_SIZE] = byte;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the text of the most recent records in memory, independent of the record ring, so that
+/// it can be read back as plain text
+pub struct MemorySink {
+    text: IrqSpinlock<TextRing>,
+}
+
+impl MemorySink {
+    pub const fn new() -> Self {
+        MemorySink {
+            text: IrqSpinlock::with_class(
+                lock_class!("LOG_MEMORY_SINK"),
+                TextRing {
+                    bytes: [0; MEMORY_SINK_SIZE],
+                    written: 0,
+                },
+            ),
+        }
+    }
+
+    /// Calls `f` with the text the sink holds, oldest first, in at most two pieces. The oldest line
+    /// may be cut off at its start.
+    pub fn read(&self, mut f: impl FnMut(&[u8])) {
+        let text = self.text.lock();
+        let held = text.written.min(MEMORY_SINK_SIZE);
+        let start = (text.written - held) % MEMORY_SINK_SIZE;
+        let first = &text.bytes[start..(start + held).min(MEMORY_SINK_SIZE)];
+        f(first);
+        if first.len() < held {
+            f(&text.bytes[..held - first.len()]);
+        }
+    }
+
+    /// The total number of bytes ever written to the sink
+    pub fn written(&self) -> usize {
+        self.text.lock().written
+    }
+}
+
+impl Default for MemorySink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogSink for MemorySink {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn write(&self, record: &Record) {
+        let line = Line {
+            record,
+            verbose: true,
+        };
+        let _ = writeln!(self.text.lock(), "{}", line);
+    }
+
+    fn write_lost(&self, count: usize) {
+        let _ = writeln!(self.text.lock(), "... {} log records were lost ...", count);
+    }
+
+    unsafe fn force_release(&self) {
+        unsafe { self.text.force_unlock() };
+    }
+}
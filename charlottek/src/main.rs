@@ -27,6 +27,7 @@
 extern crate alloc;
 
 pub mod cpu;
+pub mod debug;
 pub mod drivers;
 pub mod environment;
 pub mod event;
@@ -38,6 +39,7 @@ pub mod log;
 pub mod memory;
 pub mod panic;
 pub mod self_test;
+pub mod time;
 
 use cpu::multiprocessor;
 use isa::interface::system_info::CpuInfoIfce;
@@ -54,6 +56,8 @@ static INIT_BARRIER: Lazy<Barrier> = Lazy::new(|| Barrier::new(get_lp_count() as
 /// that it can be called by Limine or any other Limine Boot Protocol compliant bootloader.
 #[unsafe(no_mangle)]
 pub extern "C" fn bsp_main() -> ! {
+    environment::cmdline::init();
+    log::filter::apply_params();
     logln!(
         "CharlotteOS Kernel Version {}.{}.{}",
         (KERNEL_VERSION.0),
@@ -61,6 +65,7 @@ pub extern "C" fn bsp_main() -> ! {
         (KERNEL_VERSION.2)
     );
     logln!("========================================================================");
+    environment::cmdline::report();
     logln!("Initializing the system using the bootstrap processor...");
     unsafe {
         multiprocessor::assign_id();
@@ -69,6 +74,7 @@ pub extern "C" fn bsp_main() -> ! {
     logln!("System initialized.");
     logln!("Starting secondary LPs...");
     multiprocessor::start_secondary_lps().expect("Failed to start secondary LPs");
+    time::lp_sync::synchronize_aps();
     INIT_BARRIER.wait();
     self_test::run_self_tests();
     logln!("System Information:");
@@ -76,8 +82,9 @@ pub extern "C" fn bsp_main() -> ! {
     logln!("CPU Model: {}", (CpuInfo::get_model()));
     logln!("Physical Address bits implemented: {}", (CpuInfo::get_paddr_sig_bits()));
     logln!("Virtual Address bits implemented: {}", (CpuInfo::get_vaddr_sig_bits()));
+    debug::monitor::enter_at_boot_if_requested();
     logln!("Nothing left to do. Waiting for interrupts...");
-    halt!()
+    cpu::timers::idle()
 }
 /// This is the application processor's entry point into the kernel. The `ap_main` function is
 /// called by each application processor upon entering the kernel. It initializes the processor and
@@ -91,5 +98,5 @@ pub unsafe extern "C" fn ap_main(_cpuinfo: &Cpu) -> ! {
     init::ap_init();
     INIT_BARRIER.wait();
     logln!("LP{}: Nothing left to do. Waiting for interrupts...", (get_lp_id!()));
-    halt!()
+    cpu::timers::idle()
 }
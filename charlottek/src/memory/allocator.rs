@@ -1,3 +1,7 @@
+use core::arch::asm;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use lazy_static::lazy_static;
 use spin::Mutex;
 use spinning_top::RawSpinlock;
@@ -5,11 +9,15 @@ use talc::{ErrOnOom, Span, Talc, Talck};
 
 use super::PHYSICAL_FRAME_ALLOCATOR;
 use super::vmem::{MemoryMapping, VAddr};
+use crate::cpu::sync::lockdep::{self, LockClass};
+use crate::environment::cmdline::Size;
 use crate::isa::interface::memory::address::VirtualAddress;
 use crate::isa::interface::memory::{AddressSpaceInterface, MemoryInterface};
+use crate::isa::lp::ops::{interrupts_enabled, mask_interrupts, unmask_interrupts};
 use crate::isa::memory::MemoryInterfaceImpl;
 use crate::isa::memory::address::VADDR_SIG_BITS;
 use crate::isa::memory::paging::{AddressSpace, PAGE_SIZE};
+use crate::{kernel_param, warn};
 
 lazy_static! {
     pub static ref ALLOCATOR_SPAN: Mutex<Span> = Mutex::new(Span::empty());
@@ -19,17 +27,86 @@ lazy_static! {
     static ref HIGHER_HALF_END: VAddr = VAddr::from(0xffff_ffff_ffff_ffff); // 64-bit higher half end address
 }
 
+static KERNEL_ALLOCATOR_CLASS: LockClass = LockClass::new("KERNEL_ALLOCATOR");
+
+/// The kernel allocator's lock, a spinlock that reports its acquisitions and releases to lockdep.
+/// Interrupts are masked while it is held so that interrupt handlers, e.g. timer callbacks, may
+/// allocate and free memory.
+pub struct KernelAllocatorLock {
+    raw: RawSpinlock,
+    interrupts_were_enabled: AtomicBool,
+}
+
+unsafe impl lock_api::RawMutex for KernelAllocatorLock {
+    type GuardMarker = lock_api::GuardSend;
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = KernelAllocatorLock {
+        raw: RawSpinlock::INIT,
+        interrupts_were_enabled: AtomicBool::new(false),
+    };
+
+    #[track_caller]
+    fn lock(&self) {
+        let interrupts_were_enabled = interrupts_enabled();
+        mask_interrupts!();
+        lockdep::will_acquire(Some(&KERNEL_ALLOCATOR_CLASS), Location::caller());
+        self.raw.lock();
+        lockdep::note_acquire(Some(&KERNEL_ALLOCATOR_CLASS), Location::caller(), true);
+        self.interrupts_were_enabled.store(interrupts_were_enabled, Ordering::Relaxed);
+    }
+
+    #[track_caller]
+    fn try_lock(&self) -> bool {
+        let interrupts_were_enabled = interrupts_enabled();
+        mask_interrupts!();
+        let acquired = self.raw.try_lock();
+        if acquired {
+            lockdep::note_acquire(Some(&KERNEL_ALLOCATOR_CLASS), Location::caller(), true);
+            self.interrupts_were_enabled.store(interrupts_were_enabled, Ordering::Relaxed);
+        } else if interrupts_were_enabled {
+            unmask_interrupts!();
+        }
+        acquired
+    }
+
+    unsafe fn unlock(&self) {
+        let interrupts_were_enabled = self.interrupts_were_enabled.load(Ordering::Relaxed);
+        lockdep::note_release(Some(&KERNEL_ALLOCATOR_CLASS));
+        unsafe { self.raw.unlock() }
+        if interrupts_were_enabled {
+            unmask_interrupts!();
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        self.raw.is_locked()
+    }
+}
+
 #[global_allocator]
-pub static mut KERNEL_ALLOCATOR: Talck<RawSpinlock, ErrOnOom> =
-    Talc::new(ErrOnOom).lock::<RawSpinlock>();
+pub static mut KERNEL_ALLOCATOR: Talck<KernelAllocatorLock, ErrOnOom> =
+    Talc::new(ErrOnOom).lock::<KernelAllocatorLock>();
 
-const KERNEL_HEAP_PAGE_COUNT: usize = 8192; // 32 MiB kernel heap size
+/// Smaller heaps are not enough for the kernel to initialize itself
+const MIN_KERNEL_HEAP_SIZE: Size = Size::from_mib(4);
+
+kernel_param! {
+    /// The size of the kernel heap, all of which is mapped when the allocator is initialized
+    static KERNEL_HEAP_SIZE: Size = ("heap.max", Size::from_mib(32));
+}
 
 pub fn init_allocator() -> Result<(), ()> {
+    let mut heap_size = KERNEL_HEAP_SIZE.get();
+    if heap_size < MIN_KERNEL_HEAP_SIZE {
+        warn!("A {} kernel heap is too small, using {} instead.", heap_size, MIN_KERNEL_HEAP_SIZE);
+        heap_size = MIN_KERNEL_HEAP_SIZE;
+    }
+    let kernel_heap_page_count = heap_size.bytes().div_ceil(PAGE_SIZE);
     let kernel_heap_start = <MemoryInterfaceImpl as MemoryInterface>::AddressSpace::get_current()
-        .find_free_region(KERNEL_HEAP_PAGE_COUNT, (*HIGHER_HALF_START, *HIGHER_HALF_END))
+        .find_free_region(kernel_heap_page_count, (*HIGHER_HALF_START, *HIGHER_HALF_END))
         .expect("Failed to find free region for kernel heap");
-    let kernel_heap_size = KERNEL_HEAP_PAGE_COUNT * PAGE_SIZE;
+    let kernel_heap_size = kernel_heap_page_count * PAGE_SIZE;
 
     let kernel_heap_span = Span::new(
         kernel_heap_start.into_mut(),
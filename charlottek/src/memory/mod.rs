@@ -6,6 +6,7 @@ pub use pmem::{MemoryInterface, PAddr, PhysicalFrameAllocator};
 pub use spin::{Lazy, Mutex, RwLock};
 pub use vmem::VAddr;
 
+use crate::cpu::sync::Spinlock;
 use crate::environment::boot_protocol::limine::{HHDM_REQUEST, MEMORY_MAP_REQUEST};
 pub use crate::isa::interface::memory::AddressSpaceInterface;
 pub use crate::isa::memory::paging::AddressSpace;
@@ -25,8 +26,11 @@ pub static HHDM_BASE: Lazy<VAddr> = Lazy::new(|| {
             .offset() as usize,
     )
 });
-pub static PHYSICAL_FRAME_ALLOCATOR: Lazy<Mutex<PhysicalFrameAllocator>> = Lazy::new(|| {
-    Mutex::new(PhysicalFrameAllocator::from(
-        MEMORY_MAP_REQUEST.get_response().expect("Limine failed to provide a memory map."),
-    ))
+pub static PHYSICAL_FRAME_ALLOCATOR: Lazy<Spinlock<PhysicalFrameAllocator>> = Lazy::new(|| {
+    Spinlock::with_class(
+        crate::lock_class!("PHYSICAL_FRAME_ALLOCATOR"),
+        PhysicalFrameAllocator::from(
+            MEMORY_MAP_REQUEST.get_response().expect("Limine failed to provide a memory map."),
+        ),
+    )
 });
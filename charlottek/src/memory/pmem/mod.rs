@@ -28,6 +28,15 @@ impl From<PAddrError> for Error {
     }
 }
 
+/// A count of the frames the allocator tracks
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    /// Every frame up to the highest physical address in the memory map
+    pub total: usize,
+    /// The frames that can be allocated
+    pub free:  usize,
+}
+
 #[derive(Debug)]
 pub struct PhysicalFrameAllocator {
     bitmap_ptr: *mut u8,
@@ -80,6 +89,19 @@ impl PhysicalFrameAllocator {
     }
 }
 
+impl PhysicalFrameAllocator {
+    /// Counts the free frames. Frames that are allocated, reserved or not usable RAM at all are
+    /// not told apart.
+    pub fn stats(&self) -> FrameStats {
+        let bitmap = unsafe { core::slice::from_raw_parts(self.bitmap_ptr, self.bitmap_len) };
+        let free = bitmap.iter().map(|byte| byte.count_zeros() as usize).sum();
+        FrameStats {
+            total: self.bitmap_len * 8,
+            free,
+        }
+    }
+}
+
 // There should be a From implementation for each type of memory map we support.
 
 impl From<&MemoryMapResponse> for PhysicalFrameAllocator {
@@ -109,6 +131,12 @@ impl From<&MemoryMapResponse> for PhysicalFrameAllocator {
         }
         // Mark the bitmap region as unusable.
         mark_pfa_bitmap_unusable(pfa.bitmap_ptr, bitmap_addr, bitmap_size);
+        // The persistent log has to be left untouched until the next boot.
+        if let Some((base, length)) =
+            crate::log::persist::reserve_region(response, bitmap_addr, bitmap_size)
+        {
+            mark_pfa_bitmap_unusable(pfa.bitmap_ptr, base, length);
+        }
         logln!("PhysicalFrameAllocator bitmap initialized.");
 
         pfa
@@ -0,0 +1,49 @@
+//! # Memory Mapped I/O
+//!
+//! Since base revision 3 of the Limine protocol the higher half direct mapping only covers memory,
+//! so device registers are not reachable through it. Drivers map their registers with [`map`]
+//! instead, which maps them uncached into a part of the higher half set aside for devices. Devices
+//! are never removed, so neither are their mappings.
+
+use super::{MemoryMapping, PAddr, PageType, VAddr};
+use crate::cpu::sync::Spinlock;
+use crate::isa::interface::memory::{AddressSpaceInterface, MemoryInterface};
+use crate::isa::memory::MemoryInterfaceImpl;
+use crate::isa::memory::paging::{AddressSpace, PAGE_SIZE};
+use crate::lock_class;
+
+/// The top level page table slot below the kernel image, far above the HHDM and the kernel heap
+const MMIO_WINDOW_START: usize = 0xffff_ff00_0000_0000;
+const MMIO_WINDOW_END: usize = 0xffff_ff80_0000_0000;
+
+/// The first unused address of the window
+static MMIO_WINDOW: Spinlock<usize> =
+    Spinlock::with_class(lock_class!("MMIO_WINDOW"), MMIO_WINDOW_START);
+
+/// Maps the `size` bytes of device memory at `paddr` uncached and returns the virtual address of
+/// `paddr`
+pub fn map(
+    paddr: PAddr,
+    size: usize,
+) -> Result<VAddr, <MemoryInterfaceImpl as MemoryInterface>::Error> {
+    let paddr: u64 = paddr.into();
+    let offset = paddr as usize % PAGE_SIZE;
+    let n_pages = (offset + size.max(1)).div_ceil(PAGE_SIZE);
+    let mut next = MMIO_WINDOW.lock();
+    if (MMIO_WINDOW_END - *next) / PAGE_SIZE < n_pages {
+        return Err(
+            <MemoryInterfaceImpl as MemoryInterface>::Error::NoRequestedVAddrRegionAvailable,
+        );
+    }
+    let base = *next;
+    *next = base + n_pages * PAGE_SIZE;
+    let mut address_space = AddressSpace::get_current();
+    for page in 0..n_pages {
+        address_space.map_page(MemoryMapping {
+            vaddr: VAddr::from(base + page * PAGE_SIZE),
+            paddr: PAddr::from(paddr - offset as u64 + (page * PAGE_SIZE) as u64),
+            page_type: PageType::Mmio,
+        })?;
+    }
+    Ok(VAddr::from(base + offset))
+}
@@ -1,10 +1,11 @@
+use core::arch::asm;
 use core::panic::PanicInfo;
 
-use crate::isa::lp::ops::halt;
-use crate::logln;
+use crate::debug::crash;
+use crate::isa::lp::ops::mask_interrupts;
 
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    logln!("{}", _info);
-    halt!()
+fn panic(info: &PanicInfo) -> ! {
+    mask_interrupts!();
+    crash::report_panic(info)
 }
@@ -0,0 +1,48 @@
+use alloc::format;
+use alloc::vec::Vec;
+
+use crate::debug::backtrace;
+use crate::debug::symbols::{self, Symbolized};
+use crate::isa::lp::ops::frame_pointer;
+use crate::logln;
+
+#[inline(never)]
+fn collect_frames() -> Vec<usize> {
+    let mut frames = Vec::new();
+    backtrace::walk(frame_pointer(), |address| frames.push(address));
+    frames
+}
+
+pub fn test_backtraces() {
+    logln!("Starting backtrace self-test...");
+    logln!("Backtrace self-test: walking the current stack...");
+    let frames = collect_frames();
+    assert!(!frames.is_empty(), "The frame record chain is empty");
+    assert!(
+        frames.iter().all(|address| symbols::is_kernel_text(*address)),
+        "A return address outside of the kernel's code was reported"
+    );
+
+    let entry = test_backtraces as *const () as usize;
+    if symbols::resolve(entry).is_none() {
+        logln!("Backtrace self-test: no symbol table, skipping symbolization tests.");
+        logln!("Backtrace self-test passed.");
+        return;
+    }
+    logln!("Backtrace self-test: symbolizing addresses...");
+    let (_, offset) = symbols::resolve(entry).unwrap();
+    assert_eq!(offset, 0, "The entry point of a function is not at offset zero");
+    let name = format!("{}", Symbolized(entry));
+    assert!(
+        name.ends_with("self_test::backtrace::test_backtraces+0x0"),
+        "A function was symbolized as {}",
+        name
+    );
+    let caller = format!("{}", Symbolized(frames[0] - 1));
+    assert!(
+        caller.contains("test_backtraces"),
+        "The innermost frame belongs to {} rather than the caller of collect_frames",
+        caller
+    );
+    logln!("Backtrace self-test passed.");
+}
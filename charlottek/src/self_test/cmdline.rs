@@ -0,0 +1,64 @@
+use alloc::vec::Vec;
+
+use crate::environment::cmdline::{self, Param, ParamEntry, ParamValue, ParseError, Size};
+use crate::log::Level;
+use crate::logln;
+
+/// Not declared with `kernel_param!` so that it stays out of the registry
+static TEST_PARAM: Param<Size> = Param::new("selftest.size", Size(4096));
+
+pub fn test_cmdline() {
+    logln!("Starting kernel command line self-test...");
+    logln!("Kernel command line self-test: splitting a command line...");
+    let parsed: Vec<_> =
+        cmdline::tokens("  log.level=debug selftest  name=\"two words\" empty= ").collect();
+    assert_eq!(
+        parsed,
+        [
+            ("log.level", Some("debug")),
+            ("selftest", None),
+            ("name", Some("two words")),
+            ("empty", Some("")),
+        ]
+    );
+    assert_eq!(cmdline::tokens("").count(), 0);
+
+    logln!("Kernel command line self-test: parsing values...");
+    assert_eq!(bool::parse(None), Ok(true));
+    assert_eq!(bool::parse(Some("OFF")), Ok(false));
+    assert_eq!(bool::parse(Some("maybe")), Err(ParseError::InvalidValue));
+    assert_eq!(Level::parse(Some("trace")), Ok(Level::Trace));
+    assert_eq!(Level::parse(None), Err(ParseError::MissingValue));
+    assert_eq!(Size::parse(Some("512M")), Ok(Size(512 << 20)));
+    assert_eq!(Size::parse(Some("2g")), Ok(Size(2 << 30)));
+    assert_eq!(Size::parse(Some("1000")), Ok(Size(1000)));
+    assert_eq!(Size::parse(Some("M")), Err(ParseError::InvalidValue));
+    assert_eq!(Size::parse(Some("99999999999999999999K")), Err(ParseError::InvalidValue));
+    assert_eq!(alloc::format!("{} {}", Size(3 << 20), Size(1000)), "3M 1000");
+
+    logln!("Kernel command line self-test: storing a value...");
+    assert!(!TEST_PARAM.is_set());
+    assert_eq!(TEST_PARAM.get(), Size(4096));
+    assert_eq!(TEST_PARAM.set(Some("oops")), Err(ParseError::InvalidValue));
+    assert!(!TEST_PARAM.is_set());
+    assert_eq!(TEST_PARAM.set(Some("8K")), Ok(()));
+    assert_eq!(TEST_PARAM.set(Some("16K")), Ok(()));
+    assert_eq!(TEST_PARAM.get(), Size(8192), "A stored value was replaced");
+
+    logln!("Kernel command line self-test: looking up declared parameters...");
+    for name in ["log.level", "selftest", "heap.max", "console", "gdb", "monitor.panic"] {
+        assert!(cmdline::find(name).is_some(), "The parameter \"{}\" is not registered", name);
+    }
+    assert!(cmdline::find("selftest.size").is_none());
+    let params = cmdline::params();
+    for (i, param) in params.iter().enumerate() {
+        assert!(
+            params[i + 1..].iter().all(|other| other.name() != param.name()),
+            "The parameter \"{}\" is declared more than once",
+            (param.name())
+        );
+    }
+    // The self tests only run if `selftest` was left on.
+    assert_eq!(alloc::format!("{}", (cmdline::find("selftest").unwrap().value())), "true");
+    logln!("Kernel command line self-test passed.");
+}
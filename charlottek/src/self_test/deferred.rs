@@ -0,0 +1,59 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::time::Duration;
+
+use crate::cpu::deferred::work_queue::{self, WorkQueue};
+use crate::cpu::deferred::{queue_delayed_work, schedule_tasklet};
+use crate::cpu::scheduler::GLOBAL_SCHEDULER;
+use crate::cpu::sync::Deadline;
+use crate::cpu::sync::context::in_interrupt_context;
+use crate::cpu::timers::add_timer;
+use crate::isa::lp::ops::{get_lp_id, wait_for_interrupt};
+use crate::logln;
+
+static TEST_WORK_QUEUE: WorkQueue = WorkQueue::new("self-test");
+
+pub fn test_deferred() {
+    logln!("Starting deferred work self-test...");
+    logln!("Deferred work self-test: tasklets run on interrupt exit...");
+    let tasklet_ran = Arc::new(AtomicBool::new(false));
+    {
+        let tasklet_ran = tasklet_ran.clone();
+        add_timer(Deadline::after(Duration::from_millis(1)), move || {
+            schedule_tasklet(move || {
+                assert!(in_interrupt_context(), "A tasklet ran outside of softirq context");
+                tasklet_ran.store(true, Ordering::Release);
+            });
+        });
+    }
+    let give_up = Deadline::after(Duration::from_secs(1));
+    while !tasklet_ran.load(Ordering::Acquire) {
+        assert!(!give_up.has_expired(), "A tasklet did not run within a second");
+        wait_for_interrupt();
+    }
+
+    logln!("Deferred work self-test: work queues run items in order...");
+    let next = Arc::new(AtomicU32::new(0));
+    for index in 0..4 {
+        let next = next.clone();
+        TEST_WORK_QUEUE.queue(move || {
+            assert_eq!(next.fetch_add(1, Ordering::Relaxed), index, "Work ran out of order");
+        });
+    }
+    assert_eq!(TEST_WORK_QUEUE.run_pending(), 4, "Not all queued work was run");
+    assert!(!TEST_WORK_QUEUE.has_pending());
+
+    logln!("Deferred work self-test: serving the system work queue...");
+    assert_eq!(
+        work_queue::idle_serves_system_queue(),
+        GLOBAL_SCHEDULER.try_get_lp_scheduler(get_lp_id!()).is_none(),
+        "The system work queue has no worker thread on an LP with a scheduler"
+    );
+
+    logln!("Deferred work self-test: cancelling delayed work...");
+    let delayed = queue_delayed_work(Duration::from_millis(1), || {
+        panic!("Cancelled delayed work was queued");
+    });
+    assert!(delayed.cancel(), "Pending delayed work could not be cancelled");
+    logln!("Deferred work self-test passed.");
+}
@@ -0,0 +1,58 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use crate::cpu::sync::Deadline;
+use crate::cpu::timers::add_timer;
+use crate::event::{Completion, Event, EventSource, Sentinel};
+use crate::isa::lp::ops::wait_for_interrupt;
+use crate::logln;
+
+pub fn test_events() {
+    logln!("Starting event self-test...");
+    logln!("Event self-test: broadcasting to observers...");
+    let source = EventSource::new();
+    let completion = Arc::new(Completion::new());
+    let sentinel = Arc::new(Sentinel::new());
+    source.register_observer(completion.clone());
+    let sentinel_id = source.register_observer(sentinel.clone());
+    source.signal();
+    source.signal();
+    assert!(completion.is_complete(), "A completion was not completed by its event");
+    assert_eq!(sentinel.get_times_notified(), 2, "A sentinel missed a notification");
+    assert!(source.unregister_observer(sentinel_id));
+    assert!(!source.unregister_observer(sentinel_id), "An observer was unregistered twice");
+    source.signal();
+    assert_eq!(sentinel.get_times_notified(), 2, "An unregistered observer was notified");
+
+    logln!("Event self-test: completions...");
+    let completion = Completion::new();
+    assert!(!completion.wait_timeout(Duration::from_millis(1)), "An idle completion completed");
+    let continuation_ran = Arc::new(AtomicBool::new(false));
+    {
+        let continuation_ran = continuation_ran.clone();
+        completion.on_complete(move || continuation_ran.store(true, Ordering::Relaxed));
+    }
+    assert!(completion.complete());
+    assert!(!completion.complete(), "A completion completed twice");
+    assert!(continuation_ran.load(Ordering::Relaxed), "A continuation did not run");
+    completion.wait();
+
+    logln!("Event self-test: signalling from interrupt context...");
+    let source = Arc::new(EventSource::new());
+    let completion = Arc::new(Completion::new());
+    source.register_observer(completion.clone());
+    {
+        let source = source.clone();
+        add_timer(Deadline::after(Duration::from_millis(1)), move || source.signal());
+    }
+    let give_up = Deadline::after(Duration::from_secs(1));
+    while !completion.is_complete() {
+        assert!(
+            !give_up.has_expired(),
+            "An event signalled from an interrupt handler was not observed"
+        );
+        wait_for_interrupt();
+    }
+    logln!("Event self-test passed.");
+}
@@ -0,0 +1,51 @@
+use alloc::format;
+use core::time::Duration;
+
+use crate::cpu::sync::Deadline;
+use crate::isa::interrupts::exceptions::frame::ExceptionFrame;
+use crate::isa::interrupts::exceptions::report::{PageFaultError, SelectorError};
+use crate::isa::interrupts::exceptions::{nmi_count, send_self_nmi};
+use crate::logln;
+
+const NMI_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub fn test_exception_reports() {
+    logln!("Starting exception report self-test...");
+    logln!("Exception report self-test: decoding page fault error codes...");
+    assert_eq!(
+        format!("{}", PageFaultError(PageFaultError::PRESENT | PageFaultError::WRITE)),
+        "protection violation on write by the kernel"
+    );
+    assert_eq!(
+        format!("{}", PageFaultError(PageFaultError::USER | PageFaultError::INSTRUCTION_FETCH)),
+        "not present page on instruction fetch by userspace"
+    );
+
+    logln!("Exception report self-test: decoding selector error codes...");
+    assert_eq!(format!("{}", SelectorError(0)), "not caused by a segment selector");
+    assert_eq!(format!("{}", SelectorError(0x18)), "GDT entry 0x3 (selector 0x0018)");
+    assert_eq!(
+        format!("{}", SelectorError(0x103)),
+        "IDT entry 0x20 (selector 0x0100), raised while delivering an external event"
+    );
+
+    logln!("Exception report self-test: formatting a register dump...");
+    let mut frame = ExceptionFrame::default();
+    frame.gprs.rax = 0xdead_beef;
+    frame.interrupt_frame.rip = 0xffff_ffff_8000_1234;
+    frame.interrupt_frame.cs = 0x8;
+    assert!(frame.is_kernel_mode(), "A kernel code segment was reported as userspace");
+    let dump = format!("{}", frame);
+    assert!(dump.contains("RAX=00000000deadbeef"), "The register dump is missing RAX");
+    assert!(dump.contains("RIP=ffffffff80001234"), "The register dump is missing RIP");
+
+    logln!("Exception report self-test: delivering an NMI through the IDT...");
+    let nmis_before = nmi_count();
+    send_self_nmi();
+    let deadline = Deadline::after(NMI_TIMEOUT);
+    while nmi_count() == nmis_before && !deadline.has_expired() {
+        core::hint::spin_loop();
+    }
+    assert!(nmi_count() > nmis_before, "The self NMI did not reach its handler");
+    logln!("Exception report self-test passed.");
+}
@@ -0,0 +1,39 @@
+use core::time::Duration;
+
+use crate::cpu::executor::timer::Elapsed;
+use crate::cpu::executor::{block_on, sleep, spawn, timeout, yield_now};
+use crate::cpu::sync::Deadline;
+use crate::event::Completion;
+use crate::logln;
+
+static GATE: Completion = Completion::new();
+
+pub fn test_executor() {
+    logln!("Starting async executor self-test...");
+    logln!("Async executor self-test: spawning and joining tasks...");
+    let task = spawn(async {
+        yield_now().await;
+        21 * 2
+    });
+    assert_eq!(block_on(task), 42, "A task produced the wrong output");
+
+    logln!("Async executor self-test: timer futures...");
+    let deadline = Deadline::after(Duration::from_millis(2));
+    block_on(sleep(Duration::from_millis(2)));
+    assert!(deadline.has_expired(), "A sleep future resolved early");
+    let result = block_on(timeout(Duration::from_millis(1), GATE.wait_async()));
+    assert_eq!(result, Err(Elapsed), "A timeout did not elapse");
+
+    logln!("Async executor self-test: completions wake tasks...");
+    let waiter = spawn(async {
+        GATE.wait_async().await;
+        true
+    });
+    let completer = spawn(async {
+        sleep(Duration::from_millis(1)).await;
+        GATE.complete();
+    });
+    assert!(block_on(waiter), "A task waiting on a completion was not woken");
+    block_on(completer);
+    logln!("Async executor self-test passed.");
+}
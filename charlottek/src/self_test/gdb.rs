@@ -0,0 +1,86 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::debug::gdb::packet::{Packet, decode_hex, parse_hex};
+use crate::debug::gdb::registers::{self, REGISTER_COUNT, RFLAGS, RIP};
+use crate::debug::gdb::{clear_breakpoint, set_breakpoint, set_stop_hook};
+use crate::isa::interrupts::exceptions::frame::ExceptionFrame;
+use crate::logln;
+
+/// The RIP of the last stop the hook saw
+static STOPPED_AT: AtomicU64 = AtomicU64::new(0);
+static STOPS: AtomicUsize = AtomicUsize::new(0);
+
+fn record_stop(frame: &mut ExceptionFrame) {
+    STOPPED_AT.store(frame.interrupt_frame.rip, Ordering::Relaxed);
+    STOPS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Removes the breakpoint it stopped at before resuming, as GDB does before it continues
+fn record_stop_and_clear(frame: &mut ExceptionFrame) {
+    record_stop(frame);
+    assert!(clear_breakpoint(frame.interrupt_frame.rip as usize));
+}
+
+#[inline(never)]
+fn breakpoint_target(value: u64) -> u64 {
+    core::hint::black_box(value) + 1
+}
+
+pub fn test_gdb() {
+    logln!("Starting GDB stub self-test...");
+    logln!("GDB stub self-test: encoding and decoding hex...");
+    let mut packet = Packet::new();
+    let _ = write!(packet, "m");
+    packet.push_hex(&[0x00, 0x7f, 0xab]);
+    assert_eq!(packet.as_bytes(), b"m007fab");
+    let mut bytes = [0; 4];
+    assert_eq!(decode_hex(b"deadBEEF", &mut bytes), Some(4));
+    assert_eq!(bytes, [0xde, 0xad, 0xbe, 0xef]);
+    assert_eq!(decode_hex(b"abc", &mut bytes), None);
+    assert_eq!(decode_hex(b"0011223344", &mut bytes), None);
+    assert_eq!(decode_hex(b"zz", &mut bytes), None);
+    assert_eq!(parse_hex(b"ffffffff80000000"), Some(0xffff_ffff_8000_0000));
+    assert_eq!(parse_hex(b""), None);
+    assert_eq!(parse_hex(b"10000000000000000"), None);
+
+    logln!("GDB stub self-test: mapping registers to the exception frame...");
+    let mut frame = ExceptionFrame::default();
+    frame.gprs.rbx = 0x1234;
+    frame.interrupt_frame.rsp = 0x5678;
+    frame.interrupt_frame.cs = 0x08;
+    assert_eq!(registers::read(&frame, 1), Some(0x1234));
+    assert_eq!(registers::read(&frame, 7), Some(0x5678));
+    assert_eq!(registers::read(&frame, 18), Some(0x08));
+    assert_eq!(registers::read(&frame, 20), Some(0));
+    assert_eq!(registers::read(&frame, REGISTER_COUNT), None);
+    assert!(registers::write(&mut frame, RIP, 0xffff_ffff_8000_1000));
+    assert_eq!(frame.interrupt_frame.rip, 0xffff_ffff_8000_1000);
+    assert!(registers::write(&mut frame, RFLAGS, 0x202));
+    assert_eq!(frame.interrupt_frame.rflags, 0x202);
+    assert!(!registers::write(&mut frame, 18, 0x10));
+    assert_eq!(frame.interrupt_frame.cs, 0x08);
+    assert_eq!(registers::size_of(RIP), 8);
+    assert_eq!(registers::size_of(RFLAGS), 4);
+
+    logln!("GDB stub self-test: stopping at an explicit breakpoint...");
+    set_stop_hook(Some(record_stop));
+    let after_int3: u64;
+    unsafe { core::arch::asm!("int3", "2:", "lea {}, [rip + 2b]", out(reg) after_int3) };
+    assert_eq!(STOPS.load(Ordering::Relaxed), 1);
+    // An explicit breakpoint stays in place, so execution continues after it.
+    assert_eq!(STOPPED_AT.load(Ordering::Relaxed), after_int3);
+
+    logln!("GDB stub self-test: stopping at an inserted breakpoint...");
+    set_stop_hook(Some(record_stop_and_clear));
+    let target = core::hint::black_box(breakpoint_target as fn(u64) -> u64);
+    assert!(set_breakpoint(target as usize), "The breakpoint could not be inserted");
+    assert_eq!(target(41), 42);
+    assert_eq!(STOPS.load(Ordering::Relaxed), 2, "The inserted breakpoint was not hit");
+    // The replaced instruction has to run once the breakpoint is gone, so RIP is rewound to it.
+    assert_eq!(STOPPED_AT.load(Ordering::Relaxed), target as usize as u64);
+    assert_eq!(target(1), 2);
+    assert_eq!(STOPS.load(Ordering::Relaxed), 2, "The breakpoint was not removed");
+    set_stop_hook(None);
+    logln!("GDB stub self-test passed.");
+}
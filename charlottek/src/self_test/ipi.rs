@@ -0,0 +1,26 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::cpu::multiprocessor::lp_mask::{AtomicLpMask, LpMask};
+use crate::cpu::multiprocessor::online_lps;
+use crate::isa::interrupts::ipis::call_on_lps;
+use crate::isa::lp::ops::get_lp_id;
+use crate::logln;
+
+pub fn test_ipis() {
+    logln!("Starting IPI self-test...");
+    logln!("IPI self-test: synchronous calls on the current LP...");
+    let calls = AtomicU32::new(0);
+    call_on_lps(LpMask::single(get_lp_id!()), || {
+        calls.fetch_add(1, Ordering::Relaxed);
+    });
+    assert_eq!(calls.load(Ordering::Relaxed), 1, "A local call did not run exactly once");
+
+    logln!("IPI self-test: synchronous calls on every online LP...");
+    let targets = online_lps();
+    let ran_on = AtomicLpMask::empty();
+    call_on_lps(targets, || {
+        assert!(ran_on.insert(get_lp_id!()), "A call ran twice on the same LP");
+    });
+    assert_eq!(ran_on.load(), targets, "A call returned before every target ran it");
+    logln!("IPI self-test passed.");
+}
@@ -0,0 +1,68 @@
+use crate::isa::interrupts::context_switch::CONTEXT_SWITCH_VECTOR;
+use crate::isa::interrupts::idt::{FIRST_DYNAMIC_VECTOR, Idt};
+use crate::isa::interrupts::ioapic;
+use crate::isa::interrupts::ipis::IPI_VECTOR;
+use crate::isa::interrupts::irq::{IrqError, free_irq, mask_irq, request_irq};
+use crate::isa::interrupts::x2apic::SPURIOUS_VECTOR;
+use crate::isa::interrupts::x2apic::timer::TIMER_VECTOR;
+use crate::isa::lp::ops::get_lp_id;
+use crate::logln;
+
+/// The ISA IRQ of the second parallel port, which nothing in the kernel uses
+const TEST_ISA_IRQ: u8 = 5;
+
+pub fn test_irqs() {
+    logln!("Starting IRQ self-test...");
+    logln!("IRQ self-test: dynamic vector allocation...");
+    let lp_id = get_lp_id!();
+    let first = Idt::allocate_vector(lp_id).expect("No dynamic vector is free");
+    let second = Idt::allocate_vector(lp_id).expect("No dynamic vector is free");
+    assert_ne!(first, second, "A vector was allocated twice");
+    for vector in [first, second] {
+        assert!(vector >= FIRST_DYNAMIC_VECTOR, "An exception vector was allocated");
+        assert!(
+            ![CONTEXT_SWITCH_VECTOR, IPI_VECTOR, TIMER_VECTOR, SPURIOUS_VECTOR].contains(&vector),
+            "The vector of a fixed gate was allocated"
+        );
+    }
+    let block = Idt::allocate_vectors(lp_id, 8).expect("No block of 8 vectors is free");
+    assert_eq!(block % 8, 0, "A block of vectors was not aligned to its size");
+    assert!(
+        ![first, second].iter().any(|vector| (block..block + 8).contains(vector)),
+        "A block overlaps vectors that were already allocated"
+    );
+    Idt::free_vectors(lp_id, block, 8);
+    Idt::free_vector(lp_id, first);
+    Idt::free_vector(lp_id, second);
+    assert_eq!(
+        Idt::allocate_vector(lp_id),
+        Some(first.min(second)),
+        "Freed vectors were not reused"
+    );
+    Idt::free_vector(lp_id, first.min(second));
+
+    let gsi = ioapic::isa_irq_gsi(TEST_ISA_IRQ).expect("ISA IRQs should always have a GSI");
+    if ioapic::routing_of(gsi) == Err(IrqError::NoSuchGsi) {
+        logln!("IRQ self-test: no IOAPIC receives GSI {}, skipping routing tests.", gsi);
+        logln!("IRQ self-test passed.");
+        return;
+    }
+    logln!("IRQ self-test: routing GSI {} to the current LP...", gsi);
+    request_irq(gsi, || {}, lp_id).expect("Failed to request the test IRQ");
+    assert_eq!(
+        request_irq(gsi, || {}, lp_id),
+        Err(IrqError::AlreadyRequested),
+        "A GSI was requested twice"
+    );
+    let (vector, masked) = ioapic::routing_of(gsi).unwrap();
+    assert!(vector >= FIRST_DYNAMIC_VECTOR, "The GSI was routed to an exception vector");
+    assert!(!masked, "A requested GSI was left masked");
+    mask_irq(gsi).unwrap();
+    assert!(ioapic::routing_of(gsi).unwrap().1, "Masking the GSI had no effect");
+
+    logln!("IRQ self-test: freeing GSI {}...", gsi);
+    free_irq(gsi).expect("Failed to free the test IRQ");
+    assert!(ioapic::routing_of(gsi).unwrap().1, "A freed GSI was left unmasked");
+    assert_eq!(free_irq(gsi), Err(IrqError::NotRequested), "A GSI was freed twice");
+    logln!("IRQ self-test passed.");
+}
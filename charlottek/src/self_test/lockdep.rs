@@ -0,0 +1,50 @@
+use crate::logln;
+
+pub fn test_lockdep() {
+    logln!("Starting lockdep self-test...");
+    #[cfg(feature = "lockdep")]
+    {
+        use crate::cpu::sync::lockdep::order;
+        use crate::cpu::sync::{Mutex, Spinlock};
+        use crate::lock_class;
+
+        if !order::is_enabled() {
+            logln!("Lockdep self-test: lock order validation has been turned off, skipping.");
+            return;
+        }
+        let a = Spinlock::with_class(lock_class!("SELF_TEST_LOCKDEP_A"), ());
+        let b = Spinlock::with_class(lock_class!("SELF_TEST_LOCKDEP_B"), ());
+        let mutex = Mutex::with_class(lock_class!("SELF_TEST_LOCKDEP_MUTEX"), ());
+        let reports = order::report_count();
+
+        logln!("Lockdep self-test: holding a mutex while acquiring spinlocks...");
+        {
+            let _mutex = mutex.try_lock().expect("Failed to acquire an unshared mutex");
+            let _a = a.lock();
+        }
+        {
+            let _a = a.lock();
+            let _mutex = mutex.try_lock().expect("Failed to acquire an unshared mutex");
+        }
+        assert_eq!(order::report_count(), reports, "A sleeping lock's order was validated");
+
+        logln!("Lockdep self-test: acquiring two spinlocks in a consistent order...");
+        for _ in 0..2 {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+        assert_eq!(order::report_count(), reports, "A consistent lock order was reported");
+
+        logln!("Lockdep self-test: acquiring two spinlocks in the opposite order...");
+        {
+            let _b = b.lock();
+            let _a = a.lock();
+        }
+        assert_eq!(order::report_count(), reports + 1, "A lock order inversion was not reported");
+        assert!(!order::is_enabled(), "Validation continued after a report");
+        order::enable();
+    }
+    #[cfg(not(feature = "lockdep"))]
+    logln!("Lockdep self-test: lock order validation is not built in, skipping.");
+    logln!("Lockdep self-test passed.");
+}
@@ -0,0 +1,99 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::log::filter::{self, level_for};
+use crate::log::persist::{self, REGION_SIZE, Region};
+use crate::log::ring::{LogRing, MAX_MESSAGE_LENGTH, RING_CAPACITY, ReadResult};
+use crate::log::sink::MEMORY_SINK;
+use crate::log::{Level, RING};
+use crate::{debug, info, logln, trace};
+
+static TEST_RING: LogRing = LogRing::new();
+
+fn read(ring: &LogRing, sequence: usize) -> Option<String> {
+    match ring.read(sequence) {
+        ReadResult::Record(record) => Some(String::from(record.message())),
+        _ => None,
+    }
+}
+
+pub fn test_log() {
+    logln!("Starting kernel log self-test...");
+    logln!("Kernel log self-test: writing and reading records...");
+    for i in 0..3 {
+        TEST_RING.write(Level::Info, module_path!(), 0, format_args!("record {}", i));
+    }
+    assert_eq!(TEST_RING.next_sequence(), 3);
+    assert_eq!(read(&TEST_RING, 1).as_deref(), Some("record 1"));
+    assert!(matches!(TEST_RING.read(3), ReadResult::Pending));
+
+    logln!("Kernel log self-test: truncating a long message...");
+    let long = "x".repeat(MAX_MESSAGE_LENGTH + 10);
+    TEST_RING.write(Level::Warn, module_path!(), 0, format_args!("{}", long));
+    let ReadResult::Record(record) = TEST_RING.read(3) else {
+        panic!("The long record could not be read back");
+    };
+    assert!(record.is_truncated());
+    assert_eq!(record.message().len(), MAX_MESSAGE_LENGTH);
+
+    logln!("Kernel log self-test: wrapping around the ring...");
+    for i in 0..RING_CAPACITY {
+        TEST_RING.write(Level::Debug, module_path!(), 0, format_args!("wrap {}", i));
+    }
+    assert!(matches!(TEST_RING.read(0), ReadResult::Lost), "An overwritten record was read");
+    let oldest = TEST_RING.oldest_sequence();
+    assert_eq!(read(&TEST_RING, oldest).as_deref(), Some("wrap 0"));
+    assert_eq!(TEST_RING.dropped(), 0);
+
+    logln!("Kernel log self-test: matching module levels...");
+    filter::set_module_level("cpu", Some(Level::Trace));
+    assert_eq!(level_for("charlottek::cpu::timers"), Level::Trace);
+    assert_eq!(level_for("cpu"), Level::Trace);
+    assert_eq!(level_for("charlottek::cpuid"), filter::max_level());
+    filter::set_module_level("charlottek::cpu::timers", Some(Level::Error));
+    assert_eq!(level_for("charlottek::cpu::timers::local"), Level::Error);
+    assert_eq!(level_for("charlottek::cpu::scheduler"), Level::Trace);
+    let mut levels = Vec::new();
+    filter::for_each_module_level(|module, level| levels.push((String::from(module), level)));
+    assert_eq!(levels.len(), 2);
+    filter::set_module_level("cpu", None);
+    filter::set_module_level("cpu::timers", None);
+    assert_eq!(level_for("charlottek::cpu::timers"), filter::max_level());
+
+    logln!("Kernel log self-test: filtering records...");
+    filter::set_module_level(module_path!(), Some(Level::Info));
+    let next = RING.next_sequence();
+    debug!("This record is filtered out");
+    trace!("So is this one");
+    assert_eq!(RING.next_sequence(), next, "A filtered out record was written to the ring");
+    filter::set_module_level(module_path!(), None);
+
+    logln!("Kernel log self-test: draining to the memory sink...");
+    let written = MEMORY_SINK.written();
+    info!("Kernel log self-test marker {}", next);
+    crate::log::flush();
+    assert!(MEMORY_SINK.written() > written);
+    let marker = alloc::format!("INFO  self_test::log: Kernel log self-test marker {}", next);
+    let mut text = Vec::new();
+    MEMORY_SINK.read(|bytes| text.extend_from_slice(bytes));
+    assert!(
+        text.windows(marker.len()).any(|window| window == marker.as_bytes()),
+        "The memory sink does not hold the latest record"
+    );
+
+    logln!("Kernel log self-test: recovering a persistent log region...");
+    let mut region = unsafe { Box::<Region>::new_zeroed().assume_init() };
+    assert!(persist::recover(&region).is_none(), "An uninitialized region was recovered");
+    region.reset();
+    region.write_text("first line\nsecond line\n");
+    assert!(persist::recover(&region).is_none(), "An unsealed region was recovered");
+    region.set_summary(b"self-test crash");
+    region.seal();
+    let recovered = persist::recover(&region).expect("A sealed region was not recovered");
+    assert_eq!(recovered.text.as_deref(), Some("first line\nsecond line\n"));
+    assert_eq!(recovered.crash_summary.as_deref(), Some("self-test crash"));
+    unsafe { *(&raw mut *region).cast::<u8>().add(REGION_SIZE - 1) ^= 1 };
+    assert!(persist::recover(&region).is_none(), "A corrupted region was recovered");
+    logln!("Kernel log self-test passed.");
+}
@@ -0,0 +1,46 @@
+use alloc::vec::Vec;
+
+use crate::cpu::multiprocessor::MAX_LP_COUNT;
+use crate::cpu::multiprocessor::lp_mask::{AtomicLpMask, LpMask};
+use crate::isa::lp::LpId;
+use crate::logln;
+
+pub fn test_lp_masks() {
+    logln!("Starting LP mask self-test...");
+    logln!("LP mask self-test: inserting and removing LPs...");
+    let last = MAX_LP_COUNT as LpId - 1;
+    let mut mask = LpMask::empty();
+    assert!(mask.is_empty());
+    for lp_id in [last, 64, 0, 63, 1] {
+        mask.insert(lp_id);
+    }
+    mask.insert(64);
+    assert_eq!(mask.count(), 5, "Inserting an LP twice counted it twice");
+    assert!(mask.contains(63) && mask.contains(64) && !mask.contains(65));
+    assert_eq!(mask.iter().collect::<Vec<_>>(), [0, 1, 63, 64, last]);
+    mask.remove(63);
+    mask.remove(2);
+    assert_eq!(mask.iter().collect::<Vec<_>>(), [0, 1, 64, last]);
+    assert_eq!(LpMask::single(64).iter().collect::<Vec<_>>(), [64]);
+    assert_eq!(LpMask::all().count(), MAX_LP_COUNT as u32);
+    assert_eq!(LpMask::default(), LpMask::empty());
+
+    logln!("LP mask self-test: combining masks...");
+    let mut other = LpMask::single(1);
+    other.insert(65);
+    assert_eq!(mask.intersection(&other), LpMask::single(1));
+    assert_eq!(mask.union(&other).iter().collect::<Vec<_>>(), [0, 1, 64, 65, last]);
+    assert!(mask.intersection(&LpMask::single(2)).is_empty());
+    assert_eq!(mask.intersection(&LpMask::all()), mask);
+
+    logln!("LP mask self-test: updating an atomic mask...");
+    let atomic = AtomicLpMask::empty();
+    assert!(atomic.insert(64));
+    assert!(!atomic.insert(64), "An LP already present was inserted again");
+    assert!(atomic.insert(0));
+    assert!(atomic.contains(64) && !atomic.contains(1));
+    assert!(atomic.remove(0));
+    assert!(!atomic.remove(0), "An LP that was not present was removed");
+    assert_eq!(atomic.load(), LpMask::single(64));
+    logln!("LP mask self-test passed.");
+}
@@ -0,0 +1,72 @@
+use alloc::vec::Vec;
+
+use crate::isa::mca;
+use crate::isa::mca::record::{
+    ErrorLog,
+    ErrorRecord,
+    MCG_STATUS_RIPV,
+    STATUS_AR,
+    STATUS_PCC,
+    STATUS_UC,
+    STATUS_VAL,
+    Severity,
+    Source,
+};
+use crate::logln;
+
+fn test_record(bank: u8) -> ErrorRecord {
+    ErrorRecord {
+        lp_id: 0,
+        bank,
+        severity: Severity::Corrected,
+        source: Source::Poll,
+        status: STATUS_VAL | 0x009f,
+        address: Some(0x1000),
+        misc: None,
+        timestamp: 0,
+    }
+}
+
+pub fn test_mca() {
+    logln!("Starting machine check self-test...");
+    logln!("Machine check self-test: classifying errors...");
+    let uncorrected = STATUS_VAL | STATUS_UC;
+    assert_eq!(Severity::classify(STATUS_VAL, Some(0)), Severity::Corrected);
+    assert_eq!(Severity::classify(uncorrected, None), Severity::UncorrectedRecoverable);
+    assert_eq!(
+        Severity::classify(uncorrected, Some(MCG_STATUS_RIPV)),
+        Severity::UncorrectedRecoverable
+    );
+    assert_eq!(Severity::classify(uncorrected, Some(0)), Severity::Fatal);
+    assert_eq!(
+        Severity::classify(uncorrected | STATUS_PCC, Some(MCG_STATUS_RIPV)),
+        Severity::Fatal
+    );
+    assert_eq!(Severity::classify(uncorrected | STATUS_AR, Some(MCG_STATUS_RIPV)), Severity::Fatal);
+
+    logln!("Machine check self-test: filling an error log...");
+    let log = ErrorLog::new();
+    assert!(log.is_empty());
+    for bank in 0..3 {
+        log.push(test_record(bank));
+    }
+    let mut new = Vec::new();
+    assert_eq!(log.for_each_new(|record| new.push(record.bank)), 0);
+    assert_eq!(new, [0, 1, 2]);
+    assert_eq!(log.for_each_new(|_| panic!("A record was passed twice")), 0);
+
+    logln!("Machine check self-test: overflowing the error log...");
+    for bank in 0..100 {
+        log.push(test_record(bank));
+    }
+    let mut held = 0;
+    log.for_each(|_| held += 1);
+    assert_eq!(held, 64, "The log does not hold its capacity of records once full");
+    let mut new = 0;
+    let lost = log.for_each_new(|_| new += 1);
+    assert_eq!((new, lost), (64, 36), "Overwritten records were not counted as lost");
+
+    logln!("Machine check self-test: polling the local banks...");
+    mca::poll_local();
+    logln!("Machine check self-test passed.");
+}
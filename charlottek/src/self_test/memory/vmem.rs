@@ -1,6 +1,10 @@
+use alloc::vec::Vec;
+
 use crate::isa::interface::memory::AddressSpaceInterface;
 use crate::isa::interface::memory::address::VirtualAddress;
-use crate::isa::memory::paging::AddressSpace;
+use crate::isa::lp::ops::get_lp_id;
+use crate::isa::memory::paging::{AddressSpace, PAGE_SIZE};
+use crate::isa::memory::tlb::shootdown::{ShootdownBatch, active_lps};
 use crate::logln;
 use crate::memory::PHYSICAL_FRAME_ALLOCATOR;
 use crate::memory::vmem::{MemoryMapping, PageType, VAddr};
@@ -45,6 +49,43 @@ pub fn test_vmem() {
         logln!("Unmapping test page.");
         current_as.unmap_page(higher_half_start).expect("Error unmapping page.");
         logln!("Test page successfully unmapped.");
-        logln!("All virtual memory tests passed!");
     }
+    logln!("Checking the active-LP mask of the current address space.");
+    assert!(
+        active_lps(current_as.get_root_table()).contains(get_lp_id!()),
+        "The current LP is not in the active-LP mask of the address space it has loaded"
+    );
+    logln!("Unmapping several pages with a single shootdown.");
+    const N_BATCHED_PAGES: usize = 4;
+    let batch_base = VAddr::from(0xffff_ffff_ffff_f000usize - N_BATCHED_PAGES * PAGE_SIZE);
+    let pages = (0..N_BATCHED_PAGES).map(|n| batch_base + (n * PAGE_SIZE) as isize);
+    for vaddr in pages.clone() {
+        let frame = PHYSICAL_FRAME_ALLOCATOR.lock().allocate_frame().unwrap();
+        current_as
+            .map_page(MemoryMapping {
+                vaddr,
+                paddr: frame,
+                page_type: PageType::KernelData,
+            })
+            .expect("Error mapping a page to unmap in a batch.");
+        unsafe { vaddr.into_mut::<u32>().write(MAGIC_NUMBER) };
+    }
+    let mut batch = ShootdownBatch::new(&current_as);
+    let frames: Vec<_> = pages
+        .clone()
+        .map(|vaddr| {
+            current_as.unmap_page_batched(vaddr, &mut batch).expect("Error unmapping a page.")
+        })
+        .collect();
+    assert!(!batch.is_empty(), "Unmapped pages were not added to the shootdown batch");
+    batch.flush();
+    assert!(batch.is_empty());
+    // The frames may only be reused once no LP can reach them through a stale TLB entry.
+    for frame in frames {
+        PHYSICAL_FRAME_ALLOCATOR.lock().deallocate_frame(frame).unwrap();
+    }
+    for vaddr in pages {
+        assert!(!current_as.is_mapped(vaddr).unwrap(), "A page in the batch is still mapped");
+    }
+    logln!("All virtual memory tests passed!");
 }
@@ -5,14 +5,62 @@
 //! some tests in this module. In software engineering terminology the tests in this module should
 //! be whitebox integration tests that can be run after charlottek initializes itself.
 
+pub mod backtrace;
+pub mod cmdline;
+pub mod deferred;
+pub mod event;
+pub mod exceptions;
+pub mod executor;
+pub mod gdb;
+pub mod ipi;
+pub mod irq;
+pub mod lockdep;
+pub mod log;
+pub mod lp_mask;
+pub mod mca;
 pub mod memory;
+pub mod monitor;
+pub mod msi;
+pub mod scheduler;
+pub mod sync;
+pub mod time;
+pub mod timers;
+pub mod watchdog;
 
-use crate::logln;
+use crate::{kernel_param, logln};
+
+kernel_param! {
+    static ENABLED: bool = ("selftest", true);
+}
 
 pub fn run_self_tests() {
+    if !ENABLED.get() {
+        logln!("Self tests are disabled on the kernel command line.");
+        return;
+    }
     logln!("Running self tests...");
+    exceptions::test_exception_reports();
+    backtrace::test_backtraces();
     memory::pmem::test_pmem();
     memory::vmem::test_vmem();
     memory::allocator::test_allocator();
+    sync::test_sync();
+    lockdep::test_lockdep();
+    time::test_time();
+    timers::test_timers();
+    deferred::test_deferred();
+    event::test_events();
+    executor::test_executor();
+    scheduler::test_scheduler();
+    lp_mask::test_lp_masks();
+    ipi::test_ipis();
+    irq::test_irqs();
+    msi::test_msi();
+    watchdog::test_watchdog();
+    mca::test_mca();
+    log::test_log();
+    gdb::test_gdb();
+    monitor::test_monitor();
+    cmdline::test_cmdline();
     logln!("Testing Complete. All Tests Passed!");
 }
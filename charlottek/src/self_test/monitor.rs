@@ -0,0 +1,38 @@
+use crate::debug::memory;
+use crate::isa::lp::ops::{TSC_AUX_MSR, read_msr, try_read_msr};
+use crate::isa::memory::paging::pth_walker::PthWalker;
+use crate::logln;
+use crate::memory::{AddressSpace, AddressSpaceInterface, PHYSICAL_FRAME_ALLOCATOR, VAddr};
+
+/// An MSR in the reserved range that no processor implements
+const UNIMPLEMENTED_MSR: u32 = 0x4000_1000;
+
+pub fn test_monitor() {
+    logln!("Starting kernel monitor self-test...");
+    logln!("Kernel monitor self-test: probing MSRs...");
+    assert_eq!(try_read_msr(TSC_AUX_MSR), Some(unsafe { read_msr(TSC_AUX_MSR) }));
+    assert_eq!(try_read_msr(UNIMPLEMENTED_MSR), None);
+
+    logln!("Kernel monitor self-test: accessing memory through the checks...");
+    let mut target = [0u8; 8];
+    let address = target.as_mut_ptr() as usize;
+    assert!(memory::write(address, &0x1122_3344_5566_7788u64.to_le_bytes()));
+    let mut read_back = [0; 8];
+    assert_eq!(memory::read(address, &mut read_back), 8);
+    assert_eq!(u64::from_le_bytes(read_back), 0x1122_3344_5566_7788);
+    assert_eq!(memory::read(0, &mut read_back), 0, "The null page was read");
+    assert_eq!(memory::read(0x8000_0000_0000_0000, &mut read_back), 0);
+    assert!(!memory::write(0x8000_0000_0000_0000, &[0]));
+
+    logln!("Kernel monitor self-test: walking the page tables...");
+    let mut address_space = AddressSpace::get_current();
+    let walker = PthWalker::new(&mut address_space, VAddr::from(address));
+    let entries = walker.translation_entries();
+    let last = entries.iter().flatten().last().expect("The walk returned no entries");
+    assert!(last.is_present(), "The stack is not mapped according to the walk");
+
+    logln!("Kernel monitor self-test: counting frames...");
+    let stats = PHYSICAL_FRAME_ALLOCATOR.lock().stats();
+    assert!(stats.free > 0 && stats.free < stats.total);
+    logln!("Kernel monitor self-test passed.");
+}
@@ -0,0 +1,49 @@
+use crate::drivers::pci;
+use crate::drivers::pci::msi::{CAPABILITY_ID_MSI, CAPABILITY_ID_MSIX};
+use crate::isa::interrupts::msi;
+use crate::isa::interrupts::x2apic::lapic_id_of;
+use crate::isa::lp::ops::get_lp_id;
+use crate::logln;
+
+pub fn test_msi() {
+    logln!("Starting MSI self-test...");
+    logln!("MSI self-test: allocating a block of message vectors...");
+    let lp_id = get_lp_id!();
+    let vectors = msi::allocate(lp_id, 4, |_| {}).expect("Failed to allocate MSI vectors");
+    assert_eq!(vectors.first_vector % 4, 0, "A block of MSI vectors was not aligned to its size");
+    let destination = lapic_id_of(lp_id).unwrap().physical;
+    for index in 0..vectors.count {
+        let message = vectors.message(index);
+        assert_eq!(message.address >> 20, 0xfee, "An MSI address is outside of the APIC range");
+        assert_eq!(
+            (message.address >> 12) & 0xff,
+            destination as u64,
+            "An MSI address does not select the target LP"
+        );
+        assert_eq!(
+            message.data,
+            (vectors.first_vector as usize + index) as u32,
+            "An MSI data word does not select its vector"
+        );
+    }
+    let first_vector = vectors.first_vector;
+    msi::free(vectors);
+    let vectors = msi::allocate(lp_id, 4, |_| {}).expect("Failed to reallocate MSI vectors");
+    assert_eq!(vectors.first_vector, first_vector, "Freed MSI vectors were not reused");
+    msi::free(vectors);
+
+    logln!("MSI self-test: looking for functions with MSI capabilities...");
+    let (mut n_functions, mut n_msi, mut n_msix) = (0, 0, 0);
+    pci::for_each_function(|config| {
+        n_functions += 1;
+        n_msi += config.find_capability(CAPABILITY_ID_MSI).is_some() as usize;
+        n_msix += config.find_capability(CAPABILITY_ID_MSIX).is_some() as usize;
+    });
+    logln!(
+        "MSI self-test: {} PCI functions, {} support MSI and {} support MSI-X.",
+        n_functions,
+        n_msi,
+        n_msix
+    );
+    logln!("MSI self-test passed.");
+}
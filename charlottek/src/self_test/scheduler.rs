@@ -0,0 +1,56 @@
+use crate::cpu::multiprocessor::{is_lp_online, set_local_lp_offline, set_local_lp_online};
+use crate::cpu::scheduler::{GLOBAL_SCHEDULER, TrackedThread};
+use crate::cpu::threads::ThreadId;
+use crate::isa::lp::ops::get_lp_id;
+use crate::logln;
+
+fn tracked_as(tid: ThreadId, tracked: TrackedThread) -> bool {
+    GLOBAL_SCHEDULER
+        .try_list_tracked_threads()
+        .expect("The global scheduler's locks are held")
+        .contains(&(tid, tracked))
+}
+
+pub fn test_scheduler() {
+    logln!("Starting scheduler self-test...");
+    logln!("Scheduler self-test: taking the current LP offline...");
+    let lp_id = get_lp_id!();
+    set_local_lp_offline();
+    assert!(!is_lp_online(lp_id), "The LP is still online");
+    // The LP's system work queue worker is pinned to it and so has nowhere else to go.
+    let parked: Option<ThreadId> = GLOBAL_SCHEDULER
+        .try_list_tracked_threads()
+        .expect("The global scheduler's locks are held")
+        .into_iter()
+        .find_map(|(tid, tracked)| (tracked == TrackedThread::Parked(lp_id)).then_some(tid));
+    let worker = parked.expect("The LP's system worker was not parked");
+    assert!(
+        GLOBAL_SCHEDULER.get_local_lp_scheduler().lock().thread_mut(worker).is_none(),
+        "A parked thread was left on the offline LP"
+    );
+
+    logln!("Scheduler self-test: waking a parked thread...");
+    // The worker is blocked unless it happened to be running work items.
+    let was_blocked = tracked_as(worker, TrackedThread::Blocked(lp_id));
+    if was_blocked {
+        GLOBAL_SCHEDULER.wake_thread(worker);
+        assert!(
+            tracked_as(worker, TrackedThread::ReadyUnassigned),
+            "A thread woken while its LP was offline was not set aside"
+        );
+    }
+
+    logln!("Scheduler self-test: bringing the current LP back online...");
+    set_local_lp_online();
+    assert!(is_lp_online(lp_id), "The LP is not online");
+    assert!(!tracked_as(worker, TrackedThread::Parked(lp_id)), "The worker is still parked");
+    assert!(
+        !tracked_as(worker, TrackedThread::ReadyUnassigned),
+        "The woken worker was not handed back to its LP"
+    );
+    assert!(
+        GLOBAL_SCHEDULER.get_local_lp_scheduler().lock().thread_mut(worker).is_some(),
+        "The worker was not returned to its LP"
+    );
+    logln!("Scheduler self-test passed.");
+}
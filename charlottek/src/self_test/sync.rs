@@ -0,0 +1,132 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use crate::cpu::multiprocessor::online_lps;
+use crate::cpu::sync::{
+    Condvar,
+    Deadline,
+    IrqSpinlock,
+    Mutex,
+    RcuCell,
+    RwLock,
+    Semaphore,
+    Spinlock,
+    WaitQueue,
+    rcu,
+    rcu_read_lock,
+    synchronize_rcu,
+};
+use crate::isa::interrupts::ipis::call_on_lps;
+use crate::isa::lp::ops::interrupts_enabled;
+use crate::klib::collections::id_table::IdTable;
+use crate::logln;
+
+/// How long a reader is held across an update before checking that the old version survived
+const RCU_READER_HOLD: Duration = Duration::from_millis(20);
+
+pub fn test_sync() {
+    logln!("Starting synchronization primitive self-test...");
+    logln!("Sync self-test: Mutex exclusion...");
+    let mutex = Mutex::new(0u64);
+    {
+        let mut guard = mutex.lock();
+        *guard += 1;
+        assert!(mutex.try_lock().is_none(), "Mutex was acquired twice");
+        assert!(
+            mutex.lock_timeout(Duration::from_millis(1)).is_none(),
+            "Timed mutex acquisition succeeded while the mutex was held"
+        );
+    }
+    assert_eq!(*mutex.try_lock().expect("Mutex was not released"), 1);
+    logln!("Sync self-test: RwLock readers and writers...");
+    let rwlock = RwLock::new(0u64);
+    {
+        let _reader_a = rwlock.read();
+        let _reader_b = rwlock.try_read().expect("Concurrent readers were not permitted");
+        assert!(rwlock.try_write().is_none(), "Writer acquired a lock held by readers");
+    }
+    *rwlock.write() = 2;
+    assert_eq!(*rwlock.read(), 2);
+    logln!("Sync self-test: Semaphore counting...");
+    let semaphore = Semaphore::new(2);
+    semaphore.acquire();
+    assert!(semaphore.try_acquire());
+    assert!(!semaphore.acquire_timeout(Duration::from_millis(1)));
+    semaphore.release();
+    assert_eq!(semaphore.available(), 1);
+    logln!("Sync self-test: Condvar and WaitQueue timeouts...");
+    let condvar = Condvar::new();
+    let (guard, result) = condvar.wait_timeout(mutex.lock(), Duration::from_millis(1));
+    assert!(result.timed_out(), "Condvar wait returned without a notification");
+    assert_eq!(*guard, 1);
+    drop(guard);
+    let wait_queue = WaitQueue::new();
+    assert!(wait_queue.wait_timeout(Duration::from_millis(1)).timed_out());
+    assert!(!wait_queue.has_waiters(), "Timed out waiter was left in the wait queue");
+    assert!(!wait_queue.wake_one());
+    logln!("Sync self-test: Spinlocks and interrupt masking...");
+    let spinlock = Spinlock::new(0u64);
+    {
+        let _guard = spinlock.lock();
+        assert!(spinlock.try_lock().is_none(), "Spinlock was acquired twice");
+    }
+    let interrupts_were_enabled = interrupts_enabled();
+    let irq_spinlock = IrqSpinlock::new(0u64);
+    {
+        let _outer = irq_spinlock.lock();
+        assert!(!interrupts_enabled(), "Interrupts were not masked by IrqSpinlock");
+        assert!(irq_spinlock.try_lock().is_none(), "IrqSpinlock was acquired twice");
+        assert!(!interrupts_enabled(), "Failed IrqSpinlock acquisition unmasked interrupts");
+    }
+    assert_eq!(
+        interrupts_enabled(),
+        interrupts_were_enabled,
+        "IrqSpinlock did not restore the interrupt state"
+    );
+    logln!("Sync self-test: RCU updates and callbacks...");
+    static RECLAIMED: AtomicBool = AtomicBool::new(false);
+    struct Version(u64);
+    impl Drop for Version {
+        fn drop(&mut self) {
+            RECLAIMED.store(true, Ordering::Release);
+        }
+    }
+    let cell = RcuCell::new(Version(1));
+    {
+        let guard = rcu_read_lock();
+        let old = cell.read(&guard);
+        cell.update(|version| Version(version.0 + 1));
+        assert_eq!(cell.read(&guard).0, 2, "RCU update was not published");
+        // Run the callbacks from inside the critical section, on every LP, for long enough that
+        // any grace period which ignores this reader would have ended.
+        let hold = Deadline::after(RCU_READER_HOLD);
+        while !hold.has_expired() {
+            call_on_lps(online_lps(), rcu::process_callbacks);
+        }
+        assert!(
+            !RECLAIMED.load(Ordering::Acquire),
+            "An RCU protected value was reclaimed while a reader held it"
+        );
+        assert_eq!(old.0, 1, "RCU protected value changed under a reader");
+    }
+    let give_up = Deadline::after(Duration::from_secs(1));
+    while !RECLAIMED.load(Ordering::Acquire) {
+        assert!(
+            !give_up.has_expired(),
+            "An RCU protected value was not reclaimed after its readers"
+        );
+        synchronize_rcu();
+    }
+    logln!("Sync self-test: IdTable ID reuse...");
+    let table: IdTable<usize, u32> = IdTable::new();
+    let first = table.add_element(1);
+    let second = table.add_element(2);
+    table.remove_element(first);
+    // An ID that was never handed out must not be recycled.
+    table.remove_element(second + 1);
+    assert_eq!(table.add_element(3), first, "A freed ID was not reused");
+    let fresh = table.add_element(4);
+    assert!(fresh != first && fresh != second, "An ID in use was handed out again");
+    assert_eq!(*table.try_get_element_arc(second).unwrap().read(), 2);
+    logln!("Synchronization primitive self-test: PASSED");
+}
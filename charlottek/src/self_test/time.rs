@@ -0,0 +1,43 @@
+use core::time::Duration;
+
+use crate::logln;
+use crate::time::calendar::DateTime;
+use crate::time::{monotonic_now, realtime_now};
+
+pub fn test_time() {
+    logln!("Starting time keeping self-test...");
+    logln!("Time self-test: calendar conversions...");
+    let leap_day = DateTime {
+        year: 2024,
+        month: 2,
+        day: 29,
+        hour: 23,
+        minute: 59,
+        second: 59,
+        nanosecond: 0,
+    };
+    assert_eq!(leap_day.to_unix_time(), Some(Duration::from_secs(1_709_251_199)));
+    assert_eq!(DateTime::from_unix_time(Duration::from_secs(1_709_251_199)), leap_day);
+    assert_eq!(DateTime::from_unix_time(Duration::ZERO).to_unix_time(), Some(Duration::ZERO));
+    assert!(
+        DateTime {
+            day: 29,
+            year: 2100,
+            ..leap_day
+        }
+        .to_unix_time()
+        .is_none(),
+        "2100 was treated as a leap year"
+    );
+
+    logln!("Time self-test: monotonic time...");
+    let mut previous = monotonic_now();
+    for _ in 0..1000 {
+        let now = monotonic_now();
+        assert!(now >= previous, "Monotonic time went backwards");
+        previous = now;
+    }
+    let realtime = realtime_now();
+    assert!(realtime_now() >= realtime, "Wall clock time went backwards");
+    logln!("Time keeping self-test passed.");
+}
@@ -0,0 +1,44 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+
+use crate::cpu::sync::{Deadline, IrqSpinlock};
+use crate::cpu::timers::{add_timer, cancel_timer, sleep};
+use crate::isa::lp::ops::wait_for_interrupt;
+use crate::logln;
+
+pub fn test_timers() {
+    logln!("Starting kernel timer self-test...");
+    logln!("Timer self-test: sleep...");
+    let deadline = Deadline::after(Duration::from_millis(2));
+    sleep(Duration::from_millis(2));
+    assert!(deadline.has_expired(), "sleep returned before its duration had elapsed");
+
+    logln!("Timer self-test: timers fire in deadline order...");
+    let order = Arc::new(IrqSpinlock::new(alloc::vec::Vec::new()));
+    let fired = Arc::new(AtomicU32::new(0));
+    for (index, delay_ms) in [(1u32, 10u64), (0, 5)] {
+        let order = order.clone();
+        let fired = fired.clone();
+        add_timer(Deadline::after(Duration::from_millis(delay_ms)), move || {
+            order.lock().push(index);
+            fired.fetch_add(1, Ordering::Release);
+        });
+    }
+    let cancelled = {
+        let fired = fired.clone();
+        add_timer(Deadline::after(Duration::from_millis(1)), move || {
+            fired.fetch_add(100, Ordering::Release);
+        })
+    };
+    assert!(cancel_timer(&cancelled), "A pending timer could not be cancelled");
+    assert!(!cancel_timer(&cancelled), "A timer was cancelled twice");
+    let give_up = Deadline::after(Duration::from_secs(1));
+    while fired.load(Ordering::Acquire) < 2 {
+        assert!(!give_up.has_expired(), "Timers did not fire within a second");
+        wait_for_interrupt();
+    }
+    assert_eq!(fired.load(Ordering::Acquire), 2, "A cancelled timer fired");
+    assert_eq!(*order.lock(), [0, 1], "Timers fired out of deadline order");
+    logln!("Kernel timer self-test passed.");
+}
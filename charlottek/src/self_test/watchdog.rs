@@ -0,0 +1,48 @@
+use core::arch::asm;
+use core::time::Duration;
+
+use crate::cpu::sync::Deadline;
+use crate::debug::watchdog;
+use crate::isa::interrupts::exceptions;
+use crate::isa::interrupts::x2apic::perf_counter;
+use crate::isa::lp::ops::{mask_interrupts, unmask_interrupts};
+use crate::logln;
+
+const TEST_WINDOW: Duration = Duration::from_millis(200);
+/// Long enough for several NMIs but well within the window
+const MASKED_SPIN: Duration = Duration::from_millis(120);
+
+pub fn test_watchdog() {
+    logln!("Starting NMI watchdog self-test...");
+    if !perf_counter::is_supported() {
+        // E.g. under TCG. KVM with `-cpu host`, as the run targets use, exposes the host's PMU.
+        logln!("NMI watchdog self-test: no performance counter, skipping.");
+        return;
+    }
+    let window = watchdog::window();
+    watchdog::set_window(TEST_WINDOW);
+    watchdog::init_local();
+
+    logln!("NMI watchdog self-test: spinning with interrupts masked within the window...");
+    let nmis_before = watchdog::nmi_count();
+    let delivered_before = exceptions::nmi_count();
+    mask_interrupts!();
+    watchdog::touch();
+    let deadline = Deadline::after(MASKED_SPIN);
+    while !deadline.has_expired() {
+        core::hint::spin_loop();
+    }
+    watchdog::touch();
+    unmask_interrupts!();
+    let nmis = watchdog::nmi_count() - nmis_before;
+    assert!(nmis > 0, "No watchdog NMI was delivered while the LP was busy");
+    assert!(
+        exceptions::nmi_count() - delivered_before >= nmis,
+        "Watchdog NMIs were counted that did not arrive through the NMI gate"
+    );
+    logln!("NMI watchdog self-test: {} NMIs were delivered without reporting a lockup.", nmis);
+
+    watchdog::set_window(window);
+    watchdog::init_local();
+    logln!("NMI watchdog self-test passed.");
+}
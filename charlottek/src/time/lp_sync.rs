@@ -0,0 +1,143 @@
+//! # Timestamp Counter Synchronization
+//!
+//! The timestamp counters of different LPs are not guaranteed to agree. Each AP measures the offset
+//! of its counter from the BSP's while it is brought up: it repeatedly asks the BSP for its counter
+//! value and assumes that the BSP read it halfway between sending the request and receiving the
+//! reply. The sample with the shortest round trip is the most precise and is used. Offsets within
+//! its uncertainty are considered noise and ignored.
+//!
+//! The BSP only answers for a limited time so that an AP which never checks in cannot stall the
+//! boot. APs that are not synchronized by then assume their counter agrees with the BSP's.
+
+use core::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering};
+
+use crate::cpu::multiprocessor::lp_mask::AtomicLpMask;
+use crate::cpu::multiprocessor::{MAX_LP_COUNT, get_lp_count};
+use crate::isa::lp::LpId;
+use crate::isa::lp::ops::{get_lp_id, read_timestamp};
+use crate::time::timestamp_frequency;
+use crate::{logln, warn};
+
+const SYNC_ROUNDS: usize = 16;
+/// How long the BSP answers requests for, in milliseconds
+const SYNC_TIMEOUT_MS: u64 = 1000;
+
+const PHASE_IDLE: u32 = 0;
+const PHASE_REQUESTED: u32 = 1;
+const PHASE_ANSWERED: u32 = 2;
+const NO_LP: u32 = u32::MAX;
+
+/// The amount added to each LP's counter to obtain the BSP's counter value
+static OFFSETS: [AtomicI64; MAX_LP_COUNT] = [const { AtomicI64::new(0) }; MAX_LP_COUNT];
+
+/// The AP currently being synchronized
+static SYNC_OWNER: AtomicU32 = AtomicU32::new(NO_LP);
+static SYNC_PHASE: AtomicU32 = AtomicU32::new(PHASE_IDLE);
+static BSP_TIMESTAMP: AtomicU64 = AtomicU64::new(0);
+static SYNCHRONIZED_APS: AtomicLpMask = AtomicLpMask::empty();
+/// Set once the BSP has stopped answering requests
+static SYNC_CLOSED: AtomicBool = AtomicBool::new(false);
+
+fn offset() -> i64 {
+    OFFSETS.get(get_lp_id!() as usize).map_or(0, |offset| offset.load(Ordering::Relaxed))
+}
+
+/// Converts a value of the current LP's counter to the corresponding value of the BSP's counter
+pub fn synchronized_timestamp(local: u64) -> u64 {
+    local.wrapping_add_signed(offset())
+}
+
+/// Converts a value of the BSP's counter to the corresponding value of the current LP's counter
+pub fn local_timestamp(synchronized: u64) -> u64 {
+    synchronized.wrapping_add_signed(offset().wrapping_neg())
+}
+
+/// Answers the synchronization requests of the APs until all of them have been synchronized or
+/// the time allowed for it has run out, in which case the LPs left out are reported. Must be called
+/// on the BSP once the APs have been started.
+pub fn synchronize_aps() {
+    let n_aps = get_lp_count() - 1;
+    let deadline = read_timestamp()
+        .saturating_add(timestamp_frequency().saturating_mul(SYNC_TIMEOUT_MS) / 1000);
+    while SYNCHRONIZED_APS.load().count() < n_aps {
+        if read_timestamp() >= deadline {
+            SYNC_CLOSED.store(true, Ordering::Release);
+            let bsp_id = get_lp_id!();
+            for lp_id in (0..get_lp_count() as LpId).filter(|&lp_id| lp_id != bsp_id) {
+                if !SYNCHRONIZED_APS.contains(lp_id) {
+                    warn!(
+                        "LP{} did not synchronize its timestamp counter within {} ms.",
+                        lp_id, SYNC_TIMEOUT_MS
+                    );
+                }
+            }
+            return;
+        }
+        if SYNC_PHASE.load(Ordering::Acquire) == PHASE_REQUESTED {
+            BSP_TIMESTAMP.store(read_timestamp(), Ordering::Relaxed);
+            SYNC_PHASE.store(PHASE_ANSWERED, Ordering::Release);
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Measures the offset of the current AP's counter from the BSP's. The BSP must be running
+/// [`synchronize_aps`]. If it has stopped answering, the offset is left at zero.
+pub fn synchronize_local() {
+    let lp_id = get_lp_id!();
+    while SYNC_OWNER
+        .compare_exchange_weak(NO_LP, lp_id, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        if SYNC_CLOSED.load(Ordering::Acquire) {
+            warn_unsynchronized(lp_id);
+            return;
+        }
+        core::hint::spin_loop();
+    }
+    let mut best_round_trip = u64::MAX;
+    let mut best_offset = 0i64;
+    for _ in 0..SYNC_ROUNDS {
+        let start = read_timestamp();
+        SYNC_PHASE.store(PHASE_REQUESTED, Ordering::Release);
+        while SYNC_PHASE.load(Ordering::Acquire) != PHASE_ANSWERED {
+            if SYNC_CLOSED.load(Ordering::Acquire) {
+                SYNC_PHASE.store(PHASE_IDLE, Ordering::Relaxed);
+                SYNC_OWNER.store(NO_LP, Ordering::Release);
+                warn_unsynchronized(lp_id);
+                return;
+            }
+            core::hint::spin_loop();
+        }
+        let end = read_timestamp();
+        let bsp_timestamp = BSP_TIMESTAMP.load(Ordering::Relaxed);
+        SYNC_PHASE.store(PHASE_IDLE, Ordering::Relaxed);
+        let round_trip = end.wrapping_sub(start);
+        if round_trip < best_round_trip {
+            best_round_trip = round_trip;
+            best_offset = bsp_timestamp.wrapping_sub(start + round_trip / 2) as i64;
+        }
+    }
+    let offset = if best_offset.unsigned_abs() <= best_round_trip / 2 {
+        0
+    } else {
+        best_offset
+    };
+    OFFSETS[lp_id as usize].store(offset, Ordering::Relaxed);
+    SYNC_OWNER.store(NO_LP, Ordering::Release);
+    SYNCHRONIZED_APS.insert(lp_id);
+    logln!(
+        "LP{}: Timestamp counter offset from the BSP is {} (round trip {}).",
+        lp_id,
+        offset,
+        best_round_trip
+    );
+}
+
+fn warn_unsynchronized(lp_id: LpId) {
+    warn!(
+        "LP{}: The BSP stopped answering before the timestamp counter was synchronized. Assuming \
+         it agrees with the BSP's.",
+        lp_id
+    );
+}
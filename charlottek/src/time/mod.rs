@@ -0,0 +1,162 @@
+//! # Time Keeping
+//!
+//! Kernel time is kept with the timestamp counter of each LP. The BSP calibrates the counter once
+//! during initialization and every AP measures the offset of its counter from the BSP's as it comes
+//! up so that [`monotonic_now`] agrees across LPs. Until the counter has been calibrated a nominal
+//! frequency is assumed; the calibration preserves the current time so monotonic time never jumps
+//! backwards.
+//!
+//! Wall clock time is kept as an offset from monotonic time. It is initialized from the CMOS RTC,
+//! or from the UEFI runtime services on platforms without one, and can be adjusted with
+//! [`set_realtime`].
+
+pub mod calendar;
+pub mod lp_sync;
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use calendar::DateTime;
+use spin::Lazy;
+
+use crate::drivers::rtc::cmos;
+use crate::environment::firmware::uefi_rt;
+use crate::isa::interface::system_info::CpuInfoIfce;
+use crate::isa::interface::timers::TimestampCounterIfce;
+use crate::isa::lp::ops::read_timestamp;
+use crate::isa::system_info::CpuInfo;
+use crate::isa::timers::TimestampCounter;
+use crate::logln;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const NOMINAL_TIMESTAMP_FREQUENCY: u64 = 1_000_000_000;
+// Timestamps are converted to nanoseconds by multiplying with a 32.32 fixed point scale.
+const SCALE_SHIFT: u32 = 32;
+
+static TIMESTAMP_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+static FALLBACK_FREQUENCY: Lazy<u64> =
+    Lazy::new(|| CpuInfo::get_timestamp_frequency().unwrap_or(NOMINAL_TIMESTAMP_FREQUENCY));
+
+/// Monotonic time is `BASE_NANOS` plus the time elapsed since the synchronized timestamp counter
+/// read `BASE_TIMESTAMP`. Both only change when the counter is calibrated on the BSP before any
+/// AP is started.
+static BASE_TIMESTAMP: AtomicU64 = AtomicU64::new(0);
+static BASE_NANOS: AtomicU64 = AtomicU64::new(0);
+/// Nanoseconds per timestamp counter increment in 32.32 fixed point, or zero until calibrated
+static SCALE: AtomicU64 = AtomicU64::new(0);
+
+/// The wall clock time at which monotonic time was zero, in nanoseconds since the UNIX epoch
+static REALTIME_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+fn scale_for(frequency: u64) -> u64 {
+    ((NANOS_PER_SECOND as u128) << SCALE_SHIFT).div_ceil(frequency as u128) as u64
+}
+
+fn scale() -> u64 {
+    match SCALE.load(Ordering::Relaxed) {
+        0 => scale_for(*FALLBACK_FREQUENCY),
+        scale => scale,
+    }
+}
+
+/// The frequency of the timestamp counter in Hz
+pub fn timestamp_frequency() -> u64 {
+    match TIMESTAMP_FREQUENCY.load(Ordering::Relaxed) {
+        0 => *FALLBACK_FREQUENCY,
+        frequency => frequency,
+    }
+}
+
+fn nanos_at(timestamp: u64) -> u64 {
+    let elapsed = timestamp.saturating_sub(BASE_TIMESTAMP.load(Ordering::Relaxed));
+    let nanos = (elapsed as u128 * scale() as u128) >> SCALE_SHIFT;
+    BASE_NANOS.load(Ordering::Relaxed).saturating_add(u64::try_from(nanos).unwrap_or(u64::MAX))
+}
+
+/// Returns the number of nanoseconds since an arbitrary point during boot. The value never
+/// decreases and is consistent across LPs within the precision of their synchronization.
+pub fn monotonic_now() -> u64 {
+    nanos_at(lp_sync::synchronized_timestamp(read_timestamp()))
+}
+
+/// Returns the value the current LP's timestamp counter has at the given monotonic time
+pub fn local_timestamp_at(nanos: u64) -> u64 {
+    let elapsed = nanos.saturating_sub(BASE_NANOS.load(Ordering::Relaxed)) as u128;
+    let ticks = (elapsed << SCALE_SHIFT) / scale() as u128;
+    let timestamp = BASE_TIMESTAMP
+        .load(Ordering::Relaxed)
+        .saturating_add(u64::try_from(ticks).unwrap_or(u64::MAX));
+    lp_sync::local_timestamp(timestamp)
+}
+
+/// Returns the time since the UNIX epoch
+pub fn realtime_now() -> Duration {
+    Duration::from_nanos(REALTIME_OFFSET.load(Ordering::Relaxed).saturating_add(monotonic_now()))
+}
+
+/// Sets the wall clock to the given time since the UNIX epoch
+pub fn set_realtime(time: Duration) {
+    let nanos = u64::try_from(time.as_nanos()).unwrap_or(u64::MAX);
+    REALTIME_OFFSET.store(nanos.saturating_sub(monotonic_now()), Ordering::Relaxed);
+}
+
+/// Calibrates the timestamp counter and initializes the wall clock. Must be called on the BSP
+/// before any AP is started.
+pub fn init() {
+    if !TimestampCounter::is_invariant() {
+        logln!("Time: The timestamp counter is not invariant; time may drift in low power states.");
+    }
+    let frequency = match TimestampCounter::measure_frequency() {
+        Some(frequency) => frequency,
+        None => {
+            logln!("Time: No reference clock to calibrate the timestamp counter against.");
+            *FALLBACK_FREQUENCY
+        }
+    };
+    // Rebase monotonic time on the current instant so that it continues from its current value.
+    let timestamp = lp_sync::synchronized_timestamp(read_timestamp());
+    BASE_NANOS.store(nanos_at(timestamp), Ordering::Relaxed);
+    BASE_TIMESTAMP.store(timestamp, Ordering::Relaxed);
+    SCALE.store(scale_for(frequency), Ordering::Relaxed);
+    TIMESTAMP_FREQUENCY.store(frequency, Ordering::Relaxed);
+    logln!("Time: Timestamp counter frequency is {} Hz.", frequency);
+    match read_wall_clock() {
+        Some(date_time) => {
+            set_realtime(date_time.to_unix_time().unwrap_or_default());
+            logln!("Time: Wall clock set to {}.", date_time);
+        }
+        None => logln!("Time: No wall clock is available; wall clock time starts at the epoch."),
+    }
+}
+
+fn read_wall_clock() -> Option<DateTime> {
+    let date_time = if cmos::is_present() {
+        cmos::read_date_time()
+    } else {
+        uefi_rt::get_time()
+    };
+    date_time.filter(|date_time| date_time.to_unix_time().is_some())
+}
+
+/// A monotonic timestamp formatted for log messages as seconds since boot
+#[derive(Debug, Clone, Copy)]
+pub struct LogTimestamp(u64);
+
+impl LogTimestamp {
+    pub fn now() -> Self {
+        LogTimestamp(monotonic_now())
+    }
+
+    /// A timestamp for the given monotonic time in nanoseconds
+    pub const fn from_nanos(nanos: u64) -> Self {
+        LogTimestamp(nanos)
+    }
+}
+
+impl fmt::Display for LogTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let micros = self.0 / 1000;
+        write!(f, "[{:>5}.{:06}]", micros / 1_000_000, micros % 1_000_000)
+    }
+}